@@ -32,9 +32,8 @@ fn main() -> Result<()> {
     // Show what affects the build
     println!("\nEnvironment variables tracked:");
     for var in ENV_VARS_THAT_AFFECT_BUILD {
-        match std::env::var(var) {
-            Ok(val) => println!("  {} = {}", var, val),
-            Err(_) => {}
+        if let Ok(val) = std::env::var(var) {
+            println!("  {} = {}", var, val);
         }
     }
 
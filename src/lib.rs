@@ -48,7 +48,7 @@
 //!
 //! ```toml
 //! [dependencies]
-//! cargo-save = "0.2"
+//! cargo-save = "0.3"
 //! ```
 //!
 //! Or install as a CLI tool:
@@ -90,8 +90,11 @@
 //!
 //! # Error Handling
 //!
-//! This crate uses [`anyhow`] for error handling. All fallible operations return
-//! `anyhow::Result<T>`, which provides rich error context and easy error propagation.
+//! Core operations such as [`CacheManager::new`], [`CacheManager::compute_workspace_state`]
+//! and [`CacheManager::load_build_cache`] return [`CacheResult<T>`], whose
+//! [`CacheError`] can be matched to tell e.g. a missing workspace from a corrupt
+//! cache file. Other operations return `anyhow::Result<T>`. `CacheError`
+//! converts into [`anyhow::Error`], so `?` works in either kind of function.
 //!
 //! # Examples
 //!
@@ -166,7 +169,12 @@
 //!
 //! # Feature Flags
 //!
-//! This crate does not currently use feature flags. All functionality is enabled by default.
+//! Neither feature is enabled by default.
+//!
+//! - `remote`: HTTP and S3 remote cache backends (`HttpRemoteCache`,
+//!   `S3RemoteCache`), configured with `[remote] url` in `cargo-save.toml`
+//!   or `CARGO_SAVE_REMOTE_URL`
+//! - `tui`: the interactive `ui` command for browsing builds and logs
 //!
 //! # Platform Support
 //!
@@ -186,7 +194,7 @@
 //! | Git Integration | Native | None | None |
 //! | Workspace Aware | ✅ | ❌ | ⚠️ |
 //! | Build Log Storage | ✅ | ❌ | ❌ |
-//! | Distributed Caching | ❌ | ✅ | ❌ |
+//! | Distributed Caching | ✅ (`remote` feature) | ✅ | ❌ |
 //! | Setup Complexity | Zero | Daemon | Manual |
 //!
 //! See the [README](https://github.com/HautlyS/cargo-save) for a detailed comparison.
@@ -196,48 +204,71 @@
 use anyhow::{Context, Result};
 use blake3::Hasher as Blake3Hasher;
 use cargo_metadata::{Metadata, MetadataCommand, Package};
-use clap::Parser;
+use clap::{Args, Parser};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::fs::{self, File};
 use std::io::{self, BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::{Command, Output, Stdio};
 use std::time::{Duration, SystemTime};
 use walkdir::WalkDir;
 
+/// Logs a message at [`LogLevel::Error`] with the `[cargo-save]` prefix.
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        $crate::log($crate::LogLevel::Error, format_args!($($arg)*))
+    };
+}
+
+/// Logs a message at [`LogLevel::Info`] with the `[cargo-save]` prefix.
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        $crate::log($crate::LogLevel::Info, format_args!($($arg)*))
+    };
+}
+
+/// Logs a message at [`LogLevel::Debug`] with the `[cargo-save]` prefix.
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        $crate::log($crate::LogLevel::Debug, format_args!($($arg)*))
+    };
+}
+
 /// Command-line interface for cargo-save
-///
-/// This enum defines all the subcommands available in the cargo-save CLI.
 #[derive(Parser)]
 #[command(name = "cargo-save")]
 #[command(
     about = "Smart caching cargo wrapper with git-based incremental builds",
     version
 )]
-pub enum Cli {
+pub struct Cli {
+    /// Only print errors from cargo-save itself (cargo output is unaffected)
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Subcommand to run
+    #[command(subcommand)]
+    pub command: Commands,
+}
+
+/// Subcommands available in the cargo-save CLI.
+#[derive(clap::Subcommand)]
+pub enum Commands {
     /// Save subcommand (called as `cargo save`)
     #[command(name = "save")]
-    Save {
-        /// The cargo subcommand to run
-        subcommand: String,
-        /// Arguments to pass to cargo
-        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
-        args: Vec<String>,
-    },
+    Save(SaveArgs),
 
     /// Direct invocation (called as `cargo-save`)
     #[command(hide = true)]
-    Direct {
-        /// The cargo subcommand to run
-        subcommand: String,
-        /// Arguments to pass to cargo
-        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
-        args: Vec<String>,
-    },
+    Direct(SaveArgs),
 
     /// Query cached build logs
+    ///
+    /// Exits with 0 on success, 1 when `--exit-on-no-match` is set and grep,
+    /// errors or warnings printed nothing, and 2 when there is no log to
+    /// query or it cannot be read.
     #[command(name = "query")]
     Query {
         /// Query mode: head, tail, grep, range, errors, warnings, all
@@ -250,17 +281,56 @@ pub enum Cli {
         /// Query the Nth most recent build
         #[arg(short, long)]
         last: Option<usize>,
+        /// Only consider builds since this time (30m, 2h, 3d or RFC 3339)
+        #[arg(long, value_parser = parse_since)]
+        since: Option<SystemTime>,
+        /// Stream new lines of a running build (tail mode only)
+        #[arg(short, long)]
+        follow: bool,
+        /// Which output stream to query
+        #[arg(long, value_enum, default_value = "both")]
+        stream: LogStream,
+        /// Search all cached builds, printing `<cache-id>: <line>` (grep, errors, warnings)
+        #[arg(long, conflicts_with_all = ["id", "last", "follow"])]
+        all_builds: bool,
+        /// Exit with status 1 if grep, errors or warnings find no lines
+        #[arg(long)]
+        exit_on_no_match: bool,
     },
 
     /// List cached builds
     #[command(name = "list")]
     List {
-        /// Show verbose information
+        /// Show verbose information (same as `--format long`)
         #[arg(short, long)]
         verbose: bool,
+        /// Output layout: plain table, short one-liners or long details
+        #[arg(long, value_enum, default_value = "plain")]
+        format: ListFormat,
         /// Only show caches for current workspace
         #[arg(short, long)]
         workspace: bool,
+        /// Only show builds with this outcome
+        #[arg(long, value_enum)]
+        status: Option<BuildStatus>,
+        /// Only show builds of this cargo subcommand (build, test, ...)
+        #[arg(long)]
+        subcommand: Option<String>,
+        /// Only show builds since this time (30m, 2h, 3d or RFC 3339)
+        #[arg(long, value_parser = parse_since)]
+        since: Option<SystemTime>,
+        /// Sort builds by this field
+        #[arg(long, value_enum, default_value = "time")]
+        sort: ListSort,
+        /// Reverse the sort order
+        #[arg(long)]
+        reverse: bool,
+        /// Show at most this many builds
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Print builds as JSON
+        #[arg(long)]
+        json: bool,
     },
 
     /// Clean old cache files
@@ -275,20 +345,78 @@ pub enum Cli {
         /// Skip confirmation prompt
         #[arg(short, long)]
         force: bool,
+        /// List the files that would be removed without removing them
+        #[arg(long)]
+        dry_run: bool,
+        /// Only clean build logs and their metadata
+        #[arg(long, conflicts_with = "incremental_only")]
+        logs_only: bool,
+        /// Only clean incremental caches and unreferenced objects
+        #[arg(long)]
+        incremental_only: bool,
+    },
+
+    /// Prune the workspace build-logs/ directory
+    #[command(name = "prune-logs")]
+    PruneLogs {
+        /// Remove logs older than this many days
+        #[arg(short, long, default_value = "7")]
+        days: u64,
+        /// Keep only this many most recent logs
+        #[arg(short, long)]
+        keep: Option<usize>,
     },
 
+    /// Remove incremental caches of packages no longer in the workspace
+    #[command(name = "gc")]
+    Gc,
+
     /// Show cache statistics
     #[command(name = "stats")]
-    Stats,
+    Stats {
+        /// List the N packages with the longest recorded build time
+        #[arg(long, value_name = "N")]
+        slowest: Option<usize>,
+        /// Report hits, durations and artifact size per package
+        #[arg(long, conflicts_with = "slowest")]
+        per_package: bool,
+        /// Print the per-package report as JSON
+        #[arg(long, requires = "per_package")]
+        json: bool,
+    },
 
     /// Invalidate caches
     #[command(name = "invalidate")]
     Invalidate {
-        /// Package names to invalidate
+        /// Package names or patterns to invalidate
         packages: Vec<String>,
         /// Invalidate all caches
         #[arg(short, long)]
         all: bool,
+        /// How package arguments are matched
+        #[arg(long = "match", value_enum, default_value = "prefix")]
+        match_mode: MatchMode,
+        /// Only invalidate entries built with this profile
+        #[arg(long, value_enum)]
+        profile: Option<Profile>,
+        /// Only invalidate entries built with these features (as passed to --features)
+        #[arg(long)]
+        features: Option<String>,
+        /// Only invalidate entries written by this cargo subcommand, e.g. test
+        #[arg(long)]
+        subcommand: Option<String>,
+        /// Only invalidate entries older than this (30m, 2h, 3d or RFC 3339)
+        #[arg(long, value_parser = parse_since)]
+        older_than: Option<SystemTime>,
+        /// Only invalidate packages whose sources changed since they were cached
+        #[arg(long)]
+        changed: bool,
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        force: bool,
+        /// List the entries that would be removed without removing them
+        #[arg(long)]
+        dry_run: bool,
     },
 
     /// Show workspace status
@@ -297,43 +425,482 @@ pub enum Cli {
         /// Show package hashes
         #[arg(long)]
         hashes: bool,
+        /// Print untruncated hashes
+        #[arg(long)]
+        full_hashes: bool,
+        /// Annotate packages that need a rebuild with the reason
+        #[arg(long)]
+        explain: bool,
+        /// Hash the sources at this git commit, tag or branch instead of HEAD
+        #[arg(long, value_name = "REF")]
+        git_ref: Option<String>,
+        /// Hash only committed files, ignoring uncommitted changes in git
+        #[arg(long)]
+        committed_only: bool,
+        /// Path to Cargo.toml of the workspace to inspect
+        #[arg(long, value_name = "PATH")]
+        manifest_path: Option<PathBuf>,
     },
 
     /// Generate cache key for CI systems
     #[command(name = "cache-key")]
     CacheKey {
-        /// CI platform: github, gitlab, etc.
-        #[arg(short, long, default_value = "github")]
-        platform: String,
+        /// CI platform the key is formatted for
+        #[arg(short, long, value_enum, default_value = "github")]
+        platform: CiPlatform,
+        /// Append CARGO_BUILD_TARGET to the key
+        #[arg(long)]
+        include_target: bool,
+        /// Use untruncated hashes in the key
+        #[arg(long)]
+        full_hashes: bool,
+        /// Fold the value of this environment variable into the key (repeatable)
+        #[arg(long = "include-env", value_name = "VAR")]
+        include_env: Vec<String>,
+        /// Fold this feature list into the key
+        #[arg(long, value_name = "FEATURES")]
+        include_features: Option<String>,
+        /// Print the incremental cache key of this package instead
+        #[arg(long, value_name = "PACKAGE")]
+        incremental: Option<String>,
+        /// Cargo subcommand the incremental cache key is for
+        #[arg(long, default_value = "build", requires = "incremental")]
+        subcommand: String,
+        /// Cargo arguments of the build the incremental cache key is for
+        #[arg(
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            requires = "incremental"
+        )]
+        args: Vec<String>,
     },
 
-    /// Pre-warm cache by computing hashes
+    /// Print the cache directory
+    #[command(name = "cache-path")]
+    CachePath,
+
+    /// Pre-warm cache by computing hashes or building
     #[command(name = "warm")]
     Warm {
         /// Use release profile
         #[arg(long)]
         release: bool,
+        /// Run the build to populate the cache instead of only computing hashes
+        #[arg(long)]
+        build: bool,
+        /// Hash only committed files, ignoring uncommitted changes in git
+        #[arg(long)]
+        committed_only: bool,
+        /// Path to Cargo.toml of the workspace to warm
+        #[arg(long, value_name = "PATH")]
+        manifest_path: Option<PathBuf>,
+    },
+
+    /// Restore cached artifacts into target/, then run the build
+    #[command(name = "restore")]
+    Restore {
+        /// Only restore artifacts, without running cargo afterwards
+        #[arg(long)]
+        artifacts_only: bool,
+        /// Cargo subcommand whose cache entries are restored
+        #[arg(long, default_value = "build")]
+        subcommand: String,
+        /// Cargo arguments of the build, e.g. --release or --target-dir
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
     },
 
     /// Install git hooks for auto-invalidation
     #[command(name = "install-hooks")]
     InstallHooks,
 
+    /// Browse cached builds and their logs interactively
+    #[command(name = "ui")]
+    Ui,
+
     /// Check environment and integration status
     #[command(name = "doctor")]
-    Doctor,
+    Doctor {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
 
     /// Setup sccache for cross-project caching
     #[command(name = "setup-sccache")]
     SetupSccache,
+
+    /// Export the incremental cache as a compressed archive
+    #[command(name = "export")]
+    Export {
+        /// Path of the archive to write
+        out: PathBuf,
+    },
+
+    /// Import a cache archive created by `export`
+    #[command(name = "import")]
+    Import {
+        /// Path of the archive to read
+        archive: PathBuf,
+    },
+
+    /// Compare the inputs of two cached builds
+    #[command(name = "diff")]
+    Diff {
+        /// Cache ID of the earlier build
+        a: String,
+        /// Cache ID of the later build
+        b: String,
+    },
+
+    /// Explain which input invalidated a package's cache
+    #[command(name = "why-rebuild", alias = "bisect-cache")]
+    WhyRebuild {
+        /// Package to explain
+        package: String,
+        /// Cargo subcommand whose cache entries are preferred
+        #[arg(long, default_value = "build")]
+        subcommand: String,
+        /// Cargo arguments of the build, e.g. --release or --features
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Print the packages a build would rebuild, one per line
+    #[command(name = "changed")]
+    Changed {
+        /// Cargo subcommand whose cache entries are checked
+        #[arg(long, default_value = "build")]
+        subcommand: String,
+        /// Print the names as a JSON array
+        #[arg(long)]
+        json: bool,
+        /// Hash only committed files, ignoring uncommitted changes in git
+        #[arg(long)]
+        committed_only: bool,
+        /// Cargo arguments of the build, e.g. --release or --features
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Show builds, durations and cache hit rate per day
+    #[command(name = "history")]
+    History {
+        /// Number of days to cover
+        #[arg(long, default_value_t = 30)]
+        days: u64,
+        /// Print the history as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print the workspace dependency graph, highlighting changed packages
+    #[command(name = "graph")]
+    Graph {
+        /// Output format: Graphviz DOT or JSON
+        #[arg(long, value_enum, default_value = "dot")]
+        format: GraphFormat,
+    },
+}
+
+/// Arguments shared by the `save` and direct invocation forms.
+#[derive(Args, Debug, Clone)]
+pub struct SaveArgs {
+    /// Report the build plan without running cargo
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Build independent changed packages in parallel waves using N jobs
+    #[arg(long, value_name = "N")]
+    pub parallel_packages: Option<usize>,
+    /// Never cache this package; it is rebuilt every time (repeatable)
+    #[arg(long = "no-cache-package", value_name = "NAME")]
+    pub no_cache_packages: Vec<String>,
+    /// Hash only committed files, ignoring uncommitted changes in git
+    #[arg(long)]
+    pub committed_only: bool,
+    /// Hash the sources at this git commit, tag or branch, which must be
+    /// checked out without uncommitted changes
+    #[arg(long, value_name = "REF")]
+    pub git_ref: Option<String>,
+    /// Seconds to wait for another cargo-save building this workspace [default: 300]
+    #[arg(long, value_name = "SECONDS")]
+    pub wait: Option<u64>,
+    /// Load build environment variables from a dotenv file
+    #[arg(long, value_name = "PATH")]
+    pub env_file: Option<PathBuf>,
+    /// Let --env-file values replace variables already set in the environment
+    #[arg(long, requires = "env_file")]
+    pub env_file_override: bool,
+    /// Print how long each phase of cargo-save took
+    #[arg(long)]
+    pub timing_report: bool,
+    /// Print a JSON summary of the build to stdout when it finishes
+    #[arg(long)]
+    pub summary_json: bool,
+    /// Hash all files of packages outside git, not only known source extensions
+    #[arg(long)]
+    pub no_default_ignores: bool,
+    /// Progress output format; `cargo-save-json` streams events to stdout
+    #[arg(long, value_enum, value_name = "FMT", default_value_t)]
+    pub message_format: MessageFormat,
+    /// The cargo subcommand to run
+    pub subcommand: String,
+    /// Arguments to pass to cargo
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub args: Vec<String>,
+}
+
+/// Errors returned by the core [`CacheManager`] operations.
+///
+/// Converts into [`anyhow::Error`] with `?`, so CLI-style callers can keep
+/// using `anyhow::Result`.
+#[derive(Debug, thiserror::Error)]
+pub enum CacheError {
+    /// `cargo metadata` failed for a reason other than a missing workspace
+    #[error("Failed to get cargo metadata: {0}")]
+    Metadata(cargo_metadata::Error),
+    /// The directory or manifest path `cargo metadata` inspected is not
+    /// inside a Cargo workspace
+    #[error("{} is not inside a Cargo workspace", .0.display())]
+    NotAWorkspace(PathBuf),
+    /// The cargo binary to run does not exist
+    #[error("{0} not found on PATH; set CARGO or CARGO_SAVE_CARGO_BIN")]
+    CargoNotFound(String),
+    /// A git command failed or the path is not in a git repository
+    #[error("Git error: {0}")]
+    Git(String),
+    /// A cache file exists but cannot be parsed
+    #[error("Corrupt cache file {}: {source}", path.display())]
+    CorruptCache {
+        /// File that failed to parse
+        path: PathBuf,
+        /// Parse error
+        source: serde_json::Error,
+    },
+    /// No metadata exists for the requested build
+    #[error("No build metadata found for {0}")]
+    BuildNotFound(String),
+    /// The cache holds no build logs to query
+    #[error("No cached logs found")]
+    NoLogs,
+    /// Another cargo-save process held a cache lock for too long
+    #[error(
+        "Another cargo-save is running: timed out waiting for {}",
+        .0.display()
+    )]
+    Locked(PathBuf),
+    /// Filesystem error
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl From<cargo_metadata::Error> for CacheError {
+    fn from(err: cargo_metadata::Error) -> Self {
+        if let cargo_metadata::Error::CargoMetadata { stderr } = &err {
+            if let Some(path) = missing_manifest_path(stderr) {
+                return CacheError::NotAWorkspace(path);
+            }
+        }
+        match err {
+            cargo_metadata::Error::Io(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                CacheError::CargoNotFound(cargo_bin().to_string_lossy().into_owned())
+            }
+            err => CacheError::Metadata(err),
+        }
+    }
 }
 
+/// Returns the path `cargo metadata` looked for a manifest at, if its
+/// `stderr` reports that there is none: the directory it searched upwards
+/// from, or the `--manifest-path` that does not exist.
+fn missing_manifest_path(stderr: &str) -> Option<PathBuf> {
+    let line = stderr.lines().find(|line| {
+        line.contains("could not find `Cargo.toml` in `")
+            || (line.contains("manifest path `") && line.ends_with("` does not exist"))
+    })?;
+    // Cargo quotes the path last, in backticks
+    line.split('`').skip(1).step_by(2).last().map(PathBuf::from)
+}
+
+/// Result type of the core [`CacheManager`] operations.
+pub type CacheResult<T> = std::result::Result<T, CacheError>;
+
+/// Exit code and captured `(line, is_stderr)` pairs of a finished cargo process.
+type CapturedOutput = (Option<i32>, Vec<(String, bool)>);
+
 const CACHE_VERSION: &str = "v4";
+/// File marking a versioned directory as a cargo-save cache, so migration
+/// never removes a `vN` directory it did not create.
+const CACHE_MARKER_FILE: &str = ".cargo-save-cache";
+/// How often `query tail --follow` checks the log for new output.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long `query tail --follow` waits for a log file to be created.
+const FOLLOW_WAIT: Duration = Duration::from_secs(5);
+/// How long to wait for another cargo-save to release a cache lock.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a build waits for another build of the same workspace by default.
+const BUILD_WAIT: Duration = Duration::from_secs(300);
+/// How old an unreferenced object must be before `clean` deletes it.
+const OBJECT_GRACE: Duration = Duration::from_secs(60 * 60);
+/// How often a waiting process retries a held cache lock.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How many times a git command failing with a transient error is run.
+const GIT_ATTEMPTS: u32 = 3;
+/// Wait before the first git retry; doubled for each further retry.
+const GIT_RETRY_BACKOFF: Duration = Duration::from_millis(50);
 const LOG_PREFIX: &str = "[cargo-save]";
+/// File in the metadata directory memoizing package hashes per workspace;
+/// it is no build metadata.
+const WORKSPACE_MEMO_FILE: &str = "workspace_state.json";
+/// Hash prefix length used in cache keys and lock names; never changes with
+/// the display length so on-disk keys stay stable.
+const HASH_KEY_LEN: usize = 16;
+/// Hash prefix length shown to users unless `CARGO_SAVE_HASH_LEN` overrides it.
 const HASH_DISPLAY_LEN: usize = 16;
+/// Longest cache key used as a file name as is; longer keys are shortened
+/// and suffixed with a hash of the full key.
+const MAX_KEY_FILE_LEN: usize = 200;
+
+/// Returns how many characters of a hash are shown: `CARGO_SAVE_HASH_LEN`
+/// clamped to 8..=64, or 16 by default.
+pub fn hash_display_len() -> usize {
+    std::env::var("CARGO_SAVE_HASH_LEN")
+        .ok()
+        .and_then(|len| len.parse::<usize>().ok())
+        .map_or(HASH_DISPLAY_LEN, |len| len.clamp(8, 64))
+}
+
+/// Shortens `hash` to [`hash_display_len`] characters for display, or keeps
+/// it whole when `full` is set.
+pub fn display_hash(hash: &str, full: bool) -> &str {
+    if full {
+        hash
+    } else {
+        &hash[..hash.len().min(hash_display_len())]
+    }
+}
+
+/// Verbosity of cargo-save's own `[cargo-save]` messages.
+///
+/// Cargo's output is always passed through regardless of the level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// No cargo-save messages at all
+    Off,
+    /// Errors and warnings only
+    Error,
+    /// Progress messages such as the build plan (default)
+    Info,
+    /// Additional diagnostics such as cache keys and hashes
+    Debug,
+}
+
+impl LogLevel {
+    /// Parses a `CARGO_SAVE_LOG` value (`off`, `error`, `info` or `debug`).
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" => Some(LogLevel::Off),
+            "error" => Some(LogLevel::Error),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+}
+
+/// Whether to colorize output, following cargo's `CARGO_TERM_COLOR`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color only when writing to a terminal
+    #[default]
+    Auto,
+    /// Always emit color escapes
+    Always,
+    /// Never emit color escapes
+    Never,
+}
+
+impl ColorChoice {
+    /// Reads `CARGO_TERM_COLOR` (`auto`, `always` or `never`), defaulting to `auto`.
+    pub fn from_env() -> Self {
+        match std::env::var("CARGO_TERM_COLOR")
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "always" => ColorChoice::Always,
+            "never" => ColorChoice::Never,
+            _ => ColorChoice::Auto,
+        }
+    }
+
+    /// Returns true if output written to stdout should be colored.
+    pub fn enabled_for_stdout(self) -> bool {
+        use std::io::IsTerminal;
+
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => io::stdout().is_terminal(),
+        }
+    }
+}
+
+/// Applies `style` to `text` when `color` is set.
+fn paint(text: &str, style: owo_colors::Style, color: bool) -> String {
+    use owo_colors::OwoColorize;
+
+    if color {
+        text.style(style).to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Current log level, or `u8::MAX` until first read from `CARGO_SAVE_LOG`.
+static LOG_LEVEL: std::sync::atomic::AtomicU8 = std::sync::atomic::AtomicU8::new(u8::MAX);
+
+/// Returns the current log level.
+///
+/// Defaults to the value of `CARGO_SAVE_LOG`, or [`LogLevel::Info`] if it is
+/// unset or invalid.
+pub fn log_level() -> LogLevel {
+    use std::sync::atomic::Ordering;
+
+    match LOG_LEVEL.load(Ordering::Relaxed) {
+        0 => LogLevel::Off,
+        1 => LogLevel::Error,
+        2 => LogLevel::Info,
+        3 => LogLevel::Debug,
+        _ => {
+            let level = std::env::var("CARGO_SAVE_LOG")
+                .ok()
+                .and_then(|v| LogLevel::parse(&v))
+                .unwrap_or(LogLevel::Info);
+            set_log_level(level);
+            level
+        }
+    }
+}
+
+/// Overrides the log level for the rest of the process.
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Prints a `[cargo-save]` message to stderr if `level` is enabled.
+pub fn log(level: LogLevel, args: std::fmt::Arguments<'_>) {
+    if level != LogLevel::Off && level <= log_level() {
+        eprintln!("{} {}", LOG_PREFIX, args);
+    }
+}
 
 /// Environment variables that can affect the build output.
 /// These are included in the cache key to ensure cache correctness.
+///
+/// `CARGO_INCREMENTAL` is among them because toggling it changes what cargo
+/// writes to the profile directory, so recorded target file sizes from the
+/// other mode cannot be trusted.
 pub const ENV_VARS_THAT_AFFECT_BUILD: &[&str] = &[
     "RUSTFLAGS",
     "RUSTDOCFLAGS",
@@ -354,6 +921,15 @@ pub const ENV_VARS_THAT_AFFECT_BUILD: &[&str] = &[
     "LINKER",
 ];
 
+/// Cargo subcommands that produce nothing to cache. They are run without
+/// hashing the workspace, only their output is logged.
+pub const PASSTHROUGH_SUBCOMMANDS: &[&str] = &["fmt", "tree", "metadata", "search", "login"];
+
+/// Variables of [`ENV_VARS_THAT_AFFECT_BUILD`] naming a compiler or tool whose
+/// `--version` output is hashed along with the path, so upgrading the tool in
+/// place invalidates caches of crates with C build scripts.
+const TOOL_ENV_VARS: &[&str] = &["CC", "CXX", "AR", "LINKER"];
+
 /// Git repository information for advanced git features support.
 #[derive(Debug, Clone)]
 pub struct GitRepoInfo {
@@ -398,6 +974,210 @@ pub struct BuildCache {
     pub duration_ms: u64,
     /// Hash of relevant environment variables
     pub env_hash: String,
+    /// Values of the variables in [`ENV_VARS_THAT_AFFECT_BUILD`] that were set
+    #[serde(default)]
+    pub env_vars: BTreeMap<String, String>,
+    /// Number of compiler errors in the build output
+    #[serde(default)]
+    pub error_count: usize,
+    /// Number of compiler warnings in the build output
+    #[serde(default)]
+    pub warning_count: usize,
+    /// Git commit checked out in the workspace (None outside git)
+    #[serde(default)]
+    pub commit: Option<String>,
+    /// Git branch checked out in the workspace (None if detached or outside git)
+    #[serde(default)]
+    pub branch: Option<String>,
+}
+
+/// Running count of compiler errors and warnings in cargo output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiagnosticCounts {
+    /// Number of errors
+    pub errors: usize,
+    /// Number of warnings
+    pub warnings: usize,
+}
+
+impl DiagnosticCounts {
+    /// Counts every diagnostic in a captured log.
+    pub fn from_log(content: &str) -> Self {
+        let mut counts = Self::default();
+        for line in content.lines() {
+            counts.record(line);
+        }
+        counts
+    }
+
+    /// Counts the diagnostic on one line of cargo output, if any.
+    ///
+    /// `--message-format=json` lines are classified by their `level`; plain
+    /// output by its `error[`, `error:` and `warning:` prefixes. Cargo's
+    /// summaries (`generated N warnings`, `could not compile`, `aborting due
+    /// to`, `build failed`) repeat diagnostics already counted and are skipped.
+    pub fn record(&mut self, line: &str) {
+        let line = line.trim_start();
+        if line.starts_with('{') {
+            if let Ok(message) = serde_json::from_str::<serde_json::Value>(line) {
+                if message["reason"] == "compiler-message" {
+                    match message["message"]["level"].as_str() {
+                        Some("error") => self.errors += 1,
+                        Some("warning") => self.warnings += 1,
+                        _ => {}
+                    }
+                }
+                return;
+            }
+        }
+
+        if line.starts_with("error[") || line.starts_with("error:") {
+            let summary = line.starts_with("error: could not compile")
+                || line.starts_with("error: aborting due to");
+            if !summary {
+                self.errors += 1;
+            }
+        } else if let Some(rest) = line.strip_prefix("warning:") {
+            // e.g. "warning: `foo` (lib) generated 2 warnings (run `cargo fix` ...)"
+            let summary = (rest.trim_start().starts_with('`') && rest.contains(" generated "))
+                || rest.trim_start().starts_with("build failed");
+            if !summary {
+                self.warnings += 1;
+            }
+        }
+    }
+}
+
+/// Packages cargo started compiling and packages it failed to compile, as
+/// seen in its output.
+///
+/// Lets a failed build, e.g. with `--keep-going`, still cache the packages
+/// that compiled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompileOutcomes {
+    /// Packages with a `Compiling` or `Checking` line
+    pub started: BTreeSet<String>,
+    /// Packages named by an `error:` line, e.g. `could not compile` or a
+    /// failed build script
+    pub failed: BTreeSet<String>,
+}
+
+impl CompileOutcomes {
+    /// Records the package a line of cargo output starts or fails, if any.
+    pub fn record(&mut self, line: &str) {
+        if let Some(name) = started_package(line) {
+            self.started.insert(name.to_string());
+        } else if let Some(rest) = line.trim_start().strip_prefix("error:") {
+            // Cargo quotes the failing package first, alone (`could not
+            // compile `pkg``) or with its version (`failed to run custom build
+            // command for `pkg v0.1.0 (..)``)
+            let quoted = rest
+                .split('`')
+                .nth(1)
+                .and_then(|q| q.split_whitespace().next());
+            if let Some(name) = quoted {
+                self.failed.insert(name.to_string());
+            }
+        }
+    }
+
+    /// Returns true if cargo compiled `package` and reported no failure for it.
+    pub fn succeeded(&self, package: &str) -> bool {
+        self.started.contains(package) && !self.failed.contains(package)
+    }
+}
+
+/// Returns the crates listed in rustdoc's `crates.js`
+/// (`window.ALL_CRATES = ["a","b"];`).
+fn documented_crates(crates_js: &str) -> Vec<String> {
+    crates_js
+        .split_once('[')
+        .and_then(|(_, rest)| rest.split_once(']'))
+        .map(|(list, _)| {
+            list.split(',')
+                .map(|name| name.trim().trim_matches('"').to_string())
+                .filter(|name| !name.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns true if `dir` holds a cargo-save cache of some version.
+fn is_cache_version_dir(dir: &Path) -> bool {
+    dir.join(CACHE_MARKER_FILE).is_file()
+        || dir.join("incremental").is_dir()
+        || dir.join("metadata").is_dir()
+}
+
+/// Returns true if `path` lies under `dir` without leaving it through `..`.
+fn is_within(path: &Path, dir: &Path) -> bool {
+    path.starts_with(dir)
+        && !path
+            .components()
+            .any(|c| matches!(c, std::path::Component::ParentDir))
+}
+
+/// Returns the package a `Compiling` or `Checking` line of cargo output
+/// starts building.
+fn started_package(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    line.strip_prefix("Compiling ")
+        .or_else(|| line.strip_prefix("Checking "))?
+        .split_whitespace()
+        .next()
+}
+
+/// Numbers the `Compiling`/`Checking` lines of the packages a build plan
+/// rebuilds as `[x/y]`.
+///
+/// Dependency crates cargo builds along the way are not annotated, so the
+/// counter never exceeds the number of planned packages.
+#[derive(Debug, Clone)]
+pub struct ProgressCounter {
+    pending: HashSet<String>,
+    total: usize,
+    done: usize,
+    streams: Option<LogStream>,
+}
+
+impl ProgressCounter {
+    /// Creates a counter for `packages`, annotating lines on `streams`
+    /// (`None` disables annotations).
+    pub fn new(packages: impl IntoIterator<Item = String>, streams: Option<LogStream>) -> Self {
+        let pending: HashSet<String> = packages.into_iter().collect();
+        Self {
+            total: pending.len(),
+            pending,
+            done: 0,
+            streams,
+        }
+    }
+
+    /// Reads the annotated streams from `CARGO_SAVE_PROGRESS`: `stderr`,
+    /// `stdout`, `off`, or both streams when unset.
+    pub fn streams_from_env() -> Option<LogStream> {
+        match std::env::var("CARGO_SAVE_PROGRESS").as_deref() {
+            Ok("off") | Ok("0") => None,
+            Ok("stdout") => Some(LogStream::Out),
+            Ok("stderr") => Some(LogStream::Err),
+            _ => Some(LogStream::Both),
+        }
+    }
+
+    /// Returns the ` [x/y]` suffix for `line` if it starts a planned package
+    /// on an annotated stream.
+    pub fn annotate(&mut self, line: &str, is_stderr: bool) -> Option<String> {
+        let on_stream = match self.streams? {
+            LogStream::Out => !is_stderr,
+            LogStream::Err => is_stderr,
+            LogStream::Both => true,
+        };
+        if !on_stream || !self.pending.remove(started_package(line)?) {
+            return None;
+        }
+        self.done += 1;
+        Some(format!(" [{}/{}]", self.done, self.total))
+    }
 }
 
 /// Represents an incremental cache entry for a single package.
@@ -429,6 +1209,62 @@ pub struct IncrementalCache {
     pub build_success: bool,
     /// Build duration in milliseconds
     pub duration_ms: u64,
+    /// Object store hash of each target file, used to restore missing files
+    #[serde(default)]
+    pub objects: Vec<(PathBuf, String)>,
+    /// Whether the package's tests passed, for entries recorded by `test`
+    #[serde(default)]
+    pub tests_passed: Option<bool>,
+    /// Root of the workspace the entry was recorded in; `None` for entries
+    /// written by older versions
+    #[serde(default)]
+    pub workspace_root: Option<PathBuf>,
+    /// Number of builds that skipped the package thanks to this entry
+    #[serde(default)]
+    pub hits: u64,
+    /// Toolchain hash of the workspace when the entry was written
+    #[serde(default)]
+    pub toolchain_hash: Option<String>,
+    /// Cargo subcommand of the build that wrote the entry; `None` for entries
+    /// written by older versions or through the library without one
+    #[serde(default)]
+    pub subcommand: Option<String>,
+    /// Kind of artifacts the build that wrote the entry produced; `None`
+    /// for entries written by older versions or without a subcommand
+    #[serde(default)]
+    pub output_kind: Option<OutputKind>,
+}
+
+/// Kind of artifacts a cargo subcommand leaves in the target directory.
+///
+/// Cache keys built by `cargo save` include the subcommand, but library
+/// callers pick the command hash themselves and may share one key between
+/// `check` and `build`. The recorded kind guards entries written under such
+/// a shared key: an entry only satisfies builds asking for the same kind,
+/// since the `.rmeta` files of `check` are no use to `build`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputKind {
+    /// Crate metadata only (`check`, `clippy`)
+    Metadata,
+    /// Libraries and binaries (`build`, `run` and the rest)
+    Build,
+    /// Test harness binaries (`test`, `nextest`, `bench`)
+    Test,
+    /// Rustdoc pages (`doc`, `rustdoc`)
+    Doc,
+}
+
+impl OutputKind {
+    /// Returns the kind of artifacts `cargo <subcommand>` produces.
+    pub fn for_subcommand(subcommand: &str) -> Self {
+        match subcommand {
+            "check" | "clippy" => OutputKind::Metadata,
+            "test" | "nextest" | "bench" => OutputKind::Test,
+            "doc" | "rustdoc" => OutputKind::Doc,
+            _ => OutputKind::Build,
+        }
+    }
 }
 
 /// Represents the current state of a Cargo workspace.
@@ -446,6 +1282,34 @@ pub struct WorkspaceState {
     pub timestamp: String,
     /// Information about git features in use
     pub git_features: Option<GitFeaturesInfo>,
+    /// Members a build without `-p`/`--package` or `--workspace` covers:
+    /// `[workspace] default-members` and the members they depend on. Empty
+    /// when such a build covers every member
+    #[serde(default)]
+    pub default_members: Vec<String>,
+}
+
+impl WorkspaceState {
+    /// Returns this state with only the members a cargo build with `args`
+    /// builds, as used for build planning.
+    ///
+    /// Like cargo, a workspace with `[workspace] default-members` only builds
+    /// [`default_members`](Self::default_members) unless `-p`/`--package` or
+    /// `--workspace` select packages explicitly. Otherwise every member is
+    /// kept.
+    pub fn built_by(&self, args: &[String]) -> WorkspaceState {
+        let selects_packages = args
+            .iter()
+            .take_while(|arg| *arg != "--")
+            .any(|arg| arg.starts_with("-p") || arg.starts_with("--package"));
+        let mut state = self.clone();
+        if !selects_packages && !selects_workspace(args) && !self.default_members.is_empty() {
+            state
+                .packages
+                .retain(|p| self.default_members.contains(&p.name));
+        }
+        state
+    }
 }
 
 /// Information about Git features being used.
@@ -474,530 +1338,5174 @@ pub struct PackageHash {
     pub path: PathBuf,
     /// Hash of the package source
     pub source_hash: String,
-    /// Names of workspace dependencies
+    /// Names of workspace dependencies of any kind (normal, build and dev)
     pub dependencies: Vec<String>,
-    /// Hash of feature flags
+    /// Hash of the features the package is built with, see
+    /// [`effective_features`]
     pub features_hash: String,
+    /// How each entry of `dependencies` is declared
+    #[serde(default)]
+    pub dependency_kinds: BTreeMap<String, Vec<DependencyKind>>,
+    /// Name of the library target with `-` replaced by `_`, as used in the
+    /// file names of its artifacts, if the package has a library
+    #[serde(default)]
+    pub lib_name: Option<String>,
+    /// How `source_hash` was computed
+    #[serde(default)]
+    pub hash_method: HashMethod,
 }
 
-/// Dependency graph for workspace packages.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DependencyGraph {
-    /// Map of package names to their dependency information
-    pub packages: HashMap<String, PackageNode>,
+/// How the source hash of a package was computed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashMethod {
+    /// From the git tree and working directory status
+    #[default]
+    Git,
+    /// By walking and hashing the files, because git was unavailable or the
+    /// sources are not tracked (also when a path dependency outside the
+    /// workspace was hashed that way)
+    FileFallback,
 }
 
-/// Node in the dependency graph.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct PackageNode {
-    /// Package name
-    pub name: String,
-    /// Names of packages this package depends on
-    pub dependencies: Vec<String>,
-    /// Names of packages that depend on this package
-    pub reverse_dependencies: Vec<String>,
+impl PackageHash {
+    /// Returns the crate names `deps/` artifacts of this package start with:
+    /// the library target name and the package name with `-` replaced by `_`,
+    /// which is what the default binary target is called.
+    pub fn crate_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.lib_name.iter().cloned().collect();
+        let default_name = self.name.replace('-', "_");
+        if !names.contains(&default_name) {
+            names.push(default_name);
+        }
+        names
+    }
 }
 
-/// Central manager for all caching operations.
-///
-/// This is the main interface for using cargo-save as a library.
-/// It handles cache storage, computation, and retrieval.
-///
-/// # Example
-///
-/// ```no_run
-/// use cargo_save::CacheManager;
-///
-/// # fn main() -> anyhow::Result<()> {
-/// let cache = CacheManager::new()?;
-/// let workspace = cache.compute_workspace_state(&[])?;
+/// The manifest section a workspace dependency is declared in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum DependencyKind {
+    /// `[dependencies]`, which includes proc-macro crates
+    Normal,
+    /// `[build-dependencies]`, used by the build script
+    Build,
+    /// `[dev-dependencies]`, used by tests, examples and benches
+    Dev,
+}
+
+/// Wall-clock time spent in each phase of a cached build, reported by
+/// [`CacheManager::phase_timings`].
 ///
-/// // Check which packages need rebuilding
-/// let changed = cache.get_changed_packages(&workspace, "hash", "env", false, &[]);
-/// println!("{} packages need rebuilding", changed.len());
-/// # Ok(())
-/// # }
-/// ```
-pub struct CacheManager {
-    /// Directory for general cache files
-    pub cache_dir: PathBuf,
-    /// Directory for incremental cache files
-    pub incremental_dir: PathBuf,
-    /// Directory for metadata files
-    pub metadata_dir: PathBuf,
+/// Phases that ran more than once, e.g. for several builds with the same
+/// `CacheManager`, are summed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseTimings {
+    /// Running `cargo metadata`
+    pub metadata: Duration,
+    /// Hashing package sources, `Cargo.lock` and the toolchain, and reading
+    /// git state
+    pub source_hashing: Duration,
+    /// Comparing package hashes with the incremental caches
+    pub change_detection: Duration,
+    /// Restoring cached artifacts into the target directory
+    pub cache_restore: Duration,
+    /// Running cargo
+    pub cargo: Duration,
+    /// Writing the build log, metadata and incremental caches
+    pub cache_save: Duration,
+    /// From the start of the first phase to the end of the last one
+    pub total: Duration,
 }
 
-impl CacheManager {
-    /// Creates a new CacheManager with the default cache directory.
-    ///
-    /// The cache directory is determined by:
-    /// 1. The `CARGO_SAVE_CACHE_DIR` environment variable, if set
-    /// 2. The system cache directory (`~/.cache/cargo-save` on Linux)
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if the cache directories cannot be created.
-    ///
-    /// # Example
-    ///
-    /// ```no_run
-    /// use cargo_save::CacheManager;
-    ///
-    /// # fn main() -> anyhow::Result<()> {
-    /// let cache = CacheManager::new()?;
-    /// # Ok(())
-    /// # }
-    /// ```
-    pub fn new() -> Result<Self> {
-        let cache_dir = if let Ok(custom_dir) = std::env::var("CARGO_SAVE_CACHE_DIR") {
-            PathBuf::from(custom_dir)
-        } else {
-            dirs::cache_dir()
-                .unwrap_or_else(|| PathBuf::from("."))
-                .join("cargo-save")
-        }
-        .join(CACHE_VERSION);
+/// [`PhaseTimings`] being collected, with the instant the first phase started.
+#[derive(Debug, Default)]
+struct TimingRecorder {
+    start: Option<std::time::Instant>,
+    timings: PhaseTimings,
+}
 
-        let incremental_dir = cache_dir.join("incremental");
-        let metadata_dir = cache_dir.join("metadata");
+/// Outcome of [`CacheManager::restore_workspace_artifacts`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestoreReport {
+    /// Packages whose artifacts are all in place
+    pub restored: Vec<String>,
+    /// Packages without a valid entry, or whose objects could not be placed
+    pub missing: Vec<String>,
+    /// Number of files copied or linked from the object store
+    pub files: usize,
+}
 
-        fs::create_dir_all(&cache_dir)?;
-        fs::create_dir_all(&incremental_dir)?;
-        fs::create_dir_all(&metadata_dir)?;
+/// One-line outcome of a `save` build, printed by `--summary-json` and
+/// returned by [`CacheManager::build_summary`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildSummary {
+    /// Cache ID of the build
+    pub cache_id: String,
+    /// Exit code of cargo (None if it was killed)
+    pub exit_code: Option<i32>,
+    /// Duration of the cargo run in milliseconds, 0 if it was skipped
+    pub duration_ms: u64,
+    /// Number of packages in the build plan
+    pub total_packages: usize,
+    /// Number of packages served from the cache
+    pub cached_count: usize,
+    /// Number of packages cargo had to rebuild
+    pub rebuilt_count: usize,
+    /// Number of `error` diagnostics in the build output
+    pub error_count: usize,
+    /// Number of `warning` diagnostics in the build output
+    pub warning_count: usize,
+    /// Build log, if cargo ran
+    pub log_path: Option<PathBuf>,
+}
 
-        Ok(Self {
-            cache_dir,
-            incremental_dir,
-            metadata_dir,
+/// The set of packages a build would rebuild, as computed by [`CacheManager::plan`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildPlan {
+    /// Packages without a valid cache entry, including transitive dependents
+    pub changed_packages: Vec<PackageHash>,
+    /// Number of packages that can be served from the cache
+    pub cached_count: usize,
+    /// Total number of packages in the workspace, minus excluded ones
+    pub total_packages: usize,
+    /// Packages left out of the build with `--workspace --exclude`
+    #[serde(default)]
+    pub excluded_packages: Vec<String>,
+}
+
+impl BuildPlan {
+    /// Returns the packages of `workspace_state` the plan serves from the cache.
+    pub fn cached_packages<'a>(
+        &'a self,
+        workspace_state: &'a WorkspaceState,
+    ) -> impl Iterator<Item = &'a PackageHash> + 'a {
+        workspace_state.packages.iter().filter(|pkg| {
+            !self.changed_packages.iter().any(|c| c.name == pkg.name)
+                && !self.excluded_packages.contains(&pkg.name)
         })
     }
+}
 
-    /// Gets Cargo metadata for the current workspace.
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if cargo metadata cannot be retrieved.
-    pub fn get_cargo_metadata(&self) -> Result<Metadata> {
-        let metadata = MetadataCommand::new()
-            .exec()
-            .context("Failed to get cargo metadata")?;
-        Ok(metadata)
-    }
+/// Output format of `cargo save` progress, set with `--message-format`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MessageFormat {
+    /// `[cargo-save]` lines on stderr
+    #[default]
+    Human,
+    /// Newline-delimited [`BuildEvent`] JSON objects on stdout
+    #[value(name = "cargo-save-json")]
+    CargoSaveJson,
+}
 
-    /// Computes a hash of the current Rust toolchain.
-    ///
-    /// This includes the rustc and cargo versions.
-    pub fn compute_toolchain_hash(&self) -> Result<String> {
-        let mut hasher = Blake3Hasher::new();
+/// Progress event emitted with `--message-format cargo-save-json`.
+///
+/// Serialized like cargo's own `--message-format=json` messages: one JSON
+/// object per line, tagged with a `reason` field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum BuildEvent {
+    /// Cache state of the workspace before cargo runs
+    BuildPlan {
+        /// Cargo subcommand being run
+        subcommand: String,
+        /// Total number of packages in the workspace
+        total_packages: usize,
+        /// Number of packages served from the cache
+        cached_count: usize,
+        /// Names of the packages that need a rebuild
+        changed_packages: Vec<String>,
+    },
+    /// A package is up to date and will not be rebuilt
+    PackageCached {
+        /// Package name
+        package: String,
+        /// Package version
+        version: String,
+    },
+    /// A package has no valid cache entry and will be rebuilt
+    PackageRebuilding {
+        /// Package name
+        package: String,
+        /// Package version
+        version: String,
+    },
+    /// The build finished, or was skipped because everything was cached
+    Finished {
+        /// Cache ID of the build
+        cache_id: String,
+        /// Whether the build succeeded
+        success: bool,
+        /// Exit code of cargo, if it ran and was not killed
+        exit_code: Option<i32>,
+        /// Build duration in milliseconds
+        duration_ms: u64,
+        /// True if cargo was skipped because every package was cached
+        fresh: bool,
+        /// Why the build stopped before cargo finished, if it did
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
 
-        if let Ok(output) = Command::new("rustc").args(["--version"]).output() {
-            if output.status.success() {
-                hasher.update(&output.stdout);
-            }
-        }
+/// Builds of one day, as reported by [`CacheManager::build_history`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DailyStats {
+    /// Local date, `YYYY-MM-DD`
+    pub date: String,
+    /// Number of cargo runs recorded that day
+    pub builds: usize,
+    /// Mean duration of those runs in milliseconds
+    pub avg_duration_ms: u64,
+    /// Packages served from the cache, summed over the day's builds,
+    /// including builds skipped because everything was cached
+    pub cached_packages: usize,
+    /// Packages the day's builds covered
+    pub total_packages: usize,
+    /// `cached_packages / total_packages`, if any package was covered
+    pub cache_hit_rate: Option<f64>,
+}
 
-        if let Ok(output) = Command::new("cargo").args(["--version"]).output() {
-            if output.status.success() {
-                hasher.update(&output.stdout);
-            }
+/// Cache usage of a single package, as reported by
+/// [`CacheManager::per_package_stats`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageStats {
+    /// Package name
+    pub name: String,
+    /// Number of incremental cache entries recorded for the package
+    pub entries: usize,
+    /// Number of builds that skipped the package, summed over its entries
+    pub hits: u64,
+    /// Mean recorded build duration in milliseconds
+    pub avg_duration_ms: u64,
+    /// Build duration of the most recent entry in milliseconds
+    pub last_duration_ms: u64,
+    /// Bytes of distinct objects the package's entries keep in the store
+    pub artifact_bytes: u64,
+}
+
+/// Manifest stored at the start of an exported cache archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    /// Cache layout version the archive was exported from
+    pub cache_version: String,
+    /// Toolchain hash of the exporting workspace
+    pub toolchain_hash: String,
+    /// Root of the exporting workspace
+    pub workspace_root: PathBuf,
+    /// Timestamp of the export
+    pub created: String,
+    /// Number of incremental cache entries in the archive
+    pub entries: usize,
+    /// Number of artifact store objects in the archive
+    #[serde(default)]
+    pub objects: usize,
+}
+
+/// Options controlling which log [`CacheManager::query_logs_with`] reads.
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    /// Specific cache ID to query
+    pub cache_id: Option<String>,
+    /// Query the Nth most recent build
+    pub last: Option<usize>,
+    /// Only consider builds finished at or after this time
+    pub since: Option<SystemTime>,
+    /// Keep streaming appended lines in `tail` mode until the build finishes
+    pub follow: bool,
+    /// Which output stream of the build to read
+    pub stream: LogStream,
+    /// Highlight errors and warnings with ANSI colors
+    pub color: bool,
+    /// Search every cached log instead of one build (`grep`, `errors` and
+    /// `warnings` modes), prefixing matches with their cache ID
+    pub all_builds: bool,
+}
+
+/// Output stream of a cargo process, as recorded in the build logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogStream {
+    /// Only lines written to stdout (`<id>.out.log`)
+    Out,
+    /// Only lines written to stderr (`<id>.err.log`)
+    Err,
+    /// Both streams in the order they were received (`<id>.log`)
+    #[default]
+    Both,
+}
+
+impl LogStream {
+    /// File name suffix inserted before `.log` for this stream.
+    fn suffix(self) -> &'static str {
+        match self {
+            LogStream::Out => ".out",
+            LogStream::Err => ".err",
+            LogStream::Both => "",
         }
+    }
+}
 
-        Ok(hasher.finalize().to_hex().to_string())
+impl std::fmt::Display for LogStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LogStream::Out => "stdout",
+            LogStream::Err => "stderr",
+            LogStream::Both => "combined",
+        })
     }
+}
 
-    /// Computes a hash of the Cargo.lock file.
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if the Cargo.lock file cannot be read.
-    pub fn compute_cargo_lock_hash(&self, workspace_root: &Path) -> Result<String> {
-        let lock_file = workspace_root.join("Cargo.lock");
+/// Outcome of a cached build, derived from its exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BuildStatus {
+    /// Cargo exited with status 0
+    Success,
+    /// Cargo exited with a non-zero status
+    Failed,
+    /// Cargo was killed before reporting a status
+    Unknown,
+}
 
-        if lock_file.exists() {
-            let content = fs::read(&lock_file)?;
-            let mut hasher = Blake3Hasher::new();
-            hasher.update(&content);
-            Ok(hasher.finalize().to_hex().to_string())
-        } else {
-            Ok("no-lock-file".to_string())
+impl BuildCache {
+    /// Returns the outcome of this build.
+    pub fn status(&self) -> BuildStatus {
+        match self.exit_code {
+            Some(0) => BuildStatus::Success,
+            Some(_) => BuildStatus::Failed,
+            None => BuildStatus::Unknown,
         }
     }
 
-    /// Computes a hash of relevant environment variables.
+    /// Returns true if this build's timestamp is at or after `since`.
     ///
-    /// See [`ENV_VARS_THAT_AFFECT_BUILD`] for the list of variables included.
-    pub fn compute_env_hash(&self) -> String {
-        let mut hasher = Blake3Hasher::new();
+    /// Builds with an unparseable timestamp never match.
+    pub fn is_since(&self, since: SystemTime) -> bool {
+        chrono::DateTime::parse_from_rfc3339(&self.timestamp)
+            .is_ok_and(|time| SystemTime::from(time) >= since)
+    }
+}
 
-        for var in ENV_VARS_THAT_AFFECT_BUILD {
-            if let Ok(value) = std::env::var(var) {
-                hasher.update(var.as_bytes());
-                hasher.update(value.as_bytes());
-            }
-        }
+/// Parses a `--since` value into the point in time it refers to.
+///
+/// Accepts a relative duration counted back from now (`45s`, `30m`, `2h`,
+/// `3d`, `1w`) or an absolute RFC 3339 timestamp such as
+/// `2024-05-01T12:00:00Z`.
+///
+/// # Errors
+///
+/// Returns an error if the value is neither form, or if the duration reaches
+/// back before the Unix epoch.
+pub fn parse_since(s: &str) -> Result<SystemTime> {
+    let s = s.trim();
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Ok(time.into());
+    }
 
-        hasher.finalize().to_hex().to_string()
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(split);
+    let unit_secs: u64 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => anyhow::bail!(
+            "Invalid time '{}': expected a duration like 30m, 2h or 3d, or an RFC 3339 timestamp",
+            s
+        ),
+    };
+    if amount.is_empty() {
+        anyhow::bail!("Invalid time '{}': missing a number before '{}'", s, unit);
     }
 
-    /// Computes a hash of feature flags from command arguments.
-    ///
-    /// Recognizes `--features`, `--all-features`, and `--no-default-features`.
-    pub fn compute_features_hash(&self, args: &[String]) -> String {
-        let mut hasher = Blake3Hasher::new();
+    amount
+        .parse::<u64>()
+        .ok()
+        .and_then(|amount| amount.checked_mul(unit_secs))
+        .and_then(|secs| SystemTime::now().checked_sub(Duration::from_secs(secs)))
+        .filter(|time| *time >= SystemTime::UNIX_EPOCH)
+        .with_context(|| format!("Time '{}' is out of range", s))
+}
 
-        for (i, arg) in args.iter().enumerate() {
-            if arg == "--features" {
-                if let Some(features) = args.get(i + 1) {
-                    hasher.update(features.as_bytes());
-                }
-            } else if arg.starts_with("--features=") {
-                if let Some(features) = arg.strip_prefix("--features=") {
-                    hasher.update(features.as_bytes());
+/// Reads `KEY=value` pairs from a dotenv file such as `.cargo-save.env`.
+///
+/// Blank lines and lines starting with `#` are skipped, an `export ` prefix
+/// is allowed, and values may be wrapped in single or double quotes.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or a line is not `KEY=value`.
+pub fn load_env_file(path: &Path) -> Result<BTreeMap<String, String>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read env file {}", path.display()))?;
+
+    let mut vars = BTreeMap::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            anyhow::bail!(
+                "{}:{}: expected KEY=value, got '{}'",
+                path.display(),
+                number + 1,
+                line
+            );
+        };
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| {
+                value
+                    .strip_prefix(*quote)
+                    .and_then(|v| v.strip_suffix(*quote))
+            })
+            .unwrap_or(value);
+        vars.insert(key.trim().to_string(), value.to_string());
+    }
+    Ok(vars)
+}
+
+/// Network and lock-file flags of a cargo invocation.
+///
+/// `--frozen` is equivalent to `--offline --locked`, so both spellings
+/// produce the same mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetworkMode {
+    /// Cargo may not access the network (`--offline` or `--frozen`)
+    pub offline: bool,
+    /// Cargo may not update Cargo.lock (`--locked` or `--frozen`)
+    pub locked: bool,
+}
+
+impl NetworkMode {
+    /// Reads the network flags from cargo arguments.
+    ///
+    /// Arguments after `--` belong to the test harness or binary and are
+    /// ignored.
+    pub fn from_args(args: &[String]) -> Self {
+        let mut mode = Self::default();
+        for arg in args.iter().take_while(|arg| *arg != "--") {
+            match arg.as_str() {
+                "--offline" => mode.offline = true,
+                "--locked" => mode.locked = true,
+                "--frozen" => {
+                    mode.offline = true;
+                    mode.locked = true;
                 }
-            } else if arg == "--all-features" {
-                hasher.update(b"--all-features");
-            } else if arg == "--no-default-features" {
-                hasher.update(b"--no-default-features");
+                _ => {}
             }
         }
-
-        hasher.finalize().to_hex().to_string()
+        mode
     }
 
-    /// Gets information about the git repository at the given path.
-    ///
-    /// Returns `None` if the path is not in a git repository.
-    pub fn get_git_repo_info(&self, path: &Path) -> Option<GitRepoInfo> {
-        let git_dir_output = Command::new("git")
-            .args(["rev-parse", "--git-dir"])
-            .current_dir(path)
-            .output()
-            .ok()?;
+    /// Returns true if `arg` is one of the flags read by [`NetworkMode::from_args`].
+    pub fn is_network_flag(arg: &str) -> bool {
+        matches!(arg, "--offline" | "--locked" | "--frozen")
+    }
 
-        if !git_dir_output.status.success() {
-            return None;
+    /// Returns the cargo flags that reproduce this mode.
+    pub fn cargo_flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        if self.offline {
+            flags.push("--offline".to_string());
+        }
+        if self.locked {
+            flags.push("--locked".to_string());
         }
+        flags
+    }
+}
 
-        let git_dir_str = String::from_utf8_lossy(&git_dir_output.stdout);
-        let git_dir = PathBuf::from(git_dir_str.trim());
+/// Field used to order the output of [`CacheManager::list_caches_with`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ListSort {
+    /// Build timestamp, oldest first
+    #[default]
+    Time,
+    /// Build duration, fastest first
+    Duration,
+    /// Number of log lines, shortest first
+    Lines,
+}
 
-        let is_worktree = git_dir
-            .file_name()
-            .and_then(|n| n.to_str())
-            .map(|n| n != ".git")
-            .unwrap_or(true);
+/// Layout of the output of [`CacheManager::list_caches_with`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ListFormat {
+    /// Table of ID, status, lines and truncated command
+    #[default]
+    Plain,
+    /// One line per build with ID, status and duration
+    Short,
+    /// Every recorded field of each build, over several lines
+    Long,
+}
 
-        let worktree_root = if is_worktree {
-            Command::new("git")
-                .args(["rev-parse", "--show-toplevel"])
-                .current_dir(path)
-                .output()
-                .ok()
-                .and_then(|o| {
-                    if o.status.success() {
-                        Some(PathBuf::from(String::from_utf8_lossy(&o.stdout).trim()))
-                    } else {
-                        None
-                    }
-                })
-        } else {
-            None
-        };
+/// Output format of the `graph` command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz DOT, for piping into `dot -Tpng`
+    #[default]
+    Dot,
+    /// JSON object of nodes and edges
+    Json,
+}
 
-        let is_shallow = git_dir.join("shallow").exists();
+/// Options controlling which builds [`CacheManager::list_caches_with`] prints.
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    /// How each build is printed
+    pub format: ListFormat,
+    /// Only show builds of the current workspace
+    pub workspace_only: bool,
+    /// Only show builds with this outcome
+    pub status: Option<BuildStatus>,
+    /// Only show builds of this cargo subcommand
+    pub subcommand: Option<String>,
+    /// Only show builds finished at or after this time
+    pub since: Option<SystemTime>,
+    /// Sort order applied before `limit`
+    pub sort: ListSort,
+    /// Reverse the sort order
+    pub reverse: bool,
+    /// Show at most this many builds
+    pub limit: Option<usize>,
+    /// Print the builds as a JSON array instead of a table
+    pub json: bool,
+    /// Color build statuses with ANSI colors
+    pub color: bool,
+}
 
-        let has_lfs = Command::new("git")
-            .args(["lfs", "status"])
-            .current_dir(path)
-            .output()
-            .map(|o| o.status.success())
-            .unwrap_or(false);
+impl ListOptions {
+    /// Filters, sorts and truncates `builds` according to these options.
+    ///
+    /// The workspace filter is not applied here since it needs cargo metadata.
+    pub fn apply(&self, builds: Vec<BuildCache>) -> Vec<BuildCache> {
+        let mut builds: Vec<_> = builds
+            .into_iter()
+            .filter(|b| self.status.map_or(true, |s| b.status() == s))
+            .filter(|b| {
+                self.subcommand
+                    .as_deref()
+                    .map_or(true, |sub| b.subcommand == sub)
+            })
+            .filter(|b| self.since.map_or(true, |since| b.is_since(since)))
+            .collect();
 
-        let is_sparse = git_dir.join("info/sparse-checkout").exists();
+        match self.sort {
+            ListSort::Time => {
+                builds.sort_by_key(|b| chrono::DateTime::parse_from_rfc3339(&b.timestamp).ok())
+            }
+            ListSort::Duration => builds.sort_by_key(|b| b.duration_ms),
+            ListSort::Lines => builds.sort_by_key(|b| b.lines_count),
+        }
+        if self.reverse {
+            builds.reverse();
+        }
+        if let Some(limit) = self.limit {
+            builds.truncate(limit);
+        }
 
-        Some(GitRepoInfo {
-            is_worktree,
-            is_shallow,
-            has_lfs,
-            is_sparse,
-            git_dir,
-            worktree_root,
-        })
+        builds
     }
+}
 
-    /// Checks if a file is managed by Git LFS.
-    fn is_lfs_file(&self, path: &Path, repo_info: &GitRepoInfo) -> bool {
-        if !repo_info.has_lfs {
-            return false;
-        }
+/// How `invalidate` package arguments are matched against package names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MatchMode {
+    /// Package names starting with the argument
+    #[default]
+    Prefix,
+    /// Package names equal to the argument
+    Exact,
+    /// Shell-style glob such as `core*`
+    Glob,
+    /// Regular expression matched against the whole name
+    Regex,
+}
 
-        if let Ok(content) = fs::read_to_string(path) {
-            content.starts_with("version https://git-lfs.github.com/spec/")
-        } else {
-            false
-        }
-    }
+/// Build profile of an incremental cache entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Profile {
+    /// Non-`--release` builds
+    Debug,
+    /// `--release` builds
+    Release,
+}
 
-    /// Gets the SHA256 hash from an LFS pointer file.
-    fn get_lfs_pointer_hash(&self, path: &Path) -> Option<String> {
-        fs::read_to_string(path).ok().and_then(|content| {
-            for line in content.lines() {
-                if line.starts_with("oid sha256:") {
-                    return line
-                        .strip_prefix("oid sha256:")
-                        .map(|s| s.trim().to_string());
+/// Compiled set of package patterns used by [`CacheManager::invalidate_caches_with`].
+#[derive(Debug, Clone)]
+pub enum PackageMatcher {
+    /// Matches every package
+    Any,
+    /// Prefix patterns
+    Prefix(Vec<String>),
+    /// Exact package names
+    Exact(Vec<String>),
+    /// Compiled glob patterns
+    Glob(globset::GlobSet),
+    /// Compiled anchored regular expressions
+    Regex(regex::RegexSet),
+}
+
+impl PackageMatcher {
+    /// Compiles `patterns` according to `mode`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a glob or regular expression is invalid.
+    pub fn new(mode: MatchMode, patterns: &[String]) -> Result<Self> {
+        Ok(match mode {
+            MatchMode::Prefix => PackageMatcher::Prefix(patterns.to_vec()),
+            MatchMode::Exact => PackageMatcher::Exact(patterns.to_vec()),
+            MatchMode::Glob => {
+                let mut builder = globset::GlobSetBuilder::new();
+                for pattern in patterns {
+                    builder.add(
+                        globset::Glob::new(pattern)
+                            .with_context(|| format!("Invalid glob pattern: {}", pattern))?,
+                    );
                 }
+                PackageMatcher::Glob(builder.build()?)
             }
-            None
+            MatchMode::Regex => PackageMatcher::Regex(
+                regex::RegexSet::new(patterns.iter().map(|p| format!("^(?:{})$", p)))
+                    .context("Invalid regular expression")?,
+            ),
         })
     }
 
-    /// Computes a hash of the source files in a package.
-    ///
-    /// Uses git tree hashes when available, falling back to file content hashing.
-    /// Handles git submodules, LFS files, sparse checkouts, and worktrees.
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if source files cannot be read.
-    pub fn compute_source_hash(&self, path: &Path, _args: &[String]) -> Result<String> {
-        let mut hasher = Blake3Hasher::new();
+    /// Returns true if `name` matches any of the patterns.
+    pub fn is_match(&self, name: &str) -> bool {
+        match self {
+            PackageMatcher::Any => true,
+            PackageMatcher::Prefix(prefixes) => {
+                prefixes.iter().any(|p| name.starts_with(p.as_str()))
+            }
+            PackageMatcher::Exact(names) => names.iter().any(|n| n == name),
+            PackageMatcher::Glob(set) => set.is_match(name),
+            PackageMatcher::Regex(set) => set.is_match(name),
+        }
+    }
+}
 
-        let repo_info = self.get_git_repo_info(path);
+/// Options controlling which entries [`CacheManager::invalidate_caches_with`] removes.
+#[derive(Debug, Clone, Default)]
+pub struct InvalidateOptions {
+    /// Package name patterns to invalidate
+    pub packages: Vec<String>,
+    /// Invalidate every package (patterns are ignored)
+    pub all: bool,
+    /// How `packages` are matched against package names
+    pub match_mode: MatchMode,
+    /// Only invalidate entries built with this profile
+    pub profile: Option<Profile>,
+    /// Only invalidate entries built with this `--features` value
+    pub features: Option<String>,
+    /// Only invalidate entries written by this cargo subcommand
+    pub subcommand: Option<String>,
+    /// Only invalidate entries written before this time
+    pub older_than: Option<SystemTime>,
+    /// Only invalidate this workspace's entries of the packages it needs to
+    /// rebuild
+    pub changed: bool,
+    /// Remove the entries without asking for confirmation
+    pub force: bool,
+    /// Only report the entries that would be removed
+    pub dry_run: bool,
+}
 
-        let effective_path = if let Some(ref info) = repo_info {
-            if info.is_worktree {
-                if let Some(ref worktree_root) = info.worktree_root {
-                    worktree_root.as_path()
-                } else {
-                    path
-                }
-            } else {
-                path
-            }
-        } else {
-            path
-        };
+impl InvalidateOptions {
+    /// Returns true if any of the profile, features, subcommand, age or
+    /// changed filters is set.
+    pub fn has_filters(&self) -> bool {
+        self.profile.is_some()
+            || self.features.is_some()
+            || self.subcommand.is_some()
+            || self.older_than.is_some()
+            || self.changed
+    }
 
-        // Try to use git for fast tree hashing
-        if let Ok(output) = Command::new("git")
-            .args(["ls-tree", "-r", "HEAD"])
-            .arg(effective_path)
-            .output()
+    /// Cargo arguments selecting the builds the profile and features
+    /// filters describe.
+    fn build_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.profile == Some(Profile::Release) {
+            args.push("--release".to_string());
+        }
+        if let Some(features) = &self.features {
+            args.extend(["--features".to_string(), features.clone()]);
+        }
+        args
+    }
+}
+
+/// Options controlling what [`CacheManager::generate_cache_key_with`] folds
+/// into a CI cache key.
+#[derive(Debug, Clone, Default)]
+pub struct CacheKeyOptions {
+    /// Append `CARGO_BUILD_TARGET` (`host` when unset)
+    pub include_target: bool,
+    /// Use untruncated hashes
+    pub full_hashes: bool,
+    /// Extra environment variables whose values are hashed into the key,
+    /// e.g. a CI matrix variable
+    pub include_env: Vec<String>,
+    /// A `--features` value hashed into the key
+    pub include_features: Option<String>,
+}
+
+/// Options controlling which builds [`CacheManager::clean_old_caches_with`] removes.
+#[derive(Debug, Clone, Default)]
+pub struct CleanOptions {
+    /// Remove builds older than this many days (ignored with `keep`)
+    pub days: u64,
+    /// Keep only this many most recent builds
+    pub keep: Option<usize>,
+    /// Remove the builds without asking for confirmation
+    pub force: bool,
+    /// Only report the files that would be removed
+    pub dry_run: bool,
+    /// Which part of the cache to clean
+    pub scope: CleanScope,
+}
+
+/// Part of the cache [`CacheManager::clean_old_caches_with`] cleans.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CleanScope {
+    /// Build logs, their metadata and incremental caches
+    #[default]
+    All,
+    /// Only build logs and their metadata (`--logs-only`)
+    Logs,
+    /// Only incremental caches and unreferenced objects (`--incremental-only`)
+    Incremental,
+}
+
+impl CleanScope {
+    /// Returns true if build logs and their metadata are cleaned.
+    pub fn includes_logs(self) -> bool {
+        self != CleanScope::Incremental
+    }
+
+    /// Returns true if incremental caches and objects are cleaned.
+    pub fn includes_incremental(self) -> bool {
+        self != CleanScope::Logs
+    }
+}
+
+/// How a single package differs between two builds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PackageChange {
+    /// Package only exists in the second build
+    Added,
+    /// Package only exists in the first build
+    Removed,
+    /// Package source hash changed
+    SourceChanged,
+    /// Package feature hash changed
+    FeaturesChanged,
+}
+
+/// A change to one package between two builds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageDiff {
+    /// Package name
+    pub name: String,
+    /// What changed
+    pub change: PackageChange,
+}
+
+/// A change to one build-affecting environment variable.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvVarDiff {
+    /// Variable name
+    pub name: String,
+    /// Value in the first build (None if unset)
+    pub before: Option<String>,
+    /// Value in the second build (None if unset)
+    pub after: Option<String>,
+}
+
+/// Differences between two cached builds, as computed by [`CacheManager::diff_builds`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildDiff {
+    /// Cache ID of the first build
+    pub a: String,
+    /// Cache ID of the second build
+    pub b: String,
+    /// Per-package source and feature changes
+    pub packages: Vec<PackageDiff>,
+    /// Whether the environment hash differs
+    pub env_changed: bool,
+    /// Individual variables that changed (empty if not recorded by both builds)
+    pub env_vars: Vec<EnvVarDiff>,
+    /// Whether Cargo.lock changed
+    pub cargo_lock_changed: bool,
+    /// Whether the Rust toolchain changed
+    pub toolchain_changed: bool,
+    /// Full commands of both builds, if they differ
+    pub command: Option<(String, String)>,
+}
+
+impl BuildDiff {
+    /// Returns true if the two builds had identical inputs.
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+            && !self.env_changed
+            && !self.cargo_lock_changed
+            && !self.toolchain_changed
+            && self.command.is_none()
+    }
+}
+
+/// An input of a package's incremental cache entry that differs from the
+/// current state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RebuildFactor {
+    /// The package source hash changed
+    Source,
+    /// A variable in [`ENV_VARS_THAT_AFFECT_BUILD`] changed
+    Environment,
+    /// The enabled features changed
+    Features,
+    /// Cargo.lock or a git dependency changed
+    CargoLock,
+    /// The Rust toolchain changed
+    Toolchain,
+    /// The entry was built with the other profile
+    Profile,
+    /// Recorded target files are gone and cannot be restored
+    MissingTargetFiles,
+    /// The build that wrote the entry failed
+    BuildFailed,
+}
+
+impl RebuildFactor {
+    fn describe(self) -> &'static str {
+        match self {
+            RebuildFactor::Source => "source changed",
+            RebuildFactor::Environment => "environment changed",
+            RebuildFactor::Features => "features changed",
+            RebuildFactor::CargoLock => "Cargo.lock changed",
+            RebuildFactor::Toolchain => "toolchain changed",
+            RebuildFactor::Profile => "profile changed",
+            RebuildFactor::MissingTargetFiles => "target files missing",
+            RebuildFactor::BuildFailed => "last build failed",
+        }
+    }
+}
+
+/// Why a package is rebuilt, as computed by [`CacheManager::why_rebuild`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RebuildReason {
+    /// Package name
+    pub package: String,
+    /// Timestamp of the compared entry (None if the package has no entry)
+    pub entry_timestamp: Option<String>,
+    /// Inputs that differ from the entry; empty if it is still valid
+    pub factors: Vec<RebuildFactor>,
+    /// Recorded target files that are missing and not in the object store
+    pub missing_files: Vec<PathBuf>,
+}
+
+impl std::fmt::Display for RebuildReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.entry_timestamp.is_none() {
+            return write!(f, "{}: never cached in this workspace", self.package);
+        }
+        if self.factors.is_empty() {
+            return write!(f, "{}: cached, nothing changed", self.package);
+        }
+        write!(f, "{}: {}", self.package, self.describe_factors())
+    }
+}
+
+impl RebuildReason {
+    /// Describes the factors, e.g. `source changed, environment changed`.
+    fn describe_factors(&self) -> String {
+        let reasons: Vec<String> = self
+            .factors
+            .iter()
+            .map(|factor| match factor {
+                RebuildFactor::MissingTargetFiles => {
+                    format!("{} target files missing", self.missing_files.len())
+                }
+                _ => factor.describe().to_string(),
+            })
+            .collect();
+        reasons.join(", ")
+    }
+}
+
+/// CI system a cache key is generated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CiPlatform {
+    /// GitHub Actions (`actions/cache`)
+    Github,
+    /// GitLab CI (`cache:key`)
+    Gitlab,
+    /// CircleCI (`save_cache` / `restore_cache`)
+    Circleci,
+    /// Buildkite cache plugin
+    Buildkite,
+    /// Azure Pipelines (`Cache@2`)
+    Azure,
+    /// Jenkins job cacher
+    Jenkins,
+    /// Any other system
+    Generic,
+}
+
+impl CiPlatform {
+    /// Joins key segments in the form the platform expects.
+    fn format_key(self, segments: &[&str]) -> String {
+        match self {
+            // CircleCI caches are immutable, so keys conventionally carry a
+            // version prefix that can be bumped to bust them.
+            CiPlatform::Circleci => format!("v1-cargo-save-{}", segments.join("-")),
+            // Cache@2 keys are `|`-separated segments
+            CiPlatform::Azure => format!("\"cargo-save\" | {}", segments.join(" | ")),
+            CiPlatform::Github
+            | CiPlatform::Gitlab
+            | CiPlatform::Buildkite
+            | CiPlatform::Jenkins
+            | CiPlatform::Generic => format!("cargo-save-{}", segments.join("-")),
+        }
+    }
+
+    /// Publishes the key as a step output where the platform supports it.
+    ///
+    /// On GitHub Actions this appends `cache-key=<key>` to `$GITHUB_OUTPUT`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the output file cannot be written.
+    pub fn write_output(self, key: &str) -> Result<()> {
+        if self == CiPlatform::Github {
+            if let Ok(path) = std::env::var("GITHUB_OUTPUT") {
+                let mut file = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .with_context(|| format!("Failed to open GITHUB_OUTPUT file {}", path))?;
+                writeln!(file, "cache-key={}", key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Summary of a cache version migration performed by [`CacheManager::migrate`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MigrationReport {
+    /// Old version directories that were removed (e.g. `v2`)
+    pub removed_versions: Vec<String>,
+    /// Number of build metadata entries moved into the current version
+    pub migrated_builds: usize,
+    /// Disk space reclaimed in bytes
+    pub reclaimed_bytes: u64,
+}
+
+/// Number and total size of the files below one cache directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirUsage {
+    /// Number of files, counted recursively
+    pub files: u64,
+    /// Combined size of those files in bytes
+    pub bytes: u64,
+}
+
+/// Disk usage of the cache by subtree, from [`CacheManager::cache_usage`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheUsage {
+    /// Build logs at the top of the cache directory
+    pub build_logs: DirUsage,
+    /// Build metadata files
+    pub metadata: DirUsage,
+    /// Incremental cache entries
+    pub incremental: DirUsage,
+    /// Content-addressed artifacts
+    pub objects: DirUsage,
+}
+
+impl CacheUsage {
+    /// Combined size of all subtrees in bytes.
+    pub fn total_bytes(&self) -> u64 {
+        self.build_logs.bytes + self.metadata.bytes + self.incremental.bytes + self.objects.bytes
+    }
+}
+
+/// Environment and cache diagnostics produced by [`CacheManager::doctor_report`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DoctorReport {
+    /// Output of `git --version`, if git is available
+    pub git_version: Option<String>,
+    /// First line of `cargo nextest --version`, if installed
+    pub nextest_version: Option<String>,
+    /// Value of `RUSTC_WRAPPER`, if set
+    pub rustc_wrapper: Option<String>,
+    /// True if `RUSTC_WRAPPER` is sccache
+    pub sccache: bool,
+    /// The "Cache hits" line of `sccache --show-stats`
+    pub sccache_stats: Option<String>,
+    /// Versioned cache directory
+    pub cache_dir: PathBuf,
+    /// Combined size of build logs, metadata, incremental caches and stored
+    /// artifacts in bytes
+    pub cache_size_bytes: u64,
+    /// Number of build logs
+    pub build_logs: u64,
+    /// Number of build metadata files
+    pub metadata_files: u64,
+    /// Number of incremental cache entries
+    pub incremental_caches: u64,
+    /// Size of each cache subtree
+    #[serde(default)]
+    pub usage: CacheUsage,
+    /// Suggested actions, empty when everything looks fine
+    pub recommendations: Vec<String>,
+}
+
+/// Dependency graph for workspace packages.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DependencyGraph {
+    /// Map of package names to their dependency information
+    pub packages: HashMap<String, PackageNode>,
+}
+
+impl DependencyGraph {
+    /// Groups packages into waves that can be built independently.
+    ///
+    /// Every package in a wave only depends on packages from earlier waves
+    /// (dependencies outside of `packages` are ignored). Names within a wave
+    /// are sorted for stable output.
+    ///
+    /// Returns `None` if the packages contain a dependency cycle.
+    pub fn build_waves(&self, packages: &[String]) -> Option<Vec<Vec<String>>> {
+        let selected: HashSet<&str> = packages.iter().map(|p| p.as_str()).collect();
+
+        let mut remaining: HashMap<&str, usize> = selected
+            .iter()
+            .map(|name| {
+                let in_degree = self
+                    .packages
+                    .get(*name)
+                    .map(|node| {
+                        node.dependencies
+                            .iter()
+                            .filter(|d| selected.contains(d.as_str()) && d.as_str() != *name)
+                            .collect::<HashSet<_>>()
+                            .len()
+                    })
+                    .unwrap_or(0);
+                (*name, in_degree)
+            })
+            .collect();
+
+        let mut waves = Vec::new();
+
+        while !remaining.is_empty() {
+            let mut wave: Vec<String> = remaining
+                .iter()
+                .filter(|(_, in_degree)| **in_degree == 0)
+                .map(|(name, _)| name.to_string())
+                .collect();
+
+            if wave.is_empty() {
+                return None;
+            }
+
+            wave.sort();
+
+            for name in &wave {
+                remaining.remove(name.as_str());
+                if let Some(node) = self.packages.get(name) {
+                    for dependent in &node.reverse_dependencies {
+                        if let Some(in_degree) = remaining.get_mut(dependent.as_str()) {
+                            *in_degree = in_degree.saturating_sub(1);
+                        }
+                    }
+                }
+            }
+
+            waves.push(wave);
+        }
+
+        Some(waves)
+    }
+
+    /// Returns `seeds` followed by every package that transitively depends on them.
+    ///
+    /// Walks reverse dependencies breadth-first, visiting each package once.
+    /// Seeds keep their order and dependents follow in discovery order.
+    pub fn transitive_dependents(&self, seeds: &[String]) -> Vec<String> {
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut queue = VecDeque::new();
+        let mut result = Vec::new();
+
+        for seed in seeds {
+            if seen.insert(seed.as_str()) {
+                queue.push_back(seed.as_str());
+                result.push(seed.clone());
+            }
+        }
+
+        while let Some(name) = queue.pop_front() {
+            let Some(node) = self.packages.get(name) else {
+                continue;
+            };
+            for dependent in &node.reverse_dependencies {
+                if seen.insert(dependent.as_str()) {
+                    queue.push_back(dependent.as_str());
+                    result.push(dependent.clone());
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Renders the graph in Graphviz DOT, with an edge from each package to
+    /// each workspace package it depends on.
+    ///
+    /// Packages named in `changed` are filled red. Nodes and edges are sorted
+    /// for stable output.
+    pub fn to_dot(&self, changed: &[String]) -> String {
+        let mut names: Vec<&String> = self.packages.keys().collect();
+        names.sort();
+
+        let mut dot = String::from("digraph workspace {\n    node [shape=box];\n");
+        for name in &names {
+            if changed.contains(name) {
+                dot.push_str(&format!(
+                    "    {:?} [style=filled, fillcolor=\"#f4a3a3\"];\n",
+                    name
+                ));
+            } else {
+                dot.push_str(&format!("    {:?};\n", name));
+            }
+        }
+        for (from, to) in self.edges() {
+            dot.push_str(&format!("    {:?} -> {:?};\n", from, to));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Returns the graph as JSON: a `nodes` array of `{name, changed}` and an
+    /// `edges` array of `{from, to}`, where `from` depends on `to`.
+    pub fn to_json(&self, changed: &[String]) -> serde_json::Value {
+        let mut names: Vec<&String> = self.packages.keys().collect();
+        names.sort();
+
+        let nodes: Vec<serde_json::Value> = names
+            .iter()
+            .map(|name| serde_json::json!({ "name": name, "changed": changed.contains(name) }))
+            .collect();
+        let edges: Vec<serde_json::Value> = self
+            .edges()
+            .into_iter()
+            .map(|(from, to)| serde_json::json!({ "from": from, "to": to }))
+            .collect();
+        serde_json::json!({ "nodes": nodes, "edges": edges })
+    }
+
+    /// Sorted, deduplicated dependency edges between packages of the graph.
+    fn edges(&self) -> Vec<(&str, &str)> {
+        let mut edges: Vec<(&str, &str)> = self
+            .packages
+            .values()
+            .flat_map(|node| {
+                node.dependencies
+                    .iter()
+                    .filter(|dep| self.packages.contains_key(dep.as_str()))
+                    .map(move |dep| (node.name.as_str(), dep.as_str()))
+            })
+            .collect();
+        edges.sort();
+        edges.dedup();
+        edges
+    }
+}
+
+/// Node in the dependency graph.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageNode {
+    /// Package name
+    pub name: String,
+    /// Names of packages this package depends on
+    pub dependencies: Vec<String>,
+    /// Names of packages that depend on this package
+    pub reverse_dependencies: Vec<String>,
+}
+
+/// A shared store for incremental cache entries and artifacts.
+///
+/// When a [`CacheManager`] has a remote configured, builds look up entries
+/// missing locally remotely (downloading their artifacts into the object
+/// store) and saved entries are uploaded. Keys are `entries/<cache-key>.json`
+/// and `objects/<blake3>`.
+pub trait RemoteCache: Send + Sync {
+    /// Returns the data stored under `key`, or `None` if there is none.
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    /// Stores `data` under `key`, replacing any previous value.
+    fn put(&self, key: &str, data: &[u8]) -> Result<()>;
+}
+
+impl<T: RemoteCache + ?Sized> RemoteCache for std::sync::Arc<T> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        (**self).get(key)
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        (**self).put(key, data)
+    }
+}
+
+/// In-memory [`RemoteCache`], useful for tests.
+#[derive(Debug, Default)]
+pub struct MemoryRemoteCache {
+    entries: std::sync::Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryRemoteCache {
+    /// Creates an empty in-memory cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the stored keys in sorted order.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.entries.lock().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+}
+
+impl RemoteCache for MemoryRemoteCache {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.entries.lock().unwrap().get(key).cloned())
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+}
+
+/// [`RemoteCache`] backed by a plain HTTP server supporting `GET` and `PUT`,
+/// such as nginx with WebDAV or a bazel-remote style cache.
+#[cfg(feature = "remote")]
+pub struct HttpRemoteCache {
+    base_url: String,
+    token: Option<String>,
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "remote")]
+impl HttpRemoteCache {
+    /// Creates a cache storing keys below `base_url`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            token: None,
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(60))
+                .build(),
+        }
+    }
+
+    /// Sends `token` as a bearer token with every request.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn request(&self, method: &str, key: &str) -> ureq::Request {
+        let request = self
+            .agent
+            .request(method, &format!("{}/{}", self.base_url, key));
+        match &self.token {
+            Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+}
+
+#[cfg(feature = "remote")]
+impl RemoteCache for HttpRemoteCache {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.request("GET", key).call() {
+            Ok(response) => {
+                let mut data = Vec::new();
+                io::Read::read_to_end(&mut response.into_reader(), &mut data)?;
+                Ok(Some(data))
+            }
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.request("PUT", key).send_bytes(data)?;
+        Ok(())
+    }
+}
+
+/// [`RemoteCache`] backed by an S3 bucket (or an S3-compatible store).
+///
+/// Requests are signed with AWS Signature Version 4 using path-style URLs.
+#[cfg(feature = "remote")]
+pub struct S3RemoteCache {
+    bucket: String,
+    prefix: String,
+    region: String,
+    endpoint: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "remote")]
+impl S3RemoteCache {
+    /// Creates a cache storing keys below `prefix` in `bucket`, using the
+    /// standard `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`,
+    /// `AWS_SESSION_TOKEN` and `AWS_REGION` variables.
+    ///
+    /// `CARGO_SAVE_S3_ENDPOINT` overrides the endpoint for S3-compatible stores.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the credentials are not set.
+    pub fn from_env(bucket: impl Into<String>, prefix: impl Into<String>) -> Result<Self> {
+        let access_key =
+            std::env::var("AWS_ACCESS_KEY_ID").context("AWS_ACCESS_KEY_ID is not set")?;
+        let secret_key =
+            std::env::var("AWS_SECRET_ACCESS_KEY").context("AWS_SECRET_ACCESS_KEY is not set")?;
+        let region = std::env::var("AWS_REGION")
+            .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| "us-east-1".to_string());
+        let endpoint = std::env::var("CARGO_SAVE_S3_ENDPOINT")
+            .unwrap_or_else(|_| format!("https://s3.{}.amazonaws.com", region));
+
+        let prefix = prefix.into().trim_matches('/').to_string();
+        Ok(Self {
+            bucket: bucket.into(),
+            prefix: if prefix.is_empty() {
+                prefix
+            } else {
+                format!("{}/", prefix)
+            },
+            region,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            access_key,
+            secret_key,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(60))
+                .build(),
+        })
+    }
+
+    /// Builds a signed request for the object `key`.
+    fn request(&self, method: &str, key: &str, body: &[u8]) -> ureq::Request {
+        use sha2::Digest;
+
+        let path = format!(
+            "/{}/{}",
+            s3_uri_encode(&self.bucket),
+            s3_uri_encode(&format!("{}{}", self.prefix, key))
+        );
+        let host = self
+            .endpoint
+            .split("://")
+            .nth(1)
+            .unwrap_or(&self.endpoint)
+            .to_string();
+
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = to_hex(&sha2::Sha256::digest(body));
+
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            method, path, canonical_headers, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            to_hex(&sha2::Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = sigv4_signing_key(&self.secret_key, &date, &self.region, "s3");
+        let signature = to_hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        let mut request = self
+            .agent
+            .request(method, &format!("{}{}", self.endpoint, path))
+            .set(
+                "Authorization",
+                &format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                    self.access_key, scope, signed_headers, signature
+                ),
+            );
+        for (name, value) in &headers[1..] {
+            request = request.set(name, value);
+        }
+        request
+    }
+}
+
+#[cfg(feature = "remote")]
+impl RemoteCache for S3RemoteCache {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.request("GET", key, b"").call() {
+            Ok(response) => {
+                let mut data = Vec::new();
+                io::Read::read_to_end(&mut response.into_reader(), &mut data)?;
+                Ok(Some(data))
+            }
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.request("PUT", key, data).send_bytes(data)?;
+        Ok(())
+    }
+}
+
+/// Percent-encodes an S3 object path, keeping `/` separators.
+#[cfg(feature = "remote")]
+fn s3_uri_encode(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(feature = "remote")]
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    use hmac::Mac;
+
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(key).expect("HMAC accepts any key");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Derives the AWS Signature Version 4 signing key.
+#[cfg(feature = "remote")]
+fn sigv4_signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+#[cfg(feature = "remote")]
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns the cargo binary cargo-save runs.
+///
+/// `CARGO_SAVE_CARGO_BIN` takes precedence over `CARGO`, which cargo sets
+/// when invoking `cargo save`. Falls back to `cargo` on `PATH`.
+pub fn cargo_bin() -> std::ffi::OsString {
+    ["CARGO_SAVE_CARGO_BIN", "CARGO"]
+        .iter()
+        .filter_map(std::env::var_os)
+        .find(|bin| !bin.is_empty())
+        .unwrap_or_else(|| "cargo".into())
+}
+
+/// Converts an error spawning cargo, naming the variables to set if the
+/// binary does not exist.
+fn cargo_spawn_error(err: io::Error) -> anyhow::Error {
+    if err.kind() == io::ErrorKind::NotFound {
+        CacheError::CargoNotFound(cargo_bin().to_string_lossy().into_owned()).into()
+    } else {
+        anyhow::Error::new(err).context("Failed to spawn cargo process")
+    }
+}
+
+/// Warns once per program that `result` failed because `program` is not
+/// installed, explaining what cargo-save does without it.
+fn warn_if_missing(program: &str, result: &io::Result<Output>, consequence: &str) {
+    static WARNED: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+    if !matches!(result, Err(e) if e.kind() == io::ErrorKind::NotFound) {
+        return;
+    }
+    let mut warned = WARNED.lock().unwrap();
+    if !warned.iter().any(|p| p == program) {
+        warned.push(program.to_string());
+        log_error!("Warning: {} not found on PATH; {}", program, consequence);
+    }
+}
+
+/// Runs external programs on behalf of cargo-save.
+///
+/// Abstracts process spawning so tests can substitute canned output.
+pub trait CommandRunner: Send + Sync {
+    /// Runs `program` with `args` to completion, in `cwd` if given, and
+    /// returns its captured output.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the program cannot be spawned.
+    fn run(&self, program: &str, args: &[&str], cwd: Option<&Path>) -> io::Result<Output>;
+}
+
+/// [`CommandRunner`] that spawns real processes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealCommandRunner;
+
+impl CommandRunner for RealCommandRunner {
+    fn run(&self, program: &str, args: &[&str], cwd: Option<&Path>) -> io::Result<Output> {
+        let mut command = Command::new(program);
+        command.args(args);
+        if let Some(cwd) = cwd {
+            command.current_dir(cwd);
+        }
+        command.output()
+    }
+}
+
+/// Returns true if a failed git command is worth retrying.
+///
+/// Lock contention with a concurrent git process (e.g. `index.lock` on a busy
+/// CI machine) is transient. Everything else, in particular "not a git
+/// repository" and a missing `git` binary, fails the same way on retry.
+fn is_transient_git_failure(result: &io::Result<Output>) -> bool {
+    match result {
+        Ok(output) if output.status.success() => false,
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+            !stderr.contains("not a git repository")
+                && (stderr.contains(".lock")
+                    || stderr.contains("another git process")
+                    || stderr.contains("resource temporarily unavailable"))
+        }
+        Err(e) => matches!(
+            e.kind(),
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+        ),
+    }
+}
+
+/// Runs git with `args`, retrying transient failures up to [`GIT_ATTEMPTS`]
+/// times with exponential backoff.
+///
+/// Returns the result of the last attempt.
+fn run_git(runner: &dyn CommandRunner, args: &[&str], cwd: Option<&Path>) -> io::Result<Output> {
+    let mut backoff = GIT_RETRY_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let result = runner.run("git", args, cwd);
+        warn_if_missing("git", &result, "hashing package files without git");
+        if attempt >= GIT_ATTEMPTS || !is_transient_git_failure(&result) {
+            return result;
+        }
+        log_debug!(
+            "git {} failed transiently (attempt {}/{}), retrying in {}ms",
+            args.join(" "),
+            attempt,
+            GIT_ATTEMPTS,
+            backoff.as_millis()
+        );
+        std::thread::sleep(backoff);
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+/// Flags that do not change what cargo builds, left out of the command hash.
+///
+/// Their values (`--color always`, `-j 8`) are dropped with them.
+/// `--manifest-path` only selects the workspace, which the package hashes
+/// already identify.
+pub const IGNORED_COMMAND_FLAGS: &[&str] = &[
+    "--manifest-path",
+    "-q",
+    "--quiet",
+    "-v",
+    "-vv",
+    "-vvv",
+    "--verbose",
+    "--color",
+    "-j",
+    "--jobs",
+];
+
+/// Cargo and cargo-nextest flags that take a separate value argument, so the
+/// value stays attached to the flag when flags are reordered.
+const VALUE_FLAGS: &[&str] = &[
+    "-p",
+    "--package",
+    "--exclude",
+    "-F",
+    "--features",
+    "--target",
+    "--target-dir",
+    "--profile",
+    "--manifest-path",
+    "--bin",
+    "--example",
+    "--test",
+    "--bench",
+    "--message-format",
+    "--config",
+    "-Z",
+    "--color",
+    "-j",
+    "--jobs",
+    "-E",
+    "--filterset",
+    "-P",
+    "--partition",
+    "--test-threads",
+    "--retries",
+];
+
+/// Short flags spelled out, so `-p foo` and `--package foo` hash alike.
+const FLAG_ALIASES: &[(&str, &str)] = &[
+    ("-p", "--package"),
+    ("-F", "--features"),
+    ("-r", "--release"),
+];
+
+/// Returns cargo `args` in a canonical order for hashing.
+///
+/// Before `--`, positional values keep their order and come first, followed
+/// by the flags sorted and deduplicated, then the `--config` values in the
+/// order given. Value-bearing flags are joined with their value as
+/// `--flag=value`, short aliases (also attached, as in `-pfoo`) are spelled
+/// out, and flags in [`IGNORED_COMMAND_FLAGS`] as well as the network flags
+/// of [`NetworkMode`] are dropped. Everything from `--` on belongs to the
+/// test harness or binary and is kept verbatim.
+fn canonical_command_args(args: &[String]) -> Vec<String> {
+    let separator = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    let (cargo_args, rest) = args.split_at(separator);
+
+    let mut positional = Vec::new();
+    let mut flags = Vec::new();
+    let mut configs = Vec::new();
+    let mut iter = cargo_args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            positional.push(arg.clone());
+            continue;
+        }
+        let attached = arg
+            .get(..2)
+            .filter(|short| !arg.starts_with("--") && VALUE_FLAGS.contains(short))
+            .filter(|_| arg.len() > 2);
+        let (name, inline_value) = match (attached, arg.split_once('=')) {
+            (Some(short), _) => (short, Some(arg[2..].to_string())),
+            (None, Some((name, value))) if name.starts_with("--") => {
+                (name, Some(value.to_string()))
+            }
+            _ => (arg.as_str(), None),
+        };
+        let value = match inline_value {
+            Some(value) => Some(value),
+            None if VALUE_FLAGS.contains(&name) => iter.next().cloned(),
+            None => None,
+        };
+        if IGNORED_COMMAND_FLAGS.contains(&name) || NetworkMode::is_network_flag(name) {
+            continue;
+        }
+        let name = FLAG_ALIASES
+            .iter()
+            .find(|(short, _)| *short == name)
+            .map_or(name, |(_, long)| long);
+        if name == "--config" {
+            let value = value.map(|value| canonical_config_value(&value));
+            configs.push(format!("--config={}", value.unwrap_or_default()));
+            continue;
+        }
+        flags.push(match value {
+            Some(value) => format!("{}={}", name, value),
+            None => name.to_string(),
+        });
+    }
+    flags.sort();
+    flags.dedup();
+
+    positional.extend(flags);
+    positional.extend(configs);
+    positional.extend(rest.iter().cloned());
+    positional
+}
+
+/// Returns a `--config` value in canonical form: inline `key=value` pairs are
+/// parsed as TOML and written back as JSON, so quoting and spacing do not
+/// matter. Anything else is a config file path and is returned unchanged.
+fn canonical_config_value(value: &str) -> String {
+    match value.parse::<toml::Table>() {
+        Ok(table) if !table.is_empty() => {
+            serde_json::to_string(&table).unwrap_or_else(|_| value.to_string())
+        }
+        _ => value.to_string(),
+    }
+}
+
+/// Returns the config files passed as `--config <path>` in cargo `args`,
+/// leaving out inline `key=value` overrides.
+fn config_file_args(args: &[String]) -> Vec<PathBuf> {
+    let cargo_args: Vec<&String> = args.iter().take_while(|arg| *arg != "--").collect();
+    cargo_args
+        .iter()
+        .enumerate()
+        .filter_map(|(i, arg)| match arg.strip_prefix("--config=") {
+            Some(value) => Some(value),
+            None if *arg == "--config" => cargo_args.get(i + 1).map(|value| value.as_str()),
+            None => None,
+        })
+        .filter(|value| value.parse::<toml::Table>().map_or(true, |t| t.is_empty()))
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Returns the resolved source IDs of the git dependencies in `metadata`,
+/// e.g. `git+https://github.com/org/repo?rev=abc123#abc123...`, sorted and
+/// deduplicated.
+pub fn git_dependency_sources(metadata: &Metadata) -> Vec<String> {
+    let mut sources: Vec<String> = metadata
+        .packages
+        .iter()
+        .filter_map(|package| package.source.as_ref())
+        .map(|source| source.repr.clone())
+        .filter(|repr| repr.starts_with("git+"))
+        .collect();
+    sources.sort();
+    sources.dedup();
+    sources
+}
+
+/// Returns the features `package` is built with for cargo `args`.
+///
+/// Starts from `default` (unless `--no-default-features`) or every feature
+/// (`--all-features`), adds the `--features`/`-F` values that apply to the
+/// package (`feat` it defines or `<package>/feat`) and follows the feature
+/// table transitively. Entries enabling dependencies or their features
+/// (`dep:x`, `x/feat`, `x?/feat`) are included as written.
+pub fn effective_features(package: &Package, args: &[String]) -> BTreeSet<String> {
+    let mut requested: Vec<String> = Vec::new();
+    let mut all_features = false;
+    let mut default_features = true;
+    let mut args_iter = args.iter().take_while(|arg| *arg != "--");
+    while let Some(arg) = args_iter.next() {
+        let value = match arg.as_str() {
+            "--all-features" => {
+                all_features = true;
+                continue;
+            }
+            "--no-default-features" => {
+                default_features = false;
+                continue;
+            }
+            "--features" | "-F" => args_iter.next().map(String::as_str),
+            _ => arg
+                .strip_prefix("--features=")
+                .or_else(|| arg.strip_prefix("-F")),
+        };
+        requested.extend(
+            value
+                .unwrap_or_default()
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|feature| !feature.is_empty())
+                .map(str::to_string),
+        );
+    }
+
+    let dependency_names: HashSet<&str> = package
+        .dependencies
+        .iter()
+        .map(|dep| dep.rename.as_deref().unwrap_or(&dep.name))
+        .collect();
+    let mut pending: Vec<String> = Vec::new();
+    if all_features {
+        pending.extend(package.features.keys().cloned());
+    } else if default_features {
+        pending.push("default".to_string());
+    }
+    for feature in requested {
+        match feature.split_once('/') {
+            Some((name, feature)) if name == package.name => pending.push(feature.to_string()),
+            Some((dep, _)) if dependency_names.contains(dep) => pending.push(feature),
+            Some(_) => {}
+            None => pending.push(feature),
+        }
+    }
+
+    let mut enabled = BTreeSet::new();
+    enable_features(package, pending, &mut enabled);
+    enabled
+}
+
+/// Adds `pending` and the features they enable through the feature table of
+/// `package` to `enabled`. Returns true if anything was added.
+fn enable_features(
+    package: &Package,
+    mut pending: Vec<String>,
+    enabled: &mut BTreeSet<String>,
+) -> bool {
+    let mut added = false;
+    while let Some(feature) = pending.pop() {
+        if let Some(values) = package.features.get(&feature) {
+            if enabled.insert(feature) {
+                pending.extend(values.iter().cloned());
+                added = true;
+            }
+        } else if feature.contains(':') || feature.contains('/') {
+            added |= enabled.insert(feature);
+        }
+    }
+    added
+}
+
+/// Returns the features every workspace member is built with for cargo
+/// `args`, as cargo unifies them in one build.
+///
+/// The members `args` select (`-p`, `--workspace`, or else the default
+/// members) start from their [`effective_features`]; the feature flags of
+/// `args` apply to them only. Members they depend on get the features their
+/// dependents declare: `default` unless `default-features = false`, the
+/// `features` of the dependency, and `member/feat` entries of enabled
+/// features. Members nothing selected depends on have no features.
+fn workspace_features<'a>(
+    metadata: &'a Metadata,
+    args: &[String],
+) -> HashMap<&'a str, BTreeSet<String>> {
+    let members = metadata.workspace_packages();
+    let mut selected: HashSet<String> = selected_packages(args)
+        .into_iter()
+        .map(|spec| match spec.split_once('@') {
+            Some((name, _)) => name.to_string(),
+            None => spec,
+        })
+        .collect();
+    if selected.is_empty() && !selects_workspace(args) {
+        selected.extend(default_build_members(metadata));
+    }
+    if selected.is_empty() {
+        selected.extend(members.iter().map(|package| package.name.clone()));
+    }
+
+    let mut active: HashSet<&str> = HashSet::new();
+    let mut features: HashMap<&str, BTreeSet<String>> = HashMap::new();
+    for package in &members {
+        let name = package.name.as_str();
+        if selected.contains(name) {
+            active.insert(name);
+            features.insert(name, effective_features(package, args));
+        } else {
+            features.insert(name, BTreeSet::new());
+        }
+    }
+    let by_name: HashMap<&str, &Package> = members
+        .iter()
+        .map(|package| (package.name.as_str(), *package))
+        .collect();
+
+    // Enabled features can enable more on other members, so repeat until
+    // nothing changes
+    loop {
+        let mut requested: Vec<(&str, Vec<String>)> = Vec::new();
+        for package in members.iter().filter(|p| active.contains(p.name.as_str())) {
+            let enabled = &features[package.name.as_str()];
+            for dep in &package.dependencies {
+                if !by_name.contains_key(dep.name.as_str()) {
+                    continue;
+                }
+                let key = dep.rename.as_deref().unwrap_or(&dep.name);
+                let dep_enabled = !dep.optional
+                    || enabled.contains(key)
+                    || enabled.contains(&format!("dep:{}", key))
+                    || enabled.iter().any(|feature| {
+                        feature.split_once('/').is_some_and(|(name, _)| name == key)
+                    });
+                if !dep_enabled {
+                    continue;
+                }
+
+                let mut wanted = dep.features.clone();
+                if dep.uses_default_features {
+                    wanted.push("default".to_string());
+                }
+                for feature in enabled {
+                    let Some((name, feature)) = feature.split_once('/') else {
+                        continue;
+                    };
+                    if name.trim_end_matches('?') == key {
+                        wanted.push(feature.to_string());
+                    }
+                }
+                requested.push((dep.name.as_str(), wanted));
+            }
+        }
+
+        let mut changed = false;
+        for (name, wanted) in requested {
+            changed |= active.insert(name);
+            let enabled = features.get_mut(name).expect("member features");
+            changed |= enable_features(by_name[name], wanted, enabled);
+        }
+        if !changed {
+            return features;
+        }
+    }
+}
+
+/// Returns true if cargo `args` select the whole workspace (`--workspace`
+/// or its deprecated alias `--all`).
+fn selects_workspace(args: &[String]) -> bool {
+    args.iter()
+        .take_while(|arg| *arg != "--")
+        .any(|arg| arg == "--workspace" || arg == "--all")
+}
+
+/// Returns the names of the packages hashed with [`HashMethod::FileFallback`].
+fn file_hashed_packages(workspace_state: &WorkspaceState) -> Vec<&str> {
+    workspace_state
+        .packages
+        .iter()
+        .filter(|p| p.hash_method == HashMethod::FileFallback)
+        .map(|p| p.name.as_str())
+        .collect()
+}
+
+/// Returns the names of the members a cargo build without `-p`/`--package`
+/// or `--workspace` builds: `[workspace] default-members` and the members
+/// they depend on.
+///
+/// Empty when the workspace declares no default members, and with cargo
+/// older than 1.71, which does not report them; such a build covers every
+/// member.
+fn default_build_members(metadata: &Metadata) -> Vec<String> {
+    if cargo_metadata::workspace_default_members_is_missing(&metadata.workspace_default_members) {
+        return Vec::new();
+    }
+    let members = metadata.workspace_packages();
+    if metadata.workspace_default_packages().len() == members.len() {
+        return Vec::new();
+    }
+
+    let by_name: HashMap<&str, &Package> = members.iter().map(|p| (p.name.as_str(), *p)).collect();
+    let mut selected: HashSet<&str> = HashSet::new();
+    let mut queue: VecDeque<&str> = metadata
+        .workspace_default_packages()
+        .iter()
+        .map(|p| p.name.as_str())
+        .collect();
+    while let Some(name) = queue.pop_front() {
+        let Some(package) = by_name.get(name) else {
+            continue;
+        };
+        if selected.insert(name) {
+            queue.extend(package.dependencies.iter().map(|dep| dep.name.as_str()));
+        }
+    }
+    members
+        .into_iter()
+        .filter(|p| selected.contains(p.name.as_str()))
+        .map(|p| p.name.clone())
+        .collect()
+}
+
+/// Returns the package specs selected with `-p`/`--package` in cargo `args`.
+fn selected_packages(args: &[String]) -> Vec<String> {
+    let cargo_args: Vec<&String> = args.iter().take_while(|arg| *arg != "--").collect();
+    let mut selected = Vec::new();
+    for (i, arg) in cargo_args.iter().enumerate() {
+        if *arg == "-p" || *arg == "--package" {
+            selected.extend(cargo_args.get(i + 1).map(|spec| spec.to_string()));
+        } else if let Some(spec) = arg
+            .strip_prefix("--package=")
+            .or_else(|| arg.strip_prefix("-p"))
+        {
+            selected.push(spec.to_string());
+        }
+    }
+    selected
+}
+
+/// Returns the packages named by `--exclude` in cargo `args`.
+///
+/// Like cargo, exclusions only apply together with `--workspace`; with `-p`
+/// or no package selection they are ignored.
+fn excluded_packages(args: &[String]) -> Vec<String> {
+    if !selects_workspace(args) {
+        return Vec::new();
+    }
+    let cargo_args: Vec<&String> = args.iter().take_while(|arg| *arg != "--").collect();
+    let mut excluded = Vec::new();
+    for (i, arg) in cargo_args.iter().enumerate() {
+        if *arg == "--exclude" {
+            excluded.extend(cargo_args.get(i + 1).map(|name| name.to_string()));
+        } else if let Some(name) = arg.strip_prefix("--exclude=") {
+            excluded.push(name.to_string());
+        }
+    }
+    excluded
+}
+
+/// Returns the `--manifest-path` given in cargo `args`, if any.
+fn manifest_path_arg(args: &[String]) -> Option<PathBuf> {
+    let cargo_args: Vec<&String> = args.iter().take_while(|arg| *arg != "--").collect();
+    for (i, arg) in cargo_args.iter().enumerate() {
+        if *arg == "--manifest-path" {
+            return cargo_args.get(i + 1).map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--manifest-path=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// Reads the nearest `cargo-save.toml` in `start` or one of its ancestors.
+///
+/// Returns `None` if there is none or it is not valid TOML.
+fn find_config(start: &Path) -> Option<toml::Table> {
+    start.ancestors().find_map(|dir| {
+        let content = fs::read_to_string(dir.join("cargo-save.toml")).ok()?;
+        Some(content.parse::<toml::Table>().unwrap_or_default())
+    })
+}
+
+/// Controls the directory walk [`CacheManager::compute_source_hash`] falls
+/// back to when a package is not in a git repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashWalkOptions {
+    /// Directory names skipped anywhere below a package
+    pub exclude: Vec<String>,
+    /// Maximum directory depth below the package root
+    pub max_depth: usize,
+    /// File name endings that are hashed, without the leading dot
+    /// (`rs`, `rs.in`)
+    pub extensions: Vec<String>,
+    /// Hash every file regardless of `extensions`
+    pub all_files: bool,
+}
+
+/// Extensions of the files hashed outside git by default.
+pub const DEFAULT_HASH_EXTENSIONS: &[&str] = &[
+    "rs", "toml", "rs.in", "proto", "lalrpop", "pest", "json", "sql",
+];
+
+impl Default for HashWalkOptions {
+    fn default() -> Self {
+        Self {
+            exclude: vec![
+                "target".to_string(),
+                ".git".to_string(),
+                "node_modules".to_string(),
+            ],
+            max_depth: 10,
+            extensions: DEFAULT_HASH_EXTENSIONS
+                .iter()
+                .map(|ext| ext.to_string())
+                .collect(),
+            all_files: false,
+        }
+    }
+}
+
+impl HashWalkOptions {
+    /// Loads the options for the workspace at `start`.
+    ///
+    /// `hash_exclude`, `hash_max_depth` and `hash_extensions` from the nearest
+    /// `cargo-save.toml` apply first, then `CARGO_SAVE_HASH_EXCLUDE`
+    /// (comma-separated) and `CARGO_SAVE_HASH_MAX_DEPTH`. Excluded names add
+    /// to the defaults, while `hash_extensions` replaces
+    /// [`DEFAULT_HASH_EXTENSIONS`].
+    pub fn from_config(start: &Path) -> Self {
+        let mut options = Self::default();
+        if let Some(config) = find_config(start) {
+            if let Some(exclude) = config.get("hash_exclude").and_then(|v| v.as_array()) {
+                options.exclude.extend(
+                    exclude
+                        .iter()
+                        .filter_map(|v| v.as_str())
+                        .map(str::to_string),
+                );
+            }
+            if let Some(depth) = config
+                .get("hash_max_depth")
+                .and_then(|v| v.as_integer())
+                .and_then(|depth| usize::try_from(depth).ok())
+            {
+                options.max_depth = depth;
+            }
+            if let Some(extensions) = config.get("hash_extensions").and_then(|v| v.as_array()) {
+                options.extensions = extensions
+                    .iter()
+                    .filter_map(|v| v.as_str())
+                    .map(|ext| ext.trim_start_matches('.').to_string())
+                    .collect();
+            }
+        }
+        options.with_env()
+    }
+
+    /// Returns true if a file named `name` is hashed.
+    fn includes_file(&self, name: &str) -> bool {
+        self.all_files
+            || self.extensions.iter().any(|ext| {
+                name.strip_suffix(ext.as_str())
+                    .is_some_and(|stem| stem.len() > 1 && stem.ends_with('.'))
+            })
+    }
+
+    /// Applies `CARGO_SAVE_HASH_EXCLUDE` and `CARGO_SAVE_HASH_MAX_DEPTH`.
+    fn with_env(mut self) -> Self {
+        if let Ok(exclude) = std::env::var("CARGO_SAVE_HASH_EXCLUDE") {
+            self.exclude.extend(
+                exclude
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string),
+            );
+        }
+        if let Some(depth) = std::env::var("CARGO_SAVE_HASH_MAX_DEPTH")
+            .ok()
+            .and_then(|depth| depth.parse().ok())
+        {
+            self.max_depth = depth;
+        }
+        self
+    }
+}
+
+/// Returns the configured remote cache URL.
+///
+/// `CARGO_SAVE_REMOTE_URL` takes precedence over the `url` key in the
+/// `[remote]` table of the nearest `cargo-save.toml` above `start`.
+pub fn remote_url(start: &Path) -> Option<String> {
+    if let Ok(url) = std::env::var("CARGO_SAVE_REMOTE_URL") {
+        return Some(url).filter(|url| !url.is_empty());
+    }
+
+    #[cfg(feature = "remote")]
+    if let Some(config) = find_config(start) {
+        return config
+            .get("remote")?
+            .get("url")?
+            .as_str()
+            .map(str::to_string);
+    }
+
+    #[cfg(not(feature = "remote"))]
+    let _ = start;
+    None
+}
+
+/// Builds the remote cache configured for `start`, if any.
+///
+/// `s3://bucket/prefix` URLs use `S3RemoteCache`; `http://` and `https://`
+/// URLs use `HttpRemoteCache` with `CARGO_SAVE_REMOTE_TOKEN` as the bearer
+/// token. Both need the `remote` feature.
+///
+/// # Errors
+///
+/// Returns an error if the URL is unsupported, credentials are missing, or
+/// cargo-save was built without the `remote` feature.
+pub fn remote_cache_from_config(start: &Path) -> Result<Option<Box<dyn RemoteCache>>> {
+    let Some(url) = remote_url(start) else {
+        return Ok(None);
+    };
+
+    #[cfg(feature = "remote")]
+    {
+        if let Some(location) = url.strip_prefix("s3://") {
+            let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+            return Ok(Some(Box::new(S3RemoteCache::from_env(bucket, prefix)?)));
+        }
+        if url.starts_with("http://") || url.starts_with("https://") {
+            let mut cache = HttpRemoteCache::new(url);
+            if let Ok(token) = std::env::var("CARGO_SAVE_REMOTE_TOKEN") {
+                cache = cache.with_token(token);
+            }
+            return Ok(Some(Box::new(cache)));
+        }
+        anyhow::bail!("Unsupported remote cache URL: {}", url)
+    }
+
+    #[cfg(not(feature = "remote"))]
+    anyhow::bail!(
+        "Remote cache {} is configured but cargo-save was built without the `remote` feature",
+        url
+    )
+}
+
+/// Central manager for all caching operations.
+///
+/// This is the main interface for using cargo-save as a library.
+/// It handles cache storage, computation, and retrieval.
+///
+/// # Example
+///
+/// ```no_run
+/// use cargo_save::CacheManager;
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let cache = CacheManager::new()?;
+/// let workspace = cache.compute_workspace_state(&[])?;
+///
+/// // Check which packages need rebuilding
+/// let changed = cache.get_changed_packages(&workspace, "hash", "env", false, &[]);
+/// println!("{} packages need rebuilding", changed.len());
+/// # Ok(())
+/// # }
+/// ```
+pub struct CacheManager {
+    /// Directory for general cache files
+    pub cache_dir: PathBuf,
+    /// Directory for incremental cache files
+    pub incremental_dir: PathBuf,
+    /// Directory for metadata files
+    pub metadata_dir: PathBuf,
+    /// Content-addressed store of build artifacts, keyed by blake3 hash
+    pub objects_dir: PathBuf,
+    /// Shared store consulted for entries missing locally
+    remote: Option<Box<dyn RemoteCache>>,
+    /// Directory walk used to hash packages outside git
+    hash_walk: HashWalkOptions,
+    /// Packages that are never cached
+    no_cache: Vec<String>,
+    /// Format of build progress output
+    message_format: MessageFormat,
+    /// Spawns git and toolchain commands
+    runner: Box<dyn CommandRunner>,
+    /// `--version` output of the tools named by [`TOOL_ENV_VARS`], by command
+    tool_versions: std::sync::Mutex<HashMap<String, Option<String>>>,
+    /// Time spent in each build phase so far
+    timings: std::sync::Mutex<TimingRecorder>,
+    /// Hash only committed files in git, ignoring uncommitted changes
+    committed_only: bool,
+    /// Git ref whose tree is hashed instead of `HEAD` and the working tree
+    git_ref: Option<String>,
+    /// Store runs of identical consecutive log lines once
+    collapse_repeated_lines: bool,
+    /// Number of builds whose logs and metadata are kept after each build
+    max_builds: Option<usize>,
+    /// How long a build waits for another build of the same workspace
+    build_wait: Duration,
+    /// Minimum age of an unreferenced object before it is swept
+    object_grace: Duration,
+    /// `Cargo.toml` of the workspace to operate on instead of the current one
+    manifest_path: Option<PathBuf>,
+    /// Variables from `--env-file` that apply on top of the process environment
+    extra_env: BTreeMap<String, String>,
+    /// Key derived from `CARGO_SAVE_CACHE_SALT` that seeds every cache key hash
+    salt_key: Option<[u8; 32]>,
+}
+
+impl CacheManager {
+    /// Creates a new CacheManager with the default cache directory.
+    ///
+    /// The cache directory is determined by:
+    /// 1. The `CARGO_SAVE_CACHE_DIR` environment variable, if set
+    /// 2. The system cache directory (`~/.cache/cargo-save` on Linux)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directories cannot be created.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cargo_save::CacheManager;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let cache = CacheManager::new()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new() -> CacheResult<Self> {
+        let cache_root = if let Ok(custom_dir) = std::env::var("CARGO_SAVE_CACHE_DIR") {
+            PathBuf::from(custom_dir)
+        } else {
+            dirs::cache_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("cargo-save")
+        };
+
+        let mut manager = Self::with_cache_dir(cache_root)?;
+        let current_dir = std::env::current_dir()?;
+        match remote_cache_from_config(&current_dir) {
+            Ok(remote) => manager.remote = remote,
+            Err(e) => log_error!("Warning: remote cache disabled: {}", e),
+        }
+        manager.hash_walk = HashWalkOptions::from_config(&current_dir);
+        let config = find_config(&current_dir);
+        if let Some(no_cache) = config
+            .as_ref()
+            .and_then(|config| config.get("no_cache")?.as_array().cloned())
+        {
+            manager.no_cache = no_cache
+                .iter()
+                .filter_map(|name| name.as_str().map(str::to_string))
+                .collect();
+        }
+        if let Some(collapse) = config
+            .as_ref()
+            .and_then(|config| config.get("collapse_repeated_lines")?.as_bool())
+        {
+            manager.collapse_repeated_lines = collapse;
+        }
+        // The environment variable takes precedence over the config file
+        if let Some(max_builds) = config
+            .as_ref()
+            .and_then(|config| config.get("max_builds")?.as_integer())
+            .and_then(|max| usize::try_from(max).ok())
+            .filter(|max| *max > 0)
+        {
+            manager.max_builds = manager.max_builds.or(Some(max_builds));
+        }
+        Ok(manager)
+    }
+
+    /// Creates a new CacheManager rooted at an explicit cache directory.
+    ///
+    /// Behaves like setting `CARGO_SAVE_CACHE_DIR`: the versioned cache layout
+    /// is created below `cache_root`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directories cannot be created.
+    pub fn with_cache_dir(cache_root: impl Into<PathBuf>) -> CacheResult<Self> {
+        let cache_dir = cache_root.into().join(CACHE_VERSION);
+
+        let incremental_dir = cache_dir.join("incremental");
+        let metadata_dir = cache_dir.join("metadata");
+        let objects_dir = cache_dir.join("objects");
+
+        fs::create_dir_all(&cache_dir)?;
+        fs::create_dir_all(&incremental_dir)?;
+        fs::create_dir_all(&metadata_dir)?;
+        fs::create_dir_all(&objects_dir)?;
+        fs::create_dir_all(cache_dir.join("locks"))?;
+        let marker = cache_dir.join(CACHE_MARKER_FILE);
+        if !marker.exists() {
+            fs::write(&marker, "")?;
+        }
+
+        let manager = Self {
+            cache_dir,
+            incremental_dir,
+            metadata_dir,
+            objects_dir,
+            remote: None,
+            hash_walk: HashWalkOptions::default().with_env(),
+            no_cache: Vec::new(),
+            message_format: MessageFormat::default(),
+            runner: Box::new(RealCommandRunner),
+            tool_versions: Default::default(),
+            timings: Default::default(),
+            committed_only: std::env::var("CARGO_SAVE_COMMITTED_ONLY")
+                .map(|v| !v.is_empty() && v != "0")
+                .unwrap_or(false),
+            git_ref: None,
+            collapse_repeated_lines: false,
+            max_builds: std::env::var("CARGO_SAVE_MAX_BUILDS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|max| *max > 0),
+            build_wait: BUILD_WAIT,
+            object_grace: OBJECT_GRACE,
+            manifest_path: None,
+            extra_env: BTreeMap::new(),
+            salt_key: std::env::var("CARGO_SAVE_CACHE_SALT")
+                .ok()
+                .and_then(|salt| salt_key(&salt)),
+        };
+
+        match manager.migrate() {
+            Ok(report) if !report.removed_versions.is_empty() => {
+                log_info!(
+                    "Removed old cache versions {} ({} builds migrated, {:.2} MB reclaimed)",
+                    report.removed_versions.join(", "),
+                    report.migrated_builds,
+                    report.reclaimed_bytes as f64 / 1024.0 / 1024.0
+                );
+            }
+            Ok(_) => {}
+            Err(e) => log_error!("Warning: cache migration failed: {}", e),
+        }
+
+        if let Err(e) = manager.compress_existing_logs() {
+            log_error!("Warning: failed to compress existing logs: {}", e);
+        }
+
+        Ok(manager)
+    }
+
+    /// Takes the cache lock `name`, serializing writes with other cargo-save
+    /// processes sharing this cache directory.
+    ///
+    /// Waits up to `CARGO_SAVE_LOCK_TIMEOUT` seconds (default 30).
+    fn lock(&self, name: &str) -> CacheResult<CacheLock> {
+        let timeout = std::env::var("CARGO_SAVE_LOCK_TIMEOUT")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map_or(LOCK_TIMEOUT, Duration::from_secs);
+        CacheLock::acquire(
+            &self.cache_dir.join("locks").join(format!("{}.lock", name)),
+            timeout,
+        )
+    }
+
+    /// Takes the build lock of the workspace at `root`, held for the whole
+    /// build so that builds of one workspace run one at a time.
+    ///
+    /// Waits up to the [`with_build_wait`](Self::with_build_wait) timeout,
+    /// naming the process holding it.
+    fn lock_workspace_build(&self, root: &Path) -> CacheResult<CacheLock> {
+        let workspace_id = blake3::hash(root.to_string_lossy().as_bytes()).to_hex();
+        let path = self
+            .cache_dir
+            .join("locks")
+            .join(format!("build-{}.lock", &workspace_id[..HASH_KEY_LEN]));
+        CacheLock::acquire_recording_pid(&path, self.build_wait)
+    }
+
+    /// Uses `remote` for entries missing from the local cache and uploads
+    /// newly saved entries to it.
+    ///
+    /// Entries are fetched before a build or `restore --artifacts-only`;
+    /// checks such as [`check_incremental_cache`](Self::check_incremental_cache)
+    /// only read the local cache. Fetched entries with files outside the
+    /// target directory are rejected.
+    pub fn with_remote(mut self, remote: impl RemoteCache + 'static) -> Self {
+        self.remote = Some(Box::new(remote));
+        self
+    }
+
+    /// Uses `options` for the file walk that hashes packages outside git.
+    pub fn with_hash_walk(mut self, options: HashWalkOptions) -> Self {
+        self.hash_walk = options;
+        self
+    }
+
+    /// Hashes every file of packages outside git instead of only those with
+    /// a configured extension. Excluded directories are still skipped.
+    pub fn with_hash_all_files(mut self, enabled: bool) -> Self {
+        self.hash_walk.all_files = enabled;
+        self
+    }
+
+    /// Seeds every hash that goes into a cache key with `salt`, like
+    /// `CARGO_SAVE_CACHE_SALT`, so users sharing a cache directory get
+    /// disjoint entries. An empty salt hashes as if none was set.
+    pub fn with_cache_salt(mut self, salt: &str) -> Self {
+        self.salt_key = salt_key(salt);
+        self
+    }
+
+    /// Returns a hasher for cache key inputs, keyed with the cache salt if
+    /// one is set.
+    fn hasher(&self) -> Blake3Hasher {
+        match &self.salt_key {
+            Some(key) => Blake3Hasher::new_keyed(key),
+            None => Blake3Hasher::new(),
+        }
+    }
+
+    /// Adds packages that are never cached, e.g. crates with nondeterministic
+    /// codegen or time-dependent build scripts.
+    ///
+    /// They always count as changed and no cache entries are written for them.
+    /// Extends the `no_cache` list read from `cargo-save.toml`.
+    pub fn with_no_cache_packages(mut self, names: impl IntoIterator<Item = String>) -> Self {
+        self.no_cache.extend(names);
+        self
+    }
+
+    /// Uses `runner` to spawn git and toolchain commands instead of running
+    /// them directly, e.g. to test detection logic without a real repository.
+    pub fn with_command_runner(mut self, runner: impl CommandRunner + 'static) -> Self {
+        self.runner = Box::new(runner);
+        self
+    }
+
+    /// Hashes only what is committed in git, so the source hash of a package
+    /// is that of its tree at `HEAD` and uncommitted changes do not
+    /// invalidate it. Also enabled by `CARGO_SAVE_COMMITTED_ONLY=1`.
+    ///
+    /// Lets a dirty checkout share cache entries with a clean CI checkout of
+    /// the same commit. Packages outside git are hashed as before.
+    pub fn with_committed_only(mut self, enabled: bool) -> Self {
+        self.committed_only = enabled;
+        self
+    }
+
+    /// Hashes the tree of `git_ref` (a commit, tag or branch) instead of
+    /// `HEAD`, ignoring uncommitted changes, so the source hashes are those
+    /// of exactly that ref, e.g. for reproducible CI builds of a tag.
+    ///
+    /// Computing the workspace state fails if the ref does not name a commit
+    /// of the workspace's repository, or a package has no files at the ref.
+    /// Builds record the ref's commit and fail unless the ref is checked out
+    /// without uncommitted changes (see
+    /// [`check_git_ref_checkout`](Self::check_git_ref_checkout)), as cargo
+    /// compiles the working tree.
+    pub fn with_git_ref(mut self, git_ref: impl Into<String>) -> Self {
+        self.git_ref = Some(git_ref.into());
+        self
+    }
+
+    /// Returns the commit `git_ref` names in the repository at `dir`.
+    fn resolve_git_ref(&self, dir: &Path, git_ref: &str) -> CacheResult<String> {
+        run_git(
+            self.runner.as_ref(),
+            &[
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("{}^{{commit}}", git_ref),
+            ],
+            Some(dir),
+        )
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .ok_or_else(|| {
+            CacheError::Git(format!(
+                "{} is not a commit of the repository at {}",
+                git_ref,
+                dir.display()
+            ))
+        })
+    }
+
+    /// Checks that the repository at `root` is a clean checkout of the ref
+    /// set with [`with_git_ref`](Self::with_git_ref): `HEAD` is the commit
+    /// the ref names and `git status --porcelain` reports nothing but the
+    /// workspace's `build-logs/`.
+    ///
+    /// Builds compile the working tree, so caching one under the hashes of
+    /// another commit would restore wrong artifacts. Does nothing without a
+    /// ref.
+    pub fn check_git_ref_checkout(&self, root: &Path) -> CacheResult<()> {
+        let Some(git_ref) = &self.git_ref else {
+            return Ok(());
+        };
+        let commit = self.resolve_git_ref(root, git_ref)?;
+        let (head, _) = self.git_head(root);
+        if head.as_deref() != Some(commit.as_str()) {
+            return Err(CacheError::Git(format!(
+                "{} ({}) is not checked out at {}; check it out before building",
+                git_ref,
+                commit,
+                root.display()
+            )));
+        }
+        // Previous builds leave their log copies in `build-logs/`
+        let status = run_git(
+            self.runner.as_ref(),
+            &["status", "--porcelain", "--", ":/", ":(exclude)build-logs"],
+            Some(root),
+        )
+        .map_err(|e| CacheError::Git(e.to_string()))?;
+        if !status.status.success() || !status.stdout.is_empty() {
+            return Err(CacheError::Git(format!(
+                "{} has uncommitted changes; commit or stash them to build {}",
+                root.display(),
+                git_ref
+            )));
+        }
+        Ok(())
+    }
+
+    /// Waits up to `wait` for another cargo-save building the same workspace
+    /// before giving up (default 5 minutes). Builds of one workspace run one
+    /// at a time.
+    pub fn with_build_wait(mut self, wait: Duration) -> Self {
+        self.build_wait = wait;
+        self
+    }
+
+    /// Keeps unreferenced objects younger than `grace` when sweeping the
+    /// object store (default 1 hour), so objects a running save has stored
+    /// but not yet recorded in an entry survive.
+    pub fn with_object_grace(mut self, grace: Duration) -> Self {
+        self.object_grace = grace;
+        self
+    }
+
+    /// Adds `vars`, e.g. from [`load_env_file`], to the environment that is
+    /// hashed and passed to cargo.
+    ///
+    /// Variables already set in the process environment keep their value
+    /// unless `override_process` is true.
+    pub fn with_env_file_vars(
+        mut self,
+        vars: BTreeMap<String, String>,
+        override_process: bool,
+    ) -> Self {
+        self.extra_env = vars
+            .into_iter()
+            .filter(|(var, _)| override_process || std::env::var_os(var).is_none())
+            .collect();
+        self
+    }
+
+    /// Operates on the workspace of `manifest_path` instead of the one
+    /// containing the current directory.
+    ///
+    /// A `--manifest-path` in the cargo arguments passed to a method still
+    /// takes precedence. Git runs in that workspace's repository, so the
+    /// hashes match those of builds run inside it.
+    pub fn with_manifest_path(mut self, manifest_path: impl Into<PathBuf>) -> Self {
+        self.manifest_path = Some(manifest_path.into());
+        self
+    }
+
+    /// Stores runs of identical consecutive build log lines once, as
+    /// `<line> (repeated ×N)`. Also enabled by `collapse_repeated_lines = true`
+    /// in `cargo-save.toml`.
+    ///
+    /// Terminal output is unaffected; `query` reads the collapsed log.
+    pub fn with_collapse_repeated_lines(mut self, enabled: bool) -> Self {
+        self.collapse_repeated_lines = enabled;
+        self
+    }
+
+    /// Keeps only the logs and metadata of the `max` most recent builds,
+    /// pruning older ones after every build, like `CARGO_SAVE_MAX_BUILDS` or
+    /// `max_builds = N` in `cargo-save.toml`. Zero keeps every build.
+    pub fn with_max_builds(mut self, max: usize) -> Self {
+        self.max_builds = (max > 0).then_some(max);
+        self
+    }
+
+    /// Reports build progress in `format`.
+    ///
+    /// With [`MessageFormat::CargoSaveJson`] the build runners print a
+    /// [`BuildEvent`] per line to stdout next to cargo's own output.
+    pub fn with_message_format(mut self, format: MessageFormat) -> Self {
+        self.message_format = format;
+        self
+    }
+
+    /// Prints `event` as a JSON line if JSON messages were requested.
+    fn emit(&self, event: &BuildEvent) {
+        if self.message_format != MessageFormat::CargoSaveJson {
+            return;
+        }
+        match serde_json::to_string(event) {
+            Ok(json) => println!("{}", json),
+            Err(e) => log_error!("Failed to serialize build event: {}", e),
+        }
+    }
+
+    /// Emits the `build-plan` event followed by a `package-cached` or
+    /// `package-rebuilding` event per workspace package. Packages removed
+    /// with `--exclude` are neither and get no event.
+    fn emit_plan(&self, subcommand: &str, plan: &BuildPlan, workspace_state: &WorkspaceState) {
+        if self.message_format != MessageFormat::CargoSaveJson {
+            return;
+        }
+        let changed: HashSet<&str> = plan
+            .changed_packages
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        self.emit(&BuildEvent::BuildPlan {
+            subcommand: subcommand.to_string(),
+            total_packages: plan.total_packages,
+            cached_count: plan.cached_count,
+            changed_packages: plan
+                .changed_packages
+                .iter()
+                .map(|p| p.name.clone())
+                .collect(),
+        });
+        let planned = workspace_state
+            .packages
+            .iter()
+            .filter(|pkg| !plan.excluded_packages.contains(&pkg.name));
+        for pkg in planned {
+            let package = pkg.name.clone();
+            let version = pkg.version.clone();
+            self.emit(&if changed.contains(pkg.name.as_str()) {
+                BuildEvent::PackageRebuilding { package, version }
+            } else {
+                BuildEvent::PackageCached { package, version }
+            });
+        }
+    }
+
+    /// Migrates caches left behind by older cache versions.
+    ///
+    /// Sibling `vN` directories next to the current cache directory are
+    /// handled as follows, if they are cargo-save caches (with the marker
+    /// file, or an `incremental` or `metadata` directory written before the
+    /// marker existed):
+    /// - The previous version (current minus one) has its build metadata and
+    ///   logs moved forward when they still parse, then it is removed
+    /// - Older versions are removed
+    /// - Newer versions (from a newer cargo-save) are left untouched
+    ///
+    /// Incremental entries are never migrated since their keys depend on the
+    /// cache layout. Called automatically by [`new`](Self::new).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache root cannot be read.
+    pub fn migrate(&self) -> Result<MigrationReport> {
+        let mut report = MigrationReport::default();
+
+        let (Some(cache_root), Some(current)) =
+            (self.cache_dir.parent(), parse_cache_version(CACHE_VERSION))
+        else {
+            return Ok(report);
+        };
+
+        let mut old_versions: Vec<(u32, PathBuf)> = fs::read_dir(cache_root)?
+            .flatten()
+            .filter(|e| is_cache_version_dir(&e.path()))
+            .filter_map(|e| {
+                let version = parse_cache_version(&e.file_name().to_string_lossy())?;
+                (version < current).then(|| (version, e.path()))
+            })
+            .collect();
+        old_versions.sort();
+
+        for (version, dir) in old_versions {
+            if version + 1 == current {
+                report.migrated_builds += self.migrate_builds_from(&dir);
+            }
+
+            let size = dir_size(&dir);
+            fs::remove_dir_all(&dir)
+                .with_context(|| format!("Failed to remove {}", dir.display()))?;
+            report.reclaimed_bytes += size;
+            report.removed_versions.push(format!("v{}", version));
+        }
+
+        Ok(report)
+    }
+
+    /// Moves parseable build metadata and their logs from an older cache directory.
+    fn migrate_builds_from(&self, old_cache_dir: &Path) -> usize {
+        let Ok(entries) = fs::read_dir(old_cache_dir.join("metadata")) else {
+            return 0;
+        };
+
+        let mut migrated = 0;
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.extension().is_some_and(|ext| ext == "json") {
+                continue;
+            }
+
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let Ok(build) = serde_json::from_str::<BuildCache>(&content) else {
+                continue;
+            };
+
+            let meta_target = self.metadata_dir.join(entry.file_name());
+            if meta_target.exists() {
+                continue;
+            }
+
+            let log_name = format!("{}.log", build.cache_id);
+            let old_log = old_cache_dir.join(&log_name);
+            if old_log.exists() && fs::copy(&old_log, self.cache_dir.join(&log_name)).is_err() {
+                continue;
+            }
+
+            if fs::write(&meta_target, content).is_ok() {
+                migrated += 1;
+            }
+        }
+
+        migrated
+    }
+
+    /// Gets Cargo metadata for the current workspace, or the one set with
+    /// [`CacheManager::with_manifest_path`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if cargo metadata cannot be retrieved.
+    pub fn get_cargo_metadata(&self) -> CacheResult<Metadata> {
+        let mut command = MetadataCommand::new();
+        command.cargo_path(cargo_bin());
+        if let Some(manifest_path) = &self.manifest_path {
+            command.manifest_path(manifest_path);
+        }
+        Ok(command.exec()?)
+    }
+
+    /// Computes a hash of the current Rust toolchain.
+    ///
+    /// This includes the rustc and cargo versions.
+    pub fn compute_toolchain_hash(&self) -> CacheResult<String> {
+        let mut hasher = self.hasher();
+
+        let cargo = cargo_bin();
+        for program in ["rustc", &cargo.to_string_lossy()] {
+            let result = self.runner.run(program, &["--version"], None);
+            warn_if_missing(
+                program,
+                &result,
+                "toolchain changes will not invalidate the cache",
+            );
+            if let Ok(output) = result {
+                if output.status.success() {
+                    hasher.update(&output.stdout);
+                }
+            }
+        }
+
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+
+    /// Computes a hash of the Cargo.lock file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Cargo.lock file cannot be read.
+    pub fn compute_cargo_lock_hash(&self, workspace_root: &Path) -> CacheResult<String> {
+        let lock_file = workspace_root.join("Cargo.lock");
+
+        if lock_file.exists() {
+            let content = fs::read(&lock_file)?;
+            let mut hasher = self.hasher();
+            hasher.update(&content);
+            Ok(hasher.finalize().to_hex().to_string())
+        } else {
+            Ok("no-lock-file".to_string())
+        }
+    }
+
+    /// Computes the lock hash of a workspace: the Cargo.lock hash combined
+    /// with the resolved source IDs of its git dependencies.
+    ///
+    /// Git source IDs carry the checked-out commit, so moving a `rev`, `tag`
+    /// or branch invalidates cached packages even when Cargo.lock was not
+    /// regenerated. Workspaces without git dependencies keep the plain
+    /// Cargo.lock hash.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Cargo.lock file cannot be read.
+    pub fn compute_dependency_lock_hash(
+        &self,
+        workspace_root: &Path,
+        metadata: &Metadata,
+    ) -> CacheResult<String> {
+        let lock_hash = self.compute_cargo_lock_hash(workspace_root)?;
+        let git_sources = git_dependency_sources(metadata);
+        if git_sources.is_empty() {
+            return Ok(lock_hash);
+        }
+
+        let mut hasher = self.hasher();
+        hasher.update(lock_hash.as_bytes());
+        for source in &git_sources {
+            hasher.update(b"\0");
+            hasher.update(source.as_bytes());
+        }
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+
+    /// Computes a hash of relevant environment variables.
+    ///
+    /// See [`ENV_VARS_THAT_AFFECT_BUILD`] for the list of variables included.
+    /// For `CC`, `CXX`, `AR` and `LINKER` the `--version` output of the named
+    /// tool is included as well, looked up once per `CacheManager`.
+    pub fn compute_env_hash(&self) -> String {
+        self.compute_env_hash_from(&self.capture_env_vars())
+    }
+
+    /// Computes the hash of [`compute_env_hash`](Self::compute_env_hash) over
+    /// `env` instead of the process environment.
+    ///
+    /// Variables of `env` outside [`ENV_VARS_THAT_AFFECT_BUILD`] are ignored.
+    pub fn compute_env_hash_from(&self, env: &BTreeMap<String, String>) -> String {
+        let mut hasher = self.hasher();
+
+        for var in ENV_VARS_THAT_AFFECT_BUILD {
+            if let Some(value) = env.get(*var) {
+                hasher.update(var.as_bytes());
+                hasher.update(value.as_bytes());
+                if TOOL_ENV_VARS.contains(var) {
+                    if let Some(version) = self.tool_version(value) {
+                        hasher.update(b"\0");
+                        hasher.update(version.as_bytes());
+                    }
+                }
+            }
+        }
+
+        hasher.finalize().to_hex().to_string()
+    }
+
+    /// Returns the `--version` output of `command`, such as `clang` or
+    /// `ccache gcc`, or `None` if it cannot be run.
+    fn tool_version(&self, command: &str) -> Option<String> {
+        let mut versions = self.tool_versions.lock().unwrap();
+        if let Some(version) = versions.get(command) {
+            return version.clone();
+        }
+
+        let mut parts = command.split_whitespace();
+        let version = parts.next().and_then(|program| {
+            let args: Vec<&str> = parts.chain(["--version"]).collect();
+            self.runner
+                .run(program, &args, None)
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        });
+        versions.insert(command.to_string(), version.clone());
+        version
+    }
+
+    /// Generates a CI cache key for the given workspace state.
+    ///
+    /// The key covers the toolchain, Cargo.lock and build environment, and
+    /// optionally `CARGO_BUILD_TARGET` (`host` when unset). Hashes are
+    /// shortened to 16 characters (8 for the environment) unless
+    /// `full_hashes` is set; `CARGO_SAVE_HASH_LEN` does not change keys.
+    pub fn generate_cache_key(
+        &self,
+        platform: CiPlatform,
+        workspace_state: &WorkspaceState,
+        include_target: bool,
+        full_hashes: bool,
+    ) -> String {
+        self.generate_cache_key_with(
+            platform,
+            workspace_state,
+            &CacheKeyOptions {
+                include_target,
+                full_hashes,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Generates a CI cache key like
+    /// [`generate_cache_key`](Self::generate_cache_key), adding a segment for
+    /// the [`CacheKeyOptions::include_env`] variables and one for
+    /// [`CacheKeyOptions::include_features`] when given, so matrix jobs that
+    /// differ only in those get distinct keys.
+    pub fn generate_cache_key_with(
+        &self,
+        platform: CiPlatform,
+        workspace_state: &WorkspaceState,
+        options: &CacheKeyOptions,
+    ) -> String {
+        let full = options.full_hashes;
+        let prefix = |hash: &str, len: usize| {
+            if full {
+                hash.to_string()
+            } else {
+                hash[..hash.len().min(len)].to_string()
+            }
+        };
+        let short = |hash: &str| prefix(hash, 8);
+        let mut segments = vec![
+            prefix(&workspace_state.toolchain_hash, HASH_KEY_LEN),
+            prefix(&workspace_state.cargo_lock_hash, HASH_KEY_LEN),
+            short(&self.compute_env_hash()),
+        ];
+        if !options.include_env.is_empty() {
+            let mut hasher = self.hasher();
+            for var in &options.include_env {
+                hasher.update(var.as_bytes());
+                match std::env::var(var) {
+                    Ok(value) => {
+                        hasher.update(b"=");
+                        hasher.update(value.as_bytes());
+                    }
+                    Err(_) => {
+                        hasher.update(b" unset");
+                    }
+                }
+                hasher.update(b"\0");
+            }
+            segments.push(short(&hasher.finalize().to_hex()));
+        }
+        if let Some(features) = &options.include_features {
+            let args = ["--features".to_string(), features.clone()];
+            segments.push(short(&self.compute_features_hash(&args)));
+        }
+        if options.include_target {
+            segments
+                .push(std::env::var("CARGO_BUILD_TARGET").unwrap_or_else(|_| "host".to_string()));
+        }
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        platform.format_key(&segments)
+    }
+
+    /// Captures the build-affecting environment variables that are set,
+    /// including those added with [`CacheManager::with_env_file_vars`].
+    pub fn capture_env_vars(&self) -> BTreeMap<String, String> {
+        ENV_VARS_THAT_AFFECT_BUILD
+            .iter()
+            .filter_map(|var| {
+                self.extra_env
+                    .get(*var)
+                    .cloned()
+                    .or_else(|| std::env::var(var).ok())
+                    .map(|v| (var.to_string(), v))
+            })
+            .collect()
+    }
+
+    /// Computes a hash of feature flags from command arguments.
+    ///
+    /// Recognizes `--features` (and its short form `-F`), `--all-features`,
+    /// and `--no-default-features`.
+    pub fn compute_features_hash(&self, args: &[String]) -> String {
+        let mut hasher = self.hasher();
+
+        for (i, arg) in args.iter().enumerate() {
+            if arg == "--features" || arg == "-F" {
+                if let Some(features) = args.get(i + 1) {
+                    hasher.update(features.as_bytes());
+                }
+            } else if let Some(features) = arg
+                .strip_prefix("--features=")
+                .or_else(|| arg.strip_prefix("-F"))
+            {
+                hasher.update(features.as_bytes());
+            } else if arg == "--all-features" {
+                hasher.update(b"--all-features");
+            } else if arg == "--no-default-features" {
+                hasher.update(b"--no-default-features");
+            }
+        }
+
+        hasher.finalize().to_hex().to_string()
+    }
+
+    /// Hashes a package's enabled `features`, as recorded in
+    /// [`PackageHash::features_hash`].
+    fn features_set_hash(&self, features: &BTreeSet<String>) -> String {
+        let mut hasher = self.hasher();
+        for feature in features {
+            hasher.update(feature.as_bytes());
+            hasher.update(b"\0");
+        }
+        hasher.finalize().to_hex().to_string()
+    }
+
+    /// Returns the commit and branch checked out at `path`.
+    ///
+    /// Both are `None` outside a git repository; the branch is `None` when
+    /// HEAD is detached.
+    pub fn git_head(&self, path: &Path) -> (Option<String>, Option<String>) {
+        let rev_parse = |args: &[&str]| {
+            run_git(self.runner.as_ref(), args, Some(path))
+                .ok()
+                .filter(|o| o.status.success())
+                .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+
+        let commit = rev_parse(&["rev-parse", "HEAD"]);
+        let branch = rev_parse(&["rev-parse", "--abbrev-ref", "HEAD"]).filter(|b| b != "HEAD");
+        (commit, branch)
+    }
+
+    /// Gets information about the git repository at the given path.
+    ///
+    /// Returns `None` if the path is not in a git repository.
+    pub fn get_git_repo_info(&self, path: &Path) -> Option<GitRepoInfo> {
+        let git_dir_output = run_git(
+            self.runner.as_ref(),
+            &["rev-parse", "--git-dir"],
+            Some(path),
+        )
+        .ok()?;
+
+        if !git_dir_output.status.success() {
+            return None;
+        }
+
+        let git_dir_str = String::from_utf8_lossy(&git_dir_output.stdout);
+        let git_dir = PathBuf::from(git_dir_str.trim());
+
+        let is_worktree = git_dir
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n != ".git")
+            .unwrap_or(true);
+
+        let worktree_root = if is_worktree {
+            run_git(
+                self.runner.as_ref(),
+                &["rev-parse", "--show-toplevel"],
+                Some(path),
+            )
+            .ok()
+            .and_then(|o| {
+                if o.status.success() {
+                    Some(PathBuf::from(String::from_utf8_lossy(&o.stdout).trim()))
+                } else {
+                    None
+                }
+            })
+        } else {
+            None
+        };
+
+        let is_shallow = git_dir.join("shallow").exists();
+
+        let has_lfs = run_git(self.runner.as_ref(), &["lfs", "status"], Some(path))
+            .map(|o| o.status.success())
+            .unwrap_or(false);
+
+        let is_sparse = git_dir.join("info/sparse-checkout").exists();
+
+        Some(GitRepoInfo {
+            is_worktree,
+            is_shallow,
+            has_lfs,
+            is_sparse,
+            git_dir,
+            worktree_root,
+        })
+    }
+
+    /// Checks if a file is managed by Git LFS.
+    fn is_lfs_file(&self, path: &Path, repo_info: &GitRepoInfo) -> bool {
+        if !repo_info.has_lfs {
+            return false;
+        }
+
+        if let Ok(content) = fs::read_to_string(path) {
+            content.starts_with("version https://git-lfs.github.com/spec/")
+        } else {
+            false
+        }
+    }
+
+    /// Gets the SHA256 hash from an LFS pointer file.
+    fn get_lfs_pointer_hash(&self, path: &Path) -> Option<String> {
+        fs::read_to_string(path).ok().and_then(|content| {
+            for line in content.lines() {
+                if line.starts_with("oid sha256:") {
+                    return line
+                        .strip_prefix("oid sha256:")
+                        .map(|s| s.trim().to_string());
+                }
+            }
+            None
+        })
+    }
+
+    /// Computes a hash of the source files in a package.
+    ///
+    /// Uses git tree hashes when available, falling back to file content hashing.
+    /// Handles git submodules, LFS files, sparse checkouts, and worktrees.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if source files cannot be read.
+    pub fn compute_source_hash(&self, path: &Path, args: &[String]) -> Result<String> {
+        self.compute_source_hash_with_method(path, args)
+            .map(|(hash, _)| hash)
+    }
+
+    /// Same as [`compute_source_hash`](Self::compute_source_hash), also
+    /// returning whether git or the file walk produced the hash.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if source files cannot be read.
+    pub fn compute_source_hash_with_method(
+        &self,
+        path: &Path,
+        _args: &[String],
+    ) -> Result<(String, HashMethod)> {
+        let mut hasher = self.hasher();
+
+        let repo_info = self.get_git_repo_info(path);
+
+        let effective_path = if let Some(ref info) = repo_info {
+            if info.is_worktree {
+                if let Some(ref worktree_root) = info.worktree_root {
+                    worktree_root.as_path()
+                } else {
+                    path
+                }
+            } else {
+                path
+            }
+        } else {
+            path
+        };
+
+        // Git prints paths relative to where it runs; running it at the
+        // repository root keeps hashes independent of the process cwd
+        let git_root = run_git(
+            self.runner.as_ref(),
+            &["rev-parse", "--show-toplevel"],
+            Some(effective_path),
+        )
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| PathBuf::from(String::from_utf8_lossy(&o.stdout).trim()))
+        .unwrap_or_else(|| effective_path.to_path_buf());
+
+        // Try to use git for fast tree hashing
+        let effective_path_str = effective_path.to_string_lossy();
+        let tree = self.git_ref.as_deref().unwrap_or("HEAD");
+        if let Ok(output) = run_git(
+            self.runner.as_ref(),
+            &["ls-tree", "-r", tree, &effective_path_str],
+            Some(&git_root),
+        ) {
+            if output.status.success() && !output.stdout.is_empty() {
+                hasher.update(&output.stdout);
+
+                // Include uncommitted changes
+                if self.committed_only || self.git_ref.is_some() {
+                    log_debug!("Ignoring uncommitted changes in {}", effective_path_str);
+                } else if let Ok(status_output) = run_git(
+                    self.runner.as_ref(),
+                    &["status", "--porcelain", &effective_path_str],
+                    Some(&git_root),
+                ) {
+                    if status_output.status.success() && !status_output.stdout.is_empty() {
+                        hasher.update(&status_output.stdout);
+
+                        let status_str = String::from_utf8_lossy(&status_output.stdout);
+                        for line in status_str.lines() {
+                            if line.len() > 3 {
+                                let file_path = &line[3..];
+                                let full_path = git_root.join(file_path);
+                                if full_path.exists() && full_path.is_file() {
+                                    self.hash_file_with_lfs_support(
+                                        &full_path,
+                                        &repo_info,
+                                        &mut hasher,
+                                    )?;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                // Include submodule status
+                if let Some(submodule_status) = self.get_submodule_status(effective_path) {
+                    if !submodule_status.is_empty() {
+                        hasher.update(b"SUBMODULES:");
+                        hasher.update(&submodule_status);
+                    }
+                }
+
+                // Include sparse checkout patterns
+                if let Some(ref info) = repo_info {
+                    if info.is_sparse {
+                        if let Some(patterns) = self.get_sparse_checkout_patterns(info) {
+                            hasher.update(b"SPARSE:");
+                            for pattern in patterns {
+                                hasher.update(pattern.as_bytes());
+                            }
+                        }
+                    }
+                }
+
+                // Include shallow clone info
+                if let Some(ref info) = repo_info {
+                    if info.is_shallow {
+                        hasher.update(b"SHALLOW_CLONE");
+                        let shallow_file = info.git_dir.join("shallow");
+                        if let Ok(content) = fs::read(&shallow_file) {
+                            hasher.update(&content);
+                        }
+                    }
+                }
+
+                return Ok((hasher.finalize().to_hex().to_string(), HashMethod::Git));
+            }
+        }
+
+        // The working tree is no stand-in for the sources at a ref
+        if let Some(git_ref) = &self.git_ref {
+            anyhow::bail!(
+                "{} has no files at {} in git; cannot hash it at that ref",
+                effective_path.display(),
+                git_ref
+            );
+        }
+
+        // Fallback to file-based hashing
+        static GIT_WARNING_SHOWN: std::sync::atomic::AtomicBool =
+            std::sync::atomic::AtomicBool::new(false);
+        if !GIT_WARNING_SHOWN.swap(true, std::sync::atomic::Ordering::Relaxed) {
+            log_error!("Warning: Git not available or not in a git repository. Using file-based hashing (less accurate).");
+        }
+
+        // Never hash the cache itself should it live inside the package
+        let cache_dir = canonicalize_or_raw(&self.cache_dir);
+        // Symlinked sources (`src/shared -> ../../shared`) are hashed by
+        // content as long as they stay inside the repository. WalkDir skips
+        // links that lead back to a directory being walked, so cycles end
+        let repo_root = fallback_repo_root(path);
+        let walk = WalkDir::new(path)
+            .follow_links(true)
+            .max_depth(self.hash_walk.max_depth)
+            .into_iter()
+            .filter_entry(|entry| {
+                if entry.path_is_symlink()
+                    && !canonicalize_or_raw(entry.path()).starts_with(&repo_root)
+                {
+                    return false;
+                }
+                if entry.depth() == 0 || !entry.file_type().is_dir() {
+                    return true;
+                }
+                let name = entry.file_name().to_string_lossy();
+                !self.hash_walk.exclude.iter().any(|ex| *ex == name)
+                    && canonicalize_or_raw(entry.path()) != cache_dir
+            });
+
+        // Traversal order depends on the filesystem, so hash in path order
+        let mut files: Vec<PathBuf> = walk
+            .filter_map(|e| e.ok())
+            .filter(|entry| {
+                entry.file_type().is_file()
+                    && self
+                        .hash_walk
+                        .includes_file(&entry.file_name().to_string_lossy())
+            })
+            .map(|entry| entry.into_path())
+            .collect();
+        files.sort();
+
+        for file in files {
+            if let Ok(content) = fs::read(&file) {
+                // Relative paths keep the hash independent of where the
+                // package is checked out
+                let relative = file.strip_prefix(path).unwrap_or(&file);
+                hasher.update(relative.to_string_lossy().replace('\\', "/").as_bytes());
+                hasher.update(&content);
+            }
+        }
+
+        Ok((
+            hasher.finalize().to_hex().to_string(),
+            HashMethod::FileFallback,
+        ))
+    }
+
+    /// Helper function to hash a file, handling LFS files specially.
+    fn hash_file_with_lfs_support(
+        &self,
+        path: &Path,
+        repo_info: &Option<GitRepoInfo>,
+        hasher: &mut Blake3Hasher,
+    ) -> Result<()> {
+        if let Some(ref info) = repo_info {
+            if self.is_lfs_file(path, info) {
+                if let Some(oid) = self.get_lfs_pointer_hash(path) {
+                    hasher.update(b"LFS:");
+                    hasher.update(oid.as_bytes());
+                    return Ok(());
+                }
+            }
+        }
+
+        if let Ok(content) = fs::read(path) {
+            hasher.update(path.to_string_lossy().as_bytes());
+            hasher.update(&content);
+        }
+
+        Ok(())
+    }
+
+    /// Gets the status of git submodules.
+    fn get_submodule_status(&self, path: &Path) -> Option<Vec<u8>> {
+        let output = run_git(self.runner.as_ref(), &["submodule", "status"], Some(path)).ok()?;
+
+        if output.status.success() {
+            Some(output.stdout)
+        } else {
+            None
+        }
+    }
+
+    /// Gets sparse checkout patterns from the git repository.
+    fn get_sparse_checkout_patterns(&self, repo_info: &GitRepoInfo) -> Option<Vec<String>> {
+        let sparse_file = repo_info.git_dir.join("info/sparse-checkout");
+        if sparse_file.exists() {
+            fs::read_to_string(&sparse_file).ok().map(|content| {
+                content
+                    .lines()
+                    .map(|l| l.trim().to_string())
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .collect()
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Computes a hash for a single package.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the package manifest directory cannot be determined
+    /// or if source hashing fails.
+    pub fn compute_package_hash(
+        &self,
+        package: &Package,
+        metadata: &Metadata,
+        args: &[String],
+    ) -> Result<PackageHash> {
+        let features = workspace_features(metadata, args)
+            .remove(package.name.as_str())
+            .unwrap_or_else(|| effective_features(package, args));
+        self.hash_package(package, metadata, args, &features)
+    }
+
+    /// Same as [`compute_package_hash`](Self::compute_package_hash), with the
+    /// package's unified `features` already resolved.
+    fn hash_package(
+        &self,
+        package: &Package,
+        metadata: &Metadata,
+        args: &[String],
+        features: &BTreeSet<String>,
+    ) -> Result<PackageHash> {
+        let manifest_dir = package
+            .manifest_path
+            .parent()
+            .context("No manifest directory")?;
+
+        let (mut source_hash, mut hash_method) =
+            self.compute_source_hash_with_method(manifest_dir.as_std_path(), args)?;
+        let features_hash = self.features_set_hash(features);
+
+        let members: HashSet<&str> = metadata
+            .workspace_packages()
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+
+        // Path dependencies outside the workspace are not tracked as packages
+        // of their own, so their sources become part of this package's hash
+        let external = self.external_path_dependencies(package, metadata);
+        if !external.is_empty() {
+            let mut hasher = self.hasher();
+            hasher.update(source_hash.as_bytes());
+            for dir in &external {
+                hasher.update(b"\0");
+                hasher.update(dir.to_string_lossy().as_bytes());
+                let (hash, method) = self.compute_source_hash_with_method(dir, args)?;
+                hasher.update(hash.as_bytes());
+                if method == HashMethod::FileFallback {
+                    hash_method = method;
+                }
+            }
+            source_hash = hasher.finalize().to_hex().to_string();
+        }
+
+        // Every kind counts: a changed proc-macro or build-dependency changes
+        // the dependent's output just like a normal dependency
+        let mut dependencies = Vec::new();
+        let mut dependency_kinds: BTreeMap<String, Vec<DependencyKind>> = BTreeMap::new();
+        for dep in &package.dependencies {
+            if !members.contains(dep.name.as_str()) {
+                continue;
+            }
+            let kind = match dep.kind {
+                cargo_metadata::DependencyKind::Build => DependencyKind::Build,
+                cargo_metadata::DependencyKind::Development => DependencyKind::Dev,
+                _ => DependencyKind::Normal,
+            };
+            let kinds = dependency_kinds.entry(dep.name.clone()).or_default();
+            if kinds.is_empty() {
+                dependencies.push(dep.name.clone());
+            }
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+
+        // `[lib] name` may differ from the package name entirely
+        let lib_name = package
+            .targets
+            .iter()
+            .find(|t| {
+                t.kind.iter().any(|k| {
+                    matches!(
+                        k.as_str(),
+                        "lib" | "rlib" | "dylib" | "cdylib" | "staticlib" | "proc-macro"
+                    )
+                })
+            })
+            .map(|t| t.name.replace('-', "_"));
+
+        Ok(PackageHash {
+            name: package.name.clone(),
+            version: package.version.to_string(),
+            path: manifest_dir.as_std_path().to_path_buf(),
+            source_hash,
+            dependencies,
+            features_hash,
+            dependency_kinds,
+            lib_name,
+            hash_method,
+        })
+    }
+
+    /// Returns the directories of the path dependencies of `package` that are
+    /// not workspace members, including their own path dependencies, sorted.
+    ///
+    /// These are typically sibling crates pulled in with `path = "../shared"`.
+    fn external_path_dependencies(&self, package: &Package, metadata: &Metadata) -> Vec<PathBuf> {
+        let members: HashSet<&cargo_metadata::PackageId> =
+            metadata.workspace_members.iter().collect();
+        let by_dir: HashMap<&Path, &Package> = metadata
+            .packages
+            .iter()
+            .filter_map(|p| Some((p.manifest_path.parent()?.as_std_path(), p)))
+            .collect();
+
+        let mut found = BTreeSet::new();
+        let mut queue = vec![package];
+        while let Some(current) = queue.pop() {
+            for dep in &current.dependencies {
+                let Some(path) = dep.path.as_ref().map(|p| p.as_std_path()) else {
+                    continue;
+                };
+                let Some(dep_package) = by_dir.get(path) else {
+                    continue;
+                };
+                if members.contains(&dep_package.id) || !path.is_dir() {
+                    continue;
+                }
+                if found.insert(path.to_path_buf()) {
+                    queue.push(dep_package);
+                }
+            }
+        }
+        found.into_iter().collect()
+    }
+
+    /// Computes the current state of the entire workspace.
+    ///
+    /// This is the main entry point for determining what needs to be built.
+    /// It computes hashes for all packages, the Cargo.lock file, and the toolchain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if cargo metadata cannot be retrieved or if hashing fails.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cargo_save::CacheManager;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let cache = CacheManager::new()?;
+    /// let workspace = cache.compute_workspace_state(&[])?;
+    ///
+    /// println!("Workspace has {} packages", workspace.packages.len());
+    /// for pkg in &workspace.packages {
+    ///     println!("  - {}", pkg.name);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn compute_workspace_state(&self, args: &[String]) -> CacheResult<WorkspaceState> {
+        self.compute_workspace_state_with_progress(args, |_, _| {})
+    }
+
+    /// Computes the workspace state, reporting progress while hashing packages.
+    ///
+    /// `on_progress` is called once per workspace package with the number of
+    /// packages hashed so far and the total. Packages are hashed in parallel,
+    /// so the callback may run on any thread.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`CacheManager::compute_workspace_state`].
+    pub fn compute_workspace_state_with_progress(
+        &self,
+        args: &[String],
+        on_progress: impl Fn(usize, usize) + Sync,
+    ) -> CacheResult<WorkspaceState> {
+        // Resolve with the build's network flags so `--locked`/`--frozen` builds
+        // never update Cargo.lock and `--offline` builds never hit the network
+        let mut command = MetadataCommand::new();
+        command
+            .cargo_path(cargo_bin())
+            .other_options(NetworkMode::from_args(args).cargo_flags());
+        if let Some(manifest_path) = manifest_path_arg(args).or_else(|| self.manifest_path.clone())
+        {
+            command.manifest_path(manifest_path);
+        }
+        let phase = std::time::Instant::now();
+        let metadata = command.exec()?;
+        self.record_phase(phase, |t| &mut t.metadata);
+        let root: PathBuf = metadata.workspace_root.clone().into();
+        if let Some(git_ref) = &self.git_ref {
+            let commit = self.resolve_git_ref(&root, git_ref)?;
+            log_debug!("Hashing sources at {} ({})", git_ref, commit);
+        }
+
+        let phase = std::time::Instant::now();
+        let workspace_packages = metadata.workspace_packages();
+        let total = workspace_packages.len();
+        let fingerprint = self.workspace_fingerprint(&root, &metadata, args);
+        let memo = fingerprint
+            .as_deref()
+            .and_then(|fingerprint| self.load_workspace_memo(&root, fingerprint));
+        let packages: Vec<PackageHash> = if let Some(packages) = memo {
+            log_debug!("Workspace unchanged, reusing package hashes");
+            on_progress(total, total);
+            packages
+        } else {
+            let hashed = std::sync::atomic::AtomicUsize::new(0);
+            let features = workspace_features(&metadata, args);
+            let empty = BTreeSet::new();
+            let packages: Vec<PackageHash> = workspace_packages
+                .par_iter()
+                .filter_map(|package| {
+                    let package_features = features.get(package.name.as_str()).unwrap_or(&empty);
+                    let hash = self
+                        .hash_package(package, &metadata, args, package_features)
+                        .ok();
+                    let done = hashed.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+                    on_progress(done, total);
+                    hash
+                })
+                .collect();
+            if let Some(fingerprint) = &fingerprint {
+                self.save_workspace_memo(&root, fingerprint, &packages);
+            }
+            packages
+        };
+
+        let cargo_lock_hash = self.compute_dependency_lock_hash(&root, &metadata)?;
+        let toolchain_hash = self.compute_toolchain_hash()?;
+
+        let git_features = self.get_git_repo_info(&root).map(|info| {
+            let has_submodules = self
+                .get_submodule_status(&root)
+                .map(|s| !s.is_empty())
+                .unwrap_or(false);
+
+            GitFeaturesInfo {
+                has_submodules,
+                is_sparse: info.is_sparse,
+                is_worktree: info.is_worktree,
+                has_lfs: info.has_lfs,
+                is_shallow: info.is_shallow,
+            }
+        });
+        self.record_phase(phase, |t| &mut t.source_hashing);
+
+        Ok(WorkspaceState {
+            root,
+            packages,
+            cargo_lock_hash,
+            toolchain_hash,
+            timestamp: chrono::Local::now().to_rfc3339(),
+            git_features,
+            default_members: default_build_members(&metadata),
+        })
+    }
+
+    /// Returns a cheap fingerprint of everything the package hashes of the
+    /// workspace at `root` depend on: the git `HEAD`, the output of
+    /// `git status` for the whole repository with the size and modification
+    /// time of each file it lists, and the options of the hash.
+    ///
+    /// `None` outside git, and when a package has path dependencies outside
+    /// the workspace, whose sources the repository status may not cover.
+    fn workspace_fingerprint(
+        &self,
+        root: &Path,
+        metadata: &Metadata,
+        args: &[String],
+    ) -> Option<String> {
+        let has_external = metadata.workspace_packages().iter().any(|package| {
+            !self
+                .external_path_dependencies(package, metadata)
+                .is_empty()
+        });
+        if has_external {
+            return None;
+        }
+
+        let git = |args: &[&str]| {
+            run_git(self.runner.as_ref(), args, Some(root))
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| output.stdout)
+        };
+        let tree = match &self.git_ref {
+            Some(git_ref) => format!("{}^{{commit}}", git_ref),
+            None => "HEAD".to_string(),
+        };
+        let head = git(&["rev-parse", "--show-toplevel", &tree])?;
+        let status = git(&["status", "--porcelain", "-z", "--untracked-files=all"])?;
+        let toplevel = String::from_utf8_lossy(&head)
+            .lines()
+            .next()
+            .map(PathBuf::from)?;
+
+        let mut hasher = self.hasher();
+        hasher.update(CACHE_VERSION.as_bytes());
+        hasher.update(&head);
+        hasher.update(&status);
+        hasher.update(self.compute_features_hash(args).as_bytes());
+        hasher.update(&[u8::from(self.committed_only)]);
+        hasher.update(self.git_ref.as_deref().unwrap_or_default().as_bytes());
+
+        // `-z` entries are `XY path`, renames and copies followed by their source
+        let mut entries = status.split(|b| *b == 0).filter(|e| !e.is_empty());
+        while let Some(entry) = entries.next() {
+            let entry = String::from_utf8_lossy(entry);
+            if entry.starts_with('R') || entry.starts_with('C') {
+                entries.next();
+            }
+            let Some(path) = entry.get(3..) else {
+                continue;
+            };
+            // Edits to an already modified file only show in its metadata
+            match fs::metadata(toplevel.join(path)) {
+                Ok(metadata) => {
+                    hasher.update(&metadata.len().to_le_bytes());
+                    if let Ok(since_epoch) = metadata
+                        .modified()
+                        .map(|t| t.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default())
+                    {
+                        hasher.update(&since_epoch.as_nanos().to_le_bytes());
+                    }
+                }
+                Err(_) => {
+                    hasher.update(b"missing");
+                }
+            }
+        }
+
+        Some(hasher.finalize().to_hex().to_string())
+    }
+
+    /// Returns the package hashes memoized for the workspace at `root` if
+    /// they were computed with `fingerprint`.
+    fn load_workspace_memo(&self, root: &Path, fingerprint: &str) -> Option<Vec<PackageHash>> {
+        let content = fs::read_to_string(self.metadata_dir.join(WORKSPACE_MEMO_FILE)).ok()?;
+        let mut memos: BTreeMap<String, WorkspaceMemo> = serde_json::from_str(&content).ok()?;
+        memos
+            .remove(&root.to_string_lossy().into_owned())
+            .filter(|memo| memo.fingerprint == fingerprint)
+            .map(|memo| memo.packages)
+    }
+
+    /// Memoizes the package hashes of the workspace at `root`, replacing
+    /// what was stored for it before.
+    fn save_workspace_memo(&self, root: &Path, fingerprint: &str, packages: &[PackageHash]) {
+        let file = self.metadata_dir.join(WORKSPACE_MEMO_FILE);
+        let Ok(_lock) = self.lock("workspace-memo") else {
+            return;
+        };
+        let mut memos: BTreeMap<String, WorkspaceMemo> = fs::read_to_string(&file)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        memos.insert(
+            root.to_string_lossy().into_owned(),
+            WorkspaceMemo {
+                fingerprint: fingerprint.to_string(),
+                packages: packages.to_vec(),
+            },
+        );
+        if let Ok(content) = serde_json::to_string(&memos) {
+            if let Err(e) = fs::write(&file, content) {
+                log_debug!("Failed to memoize workspace state: {}", e);
+            }
+        }
+    }
+
+    /// Computes the workspace state, showing a progress bar on stderr.
+    ///
+    /// The bar is only drawn when stderr is a terminal and messages are not
+    /// silenced with `--quiet`; otherwise this is
+    /// [`CacheManager::compute_workspace_state`].
+    ///
+    /// # Errors
+    ///
+    /// Same as [`CacheManager::compute_workspace_state`].
+    pub fn compute_workspace_state_with_progress_bar(
+        &self,
+        args: &[String],
+    ) -> CacheResult<WorkspaceState> {
+        use std::io::IsTerminal;
+
+        if !io::stderr().is_terminal() || log_level() < LogLevel::Info {
+            return self.compute_workspace_state(args);
+        }
+
+        let bar = indicatif::ProgressBar::new(0);
+        if let Ok(style) = indicatif::ProgressStyle::with_template(
+            "[cargo-save] Hashing packages [{bar:30}] {pos}/{len}",
+        ) {
+            bar.set_style(style.progress_chars("=> "));
+        }
+        let result = self.compute_workspace_state_with_progress(args, |done, total| {
+            bar.set_length(total as u64);
+            bar.set_position(done as u64);
+        });
+        bar.finish_and_clear();
+        result
+    }
+
+    /// Builds a dependency graph from the workspace state.
+    ///
+    /// This graph is used to determine transitive dependencies - when a package
+    /// changes, all packages that depend on it also need to be rebuilt.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cargo_save::CacheManager;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let cache = CacheManager::new()?;
+    /// let workspace = cache.compute_workspace_state(&[])?;
+    /// let graph = cache.build_dependency_graph(&workspace);
+    ///
+    /// if let Some(node) = graph.packages.get("my-package") {
+    ///     println!("Has {} dependencies", node.dependencies.len());
+    ///     println!("Has {} reverse dependencies", node.reverse_dependencies.len());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn build_dependency_graph(&self, workspace_state: &WorkspaceState) -> DependencyGraph {
+        let mut reverse_deps: HashMap<&str, Vec<String>> = HashMap::new();
+        for package in &workspace_state.packages {
+            for dep in &package.dependencies {
+                let dependents = reverse_deps.entry(dep.as_str()).or_default();
+                if dependents.last() != Some(&package.name) {
+                    dependents.push(package.name.clone());
+                }
+            }
+        }
+
+        let packages = workspace_state
+            .packages
+            .iter()
+            .map(|package| {
+                let node = PackageNode {
+                    name: package.name.clone(),
+                    dependencies: package.dependencies.clone(),
+                    reverse_dependencies: reverse_deps
+                        .remove(package.name.as_str())
+                        .unwrap_or_default(),
+                };
+                (package.name.clone(), node)
+            })
+            .collect();
+
+        DependencyGraph { packages }
+    }
+
+    /// Renders the workspace dependency graph in Graphviz DOT.
+    ///
+    /// Packages a `cargo build` would rebuild are highlighted. See
+    /// [`DependencyGraph::to_dot`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workspace state cannot be computed.
+    pub fn dependency_graph_dot(&self) -> Result<String> {
+        let (graph, changed) = self.dependency_graph_with_changes()?;
+        Ok(graph.to_dot(&changed))
+    }
+
+    /// Returns the workspace dependency graph as JSON, marking the packages
+    /// a `cargo build` would rebuild. See [`DependencyGraph::to_json`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workspace state cannot be computed.
+    pub fn dependency_graph_json(&self) -> Result<serde_json::Value> {
+        let (graph, changed) = self.dependency_graph_with_changes()?;
+        Ok(graph.to_json(&changed))
+    }
+
+    /// Builds the dependency graph of the current workspace along with the
+    /// names of the packages a `cargo build` would rebuild.
+    fn dependency_graph_with_changes(&self) -> Result<(DependencyGraph, Vec<String>)> {
+        let workspace = self.compute_workspace_state(&[])?;
+        let plan = self.plan("build", &[], &workspace)?;
+        let changed = plan.changed_packages.into_iter().map(|p| p.name).collect();
+        Ok((self.build_dependency_graph(&workspace), changed))
+    }
+
+    /// Computes a hash for a cargo command.
+    ///
+    /// This includes the subcommand and arguments, but not the working
+    /// directory: a build started from a subdirectory of the workspace is the
+    /// same build. Cache entries are scoped to the workspace root instead.
+    /// Flags are sorted with their values before hashing, so reordered flags
+    /// and flags in [`IGNORED_COMMAND_FLAGS`] do not change the hash.
+    /// `--offline`, `--locked` and `--frozen` are folded into a
+    /// [`NetworkMode`] so their spelling does not matter either.
+    /// Inline `--config key=value` overrides are compared by their parsed
+    /// value and keep their order, since the last one wins; the content of
+    /// `--config <file>` files is hashed as well.
+    /// For `run` and `bench`, arguments after `--` go to the program and are
+    /// left out, so `run -- --foo` and `run -- --bar` share cache entries.
+    pub fn compute_command_hash(&self, subcommand: &str, args: &[String]) -> String {
+        let mut hasher = self.hasher();
+        hasher.update(subcommand.as_bytes());
+
+        // The program's own arguments to `run` and `bench` do not change the
+        // build. Test harness arguments stay: they select the tests that ran
+        let separator = args
+            .iter()
+            .position(|arg| arg == "--")
+            .unwrap_or(args.len());
+        let args = match subcommand {
+            "run" | "bench" => &args[..separator],
+            _ => args,
+        };
+        let network = NetworkMode::from_args(args);
+        hasher.update(canonical_command_args(args).join(" ").as_bytes());
+        if network != NetworkMode::default() {
+            hasher.update(b"\0");
+            hasher.update(network.cargo_flags().join(" ").as_bytes());
+        }
+        for path in config_file_args(args) {
+            hasher.update(b"\0");
+            hasher.update(&fs::read(&path).unwrap_or_default());
+        }
+
+        hasher.finalize().to_hex()[..HASH_KEY_LEN].to_string()
+    }
+
+    /// Checks if the arguments indicate a release build.
+    pub fn is_release_build(&self, args: &[String]) -> bool {
+        args.iter()
+            .any(|arg| arg == "--release" || arg.starts_with("--release"))
+    }
+
+    /// Gets the target directory from arguments or environment.
+    pub fn get_target_dir(&self, args: &[String]) -> Option<PathBuf> {
+        for (i, arg) in args.iter().enumerate() {
+            if arg == "--target-dir" {
+                return args.get(i + 1).map(PathBuf::from);
+            }
+            if arg.starts_with("--target-dir=") {
+                return arg.split('=').nth(1).map(PathBuf::from);
+            }
+        }
+
+        if let Ok(target_dir) = std::env::var("CARGO_TARGET_DIR") {
+            return Some(PathBuf::from(target_dir));
+        }
+
+        None
+    }
+
+    /// Gets the target triples a build compiles for, sorted and deduplicated.
+    ///
+    /// Reads every `--target` argument, falling back to `CARGO_BUILD_TARGET`.
+    /// Custom target specs (`--target path/to/spec.json`) are named by their
+    /// file stem, as in cargo's output directory. Empty means the host.
+    pub fn get_target_triples(&self, args: &[String]) -> Vec<String> {
+        let cargo_args: Vec<&String> = args.iter().take_while(|arg| *arg != "--").collect();
+        let mut triples: Vec<String> = cargo_args
+            .iter()
+            .enumerate()
+            .filter_map(|(i, arg)| match arg.strip_prefix("--target=") {
+                Some(triple) => Some(triple.to_string()),
+                None if *arg == "--target" => cargo_args.get(i + 1).map(|t| t.to_string()),
+                None => None,
+            })
+            .collect();
+
+        if triples.is_empty() {
+            if let Ok(triple) = std::env::var("CARGO_BUILD_TARGET") {
+                triples.push(triple);
+            }
+        }
+
+        let mut triples: Vec<String> = triples
+            .into_iter()
+            .filter(|triple| !triple.is_empty())
+            .map(|triple| match triple.strip_suffix(".json") {
+                Some(_) => Path::new(&triple)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or(triple),
+                None => triple,
+            })
+            .collect();
+        triples.sort();
+        triples.dedup();
+        triples
+    }
+
+    /// Generates a cache key for a package build.
+    ///
+    /// Keys are scoped to the workspace root, since entries record absolute
+    /// target paths of that workspace. Cross builds append their target
+    /// triples; host builds keep the plain key.
+    #[allow(clippy::too_many_arguments)]
+    fn get_cache_key(
+        &self,
+        package: &PackageHash,
+        workspace_root: &Path,
+        command_hash: &str,
+        env_hash: &str,
+        is_release: bool,
+        targets: &[String],
+    ) -> String {
+        let workspace_hash = blake3::hash(workspace_root.to_string_lossy().as_bytes()).to_hex();
+        let mut key = format!(
+            "{}-{}-{}-{}-{}-{}-{}",
+            package.name,
+            &package.source_hash[..HASH_KEY_LEN],
+            command_hash,
+            &workspace_hash[..8],
+            env_hash,
+            if is_release { "release" } else { "debug" },
+            package.features_hash
+        );
+        if !targets.is_empty() {
+            key.push('-');
+            key.push_str(&targets.join("+"));
+        }
+        file_safe_key(&key)
+    }
+
+    /// Returns the key of the incremental cache entry `cargo <subcommand>
+    /// <args>` uses for `package`, the file name of the entry in
+    /// `incremental_dir` without `.json`.
+    ///
+    /// Returns `None` if `package` is not in the workspace.
+    pub fn incremental_cache_key(
+        &self,
+        package: &str,
+        subcommand: &str,
+        args: &[String],
+        workspace_state: &WorkspaceState,
+    ) -> Option<String> {
+        let package = workspace_state
+            .packages
+            .iter()
+            .find(|p| p.name == package)?;
+        Some(self.get_cache_key(
+            package,
+            &workspace_state.root,
+            &self.compute_command_hash(subcommand, args),
+            &self.compute_env_hash(),
+            self.is_release_build(args),
+            &self.get_target_triples(args),
+        ))
+    }
+
+    /// Checks if a valid incremental cache exists for a package.
+    ///
+    /// Returns `Some(IncrementalCache)` if a valid cache is found, `None` otherwise.
+    /// A cache is valid if:
+    /// - The Cargo.lock hash matches
+    /// - The environment hash matches
+    /// - The features hash matches
+    /// - The toolchain hash matches, if the entry recorded one
+    /// - The source hash matches
+    /// - All target files exist with correct sizes
+    pub fn check_incremental_cache(
+        &self,
+        package: &PackageHash,
+        workspace_state: &WorkspaceState,
+        command_hash: &str,
+        env_hash: &str,
+        is_release: bool,
+        args: &[String],
+    ) -> Option<IncrementalCache> {
+        let cache_key = self.get_cache_key(
+            package,
+            &workspace_state.root,
+            command_hash,
+            env_hash,
+            is_release,
+            &self.get_target_triples(args),
+        );
+
+        let cache_file = self.incremental_dir.join(format!("{}.json", cache_key));
+
+        if cache_file.exists() {
+            if let Ok(content) = fs::read_to_string(&cache_file) {
+                if let Ok(cache) = serde_json::from_str::<IncrementalCache>(&content) {
+                    // Check all invalidation conditions
+                    if cache.cargo_lock_hash != workspace_state.cargo_lock_hash {
+                        return None;
+                    }
+
+                    if cache.env_hash != env_hash {
+                        return None;
+                    }
+
+                    if cache.features_hash != package.features_hash {
+                        return None;
+                    }
+
+                    if cache
+                        .toolchain_hash
+                        .as_ref()
+                        .is_some_and(|hash| *hash != workspace_state.toolchain_hash)
+                    {
+                        return None;
+                    }
+
+                    let all_valid = self.missing_target_files(&cache).is_empty();
+
+                    if cache.source_hash != package.source_hash {
+                        return None;
+                    }
+
+                    if all_valid && cache.build_success {
+                        return Some(cache);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the target files of `cache` that are missing or changed and
+    /// cannot be restored from the object store.
+    fn missing_target_files(&self, cache: &IncrementalCache) -> Vec<PathBuf> {
+        let stored: HashMap<&PathBuf, &String> =
+            cache.objects.iter().map(|(p, h)| (p, h)).collect();
+        cache
+            .target_files
+            .iter()
+            .filter(|(path, expected_size)| match fs::metadata(path) {
+                Ok(metadata) if metadata.len() == *expected_size => false,
+                // Missing or changed files can be restored from the object store
+                _ => !stored
+                    .get(path)
+                    .is_some_and(|hash| self.objects_dir.join(hash).is_file()),
+            })
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Explains why `package` is rebuilt by comparing the current state with
+    /// its most recent incremental cache entry in this workspace.
+    ///
+    /// `args` are the cargo arguments of the build, which select the profile
+    /// and features. Entries of the same profile are preferred over newer
+    /// ones of the other profile. The command itself is not compared: entries
+    /// of other commands are kept side by side rather than invalidated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `package` is not a workspace member or the
+    /// incremental cache directory cannot be read.
+    pub fn why_rebuild(
+        &self,
+        package: &str,
+        workspace_state: &WorkspaceState,
+        args: &[String],
+    ) -> Result<RebuildReason> {
+        self.why_rebuild_for(package, workspace_state, None, args)
+    }
+
+    /// Same as [`why_rebuild`](Self::why_rebuild) for a `cargo <subcommand>`
+    /// build: entries written by `subcommand` with the same profile are
+    /// preferred, then entries of the same profile, before falling back to
+    /// the newest entry.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`why_rebuild`](Self::why_rebuild).
+    pub fn why_rebuild_for(
+        &self,
+        package: &str,
+        workspace_state: &WorkspaceState,
+        subcommand: Option<&str>,
+        args: &[String],
+    ) -> Result<RebuildReason> {
+        let Some(current) = workspace_state.packages.iter().find(|p| p.name == package) else {
+            anyhow::bail!("{} is not a member of this workspace", package);
+        };
+        let root = canonicalize_or_raw(&workspace_state.root);
+        let is_release = self.is_release_build(args);
+
+        let latest = fs::read_dir(&self.incremental_dir)?
+            .flatten()
+            .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+            .filter_map(|content| serde_json::from_str::<IncrementalCache>(&content).ok())
+            .filter(|cache| {
+                cache.package_name == package
+                    && cache
+                        .workspace_root
+                        .as_deref()
+                        .map_or(true, |r| canonicalize_or_raw(r) == root)
+            })
+            .max_by_key(|cache| {
+                (
+                    cache.is_release == is_release,
+                    subcommand.is_some() && cache.subcommand.as_deref() == subcommand,
+                    chrono::DateTime::parse_from_rfc3339(&cache.timestamp).ok(),
+                )
+            });
+
+        let Some(cache) = latest else {
+            return Ok(RebuildReason {
+                package: package.to_string(),
+                entry_timestamp: None,
+                factors: Vec::new(),
+                missing_files: Vec::new(),
+            });
+        };
+
+        let mut factors = Vec::new();
+        if cache.source_hash != current.source_hash {
+            factors.push(RebuildFactor::Source);
+        }
+        if cache.env_hash != self.compute_env_hash() {
+            factors.push(RebuildFactor::Environment);
+        }
+        if cache.features_hash != current.features_hash {
+            factors.push(RebuildFactor::Features);
+        }
+        if cache.cargo_lock_hash != workspace_state.cargo_lock_hash {
+            factors.push(RebuildFactor::CargoLock);
+        }
+        if cache
+            .toolchain_hash
+            .as_ref()
+            .is_some_and(|hash| *hash != workspace_state.toolchain_hash)
         {
-            if output.status.success() && !output.stdout.is_empty() {
-                hasher.update(&output.stdout);
+            factors.push(RebuildFactor::Toolchain);
+        }
+        if cache.is_release != is_release {
+            factors.push(RebuildFactor::Profile);
+        }
+        let missing_files = self.missing_target_files(&cache);
+        if !missing_files.is_empty() {
+            factors.push(RebuildFactor::MissingTargetFiles);
+        }
+        if !cache.build_success {
+            factors.push(RebuildFactor::BuildFailed);
+        }
+
+        Ok(RebuildReason {
+            package: package.to_string(),
+            entry_timestamp: Some(cache.timestamp),
+            factors,
+            missing_files,
+        })
+    }
+
+    /// Explains why each package a `cargo build` would rebuild is not cached,
+    /// keyed by package name, as shown by `status --explain`.
+    ///
+    /// Each package is compared with its most recent entry, preferring
+    /// entries of debug `build`s, as in [`why_rebuild_for`](Self::why_rebuild_for).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the incremental cache directory cannot be read.
+    pub fn explain_status(
+        &self,
+        workspace_state: &WorkspaceState,
+    ) -> Result<BTreeMap<String, String>> {
+        let plan = self.plan("build", &[], workspace_state)?;
+        self.explain_plan(&plan, workspace_state)
+    }
+
+    /// Explains the changed packages of `plan` for [`explain_status`](Self::explain_status).
+    fn explain_plan(
+        &self,
+        plan: &BuildPlan,
+        workspace_state: &WorkspaceState,
+    ) -> Result<BTreeMap<String, String>> {
+        let changed: HashSet<&str> = plan
+            .changed_packages
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        let mut reasons = BTreeMap::new();
+        for package in &plan.changed_packages {
+            let reason =
+                self.why_rebuild_for(&package.name, workspace_state, Some("build"), &[])?;
+            let summary = if reason.entry_timestamp.is_none() {
+                "no prior entry".to_string()
+            } else if !reason.factors.is_empty() {
+                reason.describe_factors()
+            } else if package
+                .dependencies
+                .iter()
+                .any(|dep| changed.contains(dep.as_str()))
+            {
+                "dependency changed".to_string()
+            } else {
+                "no entry for this command".to_string()
+            };
+            reasons.insert(package.name.clone(), summary);
+        }
+        Ok(reasons)
+    }
+
+    /// Counts a cache hit on the entries of `packages`, which a build is
+    /// about to skip.
+    ///
+    /// Entries that disappeared in the meantime are ignored.
+    fn record_cache_hits(
+        &self,
+        plan: &BuildPlan,
+        workspace_state: &WorkspaceState,
+        subcommand: &str,
+        args: &[String],
+        env_hash: &str,
+    ) {
+        let command_hash = self.compute_command_hash(subcommand, args);
+        let is_release = self.is_release_build(args);
+        let targets = self.get_target_triples(args);
+
+        for package in plan.cached_packages(workspace_state) {
+            let cache_key = self.get_cache_key(
+                package,
+                &workspace_state.root,
+                &command_hash,
+                env_hash,
+                is_release,
+                &targets,
+            );
+            let cache_file = self.incremental_dir.join(format!("{}.json", cache_key));
+            let Ok(_lock) = self.lock(&cache_key) else {
+                continue;
+            };
+            let Some(mut cache) = fs::read_to_string(&cache_file)
+                .ok()
+                .and_then(|c| serde_json::from_str::<IncrementalCache>(&c).ok())
+            else {
+                continue;
+            };
+            cache.hits += 1;
+            if let Ok(content) = serde_json::to_string_pretty(&cache) {
+                let _ = fs::write(&cache_file, content);
+            }
+        }
+
+        let record = HitRecord {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            subcommand: subcommand.to_string(),
+            cached: plan.cached_count,
+            total: plan.total_packages,
+        };
+        if let Ok(mut line) = serde_json::to_string(&record) {
+            line.push('\n');
+            let _ = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.hit_log_file())
+                .and_then(|mut file| file.write_all(line.as_bytes()));
+        }
+    }
+
+    /// Returns the file [`HitRecord`]s are appended to, one JSON object per
+    /// line.
+    fn hit_log_file(&self) -> PathBuf {
+        self.cache_dir.join("hits.jsonl")
+    }
+
+    /// Saves incremental cache for a package after a successful build.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache file cannot be written.
+    #[allow(clippy::too_many_arguments)]
+    pub fn save_incremental_cache(
+        &self,
+        package: &PackageHash,
+        workspace_state: &WorkspaceState,
+        command_hash: &str,
+        env_hash: &str,
+        is_release: bool,
+        args: &[String],
+        build_success: bool,
+        duration_ms: u64,
+    ) -> Result<()> {
+        self.write_incremental_cache(
+            package,
+            workspace_state,
+            command_hash,
+            env_hash,
+            is_release,
+            args,
+            build_success,
+            duration_ms,
+            None,
+            None,
+        )
+    }
 
-                // Include uncommitted changes
-                if let Ok(status_output) = Command::new("git")
-                    .args(["status", "--porcelain"])
-                    .arg(effective_path)
-                    .output()
-                {
-                    if status_output.status.success() && !status_output.stdout.is_empty() {
-                        hasher.update(&status_output.stdout);
+    /// Writes the incremental cache entry for a package, recording whether
+    /// its tests passed when the build ran them.
+    #[allow(clippy::too_many_arguments)]
+    fn write_incremental_cache(
+        &self,
+        package: &PackageHash,
+        workspace_state: &WorkspaceState,
+        command_hash: &str,
+        env_hash: &str,
+        is_release: bool,
+        args: &[String],
+        build_success: bool,
+        duration_ms: u64,
+        tests_passed: Option<bool>,
+        subcommand: Option<&str>,
+    ) -> Result<()> {
+        if self.no_cache.contains(&package.name) {
+            log_debug!("Not caching {} (no_cache)", package.name);
+            return Ok(());
+        }
 
-                        let status_str = String::from_utf8_lossy(&status_output.stdout);
-                        for line in status_str.lines() {
-                            if line.len() > 3 {
-                                let file_path = &line[3..];
-                                let full_path = path.join(file_path);
-                                if full_path.exists() && full_path.is_file() {
-                                    self.hash_file_with_lfs_support(
-                                        &full_path,
-                                        &repo_info,
-                                        &mut hasher,
-                                    )?;
-                                }
-                            }
+        let mut target_files = Vec::new();
+        let mut artifact_paths = Vec::new();
+
+        // Fingerprint directories use the package name, `deps/` files the
+        // library or binary crate name. The `incremental/`
+        // directory of `CARGO_INCREMENTAL=1` builds is left out: rustc rewrites
+        // its session directories on every build and recreates them when
+        // missing, so recording them would only make entries look stale
+        let crate_names = package.crate_names();
+
+        for (profile_dir, os) in self.target_profile_dirs(args, workspace_state) {
+            let deps_dir = profile_dir.join(".fingerprint");
+            let deps_build_dir = profile_dir.join("deps");
+            let extensions = artifact_extensions(os);
+
+            if deps_dir.exists() {
+                for entry in WalkDir::new(&deps_dir)
+                    .min_depth(2)
+                    .max_depth(2)
+                    .into_iter()
+                    .flatten()
+                {
+                    let in_package_dir = entry
+                        .path()
+                        .parent()
+                        .and_then(|dir| dir.file_name())
+                        .and_then(|dir| dir.to_str())
+                        .is_some_and(|dir| is_artifact_of(dir, &package.name, &[""]));
+                    if entry.file_type().is_file() && in_package_dir {
+                        if let Ok(metadata) = fs::metadata(entry.path()) {
+                            target_files.push((entry.path().to_path_buf(), metadata.len()));
                         }
                     }
                 }
+            }
 
-                // Include submodule status
-                if let Some(submodule_status) = self.get_submodule_status(effective_path) {
-                    if !submodule_status.is_empty() {
-                        hasher.update(b"SUBMODULES:");
-                        hasher.update(&submodule_status);
+            if deps_build_dir.exists() {
+                for entry in WalkDir::new(&deps_build_dir)
+                    .max_depth(1)
+                    .into_iter()
+                    .flatten()
+                {
+                    if entry.file_type().is_file() {
+                        let file_name = entry.file_name().to_string_lossy();
+                        if crate_names
+                            .iter()
+                            .any(|name| is_artifact_of(&file_name, name, extensions))
+                        {
+                            if let Ok(metadata) = fs::metadata(entry.path()) {
+                                target_files.push((entry.path().to_path_buf(), metadata.len()));
+                                artifact_paths.push(entry.path().to_path_buf());
+                            }
+                        }
                     }
                 }
+            }
 
-                // Include sparse checkout patterns
-                if let Some(ref info) = repo_info {
-                    if info.is_sparse {
-                        if let Some(patterns) = self.get_sparse_checkout_patterns(info) {
-                            hasher.update(b"SPARSE:");
-                            for pattern in patterns {
-                                hasher.update(pattern.as_bytes());
+            // rustdoc writes next to the profile directories: `doc/<crate>/`
+            // for the pages and `doc/src/<crate>/` for the rendered sources
+            if subcommand.is_some_and(Self::builds_docs) {
+                let doc_dir = profile_dir.with_file_name("doc");
+                for name in &crate_names {
+                    for dir in [doc_dir.join(name), doc_dir.join("src").join(name)] {
+                        for entry in WalkDir::new(&dir).into_iter().flatten() {
+                            if entry.file_type().is_file() {
+                                if let Ok(metadata) = entry.metadata() {
+                                    target_files.push((entry.path().to_path_buf(), metadata.len()));
+                                }
                             }
                         }
                     }
                 }
+            }
+        }
 
-                // Include shallow clone info
-                if let Some(ref info) = repo_info {
-                    if info.is_shallow {
-                        hasher.update(b"SHALLOW_CLONE");
-                        let shallow_file = info.git_dir.join("shallow");
-                        if let Ok(content) = fs::read(&shallow_file) {
-                            hasher.update(&content);
-                        }
-                    }
+        // WalkDir order depends on the filesystem; keep stored entries stable
+        target_files.sort();
+        target_files.dedup();
+        artifact_paths.sort();
+        artifact_paths.dedup();
+
+        let mut objects = Vec::new();
+        if build_success {
+            for (path, _) in &target_files {
+                match self.store_object(path) {
+                    Ok(hash) => objects.push((path.clone(), hash)),
+                    Err(e) => log_error!("Warning: failed to store {}: {}", path.display(), e),
                 }
+            }
+        }
+
+        let cache = IncrementalCache {
+            package_name: package.name.clone(),
+            package_version: package.version.clone(),
+            source_hash: package.source_hash.clone(),
+            cargo_lock_hash: workspace_state.cargo_lock_hash.clone(),
+            command_hash: command_hash.to_string(),
+            env_hash: env_hash.to_string(),
+            is_release,
+            features_hash: package.features_hash.clone(),
+            target_files,
+            artifact_paths,
+            timestamp: chrono::Local::now().to_rfc3339(),
+            build_success,
+            duration_ms,
+            objects,
+            tests_passed,
+            workspace_root: Some(workspace_state.root.clone()),
+            hits: 0,
+            toolchain_hash: Some(workspace_state.toolchain_hash.clone()),
+            subcommand: subcommand.map(str::to_string),
+            output_kind: subcommand.map(OutputKind::for_subcommand),
+        };
+
+        let cache_key = self.get_cache_key(
+            package,
+            &workspace_state.root,
+            command_hash,
+            env_hash,
+            is_release,
+            &self.get_target_triples(args),
+        );
+
+        let cache_file = self.incremental_dir.join(format!("{}.json", cache_key));
+        let content = serde_json::to_string_pretty(&cache)?;
+        {
+            let _lock = self.lock(&cache_key)?;
+            fs::write(&cache_file, &content)?;
+        }
+
+        if build_success {
+            self.push_remote_entry(&cache_key, &cache, content.as_bytes());
+        }
 
-                return Ok(hasher.finalize().to_hex().to_string());
+        Ok(())
+    }
+
+    /// Downloads the entries of `workspace_state`'s packages that are missing
+    /// locally from the remote cache, ahead of planning a build.
+    ///
+    /// Only builds fetch; inspecting the cache never touches the network.
+    fn fetch_remote_entries(
+        &self,
+        subcommand: &str,
+        args: &[String],
+        workspace_state: &WorkspaceState,
+        env_hash: &str,
+    ) {
+        if self.remote.is_none() {
+            return;
+        }
+        let command_hash = self.compute_command_hash(subcommand, args);
+        let is_release = self.is_release_build(args);
+        let triples = self.get_target_triples(args);
+        let target_dir = self
+            .get_target_dir(args)
+            .unwrap_or_else(|| workspace_state.root.join("target"));
+        for package in &workspace_state.packages {
+            let cache_key = self.get_cache_key(
+                package,
+                &workspace_state.root,
+                &command_hash,
+                env_hash,
+                is_release,
+                &triples,
+            );
+            let cache_file = self.incremental_dir.join(format!("{}.json", cache_key));
+            if !cache_file.exists() {
+                self.fetch_remote_entry(&cache_key, &cache_file, &target_dir);
             }
         }
+    }
 
-        // Fallback to file-based hashing
-        static GIT_WARNING_SHOWN: std::sync::atomic::AtomicBool =
-            std::sync::atomic::AtomicBool::new(false);
-        if !GIT_WARNING_SHOWN.swap(true, std::sync::atomic::Ordering::Relaxed) {
-            eprintln!(
-                "{} Warning: Git not available or not in a git repository. Using file-based hashing (less accurate).",
-                LOG_PREFIX
+    /// Downloads the entry `cache_key` and its objects from the remote cache.
+    ///
+    /// Objects are fetched first so a local entry never references missing
+    /// objects. Entries with files outside `target_dir` are rejected, as
+    /// restoring them would overwrite arbitrary paths. Failures are logged
+    /// and treated as a cache miss.
+    fn fetch_remote_entry(&self, cache_key: &str, cache_file: &Path, target_dir: &Path) {
+        let Some(remote) = &self.remote else {
+            return;
+        };
+
+        let result = (|| -> Result<bool> {
+            let Some(content) = remote.get(&format!("entries/{}.json", cache_key))? else {
+                return Ok(false);
+            };
+            let cache: IncrementalCache = serde_json::from_slice(&content)?;
+            let outside = cache
+                .target_files
+                .iter()
+                .map(|(path, _)| path)
+                .chain(cache.objects.iter().map(|(path, _)| path))
+                .find(|path| !is_within(path, target_dir));
+            if let Some(path) = outside {
+                anyhow::bail!(
+                    "Entry {} restores {} outside of {}",
+                    cache_key,
+                    path.display(),
+                    target_dir.display()
+                );
+            }
+
+            for (_, hash) in &cache.objects {
+                let object = self.objects_dir.join(hash);
+                if object.is_file() {
+                    continue;
+                }
+                let data = remote
+                    .get(&format!("objects/{}", hash))?
+                    .with_context(|| format!("Object {} is missing from the remote cache", hash))?;
+                if blake3::hash(&data).to_hex().as_str() != hash {
+                    anyhow::bail!("Object {} from the remote cache is corrupt", hash);
+                }
+                let tmp = self.objects_dir.join(format!("{}.tmp", hash));
+                fs::write(&tmp, &data)?;
+                fs::rename(&tmp, &object)?;
+            }
+
+            let _lock = self.lock(cache_key)?;
+            fs::write(cache_file, &content)?;
+            Ok(true)
+        })();
+
+        match result {
+            Ok(true) => log_debug!("Fetched {} from the remote cache", cache_key),
+            Ok(false) => {}
+            Err(e) => log_error!("Warning: remote cache lookup failed: {}", e),
+        }
+    }
+
+    /// Uploads a saved entry and its objects to the remote cache.
+    fn push_remote_entry(&self, cache_key: &str, cache: &IncrementalCache, content: &[u8]) {
+        let Some(remote) = &self.remote else {
+            return;
+        };
+
+        let result = (|| -> Result<()> {
+            for (_, hash) in &cache.objects {
+                let data = fs::read(self.objects_dir.join(hash))?;
+                remote.put(&format!("objects/{}", hash), &data)?;
+            }
+            remote.put(&format!("entries/{}.json", cache_key), content)
+        })();
+
+        if let Err(e) = result {
+            log_error!(
+                "Warning: failed to upload {} to the remote cache: {}",
+                cache_key,
+                e
             );
         }
+    }
+
+    /// Stores a file in the object store and returns its content hash.
+    ///
+    /// Identical files are stored only once.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or the store cannot be written.
+    pub fn store_object(&self, path: &Path) -> Result<String> {
+        let mut hasher = Blake3Hasher::new();
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+        let hash = hasher.finalize().to_hex().to_string();
+
+        let object = self.objects_dir.join(&hash);
+        if !object.exists() {
+            // Copy under a temporary name so readers never see a partial object
+            let tmp = self
+                .objects_dir
+                .join(format!("{}.tmp{}", hash, std::process::id()));
+            fs::copy(path, &tmp)?;
+            fs::rename(&tmp, &object)?;
+        }
+
+        Ok(hash)
+    }
+
+    /// Restores the target files of a cache entry that are missing or changed.
+    ///
+    /// Artifacts are copied out of the object store, never hardlinked, since
+    /// cargo and linkers rewrite files in place and would corrupt the stored
+    /// object. The copy is a reflink where the filesystem supports it.
+    ///
+    /// Returns the number of files restored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a stored object is missing or cannot be placed.
+    pub fn restore_artifacts(&self, cache: &IncrementalCache) -> Result<usize> {
+        let sizes: HashMap<&PathBuf, u64> =
+            cache.target_files.iter().map(|(p, s)| (p, *s)).collect();
+        let mut restored = 0;
+
+        for (path, hash) in &cache.objects {
+            let intact = fs::metadata(path)
+                .ok()
+                .is_some_and(|m| sizes.get(path) == Some(&m.len()));
+            if intact {
+                continue;
+            }
+
+            let object = self.objects_dir.join(hash);
+            if !object.is_file() {
+                anyhow::bail!("Object {} for {} is missing", hash, path.display());
+            }
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let _ = fs::remove_file(path);
+
+            fs::copy(&object, path)?;
+            restored += 1;
+        }
+
+        Ok(restored)
+    }
+
+    /// Deletes objects no longer referenced by any incremental cache entry.
+    ///
+    /// The sweep holds the build lock of every workspace with entries, so no
+    /// save there can store objects between reading the entries and deleting.
+    /// Objects younger than the [`with_object_grace`](Self::with_object_grace)
+    /// period are kept for saves in workspaces without entries yet.
+    ///
+    /// Returns the number of objects removed and the bytes reclaimed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directories cannot be read or a build
+    /// lock cannot be taken.
+    pub fn sweep_objects(&self) -> Result<(usize, u64)> {
+        let read_entries = || -> Result<Vec<IncrementalCache>> {
+            Ok(fs::read_dir(&self.incremental_dir)?
+                .flatten()
+                .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+                .filter_map(|content| serde_json::from_str(&content).ok())
+                .collect())
+        };
 
-        for entry in WalkDir::new(path)
-            .follow_links(false)
-            .max_depth(10)
+        let roots: BTreeSet<PathBuf> = read_entries()?
             .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            if entry.file_type().is_file() {
-                let path_str = entry.path().to_string_lossy();
+            .filter_map(|cache| cache.workspace_root)
+            .collect();
+        let _build_locks = roots
+            .iter()
+            .map(|root| self.lock_workspace_build(root))
+            .collect::<CacheResult<Vec<_>>>()?;
+
+        let referenced: HashSet<String> = read_entries()?
+            .into_iter()
+            .flat_map(|cache| cache.objects.into_iter().map(|(_, hash)| hash))
+            .collect();
+
+        let mut removed = 0;
+        let mut reclaimed = 0;
+        for entry in fs::read_dir(&self.objects_dir)?.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            // Skip objects still being written by a concurrent build
+            if name.contains(".tmp") || referenced.contains(&name) {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let age = metadata.modified().ok().and_then(|m| m.elapsed().ok());
+            if age.map_or(true, |age| age < self.object_grace) {
+                continue;
+            }
+            let size = metadata.len();
+            if fs::remove_file(entry.path()).is_ok() {
+                removed += 1;
+                reclaimed += size;
+            }
+        }
+
+        Ok((removed, reclaimed))
+    }
+
+    /// Restores the artifacts of every package with a valid cache entry for
+    /// `subcommand` and `args` into the target directory, without running
+    /// cargo, as `restore --artifacts-only` does.
+    ///
+    /// A plain cargo build afterwards only rebuilds the packages reported as
+    /// missing.
+    pub fn restore_workspace_artifacts(
+        &self,
+        subcommand: &str,
+        args: &[String],
+        workspace_state: &WorkspaceState,
+    ) -> RestoreReport {
+        let command_hash = self.compute_command_hash(subcommand, args);
+        let env_hash = self.compute_env_hash();
+        let is_release = self.is_release_build(args);
+        self.fetch_remote_entries(subcommand, args, workspace_state, &env_hash);
+
+        let mut report = RestoreReport::default();
+        for package in &workspace_state.packages {
+            let restored = self
+                .check_incremental_cache(
+                    package,
+                    workspace_state,
+                    &command_hash,
+                    &env_hash,
+                    is_release,
+                    args,
+                )
+                .map(|cache| self.restore_artifacts(&cache));
+            match restored {
+                Some(Ok(files)) => {
+                    report.files += files;
+                    report.restored.push(package.name.clone());
+                }
+                Some(Err(e)) => {
+                    log_error!("Warning: failed to restore {}: {}", package.name, e);
+                    report.missing.push(package.name.clone());
+                }
+                None => report.missing.push(package.name.clone()),
+            }
+        }
+        report
+    }
+
+    /// Restores missing artifacts of packages whose cache entries are valid.
+    ///
+    /// Returns the number of files restored.
+    fn restore_cached_artifacts(
+        &self,
+        workspace_state: &WorkspaceState,
+        changed: &[PackageHash],
+        subcommand: &str,
+        args: &[String],
+        env_hash: &str,
+    ) -> usize {
+        let command_hash = self.compute_command_hash(subcommand, args);
+        let is_release = self.is_release_build(args);
+        let changed: HashSet<&str> = changed.iter().map(|p| p.name.as_str()).collect();
+
+        let mut restored = 0;
+        for package in &workspace_state.packages {
+            if changed.contains(package.name.as_str()) {
+                continue;
+            }
+            if let Some(cache) = self.check_incremental_cache(
+                package,
+                workspace_state,
+                &command_hash,
+                env_hash,
+                is_release,
+                args,
+            ) {
+                match self.restore_artifacts(&cache) {
+                    Ok(n) => restored += n,
+                    Err(e) => log_error!("Warning: failed to restore {}: {}", package.name, e),
+                }
+            }
+        }
+
+        if restored > 0 {
+            log_info!("Restored {} artifacts from the object store", restored);
+        }
+        restored
+    }
+
+    /// Gets the list of packages that need rebuilding.
+    ///
+    /// This includes packages that:
+    /// - Don't have a valid cache entry
+    /// - Have transitive dependencies that need rebuilding
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cargo_save::CacheManager;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let cache = CacheManager::new()?;
+    /// let workspace = cache.compute_workspace_state(&[])?;
+    ///
+    /// let changed = cache.get_changed_packages(&workspace, "cmd_hash", "env_hash", false, &[]);
+    /// println!("Packages needing rebuild: {:?}", changed.iter().map(|p| &p.name).collect::<Vec<_>>());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn get_changed_packages(
+        &self,
+        workspace_state: &WorkspaceState,
+        command_hash: &str,
+        env_hash: &str,
+        is_release: bool,
+        args: &[String],
+    ) -> Vec<PackageHash> {
+        self.changed_packages_for(
+            workspace_state,
+            command_hash,
+            env_hash,
+            is_release,
+            args,
+            None,
+        )
+    }
 
-                if path_str.contains("/target/")
-                    || path_str.contains("/.git/")
-                    || path_str.contains("/node_modules/")
-                {
-                    continue;
-                }
+    /// Gets the names of the packages that need rebuilding.
+    ///
+    /// Same as [`get_changed_packages`](Self::get_changed_packages) without
+    /// cloning the package hashes, e.g. to build a CI matrix of changed
+    /// crates.
+    pub fn changed_package_names(
+        &self,
+        workspace_state: &WorkspaceState,
+        command_hash: &str,
+        env_hash: &str,
+        is_release: bool,
+        args: &[String],
+    ) -> Vec<String> {
+        self.changed_names_for(
+            workspace_state,
+            command_hash,
+            env_hash,
+            is_release,
+            args,
+            None,
+        )
+    }
 
-                if let Some(ext) = entry.path().extension() {
-                    if matches!(ext.to_str(), Some("rs") | Some("toml")) {
-                        if let Ok(content) = fs::read(entry.path()) {
-                            hasher.update(entry.path().to_string_lossy().as_bytes());
-                            hasher.update(&content);
-                        }
-                    }
-                }
-            }
-        }
+    /// Same as [`get_changed_packages`](Self::get_changed_packages), but with
+    /// a `subcommand` an entry only counts as cached if it recorded the same
+    /// [`OutputKind`], and if its tests passed when the subcommand runs them.
+    fn changed_packages_for(
+        &self,
+        workspace_state: &WorkspaceState,
+        command_hash: &str,
+        env_hash: &str,
+        is_release: bool,
+        args: &[String],
+        subcommand: Option<&str>,
+    ) -> Vec<PackageHash> {
+        let by_name: HashMap<&str, &PackageHash> = workspace_state
+            .packages
+            .iter()
+            .map(|p| (p.name.as_str(), p))
+            .collect();
 
-        Ok(hasher.finalize().to_hex().to_string())
+        self.changed_names_for(
+            workspace_state,
+            command_hash,
+            env_hash,
+            is_release,
+            args,
+            subcommand,
+        )
+        .iter()
+        .filter_map(|name| by_name.get(name.as_str()).map(|p| (*p).clone()))
+        .collect()
     }
 
-    /// Helper function to hash a file, handling LFS files specially.
-    fn hash_file_with_lfs_support(
+    /// Names of the packages [`changed_packages_for`](Self::changed_packages_for)
+    /// returns, in the same order.
+    fn changed_names_for(
         &self,
-        path: &Path,
-        repo_info: &Option<GitRepoInfo>,
-        hasher: &mut Blake3Hasher,
-    ) -> Result<()> {
-        if let Some(ref info) = repo_info {
-            if self.is_lfs_file(path, info) {
-                if let Some(oid) = self.get_lfs_pointer_hash(path) {
-                    hasher.update(b"LFS:");
-                    hasher.update(oid.as_bytes());
-                    return Ok(());
-                }
-            }
-        }
+        workspace_state: &WorkspaceState,
+        command_hash: &str,
+        env_hash: &str,
+        is_release: bool,
+        args: &[String],
+        subcommand: Option<&str>,
+    ) -> Vec<String> {
+        let require_tests = subcommand.is_some_and(|s| Self::runs_tests(s, args));
+        let output_kind = subcommand.map(OutputKind::for_subcommand);
+        // Packages without a valid cache entry
+        let stale: Vec<String> = workspace_state
+            .packages
+            .iter()
+            .filter(|package| {
+                self.no_cache.contains(&package.name)
+                    || self
+                        .check_incremental_cache(
+                            package,
+                            workspace_state,
+                            command_hash,
+                            env_hash,
+                            is_release,
+                            args,
+                        )
+                        .map_or(true, |cache| {
+                            // Entries of older versions don't record their kind
+                            let other_kind = cache
+                                .output_kind
+                                .zip(output_kind)
+                                .is_some_and(|(kind, requested)| kind != requested);
+                            other_kind || (require_tests && cache.tests_passed != Some(true))
+                        })
+            })
+            .map(|package| package.name.clone())
+            .collect();
 
-        if let Ok(content) = fs::read(path) {
-            hasher.update(path.to_string_lossy().as_bytes());
-            hasher.update(&content);
-        }
+        // Everything depending on them has to be rebuilt as well
+        let members: HashSet<&str> = workspace_state
+            .packages
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        self.build_dependency_graph(workspace_state)
+            .transitive_dependents(&stale)
+            .into_iter()
+            .filter(|name| members.contains(name.as_str()))
+            .collect()
+    }
 
-        Ok(())
+    /// Returns true for subcommands that write rustdoc output, whose
+    /// `target/doc` pages are cached along with the package.
+    fn builds_docs(subcommand: &str) -> bool {
+        matches!(subcommand, "doc" | "rustdoc")
     }
 
-    /// Gets the status of git submodules.
-    fn get_submodule_status(&self, path: &Path) -> Option<Vec<u8>> {
-        let output = Command::new("git")
-            .args(["submodule", "status"])
-            .current_dir(path)
-            .output()
-            .ok()?;
+    /// Returns true for subcommands that never use incremental caching.
+    fn skips_incremental(subcommand: &str) -> bool {
+        matches!(subcommand, "clean" | "update" | "new" | "init")
+            || Self::is_passthrough(subcommand)
+    }
 
-        if output.status.success() {
-            Some(output.stdout)
-        } else {
-            None
-        }
+    /// Returns true for subcommands in [`PASSTHROUGH_SUBCOMMANDS`], which are
+    /// run with [`CacheManager::run_cargo_passthrough`].
+    pub fn is_passthrough(subcommand: &str) -> bool {
+        PASSTHROUGH_SUBCOMMANDS.contains(&subcommand)
     }
 
-    /// Gets sparse checkout patterns from the git repository.
-    fn get_sparse_checkout_patterns(&self, repo_info: &GitRepoInfo) -> Option<Vec<String>> {
-        let sparse_file = repo_info.git_dir.join("info/sparse-checkout");
-        if sparse_file.exists() {
-            fs::read_to_string(&sparse_file).ok().map(|content| {
-                content
-                    .lines()
-                    .map(|l| l.trim().to_string())
-                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
-                    .collect()
-            })
-        } else {
-            None
+    /// Returns true if the command runs tests, so a cached entry is only
+    /// valid if those tests passed.
+    ///
+    /// `cargo nextest run` is treated like `cargo test`.
+    fn runs_tests(subcommand: &str, args: &[String]) -> bool {
+        match subcommand {
+            "test" => !args.iter().any(|arg| arg == "--no-run"),
+            "nextest" => args.first().is_some_and(|arg| arg == "run"),
+            _ => false,
         }
     }
 
-    /// Computes a hash for a single package.
-    ///
-    /// # Errors
+    /// Returns true if a build planned as `plan` is skipped because every
+    /// package is cached.
+    fn skips_cached_build(subcommand: &str, args: &[String], plan: &BuildPlan) -> bool {
+        // `doc --open` is run for the browser even when nothing changed
+        let opens_docs = args.iter().any(|arg| arg == "--open");
+        plan.changed_packages.is_empty()
+            && (matches!(subcommand, "build" | "check" | "clippy" | "test")
+                || (Self::builds_docs(subcommand) && !opens_docs)
+                || Self::runs_tests(subcommand, args))
+    }
+
+    /// Returns true if the `cargo nextest` subcommand is installed.
+    pub fn nextest_available() -> bool {
+        Command::new(cargo_bin())
+            .args(["nextest", "--version"])
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false)
+    }
+
+    /// Returns `--exclude` arguments for packages whose tests are cached.
     ///
-    /// Returns an error if the package manifest directory cannot be determined
-    /// or if source hashing fails.
-    pub fn compute_package_hash(
+    /// Only applies when the user selected the whole workspace; otherwise the
+    /// package selection is left to cargo.
+    fn test_exclude_args(
         &self,
-        package: &Package,
-        metadata: &Metadata,
         args: &[String],
-    ) -> Result<PackageHash> {
-        let manifest_dir = package
-            .manifest_path
-            .parent()
-            .context("No manifest directory")?;
-
-        let source_hash = self.compute_source_hash(manifest_dir.as_std_path(), args)?;
-        let features_hash = self.compute_features_hash(args);
-
-        let mut dependencies = Vec::new();
-
-        for dep in &package.dependencies {
-            if metadata.workspace_members.iter().any(|member_id| {
-                metadata
-                    .packages
-                    .iter()
-                    .find(|p| &p.id == member_id)
-                    .map(|p| p.name == dep.name)
-                    .unwrap_or(false)
-            }) {
-                dependencies.push(dep.name.clone());
-            }
+        plan: &BuildPlan,
+        workspace_state: &WorkspaceState,
+    ) -> Vec<String> {
+        if !selects_workspace(args) {
+            return Vec::new();
         }
-
-        Ok(PackageHash {
-            name: package.name.clone(),
-            version: package.version.to_string(),
-            path: manifest_dir.as_std_path().to_path_buf(),
-            source_hash,
-            dependencies,
-            features_hash,
-        })
+        // Packages the user excluded are already passed on in `args`
+        plan.cached_packages(workspace_state)
+            .flat_map(|p| ["--exclude".to_string(), p.name.clone()])
+            .collect()
     }
 
-    /// Computes the current state of the entire workspace.
+    /// Computes the build plan for a cargo command without running it.
     ///
-    /// This is the main entry point for determining what needs to be built.
-    /// It computes hashes for all packages, the Cargo.lock file, and the toolchain.
+    /// This performs the same change detection as [`run_cargo_with_cache`](Self::run_cargo_with_cache),
+    /// so the returned plan is exactly what a live build would rebuild.
+    /// Every package of `workspace_state` is planned; narrow it with
+    /// [`WorkspaceState::built_by`] to the members the build covers.
     ///
     /// # Errors
     ///
-    /// Returns an error if cargo metadata cannot be retrieved or if hashing fails.
+    /// Returns an error if change detection fails.
     ///
     /// # Example
     ///
@@ -1008,55 +6516,301 @@ impl CacheManager {
     /// let cache = CacheManager::new()?;
     /// let workspace = cache.compute_workspace_state(&[])?;
     ///
-    /// println!("Workspace has {} packages", workspace.packages.len());
-    /// for pkg in &workspace.packages {
-    ///     println!("  - {}", pkg.name);
-    /// }
+    /// let plan = cache.plan("build", &[], &workspace)?;
+    /// println!("{}/{} packages cached", plan.cached_count, plan.total_packages);
     /// # Ok(())
     /// # }
     /// ```
-    pub fn compute_workspace_state(&self, args: &[String]) -> Result<WorkspaceState> {
-        let metadata = self.get_cargo_metadata()?;
-        let root: PathBuf = metadata.workspace_root.clone().into();
+    pub fn plan(
+        &self,
+        subcommand: &str,
+        args: &[String],
+        workspace_state: &WorkspaceState,
+    ) -> Result<BuildPlan> {
+        self.plan_with_env_hash(subcommand, args, workspace_state, &self.compute_env_hash())
+    }
 
-        let packages: Vec<PackageHash> = metadata
-            .workspace_packages()
-            .par_iter()
-            .filter_map(|package| self.compute_package_hash(package, &metadata, args).ok())
+    /// Returns true if running `subcommand` with `args` would not rebuild
+    /// any package, i.e. every package has a valid incremental cache entry.
+    ///
+    /// Subcommands that are never cached, such as `clean` or `fmt`, always
+    /// return false. This is a cheaper check than [`plan`](Self::plan) for
+    /// tooling that only needs a yes or no.
+    pub fn is_fully_cached(
+        &self,
+        subcommand: &str,
+        args: &[String],
+        workspace_state: &WorkspaceState,
+    ) -> bool {
+        let workspace_state = &workspace_state.built_by(args);
+        if Self::skips_incremental(subcommand) || self.target_was_removed(args, workspace_state) {
+            return false;
+        }
+        let excluded = excluded_packages(args);
+        self.changed_packages_for(
+            workspace_state,
+            &self.compute_command_hash(subcommand, args),
+            &self.compute_env_hash(),
+            self.is_release_build(args),
+            args,
+            Some(subcommand),
+        )
+        .iter()
+        .all(|package| excluded.contains(&package.name))
+    }
+
+    /// Same as [`plan`](Self::plan) for a build whose environment hashes to
+    /// `env_hash`.
+    fn plan_with_env_hash(
+        &self,
+        subcommand: &str,
+        args: &[String],
+        workspace_state: &WorkspaceState,
+        env_hash: &str,
+    ) -> Result<BuildPlan> {
+        let mut changed_packages = if Self::skips_incremental(subcommand) {
+            vec![]
+        } else if self.target_was_removed(args, workspace_state) {
+            let missing: Vec<String> = self
+                .target_profile_dirs(args, workspace_state)
+                .into_iter()
+                .filter(|(dir, _)| !dir.exists())
+                .map(|(dir, _)| dir.display().to_string())
+                .collect();
+            log_info!(
+                "{} does not exist, rebuilding all packages",
+                missing.join(", ")
+            );
+            workspace_state.packages.clone()
+        } else if Self::builds_docs(subcommand) && !self.doc_site_intact(args, workspace_state) {
+            log_info!("The documentation is incomplete, rebuilding all packages");
+            workspace_state.packages.clone()
+        } else {
+            let is_release = self.is_release_build(args);
+            let command_hash = self.compute_command_hash(subcommand, args);
+            log_debug!(
+                "Command hash {}, env hash {}",
+                display_hash(&command_hash, false),
+                display_hash(env_hash, false)
+            );
+            self.changed_packages_for(
+                workspace_state,
+                &command_hash,
+                env_hash,
+                is_release,
+                args,
+                Some(subcommand),
+            )
+        };
+
+        // Excluded packages still invalidate their dependents above, but are
+        // neither built nor counted themselves
+        let excluded_packages: Vec<String> = excluded_packages(args)
+            .into_iter()
+            .filter(|name| workspace_state.packages.iter().any(|p| p.name == *name))
             .collect();
+        changed_packages.retain(|p| !excluded_packages.contains(&p.name));
 
-        let cargo_lock_hash = self.compute_cargo_lock_hash(&root)?;
-        let toolchain_hash = self.compute_toolchain_hash()?;
+        let total_packages = workspace_state.packages.len() - excluded_packages.len();
 
-        let git_features = self.get_git_repo_info(&root).map(|info| {
-            let has_submodules = self
-                .get_submodule_status(&root)
-                .map(|s| !s.is_empty())
-                .unwrap_or(false);
+        Ok(BuildPlan {
+            cached_count: total_packages - changed_packages.len(),
+            total_packages,
+            changed_packages,
+            excluded_packages,
+        })
+    }
 
-            GitFeaturesInfo {
-                has_submodules,
-                is_sparse: info.is_sparse,
-                is_worktree: info.is_worktree,
-                has_lfs: info.has_lfs,
-                is_shallow: info.is_shallow,
+    /// Returns true if the rustdoc output of a build with `args` is a whole
+    /// site: the shared `static.files/`, search index and `crates.js`, and
+    /// the pages of every crate `crates.js` lists, dependencies included.
+    ///
+    /// Cache entries only track the pages of their own crate, so a doc build
+    /// cannot be skipped while anything else is missing.
+    fn doc_site_intact(&self, args: &[String], workspace_state: &WorkspaceState) -> bool {
+        self.target_profile_dirs(args, workspace_state)
+            .into_iter()
+            .all(|(profile_dir, _)| {
+                let doc_dir = profile_dir.with_file_name("doc");
+                let Ok(crates) = fs::read_to_string(doc_dir.join("crates.js")) else {
+                    return false;
+                };
+                doc_dir.join("static.files").is_dir()
+                    && (doc_dir.join("search-index.js").is_file()
+                        || doc_dir.join("search.index").is_dir())
+                    && documented_crates(&crates)
+                        .iter()
+                        .all(|name| doc_dir.join(name).join("index.html").is_file())
+            })
+    }
+
+    /// Returns the profile directories a build with `args` writes to, each
+    /// with the OS its artifacts are built for.
+    ///
+    /// Host builds use `target/<profile>`, cross builds
+    /// `target/<triple>/<profile>` for every target triple.
+    fn target_profile_dirs(
+        &self,
+        args: &[String],
+        workspace_state: &WorkspaceState,
+    ) -> Vec<(PathBuf, &'static str)> {
+        let target_dir = self
+            .get_target_dir(args)
+            .unwrap_or_else(|| workspace_state.root.join("target"));
+        let profile = if self.is_release_build(args) {
+            "release"
+        } else {
+            "debug"
+        };
+
+        let triples = self.get_target_triples(args);
+        if triples.is_empty() {
+            return vec![(target_dir.join(profile), std::env::consts::OS)];
+        }
+        triples
+            .iter()
+            .map(|triple| (target_dir.join(triple).join(profile), triple_os(triple)))
+            .collect()
+    }
+
+    /// Returns true if the profile's target directory is gone and nothing can
+    /// be restored from the object store, so every cache entry is stale.
+    ///
+    /// This avoids stat-ing every recorded target file after `rm -rf target`.
+    /// When an entry of this workspace has its objects for this target
+    /// directory in the store, or a remote cache is configured, entries are
+    /// checked as usual so their artifacts can be restored instead.
+    fn target_was_removed(&self, args: &[String], workspace_state: &WorkspaceState) -> bool {
+        if self
+            .target_profile_dirs(args, workspace_state)
+            .iter()
+            .all(|(dir, _)| dir.exists())
+        {
+            return false;
+        }
+        let target_dir = self
+            .get_target_dir(args)
+            .unwrap_or_else(|| workspace_state.root.join("target"));
+        let restorable = self.remote.is_some()
+            || fs::read_dir(&self.incremental_dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+                .filter_map(|content| serde_json::from_str::<IncrementalCache>(&content).ok())
+                .any(|cache| {
+                    cache.workspace_root.as_ref() == Some(&workspace_state.root)
+                        && !cache.objects.is_empty()
+                        && cache.objects.iter().all(|(path, hash)| {
+                            path.starts_with(&target_dir) && self.objects_dir.join(hash).is_file()
+                        })
+                });
+        if restorable {
+            log_info!("Target directory is missing, restoring cached artifacts where possible");
+        }
+        !restorable
+    }
+
+    /// Prints a build plan in the format used before running cargo.
+    pub fn print_build_plan(&self, plan: &BuildPlan) {
+        log_info!(
+            "Build plan: {}/{} packages cached, {} need rebuild",
+            plan.cached_count,
+            plan.total_packages,
+            plan.changed_packages.len()
+        );
+        log_info!("Packages to rebuild:");
+        for pkg in &plan.changed_packages {
+            log_info!("  - {}", pkg.name);
+        }
+    }
+
+    /// Pre-warms the cache for the current workspace.
+    ///
+    /// Without `build` this only computes package hashes and reports which
+    /// packages lack a cache entry. With `build` it runs `cargo build` through
+    /// [`CacheManager::run_cargo_with_cache`] so those entries are populated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workspace state cannot be computed or the
+    /// warm-up build fails.
+    pub fn warm_cache(&self, release: bool, build: bool) -> Result<()> {
+        let mut args = vec![];
+        if release {
+            args.push("--release".to_string());
+        }
+        if let Some(manifest_path) = &self.manifest_path {
+            args.push("--manifest-path".to_string());
+            args.push(manifest_path.display().to_string());
+        }
+        let workspace = self
+            .compute_workspace_state_with_progress_bar(&args)?
+            .built_by(&args);
+        let before = self.plan("build", &args, &workspace)?;
+
+        if !build {
+            if before.changed_packages.is_empty() {
+                println!("{} All packages already cached", LOG_PREFIX);
+            } else {
+                println!(
+                    "{} Pre-computing hashes for {} packages",
+                    LOG_PREFIX,
+                    before.changed_packages.len()
+                );
+                for pkg in &before.changed_packages {
+                    println!("  - {}", pkg.name);
+                }
             }
-        });
+            return Ok(());
+        }
 
-        Ok(WorkspaceState {
-            root,
-            packages,
-            cargo_lock_hash,
-            toolchain_hash,
-            timestamp: chrono::Local::now().to_rfc3339(),
-            git_features,
-        })
+        let (_, exit_code, _, _) = self.run_cargo_with_cache("build", &args, &workspace)?;
+        if exit_code != Some(0) {
+            anyhow::bail!("Warm-up build failed with exit code {:?}", exit_code);
+        }
+
+        let after = self.plan("build", &args, &workspace)?;
+        println!(
+            "{} Cached packages: {}/{} before, {}/{} after",
+            LOG_PREFIX,
+            before.cached_count,
+            before.total_packages,
+            after.cached_count,
+            after.total_packages
+        );
+
+        Ok(())
     }
 
-    /// Builds a dependency graph from the workspace state.
+    /// Generates a unique cache ID for a build.
+    fn generate_cache_id(&self, cmd: &str, args: &[String]) -> String {
+        let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
+        let hash = self.compute_command_hash(cmd, args);
+        format!("{}-{}", timestamp, &hash[..8])
+    }
+
+    /// Runs a cargo command with caching.
     ///
-    /// This graph is used to determine transitive dependencies - when a package
-    /// changes, all packages that depend on it also need to be rebuilt.
+    /// This is the main entry point for building with cargo-save. It:
+    /// 1. Determines which packages need rebuilding
+    /// 2. Runs cargo if needed
+    /// 3. Captures and caches build output
+    /// 4. Updates incremental caches for successful builds
+    ///
+    /// # Returns
+    ///
+    /// Returns a tuple of:
+    /// - Cache ID
+    /// - Exit code (None if process was killed)
+    /// - Number of lines in build output
+    /// - Build duration in milliseconds
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cargo command cannot be executed, or the ref
+    /// set with [`with_git_ref`](Self::with_git_ref) is not checked out
+    /// without uncommitted changes.
     ///
     /// # Example
     ///
@@ -1066,472 +6820,740 @@ impl CacheManager {
     /// # fn main() -> anyhow::Result<()> {
     /// let cache = CacheManager::new()?;
     /// let workspace = cache.compute_workspace_state(&[])?;
-    /// let graph = cache.build_dependency_graph(&workspace);
     ///
-    /// if let Some(node) = graph.packages.get("my-package") {
-    ///     println!("Has {} dependencies", node.dependencies.len());
-    ///     println!("Has {} reverse dependencies", node.reverse_dependencies.len());
-    /// }
+    /// let (cache_id, exit_code, lines, duration) = cache
+    ///     .run_cargo_with_cache("build", &[], &workspace)?;
+    ///
+    /// println!("Build {} completed with exit code {:?}", cache_id, exit_code);
+    /// println!("Output: {} lines in {}ms", lines, duration);
     /// # Ok(())
     /// # }
     /// ```
-    pub fn build_dependency_graph(&self, workspace_state: &WorkspaceState) -> DependencyGraph {
-        let mut packages = HashMap::new();
-
-        for package in &workspace_state.packages {
-            let reverse_deps: Vec<String> = workspace_state
-                .packages
-                .iter()
-                .filter(|p| p.dependencies.contains(&package.name))
-                .map(|p| p.name.clone())
-                .collect();
-
-            packages.insert(
-                package.name.clone(),
-                PackageNode {
-                    name: package.name.clone(),
-                    dependencies: package.dependencies.clone(),
-                    reverse_dependencies: reverse_deps,
-                },
-            );
-        }
-
-        DependencyGraph { packages }
+    pub fn run_cargo_with_cache(
+        &self,
+        subcommand: &str,
+        args: &[String],
+        workspace_state: &WorkspaceState,
+    ) -> Result<(String, Option<i32>, usize, u64)> {
+        self.run_cargo_with_cache_env(subcommand, args, workspace_state, &self.capture_env_vars())
     }
 
-    /// Computes a hash for a cargo command.
+    /// Same as [`run_cargo_with_cache`](Self::run_cargo_with_cache), but with
+    /// the build-affecting variables taken from `env` instead of the process
+    /// environment.
     ///
-    /// This includes the subcommand, arguments, and current working directory.
-    pub fn compute_command_hash(&self, subcommand: &str, args: &[String]) -> String {
-        let mut hasher = Blake3Hasher::new();
-        hasher.update(subcommand.as_bytes());
-        hasher.update(args.join(" ").as_bytes());
-
-        if let Ok(cwd) = std::env::current_dir() {
-            hasher.update(cwd.to_string_lossy().as_bytes());
-        }
-
-        hasher.finalize().to_hex()[..HASH_DISPLAY_LEN].to_string()
-    }
-
-    /// Checks if the arguments indicate a release build.
-    pub fn is_release_build(&self, args: &[String]) -> bool {
-        args.iter()
-            .any(|arg| arg == "--release" || arg.starts_with("--release"))
+    /// `env` is hashed in place of the process environment and set on the
+    /// cargo process; variables of [`ENV_VARS_THAT_AFFECT_BUILD`] missing
+    /// from it are removed there, so the build sees exactly what was hashed.
+    /// The target directory and target triples are still read from `args`
+    /// and the process environment.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cargo command cannot be executed, or the ref
+    /// set with [`with_git_ref`](Self::with_git_ref) is not checked out
+    /// without uncommitted changes.
+    pub fn run_cargo_with_cache_env(
+        &self,
+        subcommand: &str,
+        args: &[String],
+        workspace_state: &WorkspaceState,
+        env: &BTreeMap<String, String>,
+    ) -> Result<(String, Option<i32>, usize, u64)> {
+        let cache_id = self.generate_cache_id(subcommand, args);
+        let result = self.run_build(&cache_id, subcommand, args, workspace_state, env);
+        self.finish_on_error(&cache_id, result)
     }
 
-    /// Gets the target directory from arguments or environment.
-    pub fn get_target_dir(&self, args: &[String]) -> Option<PathBuf> {
-        for (i, arg) in args.iter().enumerate() {
-            if arg == "--target-dir" {
-                return args.get(i + 1).map(PathBuf::from);
-            }
-            if arg.starts_with("--target-dir=") {
-                return arg.split('=').nth(1).map(PathBuf::from);
-            }
-        }
-
-        if let Ok(target_dir) = std::env::var("CARGO_TARGET_DIR") {
-            return Some(PathBuf::from(target_dir));
+    /// Emits a failed `finished` event if `result` is an error, so JSON
+    /// consumers also see the end of a build that stopped early.
+    fn finish_on_error<T>(&self, cache_id: &str, result: Result<T>) -> Result<T> {
+        if let Err(e) = &result {
+            self.emit(&BuildEvent::Finished {
+                cache_id: cache_id.to_string(),
+                success: false,
+                exit_code: None,
+                duration_ms: 0,
+                fresh: false,
+                error: Some(format!("{:#}", e)),
+            });
         }
-
-        None
-    }
-
-    /// Generates a cache key for a package build.
-    fn get_cache_key(
-        &self,
-        package: &PackageHash,
-        command_hash: &str,
-        env_hash: &str,
-        is_release: bool,
-        features_hash: &str,
-    ) -> String {
-        format!(
-            "{}-{}-{}-{}-{}-{}",
-            package.name,
-            &package.source_hash[..HASH_DISPLAY_LEN],
-            command_hash,
-            env_hash,
-            if is_release { "release" } else { "debug" },
-            features_hash
-        )
+        result
     }
 
-    /// Checks if a valid incremental cache exists for a package.
-    ///
-    /// Returns `Some(IncrementalCache)` if a valid cache is found, `None` otherwise.
-    /// A cache is valid if:
-    /// - The Cargo.lock hash matches
-    /// - The environment hash matches
-    /// - The features hash matches
-    /// - The source hash matches
-    /// - All target files exist with correct sizes
-    pub fn check_incremental_cache(
+    /// Runs the build `cache_id` for [`run_cargo_with_cache_env`](Self::run_cargo_with_cache_env).
+    fn run_build(
         &self,
-        package: &PackageHash,
-        workspace_state: &WorkspaceState,
-        command_hash: &str,
-        env_hash: &str,
-        is_release: bool,
+        cache_id: &str,
+        subcommand: &str,
         args: &[String],
-    ) -> Option<IncrementalCache> {
-        let features_hash = self.compute_features_hash(args);
+        workspace_state: &WorkspaceState,
+        env: &BTreeMap<String, String>,
+    ) -> Result<(String, Option<i32>, usize, u64)> {
+        self.check_git_ref_checkout(&workspace_state.root)?;
+        let workspace_state = &workspace_state.built_by(args);
+        let _build_lock = self.lock_workspace_build(&workspace_state.root)?;
+        let skip_incremental = Self::skips_incremental(subcommand);
 
-        let cache_key =
-            self.get_cache_key(package, command_hash, env_hash, is_release, &features_hash);
+        let cache_id = cache_id.to_string();
+        let env_hash = self.compute_env_hash_from(env);
 
-        let cache_file = self.incremental_dir.join(format!("{}.json", cache_key));
+        let phase = std::time::Instant::now();
+        if !skip_incremental {
+            self.fetch_remote_entries(subcommand, args, workspace_state, &env_hash);
+        }
+        let plan = self.plan_with_env_hash(subcommand, args, workspace_state, &env_hash)?;
+        self.record_phase(phase, |t| &mut t.change_detection);
+        self.emit_plan(subcommand, &plan, workspace_state);
+
+        if !skip_incremental {
+            for package in self.thrashing_packages(&plan.changed_packages) {
+                log_error!(
+                    "Warning: {} is rebuilt although nothing changed since its last build, which \
+                     modified its sources (generated or timestamped files in {}?). Keep them out \
+                     of the source hash (.gitignore, hash_exclude) or add the package to no_cache.",
+                    package.name,
+                    package.path.display()
+                );
+            }
+            self.record_cache_hits(&plan, workspace_state, subcommand, args, &env_hash);
+            let phase = std::time::Instant::now();
+            self.restore_cached_artifacts(
+                workspace_state,
+                &plan.changed_packages,
+                subcommand,
+                args,
+                &env_hash,
+            );
+            self.record_phase(phase, |t| &mut t.cache_restore);
+        }
 
-        if cache_file.exists() {
-            if let Ok(content) = fs::read_to_string(&cache_file) {
-                if let Ok(cache) = serde_json::from_str::<IncrementalCache>(&content) {
-                    // Check all invalidation conditions
-                    if cache.cargo_lock_hash != workspace_state.cargo_lock_hash {
-                        return None;
-                    }
+        let runs_tests = Self::runs_tests(subcommand, args);
+        if runs_tests && plan.cached_count > 0 {
+            let cached: Vec<&str> = plan
+                .cached_packages(workspace_state)
+                .map(|p| p.name.as_str())
+                .collect();
+            log_info!(
+                "Test-cached (unchanged, passed before): {}",
+                cached.join(", ")
+            );
+        }
 
-                    if cache.env_hash != env_hash {
-                        return None;
-                    }
+        // Skip build if all packages are cached
+        if Self::skips_cached_build(subcommand, args, &plan) {
+            log_info!("All packages cached, skipping {}", subcommand);
+            self.emit(&BuildEvent::Finished {
+                cache_id: cache_id.clone(),
+                success: true,
+                exit_code: Some(0),
+                duration_ms: 0,
+                fresh: true,
+                error: None,
+            });
+            return Ok((cache_id, Some(0), 0, 0));
+        }
 
-                    if cache.features_hash != features_hash {
-                        return None;
-                    }
+        if subcommand == "nextest" && !Self::nextest_available() {
+            anyhow::bail!(
+                "cargo-nextest is not installed; install it with `cargo install cargo-nextest --locked`"
+            );
+        }
 
-                    let all_valid = cache.target_files.iter().all(|(path, expected_size)| {
-                        match fs::metadata(path) {
-                            Ok(metadata) => metadata.len() == *expected_size,
-                            Err(_) => false,
-                        }
-                    });
+        if !plan.changed_packages.is_empty() && !skip_incremental {
+            self.print_build_plan(&plan);
+        }
 
-                    if cache.source_hash != package.source_hash {
-                        return None;
-                    }
+        let test_excludes = if runs_tests {
+            self.test_exclude_args(args, &plan, workspace_state)
+        } else {
+            Vec::new()
+        };
+        let changed_packages = plan.changed_packages;
 
-                    if all_valid && cache.build_success {
-                        return Some(cache);
+        // Check for sccache integration and prompt if not configured
+        match std::env::var("RUSTC_WRAPPER") {
+            Ok(wrapper) if wrapper.contains("sccache") => {
+                log_info!("Using sccache for cross-project caching");
+            }
+            _ => {
+                // Only prompt on actual builds, not on other commands
+                if matches!(subcommand, "build" | "test") && !changed_packages.is_empty() {
+                    // Check if we should prompt (only once per session)
+                    static PROMPTED: std::sync::atomic::AtomicBool =
+                        std::sync::atomic::AtomicBool::new(false);
+                    if !PROMPTED.swap(true, std::sync::atomic::Ordering::Relaxed) {
+                        let _ = Self::prompt_sccache_setup();
                     }
                 }
             }
         }
 
-        None
-    }
+        log_info!("Running: cargo {} {}", subcommand, args.join(" "));
+        log_info!("Cache ID: {}", cache_id);
 
-    /// Saves incremental cache for a package after a successful build.
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if the cache file cannot be written.
-    #[allow(clippy::too_many_arguments)]
-    pub fn save_incremental_cache(
-        &self,
-        package: &PackageHash,
-        workspace_state: &WorkspaceState,
-        command_hash: &str,
-        env_hash: &str,
-        is_release: bool,
-        args: &[String],
-        build_success: bool,
-        duration_ms: u64,
-    ) -> Result<()> {
-        let features_hash = self.compute_features_hash(args);
+        let start_time = std::time::Instant::now();
 
-        let target_dir = self
-            .get_target_dir(args)
-            .unwrap_or_else(|| workspace_state.root.join("target"));
+        // Spawn cargo process
+        let mut command = Command::new(cargo_bin());
+        for var in ENV_VARS_THAT_AFFECT_BUILD {
+            if !env.contains_key(*var) {
+                command.env_remove(var);
+            }
+        }
+        let mut child = command
+            .arg(subcommand)
+            .args(args)
+            .args(self.timings_flag(subcommand, args))
+            .args(test_excludes)
+            .envs(
+                self.extra_env
+                    .iter()
+                    .filter(|(var, _)| !ENV_VARS_THAT_AFFECT_BUILD.contains(&var.as_str())),
+            )
+            .envs(env)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(cargo_spawn_error)?;
 
-        let profile = if is_release { "release" } else { "debug" };
-        let deps_dir = target_dir.join(profile).join(".fingerprint");
-        let deps_build_dir = target_dir.join(profile).join("deps");
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
 
-        let mut target_files = Vec::new();
-        let mut artifact_paths = Vec::new();
+        let mut log =
+            BuildLogWriter::create(&self.cache_dir, &cache_id, self.collapse_repeated_lines)?;
+        let mut line_count = 0;
+        let mut progress = ProgressCounter::new(
+            changed_packages.iter().map(|p| p.name.clone()),
+            ProgressCounter::streams_from_env(),
+        );
+        let mut diagnostics = DiagnosticCounts::default();
+        let mut outcomes = CompileOutcomes::default();
 
-        if deps_dir.exists() {
-            for entry in WalkDir::new(&deps_dir).max_depth(2).into_iter().flatten() {
-                if entry.file_type().is_file() {
-                    let path_str = entry.path().to_string_lossy();
-                    if path_str.contains(&package.name) {
-                        if let Ok(metadata) = fs::metadata(entry.path()) {
-                            target_files.push((entry.path().to_path_buf(), metadata.len()));
-                        }
-                    }
-                }
+        // Set up channels for output capture
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tx_stderr = tx.clone();
+
+        // Spawn threads to read stdout and stderr
+        std::thread::spawn(move || {
+            let reader = BufReader::new(stdout);
+            for line in reader.lines().map_while(Result::ok) {
+                let _ = tx.send((line, false));
             }
-        }
+        });
 
-        if deps_build_dir.exists() {
-            for entry in WalkDir::new(&deps_build_dir)
-                .max_depth(1)
-                .into_iter()
-                .flatten()
-            {
-                if entry.file_type().is_file() {
-                    let path_str = entry.path().to_string_lossy();
-                    if path_str.contains(&package.name) {
-                        if let Ok(metadata) = fs::metadata(entry.path()) {
-                            target_files.push((entry.path().to_path_buf(), metadata.len()));
-                            artifact_paths.push(entry.path().to_path_buf());
-                        }
-                    }
-                }
+        std::thread::spawn(move || {
+            let reader = BufReader::new(stderr);
+            for line in reader.lines().map_while(Result::ok) {
+                let _ = tx_stderr.send((line, true));
+            }
+        });
+
+        // Process output lines
+        for (line, is_stderr) in rx {
+            let progress_info = progress.annotate(&line, is_stderr).unwrap_or_default();
+            if is_stderr {
+                eprintln!("{}{}", line, progress_info);
+            } else {
+                println!("{}{}", line, progress_info);
             }
+            log.write_line(&line, is_stderr)?;
+            diagnostics.record(&line);
+            outcomes.record(&line);
+            line_count += 1;
         }
 
-        let cache = IncrementalCache {
-            package_name: package.name.clone(),
-            package_version: package.version.clone(),
-            source_hash: package.source_hash.clone(),
-            cargo_lock_hash: workspace_state.cargo_lock_hash.clone(),
-            command_hash: command_hash.to_string(),
-            env_hash: env_hash.to_string(),
-            is_release,
-            features_hash: features_hash.clone(),
-            target_files,
-            artifact_paths,
-            timestamp: chrono::Local::now().to_rfc3339(),
-            build_success,
-            duration_ms,
+        let exit_code = child.wait()?.code();
+        let duration = start_time.elapsed().as_millis() as u64;
+        self.record_phase(start_time, |t| &mut t.cargo);
+        let build_success = exit_code == Some(0);
+
+        let built_packages = if skip_incremental {
+            vec![]
+        } else if build_success {
+            changed_packages
+        } else if runs_tests || exit_code.is_none() {
+            // Compiling is not enough, the tests of these packages may have
+            // failed; and a killed cargo may not have finished writing them
+            vec![]
+        } else {
+            let compiled: Vec<PackageHash> = changed_packages
+                .into_iter()
+                .filter(|p| outcomes.succeeded(&p.name))
+                .collect();
+            if !compiled.is_empty() {
+                log_info!(
+                    "Build failed, caching packages that compiled: {}",
+                    compiled
+                        .iter()
+                        .map(|p| p.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+            compiled
         };
 
-        let cache_key =
-            self.get_cache_key(package, command_hash, env_hash, is_release, &features_hash);
+        let phase = std::time::Instant::now();
+        drop(log);
+        self.finish_build(
+            &cache_id,
+            subcommand,
+            args,
+            workspace_state,
+            exit_code,
+            line_count,
+            diagnostics,
+            duration,
+            &built_packages,
+            env,
+        )?;
+        self.record_phase(phase, |t| &mut t.cache_save);
+        self.emit(&BuildEvent::Finished {
+            cache_id: cache_id.clone(),
+            success: exit_code == Some(0),
+            exit_code,
+            duration_ms: duration,
+            fresh: false,
+            error: None,
+        });
+        self.report_file_hashing(workspace_state);
 
-        let cache_file = self.incremental_dir.join(format!("{}.json", cache_key));
-        fs::write(&cache_file, serde_json::to_string_pretty(&cache)?)?;
+        Ok((cache_id, exit_code, line_count, duration))
+    }
 
-        Ok(())
+    /// Warns about the packages of `workspace_state` whose source hash came
+    /// from walking their files instead of git.
+    fn report_file_hashing(&self, workspace_state: &WorkspaceState) {
+        let names = file_hashed_packages(workspace_state);
+        if !names.is_empty() {
+            log_error!(
+                "Warning: {} packages used file-based hashing: {}",
+                names.len(),
+                names.join(", ")
+            );
+        }
     }
 
-    /// Gets the list of packages that need rebuilding.
-    ///
-    /// This includes packages that:
-    /// - Don't have a valid cache entry
-    /// - Have transitive dependencies that need rebuilding
-    ///
-    /// # Example
-    ///
-    /// ```no_run
-    /// use cargo_save::CacheManager;
+    /// Returns the time spent in each phase of the workspace hashing and
+    /// builds run with this `CacheManager` so far.
     ///
-    /// # fn main() -> anyhow::Result<()> {
-    /// let cache = CacheManager::new()?;
-    /// let workspace = cache.compute_workspace_state(&[])?;
+    /// Phases are recorded by [`compute_workspace_state`](Self::compute_workspace_state)
+    /// and [`run_cargo_with_cache`](Self::run_cargo_with_cache).
+    pub fn phase_timings(&self) -> PhaseTimings {
+        self.timings.lock().unwrap().timings
+    }
+
+    /// Summarizes the build `cache_id` of `cargo <subcommand> <args>`, planned
+    /// as `plan` before it ran.
     ///
-    /// let changed = cache.get_changed_packages(&workspace, "cmd_hash", "env_hash", false, &[]);
-    /// println!("Packages needing rebuild: {:?}", changed.iter().map(|p| &p.name).collect::<Vec<_>>());
-    /// # Ok(())
-    /// # }
-    /// ```
-    pub fn get_changed_packages(
+    /// Diagnostic counts and the log path come from the build metadata;
+    /// builds skipped because every package was cached have neither.
+    pub fn build_summary(
         &self,
-        workspace_state: &WorkspaceState,
-        command_hash: &str,
-        env_hash: &str,
-        is_release: bool,
+        subcommand: &str,
         args: &[String],
-    ) -> Vec<PackageHash> {
-        let mut changed = Vec::new();
-        let mut checked: HashSet<String> = HashSet::new();
+        cache_id: &str,
+        exit_code: Option<i32>,
+        duration_ms: u64,
+        plan: &BuildPlan,
+    ) -> BuildSummary {
+        // A skipped build shares its ID with a build of the same second
+        let ran = !Self::skips_cached_build(subcommand, args, plan);
+        let build = self.load_build_cache(cache_id).ok().filter(|_| ran);
+        let log_path =
+            Some(self.log_file(cache_id, LogStream::Both)).filter(|path| ran && path.exists());
+        BuildSummary {
+            cache_id: cache_id.to_string(),
+            exit_code,
+            duration_ms,
+            total_packages: plan.total_packages,
+            cached_count: plan.cached_count,
+            rebuilt_count: plan.changed_packages.len(),
+            error_count: build.as_ref().map_or(0, |b| b.error_count),
+            warning_count: build.as_ref().map_or(0, |b| b.warning_count),
+            log_path,
+        }
+    }
 
-        // First pass: find packages without valid cache
-        for package in &workspace_state.packages {
-            if self
-                .check_incremental_cache(
-                    package,
-                    workspace_state,
-                    command_hash,
-                    env_hash,
-                    is_release,
-                    args,
-                )
-                .is_none()
-            {
-                changed.push(package.clone());
-                checked.insert(package.name.clone());
-            }
+    /// Prints `timings` as a table of milliseconds per phase.
+    pub fn print_phase_timings(&self, timings: &PhaseTimings) {
+        let phases = [
+            ("cargo metadata", timings.metadata),
+            ("source hashing", timings.source_hashing),
+            ("change detection", timings.change_detection),
+            ("cache restore", timings.cache_restore),
+            ("cargo", timings.cargo),
+            ("cache save", timings.cache_save),
+            ("total", timings.total),
+        ];
+        log_info!("Timing report:");
+        for (phase, duration) in phases {
+            log_info!("  {:<16} {:>8} ms", phase, duration.as_millis());
         }
+    }
 
-        // Build dependency graph for transitive invalidation
-        let graph = self.build_dependency_graph(workspace_state);
+    /// Adds the time since `started` to the phase selected by `phase`.
+    fn record_phase(
+        &self,
+        started: std::time::Instant,
+        phase: impl FnOnce(&mut PhaseTimings) -> &mut Duration,
+    ) {
+        let mut recorder = self.timings.lock().unwrap();
+        let start = *recorder.start.get_or_insert(started);
+        *phase(&mut recorder.timings) += started.elapsed();
+        recorder.timings.total = start.elapsed();
+    }
 
-        // Iteratively find all packages that depend on changed packages
-        let mut iteration = 0;
-        loop {
-            let mut new_changed = Vec::new();
+    /// Runs a cargo subcommand that produces nothing to cache, such as
+    /// `cargo fmt`, capturing its output in a build log.
+    ///
+    /// Unlike [`run_cargo_with_cache`](Self::run_cargo_with_cache) the
+    /// workspace is not hashed and no build metadata is written; the log can
+    /// still be read with `query --id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cargo command cannot be executed or the log
+    /// cannot be written.
+    pub fn run_cargo_passthrough(
+        &self,
+        subcommand: &str,
+        args: &[String],
+    ) -> Result<(String, Option<i32>, usize, u64)> {
+        let cache_id = self.generate_cache_id(subcommand, args);
+        log_info!("Running: cargo {} {}", subcommand, args.join(" "));
+        log_info!("Cache ID: {}", cache_id);
 
-            for package in &workspace_state.packages {
-                if checked.contains(&package.name) {
-                    continue;
-                }
+        let start_time = std::time::Instant::now();
+        let mut child = Command::new(cargo_bin())
+            .arg(subcommand)
+            .args(args)
+            .envs(&self.extra_env)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(cargo_spawn_error)?;
 
-                if let Some(node) = graph.packages.get(&package.name) {
-                    for dep in &node.dependencies {
-                        if changed.iter().any(|p| &p.name == dep) {
-                            new_changed.push(package.clone());
-                            checked.insert(package.name.clone());
-                            break;
-                        }
-                    }
-                }
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tx_stderr = tx.clone();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                let _ = tx.send((line, false));
             }
-
-            if new_changed.is_empty() || iteration > workspace_state.packages.len() {
-                break;
+        });
+        std::thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                let _ = tx_stderr.send((line, true));
             }
+        });
 
-            changed.extend(new_changed);
-            iteration += 1;
+        let mut log =
+            BuildLogWriter::create(&self.cache_dir, &cache_id, self.collapse_repeated_lines)?;
+        let mut line_count = 0;
+        for (line, is_stderr) in rx {
+            if is_stderr {
+                eprintln!("{}", line);
+            } else {
+                println!("{}", line);
+            }
+            log.write_line(&line, is_stderr)?;
+            line_count += 1;
         }
 
-        changed
-    }
+        let exit_code = child.wait()?.code();
+        let duration = start_time.elapsed().as_millis() as u64;
+        self.emit(&BuildEvent::Finished {
+            cache_id: cache_id.clone(),
+            success: exit_code == Some(0),
+            exit_code,
+            duration_ms: duration,
+            fresh: false,
+            error: None,
+        });
 
-    /// Generates a unique cache ID for a build.
-    fn generate_cache_id(&self, cmd: &str, args: &[String]) -> String {
-        let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
-        let hash = self.compute_command_hash(cmd, args);
-        format!("{}-{}", timestamp, &hash[..8])
+        Ok((cache_id, exit_code, line_count, duration))
     }
 
-    /// Runs a cargo command with caching.
-    ///
-    /// This is the main entry point for building with cargo-save. It:
-    /// 1. Determines which packages need rebuilding
-    /// 2. Runs cargo if needed
-    /// 3. Captures and caches build output
-    /// 4. Updates incremental caches for successful builds
-    ///
-    /// # Returns
-    ///
-    /// Returns a tuple of:
-    /// - Cache ID
-    /// - Exit code (None if process was killed)
-    /// - Number of lines in build output
-    /// - Build duration in milliseconds
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if the cargo command cannot be executed.
-    ///
-    /// # Example
-    ///
-    /// ```no_run
-    /// use cargo_save::CacheManager;
-    ///
-    /// # fn main() -> anyhow::Result<()> {
-    /// let cache = CacheManager::new()?;
-    /// let workspace = cache.compute_workspace_state(&[])?;
-    ///
-    /// let (cache_id, exit_code, lines, duration) = cache
-    ///     .run_cargo_with_cache("build", &[], &workspace)?;
+    /// Records the outcome of a finished build.
     ///
-    /// println!("Build {} completed with exit code {:?}", cache_id, exit_code);
-    /// println!("Output: {} lines in {}ms", lines, duration);
-    /// # Ok(())
-    /// # }
-    /// ```
-    pub fn run_cargo_with_cache(
+    /// Copies the log into the workspace `build-logs/` directory, writes the
+    /// build metadata and saves incremental caches for `built_packages`.
+    #[allow(clippy::too_many_arguments)]
+    fn finish_build(
         &self,
+        cache_id: &str,
         subcommand: &str,
         args: &[String],
         workspace_state: &WorkspaceState,
-    ) -> Result<(String, Option<i32>, usize, u64)> {
-        let skip_incremental = matches!(subcommand, "clean" | "update" | "new" | "init");
-
-        let cache_id = self.generate_cache_id(subcommand, args);
+        exit_code: Option<i32>,
+        line_count: usize,
+        diagnostics: DiagnosticCounts,
+        duration: u64,
+        built_packages: &[PackageHash],
+        env: &BTreeMap<String, String>,
+    ) -> Result<()> {
         let log_file = self.cache_dir.join(format!("{}.log", cache_id));
         let meta_file = self.metadata_dir.join(format!("{}.json", cache_id));
 
         let is_release = self.is_release_build(args);
         let command_hash = self.compute_command_hash(subcommand, args);
-        let env_hash = self.compute_env_hash();
+        let env_hash = self.compute_env_hash_from(env);
 
-        let changed_packages = if skip_incremental {
-            vec![]
+        // Copy log to workspace build-logs/ directory
+        if let Ok(workspace_root) = workspace_state.root.canonicalize() {
+            let build_logs_dir = workspace_root.join("build-logs");
+            if let Ok(()) = fs::create_dir_all(&build_logs_dir) {
+                let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
+                let log_copy = build_logs_dir.join(format!("{}_{}.txt", timestamp, subcommand));
+                let _ = fs::copy(&log_file, &log_copy);
+            }
+        }
+
+        let (head, branch) = self.git_head(&workspace_state.root);
+        // The sources were hashed at the ref, not necessarily at HEAD
+        let commit = match &self.git_ref {
+            Some(git_ref) => self.resolve_git_ref(&workspace_state.root, git_ref).ok(),
+            None => head,
+        };
+
+        // Save build metadata
+        let build_cache = BuildCache {
+            cache_id: cache_id.to_string(),
+            command: format!("cargo {} {}", subcommand, args.join(" ")),
+            subcommand: subcommand.to_string(),
+            args: args.to_vec(),
+            timestamp: chrono::Local::now().to_rfc3339(),
+            exit_code,
+            workspace_state: workspace_state.clone(),
+            is_release,
+            target_dir: self.get_target_dir(args),
+            lines_count: line_count,
+            duration_ms: duration,
+            env_hash: env_hash.clone(),
+            env_vars: env
+                .iter()
+                .filter(|(var, _)| ENV_VARS_THAT_AFFECT_BUILD.contains(&var.as_str()))
+                .map(|(var, value)| (var.clone(), value.clone()))
+                .collect(),
+            error_count: diagnostics.errors,
+            warning_count: diagnostics.warnings,
+            commit,
+            branch,
+        };
+
+        // Serialize metadata and cache writes with other builds of this workspace
+        let workspace_id = blake3::hash(workspace_state.root.to_string_lossy().as_bytes())
+            .to_hex()
+            .to_string();
+        let _lock = self.lock(&format!("workspace-{}", &workspace_id[..HASH_KEY_LEN]))?;
+
+        fs::write(&meta_file, serde_json::to_string_pretty(&build_cache)?)?;
+
+        // Prefer real per-crate timings; fall back to an even split
+        let timings = if self.timings_requested(subcommand, args) {
+            self.read_build_timings(args, workspace_state)
         } else {
-            self.get_changed_packages(workspace_state, &command_hash, &env_hash, is_release, args)
+            HashMap::new()
         };
 
-        // Skip build if all packages are cached
-        if changed_packages.is_empty()
-            && matches!(subcommand, "build" | "check" | "clippy" | "test")
-        {
-            eprintln!(
-                "{} All packages cached, skipping {}",
-                LOG_PREFIX, subcommand
-            );
-            return Ok((cache_id, Some(0), 0, 0));
+        // Built packages only reach here on success, so their tests passed
+        let tests_passed = Self::runs_tests(subcommand, args).then_some(true);
+
+        // Save incremental caches for successfully built packages
+        for package in built_packages {
+            let pkg_duration = timings
+                .get(&package.name)
+                .copied()
+                .unwrap_or(duration / built_packages.len().max(1) as u64);
+
+            if let Err(e) = self.write_incremental_cache(
+                package,
+                workspace_state,
+                &command_hash,
+                &env_hash,
+                is_release,
+                args,
+                true,
+                pkg_duration,
+                tests_passed,
+                Some(subcommand),
+            ) {
+                log_error!("Failed to save cache for {}: {}", package.name, e);
+            }
         }
 
-        let total_packages = workspace_state.packages.len();
-        let cached_count = total_packages - changed_packages.len();
+        self.record_source_hashes(built_packages, args);
 
-        if !changed_packages.is_empty() && !skip_incremental {
-            eprintln!(
-                "{} Build plan: {}/{} packages cached, {} need rebuild",
-                LOG_PREFIX,
-                cached_count,
-                total_packages,
-                changed_packages.len()
-            );
-            eprintln!("{} Packages to rebuild:", LOG_PREFIX);
-            for pkg in &changed_packages {
-                eprintln!("{}   - {}", LOG_PREFIX, pkg.name);
-            }
+        if log_compression_enabled() {
+            self.compress_build_logs(cache_id);
         }
+        self.prune_excess_builds();
 
-        // Check for sccache integration and prompt if not configured
-        match std::env::var("RUSTC_WRAPPER") {
-            Ok(wrapper) if wrapper.contains("sccache") => {
-                eprintln!("{} Using sccache for cross-project caching", LOG_PREFIX);
+        log_info!("Cached {} lines to: {}", line_count, cache_id);
+        log_info!("Duration: {}ms", duration);
+
+        Ok(())
+    }
+
+    /// Removes the logs and metadata of the builds beyond the
+    /// [`with_max_builds`](Self::with_max_builds) cap, oldest first.
+    /// Incremental caches are left to `clean`.
+    fn prune_excess_builds(&self) {
+        let Some(max) = self.max_builds else {
+            return;
+        };
+        let candidates = match self.clean_candidates(0, Some(max)) {
+            Ok(candidates) => candidates,
+            Err(e) => {
+                log_error!("Warning: failed to prune old builds: {}", e);
+                return;
             }
-            _ => {
-                // Only prompt on actual builds, not on other commands
-                if matches!(subcommand, "build" | "test") && !changed_packages.is_empty() {
-                    // Check if we should prompt (only once per session)
-                    static PROMPTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
-                    if !PROMPTED.swap(true, std::sync::atomic::Ordering::Relaxed) {
-                        let _ = Self::prompt_sccache_setup();
-                    }
-                }
+        };
+        let mut removed = 0;
+        for path in candidates
+            .iter()
+            .filter(|path| !path.starts_with(&self.incremental_dir))
+        {
+            if fs::remove_file(path).is_ok() && is_build_log(path) {
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            log_debug!("Removed {} builds beyond max_builds = {}", removed, max);
+        }
+    }
+
+    /// Returns the file holding a [`SourceHashRecord`] per package directory.
+    fn source_hashes_file(&self) -> PathBuf {
+        self.cache_dir.join("source-hashes.json")
+    }
+
+    /// Loads the [`SourceHashRecord`]s, keyed by package directory.
+    fn load_source_hashes(&self) -> BTreeMap<String, SourceHashRecord> {
+        fs::read_to_string(self.source_hashes_file())
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Returns the `changed` packages whose sources are exactly as their last
+    /// build left them, although that build changed them.
+    ///
+    /// Nothing was edited since, yet the package misses the cache again: its
+    /// build writes into the package, so its source hash never settles.
+    /// Packages in the `no_cache` list are left out.
+    fn thrashing_packages<'a>(&self, changed: &'a [PackageHash]) -> Vec<&'a PackageHash> {
+        let records = self.load_source_hashes();
+        changed
+            .iter()
+            .filter(|package| !self.no_cache.contains(&package.name))
+            .filter(|package| {
+                records
+                    .get(&package.path.to_string_lossy().into_owned())
+                    .is_some_and(|record| {
+                        record.after_build == package.source_hash
+                            && record.built_from != record.after_build
+                    })
+            })
+            .collect()
+    }
+
+    /// Rehashes `built_packages` after their build and records both hashes
+    /// for [`thrashing_packages`](Self::thrashing_packages).
+    fn record_source_hashes(&self, built_packages: &[PackageHash], args: &[String]) {
+        if built_packages.is_empty() {
+            return;
+        }
+        let updates: Vec<(String, SourceHashRecord)> = built_packages
+            .iter()
+            .filter_map(|package| {
+                let after_build = self.compute_source_hash(&package.path, args).ok()?;
+                let record = SourceHashRecord {
+                    built_from: package.source_hash.clone(),
+                    after_build,
+                };
+                Some((package.path.to_string_lossy().into_owned(), record))
+            })
+            .collect();
+
+        let Ok(_lock) = self.lock("source-hashes") else {
+            return;
+        };
+        let mut records = self.load_source_hashes();
+        records.extend(updates);
+        if let Ok(content) = serde_json::to_string_pretty(&records) {
+            if let Err(e) = fs::write(self.source_hashes_file(), content) {
+                log_debug!("Failed to record source hashes: {}", e);
             }
         }
+    }
 
-        eprintln!(
-            "{} Running: cargo {} {}",
-            LOG_PREFIX,
-            subcommand,
-            args.join(" ")
-        );
-        eprintln!("{} Cache ID: {}", LOG_PREFIX, cache_id);
+    /// Returns true if the build writes a `--timings` report, either because
+    /// the user passed the flag or `CARGO_SAVE_TIMINGS` is set.
+    fn timings_requested(&self, subcommand: &str, args: &[String]) -> bool {
+        args.iter()
+            .any(|arg| arg == "--timings" || arg.starts_with("--timings="))
+            || !self.timings_flag(subcommand, args).is_empty()
+    }
 
-        let start_time = std::time::Instant::now();
+    /// Returns the extra `--timings` argument to pass to cargo when
+    /// `CARGO_SAVE_TIMINGS` is set and the user did not pass it already.
+    ///
+    /// The flag is not part of `args` so it does not affect the command hash.
+    fn timings_flag(&self, subcommand: &str, args: &[String]) -> Vec<String> {
+        let enabled = std::env::var("CARGO_SAVE_TIMINGS")
+            .map(|v| !v.is_empty() && v != "0")
+            .unwrap_or(false);
+        let supported = matches!(subcommand, "build" | "check" | "test" | "bench" | "clippy");
+        let present = args
+            .iter()
+            .any(|arg| arg == "--timings" || arg.starts_with("--timings="));
+        if enabled && supported && !present {
+            vec!["--timings".to_string()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Reads per-package durations from the `cargo-timings` report of the
+    /// last build, returning an empty map if it is missing.
+    fn read_build_timings(
+        &self,
+        args: &[String],
+        workspace_state: &WorkspaceState,
+    ) -> HashMap<String, u64> {
+        let report = self
+            .get_target_dir(args)
+            .unwrap_or_else(|| workspace_state.root.join("target"))
+            .join("cargo-timings")
+            .join("cargo-timing.html");
+        fs::read_to_string(report)
+            .map(|content| parse_timings_html(&content))
+            .unwrap_or_default()
+    }
 
-        // Spawn cargo process
-        let mut child = Command::new("cargo")
+    /// Runs cargo to completion, capturing stdout and stderr lines.
+    ///
+    /// Each captured line is paired with `true` if it came from stderr.
+    fn run_cargo_captured(&self, subcommand: &str, args: &[String]) -> Result<CapturedOutput> {
+        let mut child = Command::new(cargo_bin())
             .arg(subcommand)
             .args(args)
+            .envs(&self.extra_env)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
-            .context("Failed to spawn cargo process")?;
+            .map_err(cargo_spawn_error)?;
 
         let stdout = child.stdout.take().unwrap();
         let stderr = child.stderr.take().unwrap();
 
-        let mut log = File::create(&log_file)?;
-        let mut line_count = 0;
-        let mut compiled_count = 0;
-
-        // Set up channels for output capture
         let (tx, rx) = std::sync::mpsc::channel();
         let tx_stderr = tx.clone();
 
-        // Spawn threads to read stdout and stderr
         std::thread::spawn(move || {
             let reader = BufReader::new(stdout);
             for line in reader.lines().map_while(Result::ok) {
@@ -1546,93 +7568,345 @@ impl CacheManager {
             }
         });
 
-        // Process output lines
-        for (line, is_stderr) in rx {
-            if line.trim().starts_with("Compiling ") || line.trim().starts_with("Building ") {
-                compiled_count += 1;
-                if !changed_packages.is_empty() {
-                    let progress_info = format!(" [{}/{}]", compiled_count, changed_packages.len());
-                    if is_stderr {
-                        eprintln!("{}{}", line, progress_info);
+        let lines: Vec<(String, bool)> = rx.into_iter().collect();
+        let exit_code = child.wait()?.code();
+
+        Ok((exit_code, lines))
+    }
+
+    /// Runs a cargo command with caching, building independent packages in parallel.
+    ///
+    /// The changed packages are grouped into waves with
+    /// [`DependencyGraph::build_waves`]. Every package in a wave is built with its
+    /// own `cargo <subcommand> -p <package>` invocation on a pool of `jobs`
+    /// threads, and the next wave starts once the previous one finished. Output
+    /// of each invocation is printed as a block once it completes and all blocks
+    /// are aggregated into a single cached log.
+    ///
+    /// Falls back to [`run_cargo_with_cache`](Self::run_cargo_with_cache) when the
+    /// subcommand is not a build-like command, when the arguments already select
+    /// or exclude packages, when nothing needs rebuilding, or when the changed
+    /// packages cannot be ordered into waves. `--workspace` is not passed to the
+    /// per-package invocations.
+    ///
+    /// Note that cargo serializes concurrent invocations sharing a target
+    /// directory, so the speedup depends on how much time is spent outside of
+    /// the build directory lock.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a cargo command cannot be executed, or the ref
+    /// set with [`with_git_ref`](Self::with_git_ref) is not checked out
+    /// without uncommitted changes.
+    pub fn run_cargo_parallel(
+        &self,
+        subcommand: &str,
+        args: &[String],
+        workspace_state: &WorkspaceState,
+        jobs: usize,
+    ) -> Result<(String, Option<i32>, usize, u64)> {
+        let cache_id = self.generate_cache_id(subcommand, args);
+        let result = self.run_parallel_build(&cache_id, subcommand, args, workspace_state, jobs);
+        self.finish_on_error(&cache_id, result)
+    }
+
+    /// Runs the build `cache_id` for [`run_cargo_parallel`](Self::run_cargo_parallel).
+    fn run_parallel_build(
+        &self,
+        cache_id: &str,
+        subcommand: &str,
+        args: &[String],
+        workspace_state: &WorkspaceState,
+        jobs: usize,
+    ) -> Result<(String, Option<i32>, usize, u64)> {
+        let single = || {
+            let env = self.capture_env_vars();
+            self.run_build(cache_id, subcommand, args, workspace_state, &env)
+        };
+        self.check_git_ref_checkout(&workspace_state.root)?;
+        let workspace_state = &workspace_state.built_by(args);
+        let selects_packages = args.iter().take_while(|a| *a != "--").any(|a| {
+            a.starts_with("-p") || a.starts_with("--package") || a.starts_with("--exclude")
+        });
+
+        if !matches!(subcommand, "build" | "check" | "clippy" | "test") || selects_packages {
+            return single();
+        }
+
+        let env = self.capture_env_vars();
+        let env_hash = self.compute_env_hash_from(&env);
+        self.fetch_remote_entries(subcommand, args, workspace_state, &env_hash);
+        let plan = self.plan_with_env_hash(subcommand, args, workspace_state, &env_hash)?;
+        let graph = self.build_dependency_graph(workspace_state);
+        let changed_names: Vec<String> = plan
+            .changed_packages
+            .iter()
+            .map(|p| p.name.clone())
+            .collect();
+
+        let waves = match graph.build_waves(&changed_names) {
+            Some(waves) if !waves.is_empty() => waves,
+            // Nothing to rebuild
+            Some(_) => return single(),
+            None => {
+                log_info!("Dependencies are entangled, falling back to a single cargo invocation");
+                return single();
+            }
+        };
+
+        let _build_lock = self.lock_workspace_build(&workspace_state.root)?;
+        self.emit_plan(subcommand, &plan, workspace_state);
+        self.record_cache_hits(&plan, workspace_state, subcommand, args, &env_hash);
+        self.print_build_plan(&plan);
+        self.restore_cached_artifacts(
+            workspace_state,
+            &plan.changed_packages,
+            subcommand,
+            args,
+            &env_hash,
+        );
+
+        let cache_id = cache_id.to_string();
+
+        log_info!(
+            "Building {} packages in {} waves with {} jobs",
+            changed_names.len(),
+            waves.len(),
+            jobs
+        );
+        log_info!("Cache ID: {}", cache_id);
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs.max(1))
+            .build()
+            .context("Failed to create build thread pool")?;
+
+        let start_time = std::time::Instant::now();
+        let mut log =
+            BuildLogWriter::create(&self.cache_dir, &cache_id, self.collapse_repeated_lines)?;
+        let mut line_count = 0;
+        let mut diagnostics = DiagnosticCounts::default();
+        let mut exit_code = Some(0);
+        let mut succeeded = HashSet::new();
+        // Each invocation builds one package; `--workspace` would build them all
+        let separator = args.iter().position(|a| a == "--").unwrap_or(args.len());
+        let forwarded: Vec<String> = args
+            .iter()
+            .enumerate()
+            .filter(|(i, a)| *i >= separator || !matches!(a.as_str(), "--workspace" | "--all"))
+            .map(|(_, a)| a.clone())
+            .collect();
+
+        for (index, wave) in waves.iter().enumerate() {
+            log_info!("Wave {}/{}: {}", index + 1, waves.len(), wave.join(", "));
+
+            let results: Vec<(String, Result<CapturedOutput>)> = pool.install(|| {
+                wave.par_iter()
+                    .map(|name| {
+                        // nextest takes its own subcommand (`run`) before options
+                        let split = usize::from(subcommand == "nextest").min(forwarded.len());
+                        let mut pkg_args = forwarded[..split].to_vec();
+                        pkg_args.extend(["-p".to_string(), name.clone()]);
+                        pkg_args.extend_from_slice(&forwarded[split..]);
+                        (name.clone(), self.run_cargo_captured(subcommand, &pkg_args))
+                    })
+                    .collect()
+            });
+
+            for (name, result) in results {
+                let (code, lines) = result?;
+
+                for (line, is_stderr) in &lines {
+                    if *is_stderr {
+                        eprintln!("{}", line);
                     } else {
-                        println!("{}{}", line, progress_info);
+                        println!("{}", line);
                     }
-                } else if is_stderr {
-                    eprintln!("{}", line);
-                } else {
-                    println!("{}", line);
+                    log.write_line(line, *is_stderr)?;
+                    diagnostics.record(line);
+                    line_count += 1;
+                }
+
+                if code == Some(0) {
+                    succeeded.insert(name);
+                } else if exit_code == Some(0) {
+                    exit_code = code;
                 }
-            } else if is_stderr {
-                eprintln!("{}", line);
-            } else {
-                println!("{}", line);
             }
-            writeln!(log, "{}", line)?;
-            line_count += 1;
+
+            // Later waves depend on this one, so stop at the first failure
+            if exit_code != Some(0) {
+                break;
+            }
         }
 
-        let exit_code = child.wait()?.code();
         let duration = start_time.elapsed().as_millis() as u64;
-        let build_success = exit_code == Some(0);
 
-        // Copy log to workspace build-logs/ directory
-        if let Ok(workspace_root) = workspace_state.root.canonicalize() {
-            let build_logs_dir = workspace_root.join("build-logs");
-            if let Ok(()) = fs::create_dir_all(&build_logs_dir) {
-                let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
-                let log_copy = build_logs_dir.join(format!("{}_{}.txt", timestamp, subcommand));
-                let _ = fs::copy(&log_file, &log_copy);
-            }
-        }
+        let built_packages: Vec<PackageHash> = plan
+            .changed_packages
+            .into_iter()
+            .filter(|p| succeeded.contains(&p.name))
+            .collect();
 
-        // Save build metadata
-        let build_cache = BuildCache {
+        drop(log);
+        self.finish_build(
+            &cache_id,
+            subcommand,
+            args,
+            workspace_state,
+            exit_code,
+            line_count,
+            diagnostics,
+            duration,
+            &built_packages,
+            &env,
+        )?;
+        self.emit(&BuildEvent::Finished {
             cache_id: cache_id.clone(),
-            command: format!("cargo {} {}", subcommand, args.join(" ")),
-            subcommand: subcommand.to_string(),
-            args: args.to_vec(),
-            timestamp: chrono::Local::now().to_rfc3339(),
+            success: exit_code == Some(0),
             exit_code,
-            workspace_state: workspace_state.clone(),
-            is_release,
-            target_dir: self.get_target_dir(args),
-            lines_count: line_count,
             duration_ms: duration,
-            env_hash: env_hash.clone(),
+            fresh: false,
+            error: None,
+        });
+        self.report_file_hashing(workspace_state);
+
+        Ok((cache_id, exit_code, line_count, duration))
+    }
+
+    /// Exports the incremental cache into a zstd-compressed tarball.
+    ///
+    /// The archive starts with a `manifest.json` ([`ArchiveManifest`]) followed by
+    /// the incremental cache entries, so it can be used to seed another
+    /// machine with [`import_archive`](Self::import_archive).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive cannot be written.
+    pub fn export_archive(
+        &self,
+        out: &Path,
+        workspace_state: &WorkspaceState,
+    ) -> Result<ArchiveManifest> {
+        let list_files = |dir: &Path| -> Result<Vec<PathBuf>> {
+            let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| p.is_file())
+                .collect();
+            files.sort();
+            Ok(files)
         };
+        let entries = list_files(&self.incremental_dir)?;
+        let objects: Vec<PathBuf> = list_files(&self.objects_dir)?
+            .into_iter()
+            .filter(|p| !p.to_string_lossy().contains(".tmp"))
+            .collect();
 
-        fs::write(&meta_file, serde_json::to_string_pretty(&build_cache)?)?;
+        let manifest = ArchiveManifest {
+            cache_version: CACHE_VERSION.to_string(),
+            toolchain_hash: workspace_state.toolchain_hash.clone(),
+            workspace_root: workspace_state.root.clone(),
+            created: chrono::Local::now().to_rfc3339(),
+            entries: entries.len(),
+            objects: objects.len(),
+        };
 
-        // Save incremental caches for changed packages
-        if !skip_incremental && build_success {
-            for package in &changed_packages {
-                let pkg_duration = duration / changed_packages.len().max(1) as u64;
+        let file = File::create(out)
+            .with_context(|| format!("Failed to create archive {}", out.display()))?;
+        let encoder = zstd::Encoder::new(file, 0)?;
+        let mut builder = tar::Builder::new(encoder);
+
+        let manifest_bytes = serde_json::to_vec_pretty(&manifest)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest_bytes.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, "manifest.json", manifest_bytes.as_slice())?;
+
+        for path in &entries {
+            let name = Path::new("incremental").join(path.file_name().unwrap_or_default());
+            builder.append_path_with_name(path, name)?;
+        }
+        for path in &objects {
+            let name = Path::new("objects").join(path.file_name().unwrap_or_default());
+            builder.append_path_with_name(path, name)?;
+        }
 
-                if let Err(e) = self.save_incremental_cache(
-                    package,
-                    workspace_state,
-                    &command_hash,
-                    &env_hash,
-                    is_release,
-                    args,
-                    build_success,
-                    pkg_duration,
-                ) {
-                    eprintln!(
-                        "{} Failed to save cache for {}: {}",
-                        LOG_PREFIX, package.name, e
+        builder.into_inner()?.finish()?;
+
+        Ok(manifest)
+    }
+
+    /// Imports an archive created by [`export_archive`](Self::export_archive).
+    ///
+    /// Archives from a different cache version are refused. A warning is
+    /// printed if the archive was exported with a different toolchain, since
+    /// its entries will not match local builds.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive cannot be read, has no manifest, or was
+    /// created with a different cache version.
+    pub fn import_archive(&self, archive: &Path) -> Result<ArchiveManifest> {
+        let file = File::open(archive)
+            .with_context(|| format!("Failed to open archive {}", archive.display()))?;
+        let decoder = zstd::Decoder::new(file)?;
+        let mut tar = tar::Archive::new(decoder);
+
+        let mut manifest: Option<ArchiveManifest> = None;
+        let mut imported = 0;
+
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+
+            if path == Path::new("manifest.json") {
+                let parsed: ArchiveManifest =
+                    serde_json::from_reader(&mut entry).context("Invalid archive manifest")?;
+
+                if parsed.cache_version != CACHE_VERSION {
+                    anyhow::bail!(
+                        "Archive cache version {} does not match current version {}",
+                        parsed.cache_version,
+                        CACHE_VERSION
                     );
                 }
+
+                let toolchain_hash = self.compute_toolchain_hash()?;
+                if parsed.toolchain_hash != toolchain_hash {
+                    log_error!("Warning: archive was exported with a different toolchain; its entries may not be reused");
+                }
+
+                manifest = Some(parsed);
+                continue;
+            }
+
+            if manifest.is_none() {
+                anyhow::bail!("Archive is missing manifest.json");
+            }
+
+            if let (Ok(rest), Some(file_name)) =
+                (path.strip_prefix("incremental"), path.file_name())
+            {
+                if rest.components().count() == 1 {
+                    entry.unpack(self.incremental_dir.join(file_name))?;
+                    imported += 1;
+                }
+            } else if let (Ok(rest), Some(file_name)) =
+                (path.strip_prefix("objects"), path.file_name())
+            {
+                if rest.components().count() == 1 {
+                    entry.unpack(self.objects_dir.join(file_name))?;
+                }
             }
         }
 
-        eprintln!(
-            "{} Cached {} lines to: {}",
-            LOG_PREFIX, line_count, cache_id
-        );
-        eprintln!("{} Duration: {}ms", LOG_PREFIX, duration);
+        let manifest = manifest.context("Archive is missing manifest.json")?;
 
-        Ok((cache_id, exit_code, line_count, duration))
+        log_info!("Imported {} incremental cache entries", imported);
+
+        Ok(manifest)
     }
 
     /// Queries cached build logs.
@@ -1673,90 +7947,381 @@ impl CacheManager {
         param: Option<&str>,
         cache_id: Option<&str>,
         last: Option<usize>,
-    ) -> Result<()> {
-        let log_file = if let Some(id) = cache_id {
-            self.cache_dir.join(format!("{}.log", id))
-        } else if let Some(n) = last {
-            let entries = self.get_recent_logs(n)?;
-            if let Some(entry) = entries.last() {
-                self.cache_dir.join(format!("{}.log", entry.cache_id))
-            } else {
-                anyhow::bail!("No cached logs found");
+    ) -> Result<usize> {
+        let options = QueryOptions {
+            cache_id: cache_id.map(str::to_string),
+            last,
+            ..Default::default()
+        };
+        self.query_logs_with(mode, param, &options)
+    }
+
+    /// Queries cached build logs with the given options, printing to stdout.
+    ///
+    /// See [`query_logs`](Self::query_logs) for the supported modes. Returns
+    /// the number of lines printed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CacheError::NoLogs`] if there is no build log at all, or an
+    /// error if the log file cannot be read.
+    pub fn query_logs_with(
+        &self,
+        mode: &str,
+        param: Option<&str>,
+        options: &QueryOptions,
+    ) -> Result<usize> {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        self.query_logs_into(mode, param, options, &mut out)
+    }
+
+    /// Queries cached build logs, writing the matching lines to `out`.
+    ///
+    /// See [`query_logs`](Self::query_logs) for the supported modes. With
+    /// [`QueryOptions::follow`] set, the `tail` mode keeps streaming lines
+    /// appended to the log until the build finishes.
+    ///
+    /// Returns the number of lines written, not counting followed lines, so
+    /// zero means a `grep`, `errors` or `warnings` query found no match.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CacheError::NoLogs`] if there is no build log at all, or an
+    /// error if the log file cannot be read or written to `out`.
+    pub fn query_logs_into(
+        &self,
+        mode: &str,
+        param: Option<&str>,
+        options: &QueryOptions,
+        out: &mut dyn Write,
+    ) -> Result<usize> {
+        if options.all_builds {
+            return self.query_all_builds_into(mode, param, options, out);
+        }
+
+        let cache_id = if let Some(ref id) = options.cache_id {
+            id.clone()
+        } else if options.last.is_some() || options.since.is_some() {
+            let n = options.last.unwrap_or(1);
+            let entries = self.get_recent_logs(n, options.since)?;
+            match entries.get(n.saturating_sub(1)) {
+                Some(entry) => entry.cache_id.clone(),
+                None if options.since.is_some() => {
+                    anyhow::bail!("No cached logs found in the requested time range")
+                }
+                None if entries.is_empty() => return Err(CacheError::NoLogs.into()),
+                None => anyhow::bail!(
+                    "Only {} cached builds found, cannot query build {}",
+                    entries.len(),
+                    n
+                ),
             }
         } else {
-            self.get_latest_log()?
+            log_stem(&self.get_latest_log()?).unwrap_or_default()
         };
 
+        let mut log_file = self.log_file(&cache_id, options.stream);
+
+        let follow = options.follow && matches!(mode, "tail");
+
+        if follow {
+            // The build may not have created its log yet
+            let deadline = std::time::Instant::now() + FOLLOW_WAIT;
+            while !log_file.exists() && std::time::Instant::now() < deadline {
+                std::thread::sleep(FOLLOW_POLL_INTERVAL);
+                log_file = self.log_file(&cache_id, options.stream);
+            }
+        }
+        // A compressed log belongs to a finished build, so there is nothing to follow
+        let follow = follow && !log_file.extension().is_some_and(|ext| ext == "zst");
+
         if !log_file.exists() {
+            if options.stream != LogStream::Both {
+                anyhow::bail!(
+                    "No separate {} log recorded for build {}",
+                    options.stream,
+                    cache_id
+                );
+            }
             anyhow::bail!("Log file not found: {}", log_file.display());
         }
 
-        let content = fs::read_to_string(&log_file)?;
-        let lines: Vec<&str> = content.lines().collect();
+        let mut content = read_log(&log_file)?;
+        if follow {
+            // Leave a partially written last line to the follow loop
+            content.truncate(content.rfind('\n').map(|i| i + 1).unwrap_or(0));
+        }
+        let lines: Vec<&str> = content.lines().collect();
+
+        let mut written = 0;
+        match mode {
+            "head" => {
+                let n: usize = param.and_then(|p| p.parse().ok()).unwrap_or(50);
+                for line in lines.iter().take(n) {
+                    writeln!(out, "{}", line)?;
+                    written += 1;
+                }
+            }
+            "tail" => {
+                let n: usize = param.and_then(|p| p.parse().ok()).unwrap_or(50);
+                let start = lines.len().saturating_sub(n);
+                for line in lines.iter().skip(start) {
+                    writeln!(out, "{}", line)?;
+                    written += 1;
+                }
+
+                if follow {
+                    self.follow_log(&cache_id, &log_file, content.len() as u64, out)?;
+                }
+            }
+            "grep" => {
+                for line in lines.iter() {
+                    if grep_matches(line, param.unwrap_or("")) {
+                        writeln!(out, "{}", line)?;
+                        written += 1;
+                    }
+                }
+            }
+            "range" => {
+                let range_str = param.unwrap_or("0-10");
+                let parts: Vec<&str> = range_str.split(&['-', ':'][..]).collect();
+                if parts.len() == 2 {
+                    let start: usize = parts[0].parse().unwrap_or(0);
+                    let end: usize = parts[1].parse().unwrap_or(lines.len());
+                    for line in lines.iter().skip(start).take(end.saturating_sub(start)) {
+                        writeln!(out, "{}", line)?;
+                        written += 1;
+                    }
+                }
+            }
+            "errors" | "error" => {
+                let style = owo_colors::Style::new().red().bold();
+                for line in lines.iter() {
+                    if is_error_line(line) {
+                        writeln!(out, "{}", paint(line, style, options.color))?;
+                        written += 1;
+                    }
+                }
+            }
+            "warnings" | "warning" => {
+                let style = owo_colors::Style::new().yellow();
+                for line in lines.iter() {
+                    if line.contains("warning:") {
+                        writeln!(out, "{}", paint(line, style, options.color))?;
+                        written += 1;
+                    }
+                }
+            }
+            "all" => {
+                for line in lines {
+                    writeln!(out, "{}", line)?;
+                    written += 1;
+                }
+            }
+            _ => eprintln!("Unknown mode: {}", mode),
+        }
+
+        Ok(written)
+    }
+
+    /// Searches every cached build log for lines containing `pattern`,
+    /// oldest build first.
+    ///
+    /// Like `query grep`, an all-lowercase pattern matches case-insensitively.
+    /// Returns `(cache_id, line)` for each match.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory cannot be read.
+    pub fn grep_all(&self, pattern: &str) -> Result<Vec<(String, String)>> {
+        self.filter_all_builds(LogStream::Both, |line| grep_matches(line, pattern))
+    }
+
+    /// Returns `(cache_id, line)` for the lines of every build's `stream`
+    /// log selected by `keep`, oldest build first. Unreadable logs are skipped.
+    fn filter_all_builds(
+        &self,
+        stream: LogStream,
+        keep: impl Fn(&str) -> bool,
+    ) -> Result<Vec<(String, String)>> {
+        let mut logs: Vec<(SystemTime, String)> = fs::read_dir(&self.cache_dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| is_build_log(path))
+            .filter_map(|path| {
+                let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                Some((modified, log_stem(&path)?))
+            })
+            .collect();
+        logs.sort();
+
+        let mut matches = Vec::new();
+        for (_, cache_id) in logs {
+            let Ok(content) = read_log(&self.log_file(&cache_id, stream)) else {
+                continue;
+            };
+            matches.extend(
+                content
+                    .lines()
+                    .filter(|line| keep(line))
+                    .map(|line| (cache_id.clone(), line.to_string())),
+            );
+        }
+        Ok(matches)
+    }
+
+    /// Runs a `grep`, `errors` or `warnings` query over all cached builds,
+    /// writing `<cache_id>: <line>` for each match.
+    fn query_all_builds_into(
+        &self,
+        mode: &str,
+        param: Option<&str>,
+        options: &QueryOptions,
+        out: &mut dyn Write,
+    ) -> Result<usize> {
+        let (matches, style) = match mode {
+            "grep" => (
+                self.filter_all_builds(options.stream, |line| {
+                    grep_matches(line, param.unwrap_or(""))
+                })?,
+                None,
+            ),
+            "errors" | "error" => (
+                self.filter_all_builds(options.stream, is_error_line)?,
+                Some(owo_colors::Style::new().red().bold()),
+            ),
+            "warnings" | "warning" => (
+                self.filter_all_builds(options.stream, |line| line.contains("warning:"))?,
+                Some(owo_colors::Style::new().yellow()),
+            ),
+            _ => anyhow::bail!(
+                "--all-builds only works with the grep, errors and warnings modes, not {}",
+                mode
+            ),
+        };
+
+        let written = matches.len();
+        for (cache_id, line) in matches {
+            let line = match style {
+                Some(style) => paint(&line, style, options.color),
+                None => line,
+            };
+            writeln!(out, "{}: {}", cache_id, line)?;
+        }
+        Ok(written)
+    }
+
+    /// Streams lines appended to `log_file` after byte offset `start`.
+    ///
+    /// Returns once the build's metadata file has been written, which marks
+    /// the end of the build.
+    fn follow_log(
+        &self,
+        cache_id: &str,
+        log_file: &Path,
+        start: u64,
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let meta_file = self.metadata_dir.join(format!("{}.json", cache_id));
 
-        match mode {
-            "head" => {
-                let n: usize = param.and_then(|p| p.parse().ok()).unwrap_or(50);
-                for line in lines.iter().take(n) {
-                    println!("{}", line);
+        let mut file = File::open(log_file)?;
+        let mut position = start;
+        let mut pending = Vec::new();
+
+        loop {
+            // Check for completion before reading so the final lines are never missed
+            let finished = meta_file.exists();
+
+            let len = file.metadata()?.len();
+            if len > position {
+                file.seek(SeekFrom::Start(position))?;
+                let mut chunk = Vec::new();
+                (&mut file).take(len - position).read_to_end(&mut chunk)?;
+                position = len;
+                pending.extend_from_slice(&chunk);
+
+                if let Some(last_newline) = pending.iter().rposition(|b| *b == b'\n') {
+                    let complete: Vec<u8> = pending.drain(..=last_newline).collect();
+                    out.write_all(&complete)?;
+                    out.flush()?;
                 }
             }
-            "tail" => {
-                let n: usize = param.and_then(|p| p.parse().ok()).unwrap_or(50);
-                let start = lines.len().saturating_sub(n);
-                for line in lines.iter().skip(start) {
-                    println!("{}", line);
+
+            if finished {
+                if !pending.is_empty() {
+                    out.write_all(&pending)?;
+                    writeln!(out)?;
                 }
+                return Ok(());
             }
-            "grep" => {
-                let pattern = param.unwrap_or("");
-                let case_insensitive = pattern.to_lowercase() == pattern;
 
-                for line in lines.iter() {
-                    let matches = if case_insensitive {
-                        line.to_lowercase().contains(pattern)
-                    } else {
-                        line.contains(pattern)
-                    };
+            std::thread::sleep(FOLLOW_POLL_INTERVAL);
+        }
+    }
 
-                    if matches {
-                        println!("{}", line);
-                    }
-                }
-            }
-            "range" => {
-                let range_str = param.unwrap_or("0-10");
-                let parts: Vec<&str> = range_str.split(&['-', ':'][..]).collect();
-                if parts.len() == 2 {
-                    let start: usize = parts[0].parse().unwrap_or(0);
-                    let end: usize = parts[1].parse().unwrap_or(lines.len());
-                    for line in lines.iter().skip(start).take(end.saturating_sub(start)) {
-                        println!("{}", line);
-                    }
-                }
-            }
-            "errors" | "error" => {
-                for line in lines.iter() {
-                    if line.contains("error[") || line.contains("error:") {
-                        println!("{}", line);
-                    }
+    /// Returns the log of `stream` for a build, preferring the plain log of a
+    /// running build over the compressed log of a finished one.
+    fn log_file(&self, cache_id: &str, stream: LogStream) -> PathBuf {
+        let plain = self
+            .cache_dir
+            .join(format!("{}{}.log", cache_id, stream.suffix()));
+        let compressed = self
+            .cache_dir
+            .join(format!("{}{}.log.zst", cache_id, stream.suffix()));
+        if !plain.exists() && compressed.exists() {
+            compressed
+        } else {
+            plain
+        }
+    }
+
+    /// Compresses the logs of a finished build.
+    fn compress_build_logs(&self, cache_id: &str) {
+        for stream in [LogStream::Both, LogStream::Out, LogStream::Err] {
+            let path = self
+                .cache_dir
+                .join(format!("{}{}.log", cache_id, stream.suffix()));
+            if path.exists() {
+                if let Err(e) = compress_log(&path) {
+                    log_error!("Warning: failed to compress {}: {}", path.display(), e);
                 }
             }
-            "warnings" | "warning" => {
-                for line in lines.iter() {
-                    if line.contains("warning:") {
-                        println!("{}", line);
-                    }
-                }
+        }
+    }
+
+    /// Compresses the plain logs of finished builds left by older versions.
+    ///
+    /// Runs once per cache directory; a marker file records completion.
+    fn compress_existing_logs(&self) -> Result<()> {
+        let marker = self.cache_dir.join(".logs-compressed");
+        if marker.exists() || !log_compression_enabled() {
+            return Ok(());
+        }
+
+        for entry in fs::read_dir(&self.cache_dir)?.flatten() {
+            let path = entry.path();
+            if !path.extension().is_some_and(|ext| ext == "log") {
+                continue;
             }
-            "all" => {
-                for line in lines {
-                    println!("{}", line);
-                }
+            // Only touch finished builds; a running build still appends to its log
+            let cache_id = log_stem(&path).unwrap_or_default();
+            let cache_id = cache_id
+                .strip_suffix(".out")
+                .or_else(|| cache_id.strip_suffix(".err"))
+                .unwrap_or(&cache_id);
+            if self
+                .metadata_dir
+                .join(format!("{}.json", cache_id))
+                .exists()
+            {
+                compress_log(&path)?;
             }
-            _ => eprintln!("Unknown mode: {}", mode),
         }
 
+        fs::write(&marker, "")?;
         Ok(())
     }
 
@@ -1764,7 +8329,7 @@ impl CacheManager {
     fn get_latest_log(&self) -> Result<PathBuf> {
         let mut entries: Vec<_> = fs::read_dir(&self.cache_dir)?
             .filter_map(|e| e.ok())
-            .filter(|e| e.path().extension().is_some_and(|ext| ext == "log"))
+            .filter(|e| is_build_log(&e.path()))
             .collect();
 
         entries.sort_by_key(|e| {
@@ -1776,11 +8341,11 @@ impl CacheManager {
         entries
             .last()
             .map(|e| e.path())
-            .context("No cached logs found")
+            .ok_or_else(|| CacheError::NoLogs.into())
     }
 
-    /// Gets the N most recent build caches.
-    fn get_recent_logs(&self, n: usize) -> Result<Vec<BuildCache>> {
+    /// Gets the N most recent build caches, optionally only those since a time.
+    fn get_recent_logs(&self, n: usize, since: Option<SystemTime>) -> Result<Vec<BuildCache>> {
         let mut entries: Vec<_> = fs::read_dir(&self.metadata_dir)?
             .filter_map(|e| e.ok())
             .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
@@ -1793,10 +8358,15 @@ impl CacheManager {
         });
 
         let mut caches = Vec::new();
-        for entry in entries.into_iter().rev().take(n) {
+        for entry in entries.into_iter().rev() {
+            if caches.len() == n {
+                break;
+            }
             if let Ok(content) = fs::read_to_string(entry.path()) {
                 if let Ok(cache) = serde_json::from_str::<BuildCache>(&content) {
-                    caches.push(cache);
+                    if since.map_or(true, |since| cache.is_since(since)) {
+                        caches.push(cache);
+                    }
                 }
             }
         }
@@ -1808,19 +8378,30 @@ impl CacheManager {
     ///
     /// # Arguments
     ///
-    /// - `verbose`: Show detailed information
+    /// - `verbose`: Show detailed information, like [`ListFormat::Long`]
     /// - `workspace_only`: Only show caches for current workspace
     ///
     /// # Errors
     ///
     /// Returns an error if the cache directory cannot be read.
     pub fn list_caches(&self, verbose: bool, workspace_only: bool) -> Result<()> {
-        let current_workspace: Option<PathBuf> = if workspace_only {
-            Some(self.get_cargo_metadata()?.workspace_root.into())
-        } else {
-            None
-        };
+        self.list_caches_with(&ListOptions {
+            format: if verbose {
+                ListFormat::Long
+            } else {
+                ListFormat::Plain
+            },
+            workspace_only,
+            ..Default::default()
+        })
+    }
 
+    /// Loads every readable build metadata file, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metadata directory cannot be read.
+    pub fn load_build_caches(&self) -> Result<Vec<BuildCache>> {
         let mut entries: Vec<_> = fs::read_dir(&self.metadata_dir)?
             .filter_map(|e| e.ok())
             .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
@@ -1832,26 +8413,71 @@ impl CacheManager {
                 .unwrap_or(SystemTime::UNIX_EPOCH)
         });
 
-        println!(
-            "{:<25} {:<12} {:<8} {:<30}",
-            "Cache ID", "Status", "Lines", "Command"
-        );
-        println!("{}", "-".repeat(80));
+        Ok(entries
+            .into_iter()
+            .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+            .filter_map(|content| serde_json::from_str::<BuildCache>(&content).ok())
+            .collect())
+    }
 
-        for entry in entries {
-            if let Ok(content) = fs::read_to_string(entry.path()) {
-                if let Ok(cache) = serde_json::from_str::<BuildCache>(&content) {
-                    if let Some(ref ws) = current_workspace {
-                        if cache.workspace_state.root != *ws {
-                            continue;
-                        }
-                    }
+    /// Lists cached builds filtered and sorted according to `options`,
+    /// printing to stdout.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory cannot be read, or if
+    /// `workspace_only` is set and cargo metadata is unavailable.
+    pub fn list_caches_with(&self, options: &ListOptions) -> Result<()> {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        self.list_caches_into(options, &mut out)
+    }
 
-                    let status = match cache.exit_code {
-                        Some(0) => "✓ success",
-                        Some(_) => "✗ failed",
-                        None => "? unknown",
-                    };
+    /// Lists cached builds filtered and sorted according to `options`,
+    /// writing them to `out` in [`ListOptions::format`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory cannot be read, if
+    /// `workspace_only` is set and cargo metadata is unavailable, or if
+    /// writing to `out` fails.
+    pub fn list_caches_into(&self, options: &ListOptions, out: &mut dyn Write) -> Result<()> {
+        let mut builds = self.load_build_caches()?;
+
+        if options.workspace_only {
+            let current_workspace: PathBuf = self.get_cargo_metadata()?.workspace_root.into();
+            let current_workspace = canonicalize_or_raw(&current_workspace);
+            // Recorded roots may be relative or go through a symlink
+            builds.retain(|b| canonicalize_or_raw(&b.workspace_state.root) == current_workspace);
+        }
+
+        let builds = options.apply(builds);
+
+        if options.json {
+            writeln!(out, "{}", serde_json::to_string_pretty(&builds)?)?;
+            return Ok(());
+        }
+
+        if options.format == ListFormat::Plain {
+            writeln!(
+                out,
+                "{:<25} {:<12} {:<8} {:<30}",
+                "Cache ID", "Status", "Lines", "Command"
+            )?;
+            writeln!(out, "{}", "-".repeat(80))?;
+        }
+
+        for cache in builds {
+            let (status, style) = match cache.status() {
+                BuildStatus::Success => ("✓ success", owo_colors::Style::new().green()),
+                BuildStatus::Failed => ("✗ failed", owo_colors::Style::new().red()),
+                BuildStatus::Unknown => ("? unknown", owo_colors::Style::new().yellow()),
+            };
+
+            match options.format {
+                ListFormat::Plain => {
+                    // Pad before painting so escapes don't break column alignment
+                    let status = paint(&format!("{:<12}", status), style, options.color);
 
                     let cmd_short = if cache.command.len() > 30 {
                         format!("{}...", &cache.command[..27])
@@ -1859,142 +8485,618 @@ impl CacheManager {
                         cache.command.clone()
                     };
 
-                    println!(
-                        "{:<25} {:<12} {:<8} {:<30}",
+                    writeln!(
+                        out,
+                        "{:<25} {} {:<8} {:<30}",
                         cache.cache_id, status, cache.lines_count, cmd_short
-                    );
+                    )?;
+                }
+                ListFormat::Short => {
+                    let status = paint(&format!("{:<9}", status), style, options.color);
+                    writeln!(out, "{} {} {}ms", cache.cache_id, status, cache.duration_ms)?;
+                }
+                ListFormat::Long => {
+                    writeln!(
+                        out,
+                        "{} {}",
+                        cache.cache_id,
+                        paint(status, style, options.color)
+                    )?;
+                    writeln!(out, "  Command: {}", cache.command)?;
+                    writeln!(out, "  Args: {}", cache.args.join(" "))?;
+                    writeln!(out, "  Timestamp: {}", cache.timestamp)?;
+                    writeln!(out, "  Duration: {}ms", cache.duration_ms)?;
+                    writeln!(out, "  Lines: {}", cache.lines_count)?;
+                    writeln!(
+                        out,
+                        "  Diagnostics: {} errors, {} warnings",
+                        cache.error_count, cache.warning_count
+                    )?;
+                    writeln!(out, "  Release: {}", cache.is_release)?;
+                    writeln!(out, "  Packages: {}", cache.workspace_state.packages.len())?;
+                    writeln!(out, "  Workspace: {}", cache.workspace_state.root.display())?;
+                    if let Some(commit) = &cache.commit {
+                        match &cache.branch {
+                            Some(branch) => writeln!(out, "  Commit: {} ({})", commit, branch)?,
+                            None => writeln!(out, "  Commit: {} (detached)", commit)?,
+                        }
+                    }
+                    if let Some(target_dir) = &cache.target_dir {
+                        writeln!(out, "  Target dir: {}", target_dir.display())?;
+                    }
+                    writeln!(out, "  Env hash: {}", cache.env_hash)?;
+                    writeln!(out)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads the metadata of a single build.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no metadata exists for `cache_id` or it cannot be parsed.
+    pub fn load_build_cache(&self, cache_id: &str) -> CacheResult<BuildCache> {
+        let meta_file = self.metadata_dir.join(format!("{}.json", cache_id));
+        let content = match fs::read_to_string(&meta_file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(CacheError::BuildNotFound(cache_id.to_string()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_str(&content).map_err(|source| CacheError::CorruptCache {
+            path: meta_file,
+            source,
+        })
+    }
+
+    /// Loads the metadata of a single build by its cache ID.
+    ///
+    /// Same as [`CacheManager::load_build_cache`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CacheError::BuildNotFound`] if no metadata exists for
+    /// `cache_id`, or [`CacheError::CorruptCache`] if it cannot be parsed.
+    pub fn get_build_cache(&self, cache_id: &str) -> CacheResult<BuildCache> {
+        self.load_build_cache(cache_id)
+    }
+
+    /// Loads the metadata of the most recently recorded build.
+    ///
+    /// Builds are ordered by their recorded timestamp, then by cache ID, so
+    /// copying or touching metadata files does not change the answer.
+    /// Unreadable metadata files are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CacheError::BuildNotFound`] if no build has been recorded yet,
+    /// or an error if the metadata directory cannot be read.
+    pub fn latest_build_cache(&self) -> CacheResult<BuildCache> {
+        fs::read_dir(&self.metadata_dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter(|path| !path.ends_with(WORKSPACE_MEMO_FILE))
+            .filter_map(|path| fs::read_to_string(path).ok())
+            .filter_map(|content| serde_json::from_str::<BuildCache>(&content).ok())
+            .max_by(|a, b| {
+                let time = |b: &BuildCache| chrono::DateTime::parse_from_rfc3339(&b.timestamp).ok();
+                time(a)
+                    .cmp(&time(b))
+                    .then_with(|| a.cache_id.cmp(&b.cache_id))
+            })
+            .ok_or_else(|| CacheError::BuildNotFound("the latest build".to_string()))
+    }
+
+    /// Compares the inputs of two cached builds.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either build's metadata cannot be loaded.
+    pub fn diff_builds(&self, a: &str, b: &str) -> Result<BuildDiff> {
+        let before = self.load_build_cache(a)?;
+        let after = self.load_build_cache(b)?;
+
+        let old_packages: HashMap<&str, &PackageHash> = before
+            .workspace_state
+            .packages
+            .iter()
+            .map(|p| (p.name.as_str(), p))
+            .collect();
+        let new_packages: HashMap<&str, &PackageHash> = after
+            .workspace_state
+            .packages
+            .iter()
+            .map(|p| (p.name.as_str(), p))
+            .collect();
 
-                    if verbose {
-                        println!("  Timestamp: {}", cache.timestamp);
-                        println!("  Duration: {}ms", cache.duration_ms);
-                        println!("  Release: {}", cache.is_release);
-                        println!("  Packages: {}", cache.workspace_state.packages.len());
-                        println!();
+        let mut packages = Vec::new();
+        for (name, old) in &old_packages {
+            match new_packages.get(name) {
+                None => packages.push(PackageDiff {
+                    name: name.to_string(),
+                    change: PackageChange::Removed,
+                }),
+                Some(new) => {
+                    if old.source_hash != new.source_hash {
+                        packages.push(PackageDiff {
+                            name: name.to_string(),
+                            change: PackageChange::SourceChanged,
+                        });
+                    }
+                    if old.features_hash != new.features_hash {
+                        packages.push(PackageDiff {
+                            name: name.to_string(),
+                            change: PackageChange::FeaturesChanged,
+                        });
                     }
                 }
             }
         }
-
-        Ok(())
+        for name in new_packages.keys() {
+            if !old_packages.contains_key(name) {
+                packages.push(PackageDiff {
+                    name: name.to_string(),
+                    change: PackageChange::Added,
+                });
+            }
+        }
+        packages.sort_by(|x, y| x.name.cmp(&y.name));
+
+        let mut env_vars = Vec::new();
+        let names: std::collections::BTreeSet<&String> = before
+            .env_vars
+            .keys()
+            .chain(after.env_vars.keys())
+            .collect();
+        for name in names {
+            let old = before.env_vars.get(name);
+            let new = after.env_vars.get(name);
+            if old != new {
+                env_vars.push(EnvVarDiff {
+                    name: name.clone(),
+                    before: old.cloned(),
+                    after: new.cloned(),
+                });
+            }
+        }
+
+        let command = if before.command != after.command {
+            Some((before.command.clone(), after.command.clone()))
+        } else {
+            None
+        };
+
+        Ok(BuildDiff {
+            a: before.cache_id,
+            b: after.cache_id,
+            packages,
+            env_changed: before.env_hash != after.env_hash,
+            env_vars,
+            cargo_lock_changed: before.workspace_state.cargo_lock_hash
+                != after.workspace_state.cargo_lock_hash,
+            toolchain_changed: before.workspace_state.toolchain_hash
+                != after.workspace_state.toolchain_hash,
+            command,
+        })
+    }
+
+    /// Prints a [`BuildDiff`] in human-readable form.
+    pub fn print_build_diff(&self, diff: &BuildDiff) {
+        println!("{} Comparing {} -> {}", LOG_PREFIX, diff.a, diff.b);
+
+        if diff.is_empty() {
+            println!("  No differences in build inputs");
+            return;
+        }
+
+        if let Some((ref old, ref new)) = diff.command {
+            println!("  Command changed:");
+            println!("    - {}", old);
+            println!("    + {}", new);
+        }
+        if diff.toolchain_changed {
+            println!("  Rust toolchain changed");
+        }
+        if diff.cargo_lock_changed {
+            println!("  Cargo.lock changed");
+        }
+        if diff.env_changed {
+            if diff.env_vars.is_empty() {
+                println!("  Build environment changed");
+            }
+            for var in &diff.env_vars {
+                println!(
+                    "  {} changed: {} -> {}",
+                    var.name,
+                    var.before.as_deref().unwrap_or("<unset>"),
+                    var.after.as_deref().unwrap_or("<unset>")
+                );
+            }
+        }
+        for package in &diff.packages {
+            let what = match package.change {
+                PackageChange::Added => "added",
+                PackageChange::Removed => "removed",
+                PackageChange::SourceChanged => "source changed",
+                PackageChange::FeaturesChanged => "features changed",
+            };
+            println!("  Package {} {}", package.name, what);
+        }
+    }
+
+    /// Cleans old cache files.
+    ///
+    /// # Arguments
+    ///
+    /// - `days`: Remove caches older than this many days
+    /// - `keep`: If specified, keep only this many most recent caches
+    /// - `force`: Skip confirmation prompt
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory cannot be read.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cargo_save::CacheManager;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let cache = CacheManager::new()?;
+    ///
+    /// // Remove caches older than 7 days
+    /// cache.clean_old_caches(7, None, false)?;
+    ///
+    /// // Keep only the 10 most recent caches
+    /// cache.clean_old_caches(0, Some(10), true)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn clean_old_caches(&self, days: u64, keep: Option<usize>, force: bool) -> Result<()> {
+        self.clean_old_caches_with(&CleanOptions {
+            days,
+            keep,
+            force,
+            ..Default::default()
+        })?;
+        Ok(())
+    }
+
+    /// Returns the files [`CacheManager::clean_old_caches`] would remove: the
+    /// logs of the selected builds, their per-stream logs and their metadata,
+    /// and the incremental cache entries no retained build used.
+    ///
+    /// With `keep` all but the `keep` most recent builds are selected,
+    /// otherwise builds older than `days`. Incremental entries are shared by
+    /// builds and rewritten whenever one hits them, so their modification
+    /// time is their last use: entries older than the oldest retained build,
+    /// or than `days`, are selected.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory cannot be read.
+    pub fn clean_candidates(&self, days: u64, keep: Option<usize>) -> Result<Vec<PathBuf>> {
+        let cutoff = SystemTime::now() - Duration::from_secs(days * 86400);
+
+        let mut entries: Vec<_> = fs::read_dir(&self.cache_dir)?
+            .filter_map(|e| e.ok())
+            .filter(|e| is_build_log(&e.path()))
+            .filter_map(|e| {
+                let modified = e.metadata().and_then(|m| m.modified()).ok()?;
+                Some((modified, e.path()))
+            })
+            .collect();
+        entries.sort();
+
+        let (logs, entry_cutoff): (Vec<PathBuf>, Option<SystemTime>) = match keep {
+            Some(keep_count) => {
+                let excess = entries.len().saturating_sub(keep_count);
+                // Without any build left there is nothing to date entries by
+                let oldest_kept = entries.get(excess).map(|(modified, _)| *modified);
+                let logs = entries
+                    .into_iter()
+                    .take(excess)
+                    .map(|(_, path)| path)
+                    .collect();
+                (logs, oldest_kept)
+            }
+            None => {
+                let logs = entries
+                    .into_iter()
+                    .filter(|(modified, _)| *modified < cutoff)
+                    .map(|(_, path)| path)
+                    .collect();
+                (logs, Some(cutoff))
+            }
+        };
+
+        let mut candidates = Vec::new();
+        for log in logs {
+            let meta_path = self
+                .metadata_dir
+                .join(log_stem(&log).unwrap_or_default() + ".json");
+            let streams = self.stream_log_paths(&log);
+            candidates.push(log);
+            candidates.extend(streams);
+            if meta_path.exists() {
+                candidates.push(meta_path);
+            }
+        }
+
+        if let Some(entry_cutoff) = entry_cutoff {
+            let mut stale: Vec<PathBuf> = fs::read_dir(&self.incremental_dir)?
+                .filter_map(|e| e.ok())
+                .filter(|e| {
+                    e.metadata()
+                        .and_then(|m| m.modified())
+                        .is_ok_and(|modified| modified < entry_cutoff)
+                })
+                .map(|e| e.path())
+                .collect();
+            stale.sort();
+            candidates.extend(stale);
+        }
+        Ok(candidates)
+    }
+
+    /// Removes old cached builds selected by `options`, then sweeps objects
+    /// no longer referenced by any cache entry.
+    ///
+    /// [`CleanOptions::scope`] limits both to logs or to incremental caches;
+    /// the age and `keep` rules are the same either way.
+    ///
+    /// Returns the removed files, or with [`CleanOptions::dry_run`] the files
+    /// that would be removed, leaving everything in place.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory cannot be read or the
+    /// confirmation prompt fails.
+    pub fn clean_old_caches_with(&self, options: &CleanOptions) -> Result<Vec<PathBuf>> {
+        let candidates: Vec<PathBuf> = self
+            .clean_candidates(options.days, options.keep)?
+            .into_iter()
+            .filter(|path| {
+                if path.starts_with(&self.incremental_dir) {
+                    options.scope.includes_incremental()
+                } else {
+                    options.scope.includes_logs()
+                }
+            })
+            .collect();
+        let log_count = candidates.iter().filter(|p| is_build_log(p)).count();
+
+        if options.dry_run {
+            print_removal_preview(&candidates);
+            return Ok(candidates);
+        }
+
+        if let Some(keep_count) = options.keep {
+            if candidates.is_empty() {
+                println!(
+                    "{} No caches to remove (keeping last {})",
+                    LOG_PREFIX, keep_count
+                );
+                if options.scope.includes_incremental() {
+                    self.report_object_sweep()?;
+                }
+                return Ok(Vec::new());
+            }
+
+            let entry_count = candidates
+                .iter()
+                .filter(|p| p.starts_with(&self.incremental_dir))
+                .count();
+            let question = format!(
+                "Remove {} old cache files and {} unused incremental caches?",
+                log_count, entry_count
+            );
+            if !options.force && !confirm(&question)? {
+                println!("{} Aborted", LOG_PREFIX);
+                return Ok(Vec::new());
+            }
+        }
+
+        let mut log_bytes = 0;
+        let mut incremental_bytes = 0;
+        let removed: Vec<PathBuf> = candidates
+            .into_iter()
+            .filter(|path| {
+                let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+                if fs::remove_file(path).is_err() {
+                    return false;
+                }
+                if path.starts_with(&self.incremental_dir) {
+                    incremental_bytes += size;
+                } else {
+                    log_bytes += size;
+                }
+                true
+            })
+            .collect();
+        let removed_logs = removed.iter().filter(|p| is_build_log(p)).count();
+        let removed_entries = removed
+            .iter()
+            .filter(|p| p.starts_with(&self.incremental_dir))
+            .count();
+
+        if options.scope.includes_logs() {
+            match options.keep {
+                Some(_) => println!("{} Removed {} old cache files", LOG_PREFIX, removed_logs),
+                None => println!(
+                    "{} Removed {} cache files older than {} days",
+                    LOG_PREFIX, removed_logs, options.days
+                ),
+            }
+            println!(
+                "{} Reclaimed {:.2} MB of logs and metadata",
+                LOG_PREFIX,
+                log_bytes as f64 / 1024.0 / 1024.0
+            );
+        }
+        if options.scope.includes_incremental() {
+            println!(
+                "{} Removed {} unused incremental caches",
+                LOG_PREFIX, removed_entries
+            );
+            println!(
+                "{} Reclaimed {:.2} MB of incremental caches",
+                LOG_PREFIX,
+                incremental_bytes as f64 / 1024.0 / 1024.0
+            );
+            self.report_object_sweep()?;
+        }
+        Ok(removed)
     }
 
-    /// Cleans old cache files.
+    /// Prunes the build log copies in a workspace `build-logs/` directory.
     ///
-    /// # Arguments
+    /// Uses the same rules as [`CacheManager::clean_old_caches`]: with `keep`
+    /// only the most recent `keep` logs remain, otherwise logs older than
+    /// `days` are removed. Only files named like the copies cargo-save writes
+    /// (`<YYYYmmdd_HHMMSS>_<subcommand>.txt`) are touched.
     ///
-    /// - `days`: Remove caches older than this many days
-    /// - `keep`: If specified, keep only this many most recent caches
-    /// - `force`: Skip confirmation prompt
+    /// Returns the number of removed logs; a missing directory removes none.
     ///
     /// # Errors
     ///
-    /// Returns an error if the cache directory cannot be read.
-    ///
-    /// # Example
-    ///
-    /// ```no_run
-    /// use cargo_save::CacheManager;
-    ///
-    /// # fn main() -> anyhow::Result<()> {
-    /// let cache = CacheManager::new()?;
-    ///
-    /// // Remove caches older than 7 days
-    /// cache.clean_old_caches(7, None, false)?;
-    ///
-    /// // Keep only the 10 most recent caches
-    /// cache.clean_old_caches(0, Some(10), true)?;
-    /// # Ok(())
-    /// # }
-    /// ```
-    pub fn clean_old_caches(&self, days: u64, keep: Option<usize>, force: bool) -> Result<()> {
+    /// Returns an error if `dir` exists but cannot be read.
+    pub fn prune_build_logs(&self, dir: &Path, days: u64, keep: Option<usize>) -> Result<usize> {
+        if !dir.exists() {
+            return Ok(0);
+        }
         let cutoff = SystemTime::now() - Duration::from_secs(days * 86400);
 
-        let mut entries: Vec<_> = fs::read_dir(&self.cache_dir)?
+        let mut entries: Vec<_> = fs::read_dir(dir)?
             .filter_map(|e| e.ok())
+            .filter(|e| {
+                e.file_type().is_ok_and(|t| t.is_file())
+                    && is_workspace_build_log(&e.file_name().to_string_lossy())
+            })
             .filter_map(|e| {
                 let modified = e.metadata().and_then(|m| m.modified()).ok()?;
-                Some((e, modified))
+                Some((modified, e.path()))
             })
             .collect();
-
-        entries.sort_by_key(|(_, modified)| *modified);
-
-        if let Some(keep_count) = keep {
-            let to_remove = entries.len().saturating_sub(keep_count);
-            if to_remove == 0 {
-                println!(
-                    "{} No caches to remove (keeping last {})",
-                    LOG_PREFIX, keep_count
-                );
-                return Ok(());
+        entries.sort();
+
+        let to_remove: Vec<PathBuf> = match keep {
+            Some(keep_count) => {
+                let excess = entries.len().saturating_sub(keep_count);
+                entries
+                    .into_iter()
+                    .take(excess)
+                    .map(|(_, path)| path)
+                    .collect()
             }
+            None => entries
+                .into_iter()
+                .filter(|(modified, _)| *modified < cutoff)
+                .map(|(_, path)| path)
+                .collect(),
+        };
 
-            if !force {
-                print!(
-                    "{} Remove {} old cache files? [y/N] ",
-                    LOG_PREFIX, to_remove
-                );
-                io::stdout().flush()?;
+        Ok(to_remove
+            .iter()
+            .filter(|path| fs::remove_file(path).is_ok())
+            .count())
+    }
 
-                let mut input = String::new();
-                io::stdin().read_line(&mut input)?;
+    /// Removes incremental cache entries of packages that are no longer
+    /// members of the workspace.
+    ///
+    /// Only entries recorded in this workspace are considered, so packages of
+    /// other workspaces sharing the cache directory are kept. Entries written
+    /// before the workspace root was recorded cannot be attributed and are
+    /// left alone.
+    ///
+    /// Returns the number of removed entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the incremental cache directory cannot be read.
+    pub fn gc_removed_packages(&self, workspace_state: &WorkspaceState) -> Result<usize> {
+        let root = canonicalize_or_raw(&workspace_state.root);
+        let members: HashSet<&str> = workspace_state
+            .packages
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
 
-                if !input.trim().eq_ignore_ascii_case("y") {
-                    println!("{} Aborted", LOG_PREFIX);
-                    return Ok(());
-                }
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.incremental_dir)?.flatten() {
+            let path = entry.path();
+            if !path.extension().is_some_and(|ext| ext == "json") {
+                continue;
+            }
+            let Some(cache) = fs::read_to_string(&path)
+                .ok()
+                .and_then(|content| serde_json::from_str::<IncrementalCache>(&content).ok())
+            else {
+                continue;
+            };
+            let ours = cache
+                .workspace_root
+                .as_deref()
+                .is_some_and(|entry_root| canonicalize_or_raw(entry_root) == root);
+            if !ours || members.contains(cache.package_name.as_str()) {
+                continue;
             }
 
-            let mut removed = 0;
-            for (entry, _) in entries.into_iter().take(to_remove) {
-                if fs::remove_file(entry.path()).is_ok() {
-                    removed += 1;
-                }
-
-                let meta_path = self.metadata_dir.join(
-                    entry
-                        .path()
-                        .file_stem()
-                        .unwrap_or_default()
-                        .to_string_lossy()
-                        .to_string()
-                        + ".json",
+            let key = path.file_stem().unwrap_or_default().to_string_lossy();
+            let _lock = self.lock(&key)?;
+            if fs::remove_file(&path).is_ok() {
+                log_debug!(
+                    "Removed cache of former member {}: {}",
+                    cache.package_name,
+                    key
                 );
-                let _ = fs::remove_file(meta_path);
+                removed += 1;
             }
+        }
 
-            println!("{} Removed {} old cache files", LOG_PREFIX, removed);
-        } else {
-            let mut removed = 0;
-
-            for (entry, modified) in entries {
-                if modified < cutoff {
-                    if fs::remove_file(entry.path()).is_ok() {
-                        removed += 1;
-                    }
-
-                    let meta_path = self.metadata_dir.join(
-                        entry
-                            .path()
-                            .file_stem()
-                            .unwrap_or_default()
-                            .to_string_lossy()
-                            .to_string()
-                            + ".json",
-                    );
-                    let _ = fs::remove_file(meta_path);
-                }
-            }
+        Ok(removed)
+    }
 
+    /// Sweeps unreferenced objects and prints what was reclaimed.
+    fn report_object_sweep(&self) -> Result<()> {
+        let (removed, reclaimed) = self.sweep_objects()?;
+        if removed > 0 {
             println!(
-                "{} Removed {} cache files older than {} days",
-                LOG_PREFIX, removed, days
+                "{} Removed {} unreferenced objects ({:.2} MB)",
+                LOG_PREFIX,
+                removed,
+                reclaimed as f64 / 1024.0 / 1024.0
             );
         }
-
         Ok(())
     }
 
+    /// Returns the existing per-stream logs belonging to a combined build log.
+    fn stream_log_paths(&self, log_path: &Path) -> Vec<PathBuf> {
+        if !is_build_log(log_path) {
+            return Vec::new();
+        }
+        let cache_id = log_stem(log_path).unwrap_or_default();
+        let mut paths = Vec::new();
+        for stream in [LogStream::Out, LogStream::Err] {
+            for ext in ["log", "log.zst"] {
+                let path = self
+                    .cache_dir
+                    .join(format!("{}{}.{}", cache_id, stream.suffix(), ext));
+                if path.exists() {
+                    paths.push(path);
+                }
+            }
+        }
+        paths
+    }
+
     /// Shows cache statistics.
     ///
     /// Displays information about:
@@ -2006,49 +9108,330 @@ impl CacheManager {
     ///
     /// Returns an error if the cache directories cannot be read.
     pub fn show_stats(&self) -> Result<()> {
-        let mut total_size = 0u64;
-        let mut log_count = 0u64;
-        let mut meta_count = 0u64;
+        let usage = self.cache_usage()?;
+        let mb = |bytes: u64| bytes as f64 / 1024.0 / 1024.0;
+
+        println!("{} Cache Statistics:", LOG_PREFIX);
+        println!(
+            "  Build logs: {} ({:.2} MB)",
+            usage.build_logs.files,
+            mb(usage.build_logs.bytes)
+        );
+        println!(
+            "  Metadata files: {} ({:.2} MB)",
+            usage.metadata.files,
+            mb(usage.metadata.bytes)
+        );
+        println!(
+            "  Incremental caches: {} ({:.2} MB)",
+            usage.incremental.files,
+            mb(usage.incremental.bytes)
+        );
+        println!(
+            "  Stored artifacts: {} ({:.2} MB)",
+            usage.objects.files,
+            mb(usage.objects.bytes)
+        );
+        println!("  Total size: {:.2} MB", mb(usage.total_bytes()));
+        println!();
+        println!("  Cache directories:");
+        println!("    - {}", self.cache_dir.display());
+        println!("    - {}", self.metadata_dir.display());
+        println!("    - {}", self.incremental_dir.display());
+
+        Ok(())
+    }
+
+    /// Measures the disk usage of the cache.
+    ///
+    /// The metadata, incremental and object directories are walked
+    /// recursively, counting only files. Build logs are the top-level log
+    /// files of the cache directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory cannot be read.
+    pub fn cache_usage(&self) -> Result<CacheUsage> {
+        let mut build_logs = DirUsage::default();
         for entry in fs::read_dir(&self.cache_dir)?.flatten() {
             if let Ok(metadata) = entry.metadata() {
-                if entry.path().extension().is_some_and(|e| e == "log") {
-                    total_size += metadata.len();
-                    log_count += 1;
+                if metadata.is_file() && is_build_log(&entry.path()) {
+                    build_logs.files += 1;
+                    build_logs.bytes += metadata.len();
                 }
             }
         }
 
-        for entry in fs::read_dir(&self.metadata_dir)?.flatten() {
-            if let Ok(metadata) = entry.metadata() {
-                total_size += metadata.len();
-                meta_count += 1;
+        Ok(CacheUsage {
+            build_logs,
+            metadata: dir_usage(&self.metadata_dir),
+            incremental: dir_usage(&self.incremental_dir),
+            objects: dir_usage(&self.objects_dir),
+        })
+    }
+
+    /// Returns the `n` packages with the longest recorded build duration.
+    ///
+    /// Only the most recent incremental cache entry of each package is
+    /// considered, sorted by duration in descending order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the incremental cache directory cannot be read.
+    pub fn slowest_packages(&self, n: usize) -> Result<Vec<(String, u64)>> {
+        let mut latest: HashMap<String, IncrementalCache> = HashMap::new();
+        for entry in fs::read_dir(&self.incremental_dir)?.flatten() {
+            let Some(cache) = fs::read_to_string(entry.path())
+                .ok()
+                .and_then(|c| serde_json::from_str::<IncrementalCache>(&c).ok())
+            else {
+                continue;
+            };
+            match latest.get(&cache.package_name) {
+                Some(existing) if existing.timestamp >= cache.timestamp => {}
+                _ => {
+                    latest.insert(cache.package_name.clone(), cache);
+                }
             }
         }
 
-        let incremental_count = fs::read_dir(&self.incremental_dir)?.count() as u64;
-        for entry in fs::read_dir(&self.incremental_dir)?.flatten() {
-            if let Ok(metadata) = entry.metadata() {
-                total_size += metadata.len();
+        let mut packages: Vec<(String, u64)> = latest
+            .into_values()
+            .map(|cache| (cache.package_name, cache.duration_ms))
+            .collect();
+        packages.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        packages.truncate(n);
+        Ok(packages)
+    }
+
+    /// Prints the `n` slowest packages as reported by [`Self::slowest_packages`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the incremental cache directory cannot be read.
+    pub fn show_slowest_packages(&self, n: usize) -> Result<()> {
+        let packages = self.slowest_packages(n)?;
+        if packages.is_empty() {
+            println!("{} No package timings recorded yet", LOG_PREFIX);
+            return Ok(());
+        }
+
+        println!("{} Slowest packages:", LOG_PREFIX);
+        let width = packages
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        for (name, duration) in &packages {
+            println!(
+                "  {:<width$}  {:>8.2}s",
+                name,
+                *duration as f64 / 1000.0,
+                width = width
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Aggregates the builds of the last `days` days by local date, oldest
+    /// first, from local build metadata and `hits.jsonl`. Days without any
+    /// build are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metadata directory cannot be read.
+    pub fn build_history(&self, days: u64) -> Result<Vec<DailyStats>> {
+        let cutoff = chrono::Local::now() - chrono::Duration::days(days as i64);
+        let day_of = |timestamp: &str| {
+            chrono::DateTime::parse_from_rfc3339(timestamp)
+                .ok()
+                .map(|time| time.with_timezone(&chrono::Local))
+                .filter(|time| *time >= cutoff)
+                .map(|time| time.format("%Y-%m-%d").to_string())
+        };
+
+        // date -> (builds, total duration, cached packages, total packages)
+        let mut by_day: BTreeMap<String, (usize, u64, usize, usize)> = BTreeMap::new();
+        for build in self.load_build_caches()? {
+            if let Some(day) = day_of(&build.timestamp) {
+                let stats = by_day.entry(day).or_default();
+                stats.0 += 1;
+                stats.1 += build.duration_ms;
+            }
+        }
+        let hits = fs::read_to_string(self.hit_log_file()).unwrap_or_default();
+        for record in hits
+            .lines()
+            .filter_map(|line| serde_json::from_str::<HitRecord>(line).ok())
+        {
+            if let Some(day) = day_of(&record.timestamp) {
+                let stats = by_day.entry(day).or_default();
+                stats.2 += record.cached;
+                stats.3 += record.total;
             }
         }
 
-        let size_mb = total_size as f64 / 1024.0 / 1024.0;
+        Ok(by_day
+            .into_iter()
+            .map(|(date, (builds, duration, cached, total))| DailyStats {
+                date,
+                builds,
+                avg_duration_ms: duration / builds.max(1) as u64,
+                cached_packages: cached,
+                total_packages: total,
+                cache_hit_rate: (total > 0).then(|| cached as f64 / total as f64),
+            })
+            .collect())
+    }
 
-        println!("{} Cache Statistics:", LOG_PREFIX);
-        println!("  Build logs: {}", log_count);
-        println!("  Metadata files: {}", meta_count);
-        println!("  Incremental caches: {}", incremental_count);
-        println!("  Total size: {:.2} MB", size_mb);
-        println!();
-        println!("  Cache directories:");
-        println!("    - {}", self.cache_dir.display());
-        println!("    - {}", self.metadata_dir.display());
-        println!("    - {}", self.incremental_dir.display());
+    /// Prints [`Self::build_history`] as a table, or as JSON with `json`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metadata directory cannot be read.
+    pub fn show_build_history(&self, days: u64, json: bool) -> Result<()> {
+        let history = self.build_history(days)?;
+        if json {
+            println!("{}", serde_json::to_string_pretty(&history)?);
+            return Ok(());
+        }
+        if history.is_empty() {
+            println!("{} No builds in the last {} days", LOG_PREFIX, days);
+            return Ok(());
+        }
+
+        println!("{} Build history (last {} days):", LOG_PREFIX, days);
+        println!(
+            "  {:<10}  {:>6}  {:>9}  {:>8}",
+            "Date", "Builds", "Avg", "Hit rate"
+        );
+        for day in &history {
+            let hit_rate = day
+                .cache_hit_rate
+                .map_or_else(|| "-".to_string(), |rate| format!("{:.0}%", rate * 100.0));
+            println!(
+                "  {:<10}  {:>6}  {:>8.2}s  {:>8}",
+                day.date,
+                day.builds,
+                day.avg_duration_ms as f64 / 1000.0,
+                hit_rate
+            );
+        }
+        Ok(())
+    }
+
+    /// Reports cache usage per package: hits, build durations and the size
+    /// of the artifacts stored for it.
+    ///
+    /// Incremental cache entries are grouped by package name and sorted by
+    /// artifact size in descending order. Objects shared by several entries
+    /// of a package are counted once.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the incremental cache directory cannot be read.
+    pub fn per_package_stats(&self) -> Result<Vec<PackageStats>> {
+        let mut grouped: BTreeMap<String, Vec<IncrementalCache>> = BTreeMap::new();
+        for entry in fs::read_dir(&self.incremental_dir)?.flatten() {
+            let Some(cache) = fs::read_to_string(entry.path())
+                .ok()
+                .and_then(|c| serde_json::from_str::<IncrementalCache>(&c).ok())
+            else {
+                continue;
+            };
+            grouped
+                .entry(cache.package_name.clone())
+                .or_default()
+                .push(cache);
+        }
+
+        let mut stats: Vec<PackageStats> = grouped
+            .into_iter()
+            .map(|(name, entries)| {
+                let objects: HashSet<&str> = entries
+                    .iter()
+                    .flat_map(|e| e.objects.iter().map(|(_, hash)| hash.as_str()))
+                    .collect();
+                let artifact_bytes = objects
+                    .into_iter()
+                    .filter_map(|hash| fs::metadata(self.objects_dir.join(hash)).ok())
+                    .map(|m| m.len())
+                    .sum();
+                let total_duration: u64 = entries.iter().map(|e| e.duration_ms).sum();
+                let last_duration_ms = entries
+                    .iter()
+                    .max_by(|a, b| a.timestamp.cmp(&b.timestamp))
+                    .map_or(0, |e| e.duration_ms);
+                PackageStats {
+                    entries: entries.len(),
+                    hits: entries.iter().map(|e| e.hits).sum(),
+                    avg_duration_ms: total_duration / entries.len() as u64,
+                    last_duration_ms,
+                    artifact_bytes,
+                    name,
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| {
+            b.artifact_bytes
+                .cmp(&a.artifact_bytes)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        Ok(stats)
+    }
+
+    /// Prints [`Self::per_package_stats`] as a table, or as JSON with `json`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the incremental cache directory cannot be read.
+    pub fn show_per_package_stats(&self, json: bool) -> Result<()> {
+        let stats = self.per_package_stats()?;
+        if json {
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+            return Ok(());
+        }
+        if stats.is_empty() {
+            println!("{} No incremental caches recorded yet", LOG_PREFIX);
+            return Ok(());
+        }
+
+        println!("{} Per-package statistics:", LOG_PREFIX);
+        let width = stats
+            .iter()
+            .map(|s| s.name.len())
+            .max()
+            .unwrap_or(0)
+            .max("Package".len());
+        println!(
+            "  {:<width$}  {:>7}  {:>6}  {:>9}  {:>9}  {:>10}",
+            "Package",
+            "Entries",
+            "Hits",
+            "Avg",
+            "Last",
+            "Size",
+            width = width
+        );
+        for s in &stats {
+            println!(
+                "  {:<width$}  {:>7}  {:>6}  {:>8.2}s  {:>8.2}s  {:>7.2} MB",
+                s.name,
+                s.entries,
+                s.hits,
+                s.avg_duration_ms as f64 / 1000.0,
+                s.last_duration_ms as f64 / 1000.0,
+                s.artifact_bytes as f64 / 1024.0 / 1024.0,
+                width = width
+            );
+        }
 
         Ok(())
     }
 
-    /// Invalidates caches for specified packages or all packages.
+    /// Invalidates caches for specified packages or all packages, without
+    /// asking for confirmation.
     ///
     /// # Arguments
     ///
@@ -2059,42 +9442,179 @@ impl CacheManager {
     ///
     /// Returns an error if the cache directory cannot be read.
     pub fn invalidate_caches(&self, packages: Vec<String>, all: bool) -> Result<()> {
-        if all {
-            println!("{} Invalidating all caches...", LOG_PREFIX);
-            let mut count = 0;
-
-            for entry in fs::read_dir(&self.incremental_dir)?.flatten() {
-                if fs::remove_file(entry.path()).is_ok() {
-                    count += 1;
-                }
-            }
-
-            println!("{} Removed {} incremental cache files", LOG_PREFIX, count);
-        } else if !packages.is_empty() {
-            println!("{} Invalidating caches for: {:?}", LOG_PREFIX, packages);
-            let mut count = 0;
+        self.invalidate_caches_with(&InvalidateOptions {
+            packages,
+            all,
+            force: true,
+            ..Default::default()
+        })?;
+        Ok(())
+    }
 
-            for entry in fs::read_dir(&self.incremental_dir)?.flatten() {
-                let filename = entry.file_name().to_string_lossy().to_string();
-                for package in &packages {
-                    if filename.starts_with(package) {
-                        if fs::remove_file(entry.path()).is_ok() {
-                            count += 1;
-                        }
-                        break;
-                    }
-                }
-            }
+    /// Invalidates incremental caches selected by `options`.
+    ///
+    /// Asks for confirmation unless [`InvalidateOptions::force`] is set or
+    /// stdin is no terminal, as in git hooks. With
+    /// [`InvalidateOptions::dry_run`] the selected entries are only listed.
+    ///
+    /// Returns the number of cache entries removed, or that would be removed
+    /// in a dry run.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory cannot be read, a pattern
+    /// fails to compile or the confirmation prompt fails.
+    pub fn invalidate_caches_with(&self, options: &InvalidateOptions) -> Result<usize> {
+        use std::io::IsTerminal;
 
-            println!("{} Removed {} cache files", LOG_PREFIX, count);
+        if options.all {
+            println!("{} Invalidating all caches...", LOG_PREFIX);
+        } else if !options.packages.is_empty() {
+            println!(
+                "{} Invalidating caches for: {:?}",
+                LOG_PREFIX, options.packages
+            );
+        } else if options.has_filters() {
+            println!("{} Invalidating caches matching the filters...", LOG_PREFIX);
         } else {
             println!(
-                "{} Specify --all or package names to invalidate",
+                "{} Specify --all, package names or filters to invalidate",
                 LOG_PREFIX
             );
+            return Ok(0);
         }
 
-        Ok(())
+        let candidates = self.invalidate_candidates(options)?;
+
+        if options.dry_run {
+            print_removal_preview(&candidates);
+            return Ok(candidates.len());
+        }
+
+        // Scripts and git hooks have nobody to answer
+        if !candidates.is_empty()
+            && !options.force
+            && io::stdin().is_terminal()
+            && !confirm(&format!("Remove {} cache entries?", candidates.len()))?
+        {
+            println!("{} Aborted", LOG_PREFIX);
+            return Ok(0);
+        }
+
+        let count = candidates
+            .iter()
+            .filter(|path| fs::remove_file(path).is_ok())
+            .count();
+
+        println!("{} Removed {} cache files", LOG_PREFIX, count);
+        Ok(count)
+    }
+
+    /// Returns the incremental cache entries
+    /// [`CacheManager::invalidate_caches_with`] would remove for `options`.
+    ///
+    /// Filters combine with AND. Without `all` or `packages` every package
+    /// matches, but nothing is selected unless a filter is set. With
+    /// `changed`, the packages that need a rebuild are those of the
+    /// `subcommand` (`build` by default) with the `profile` and `features`
+    /// filters as arguments.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory cannot be read, a pattern
+    /// fails to compile, `features` is set and cargo metadata is unavailable,
+    /// or `changed` is set and the workspace state cannot be computed.
+    pub fn invalidate_candidates(&self, options: &InvalidateOptions) -> Result<Vec<PathBuf>> {
+        let matcher = if !options.packages.is_empty() && !options.all {
+            PackageMatcher::new(options.match_mode, &options.packages)?
+        } else if options.all || options.has_filters() {
+            PackageMatcher::Any
+        } else {
+            return Ok(Vec::new());
+        };
+
+        // Entries record the features of their own package, which depend on
+        // its feature table
+        let features_hashes: Option<HashMap<String, String>> = match &options.features {
+            Some(features) => {
+                let metadata = self.get_cargo_metadata()?;
+                let args = ["--features".to_string(), features.clone()];
+                Some(
+                    workspace_features(&metadata, &args)
+                        .into_iter()
+                        .map(|(name, features)| {
+                            (name.to_string(), self.features_set_hash(&features))
+                        })
+                        .collect(),
+                )
+            }
+            None => None,
+        };
+        let filtered = options.has_filters();
+        // Same-named packages of other workspaces sharing the cache are not
+        // this workspace's to invalidate
+        let changed: Option<(PathBuf, HashSet<String>)> = if options.changed {
+            let mut args = options.build_args();
+            if let Some(manifest_path) = &self.manifest_path {
+                args.push("--manifest-path".to_string());
+                args.push(manifest_path.display().to_string());
+            }
+            let subcommand = options.subcommand.as_deref().unwrap_or("build");
+            let workspace = self.compute_workspace_state(&args)?;
+            let names = self
+                .changed_package_names(
+                    &workspace,
+                    &self.compute_command_hash(subcommand, &args),
+                    &self.compute_env_hash(),
+                    self.is_release_build(&args),
+                    &args,
+                )
+                .into_iter()
+                .collect();
+            Some((workspace.root, names))
+        } else {
+            None
+        };
+
+        let mut candidates = Vec::new();
+        for entry in fs::read_dir(&self.incremental_dir)?.flatten() {
+            let path = entry.path();
+            let cache = fs::read_to_string(&path)
+                .ok()
+                .and_then(|c| serde_json::from_str::<IncrementalCache>(&c).ok());
+
+            let selected = match cache {
+                Some(cache) => {
+                    matcher.is_match(&cache.package_name)
+                        && options
+                            .profile
+                            .map_or(true, |p| (p == Profile::Release) == cache.is_release)
+                        && features_hashes.as_ref().map_or(true, |hashes| {
+                            hashes.get(&cache.package_name) == Some(&cache.features_hash)
+                        })
+                        && options
+                            .subcommand
+                            .as_ref()
+                            .map_or(true, |s| cache.subcommand.as_ref() == Some(s))
+                        && options.older_than.map_or(true, |cutoff| {
+                            chrono::DateTime::parse_from_rfc3339(&cache.timestamp)
+                                .is_ok_and(|time| SystemTime::from(time) < cutoff)
+                        })
+                        && changed.as_ref().map_or(true, |(root, names)| {
+                            cache.workspace_root.as_ref() == Some(root)
+                                && names.contains(&cache.package_name)
+                        })
+                }
+                // Unreadable entries can't be filtered, only swept by a plain --all
+                None => options.all && !filtered,
+            };
+
+            if selected {
+                candidates.push(path);
+            }
+        }
+        candidates.sort();
+        Ok(candidates)
     }
 
     /// Shows the current workspace status.
@@ -2108,18 +9628,27 @@ impl CacheManager {
     /// # Arguments
     ///
     /// - `show_hashes`: If true, show package source hashes
+    /// - `full_hashes`: If true, print hashes untruncated
+    /// - `explain`: If true, give the reason each uncached package needs a
+    ///   rebuild (see [`explain_status`](Self::explain_status))
     ///
     /// # Errors
     ///
     /// Returns an error if workspace state cannot be computed.
-    pub fn show_status(&self, show_hashes: bool) -> Result<()> {
-        let workspace = self.compute_workspace_state(&[])?;
+    pub fn show_status(&self, show_hashes: bool, full_hashes: bool, explain: bool) -> Result<()> {
+        let workspace = self.compute_workspace_state_with_progress_bar(&[])?;
 
         println!("{} Workspace Status:", LOG_PREFIX);
         println!("  Root: {}", workspace.root.display());
         println!("  Packages: {}", workspace.packages.len());
-        println!("  Cargo.lock hash: {}", &workspace.cargo_lock_hash[..16]);
-        println!("  Toolchain hash: {}", &workspace.toolchain_hash[..16]);
+        println!(
+            "  Cargo.lock hash: {}",
+            display_hash(&workspace.cargo_lock_hash, full_hashes)
+        );
+        println!(
+            "  Toolchain hash: {}",
+            display_hash(&workspace.toolchain_hash, full_hashes)
+        );
         println!();
 
         if let Some(ref git) = workspace.git_features {
@@ -2148,12 +9677,53 @@ impl CacheManager {
             println!("  Package hashes:");
             for pkg in &workspace.packages {
                 println!(
-                    "    {} {}: {}...",
+                    "    {} {}: {}{}",
                     pkg.name,
                     pkg.version,
-                    &pkg.source_hash[..16]
+                    display_hash(&pkg.source_hash, full_hashes),
+                    if full_hashes { "" } else { "..." }
                 );
             }
+            println!();
+        }
+
+        let color = ColorChoice::from_env().enabled_for_stdout();
+        let plan = self.plan("build", &[], &workspace)?;
+        let changed: HashSet<&str> = plan
+            .changed_packages
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        let reasons = if explain {
+            self.explain_plan(&plan, &workspace)?
+        } else {
+            BTreeMap::new()
+        };
+
+        println!(
+            "  Build cache ({}/{} cached):",
+            plan.cached_count, plan.total_packages
+        );
+        for pkg in &workspace.packages {
+            let marker = if changed.contains(pkg.name.as_str()) {
+                paint("✗ needs rebuild", owo_colors::Style::new().red(), color)
+            } else {
+                paint("✓ cached", owo_colors::Style::new().green(), color)
+            };
+            match reasons.get(&pkg.name) {
+                Some(reason) => println!("    {} {} ({})", marker, pkg.name, reason),
+                None => println!("    {} {}", marker, pkg.name),
+            }
+        }
+
+        let file_hashed = file_hashed_packages(&workspace);
+        if !file_hashed.is_empty() {
+            println!();
+            println!(
+                "  {} packages used file-based hashing: {}",
+                file_hashed.len(),
+                file_hashed.join(", ")
+            );
         }
 
         Ok(())
@@ -2185,15 +9755,18 @@ impl CacheManager {
     /// # Ok(())
     /// # }
     /// ```
-    pub fn install_git_hooks(&self, workspace_root: &Path) -> Result<()> {
+    pub fn install_git_hooks(&self, workspace_root: &Path) -> CacheResult<()> {
         let git_dir = Command::new("git")
             .args(["rev-parse", "--git-common-dir"])
             .current_dir(workspace_root)
             .output()
-            .context("Failed to get git directory")?;
+            .map_err(|e| CacheError::Git(format!("failed to run git: {}", e)))?;
 
         if !git_dir.status.success() {
-            anyhow::bail!("Not in a git repository");
+            return Err(CacheError::Git(format!(
+                "{} is not in a git repository",
+                workspace_root.display()
+            )));
         }
 
         let git_dir_path = PathBuf::from(String::from_utf8_lossy(&git_dir.stdout).trim());
@@ -2211,13 +9784,12 @@ if command -v cargo-save >/dev/null 2>&1; then
     # Only invalidate if HEAD changed (not just file checkouts)
     if [ "$3" = "1" ]; then
         echo "[cargo-save] Branch changed, invalidating cache..."
-        cargo-save invalidate --all 2>/dev/null || true
+        cargo-save invalidate --all --force 2>/dev/null || true
     fi
 fi
 "#;
 
-        fs::write(&post_checkout_hook, hook_content)
-            .context("Failed to write post-checkout hook")?;
+        fs::write(&post_checkout_hook, hook_content)?;
 
         #[cfg(unix)]
         {
@@ -2235,12 +9807,11 @@ fi
 
 if command -v cargo-save >/dev/null 2>&1; then
     echo "[cargo-save] Merge completed, invalidating cache..."
-    cargo-save invalidate --all 2>/dev/null || true
+    cargo-save invalidate --all --force 2>/dev/null || true
 fi
 "#;
 
-        fs::write(&post_merge_hook, merge_hook_content)
-            .context("Failed to write post-merge hook")?;
+        fs::write(&post_merge_hook, merge_hook_content)?;
 
         #[cfg(unix)]
         {
@@ -2250,13 +9821,10 @@ fi
             fs::set_permissions(&post_merge_hook, perms)?;
         }
 
-        eprintln!("{} Installed git hooks:", LOG_PREFIX);
-        eprintln!("{}   - post-checkout", LOG_PREFIX);
-        eprintln!("{}   - post-merge", LOG_PREFIX);
-        eprintln!(
-            "{} Hooks will auto-invalidate cache on branch changes",
-            LOG_PREFIX
-        );
+        log_info!("Installed git hooks:");
+        log_info!("  - post-checkout");
+        log_info!("  - post-merge");
+        log_info!("Hooks will auto-invalidate cache on branch changes");
 
         Ok(())
     }
@@ -2272,12 +9840,17 @@ fi
 
     /// Prompts user to setup sccache if not configured
     fn prompt_sccache_setup() -> Result<()> {
-        use std::io::{self, Write};
+        use std::io::{self, IsTerminal, Write};
+
+        // An empty answer means yes, so never read one from a pipe or /dev/null
+        if !io::stdin().is_terminal() {
+            return Ok(());
+        }
 
         let sccache_installed = Self::is_sccache_installed();
 
         eprintln!("\nTip: sccache provides cross-project compilation caching");
-        
+
         if sccache_installed {
             eprintln!("    sccache is installed but not configured.");
             eprint!("    Enable it now? [Y/n]: ");
@@ -2302,7 +9875,7 @@ fi
 
             if input.is_empty() || input == "y" || input == "yes" {
                 eprintln!("    Installing sccache...");
-                let status = Command::new("cargo")
+                let status = Command::new(cargo_bin())
                     .args(["install", "sccache"])
                     .status()?;
 
@@ -2318,226 +9891,950 @@ fi
             }
         }
 
-        Ok(())
+        Ok(())
+    }
+
+    /// Sets up sccache environment variable
+    fn setup_sccache_env() -> Result<()> {
+        use std::io::{self, Write};
+
+        let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".to_string());
+        let config_file = if shell.contains("zsh") {
+            "~/.zshrc"
+        } else if shell.contains("fish") {
+            "~/.config/fish/config.fish"
+        } else {
+            "~/.bashrc"
+        };
+
+        eprintln!("\n    Add to {}:", config_file);
+        eprintln!("    export RUSTC_WRAPPER=sccache");
+        eprint!("\n    Add automatically? [Y/n]: ");
+        io::stderr().flush()?;
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        let input = input.trim().to_lowercase();
+
+        if input.is_empty() || input == "y" || input == "yes" {
+            let home = std::env::var("HOME")?;
+            let config_path = config_file.replace("~", &home);
+
+            let line =
+                "\n# Enable sccache for cross-project caching\nexport RUSTC_WRAPPER=sccache\n";
+
+            if let Ok(mut file) = fs::OpenOptions::new().append(true).open(&config_path) {
+                file.write_all(line.as_bytes())?;
+                eprintln!("    Added to {}", config_file);
+                eprintln!("    Restart terminal or run: source {}", config_file);
+            } else {
+                eprintln!("    Could not write to {}", config_file);
+                eprintln!("    Add manually: export RUSTC_WRAPPER=sccache");
+            }
+        } else {
+            eprintln!(
+                "    Add manually to {}: export RUSTC_WRAPPER=sccache",
+                config_file
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Interactive setup for sccache integration
+    ///
+    /// Guides the user through installing and configuring sccache
+    /// for cross-project compilation caching.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if installation or configuration fails.
+    pub fn setup_sccache(&self) -> Result<()> {
+        println!("sccache Setup\n");
+
+        // Check current status
+        if let Ok(wrapper) = std::env::var("RUSTC_WRAPPER") {
+            if wrapper.contains("sccache") {
+                println!("sccache is already configured");
+                println!("RUSTC_WRAPPER={}\n", wrapper);
+
+                // Show stats if available
+                if let Ok(output) = Command::new("sccache").args(["--show-stats"]).output() {
+                    if output.status.success() {
+                        println!("Statistics:");
+                        println!("{}", String::from_utf8_lossy(&output.stdout));
+                    }
+                }
+                return Ok(());
+            }
+        }
+
+        // Check if installed
+        if Self::is_sccache_installed() {
+            println!("sccache is installed");
+            println!("Configuring environment...\n");
+            Self::setup_sccache_env()?;
+        } else {
+            println!("sccache is not installed");
+            Self::prompt_sccache_setup()?;
+        }
+
+        println!("\nSetup complete");
+        println!("\nNext steps:");
+        println!("  1. Restart terminal or run: source ~/.bashrc (or ~/.zshrc)");
+        println!("  2. Verify: cargo-save doctor");
+        println!("  3. Use normally: cargo-save build");
+
+        Ok(())
+    }
+
+    /// Checks environment and integration status.
+    ///
+    /// Displays diagnostic information about:
+    /// - Git availability
+    /// - sccache integration
+    /// - Cache size and location
+    /// - Recommendations for optimization
+    ///
+    /// See [`CacheManager::doctor_report`] for the same data in structured form.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if cache statistics cannot be computed.
+    pub fn doctor(&self) -> Result<()> {
+        let report = self.doctor_report()?;
+
+        println!("cargo-save environment check\n");
+
+        match &report.git_version {
+            Some(version) => println!("Git: {}", version),
+            None => println!("Git: Not found"),
+        }
+
+        match &report.nextest_version {
+            Some(version) => println!("cargo-nextest: {}", version),
+            None => {
+                println!("cargo-nextest: Not found (optional, used by `cargo save nextest run`)")
+            }
+        }
+
+        match &report.rustc_wrapper {
+            Some(wrapper) if report.sccache => {
+                println!("RUSTC_WRAPPER: {} (cross-project caching enabled)", wrapper);
+                if let Some(stats) = &report.sccache_stats {
+                    println!("  {}", stats);
+                }
+            }
+            Some(wrapper) => println!("RUSTC_WRAPPER: {} (custom wrapper)", wrapper),
+            None => println!("RUSTC_WRAPPER: Not set"),
+        }
+
+        println!();
+
+        println!("Cache Status:");
+        println!(
+            "  Size: {:.2} MB",
+            report.cache_size_bytes as f64 / 1024.0 / 1024.0
+        );
+        println!("  Build logs: {}", report.build_logs);
+        println!("  Metadata files: {}", report.metadata_files);
+        println!("  Incremental caches: {}", report.incremental_caches);
+        println!("  Location: {}", report.cache_dir.display());
+
+        if !report.recommendations.is_empty() {
+            println!();
+            println!("Recommendations:");
+            for recommendation in &report.recommendations {
+                println!("  - {}", recommendation);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Collects the environment and cache diagnostics shown by `doctor`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directories cannot be read.
+    pub fn doctor_report(&self) -> Result<DoctorReport> {
+        let mut recommendations = Vec::new();
+
+        let git_version = Command::new("git")
+            .args(["--version"])
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string());
+        if git_version.is_none() {
+            recommendations.push(
+                "Install git for optimal performance; cargo-save falls back to file hashing (slower)"
+                    .to_string(),
+            );
+        }
+
+        let nextest_version = Command::new(cargo_bin())
+            .args(["nextest", "--version"])
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| {
+                String::from_utf8_lossy(&o.stdout)
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string()
+            });
+
+        let rustc_wrapper = std::env::var("RUSTC_WRAPPER")
+            .ok()
+            .filter(|wrapper| !wrapper.is_empty());
+        let mut sccache = false;
+        let mut sccache_stats = None;
+        match &rustc_wrapper {
+            Some(wrapper) => {
+                let version_output = Command::new(wrapper)
+                    .args(["--version"])
+                    .output()
+                    .ok()
+                    .and_then(|o| String::from_utf8(o.stdout).ok())
+                    .unwrap_or_default();
+                sccache = version_output.contains("sccache");
+
+                if sccache {
+                    sccache_stats = Command::new(wrapper)
+                        .args(["--show-stats"])
+                        .output()
+                        .ok()
+                        .filter(|o| o.status.success())
+                        .and_then(|o| {
+                            String::from_utf8_lossy(&o.stdout)
+                                .lines()
+                                .find(|l| l.contains("Cache hits"))
+                                .map(|l| l.trim().to_string())
+                        });
+                }
+            }
+            None => recommendations
+                .push("Run 'cargo-save setup-sccache' for cross-project caching".to_string()),
+        }
+
+        let usage = self.cache_usage()?;
+        let cache_size_bytes = usage.total_bytes();
+
+        let size_mb = cache_size_bytes as f64 / 1024.0 / 1024.0;
+        if size_mb > 1000.0 {
+            recommendations.push(format!(
+                "Cache is large (>{:.0} MB); consider 'cargo-save clean --days 30'",
+                size_mb
+            ));
+        }
+
+        Ok(DoctorReport {
+            git_version,
+            nextest_version,
+            rustc_wrapper,
+            sccache,
+            sccache_stats,
+            cache_dir: self.cache_dir.clone(),
+            cache_size_bytes,
+            build_logs: usage.build_logs.files,
+            metadata_files: usage.metadata.files,
+            incremental_caches: usage.incremental.files,
+            usage,
+            recommendations,
+        })
+    }
+
+    /// Opens the interactive build browser in the terminal.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no builds are recorded, the terminal cannot be set
+    /// up, or cargo-save was built without the `tui` feature.
+    pub fn run_ui(&self) -> Result<()> {
+        #[cfg(feature = "tui")]
+        {
+            use crossterm::event::{self, Event, KeyEventKind};
+            use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+            use crossterm::ExecutableCommand;
+
+            let builds = self.load_build_caches()?;
+            if builds.is_empty() {
+                anyhow::bail!("No cached builds to browse");
+            }
+            let mut app = BrowserApp::new(self, builds);
+
+            terminal::enable_raw_mode()?;
+            io::stdout().execute(EnterAlternateScreen)?;
+            let result = (|| -> Result<()> {
+                let backend = ratatui::backend::CrosstermBackend::new(io::stdout());
+                let mut terminal = ratatui::Terminal::new(backend)?;
+                loop {
+                    terminal.draw(|frame| app.draw(frame))?;
+                    if let Event::Key(key) = event::read()? {
+                        if key.kind == KeyEventKind::Press && app.handle_key(self, key.code) {
+                            return Ok(());
+                        }
+                    }
+                }
+            })();
+            // Restore the terminal even if drawing failed
+            terminal::disable_raw_mode()?;
+            io::stdout().execute(LeaveAlternateScreen)?;
+            result
+        }
+
+        #[cfg(not(feature = "tui"))]
+        anyhow::bail!(
+            "cargo-save was built without the `tui` feature; reinstall with `--features tui`"
+        )
+    }
+}
+
+/// State of the `ui` build browser: builds on the left, the selected
+/// build's log on the right.
+///
+/// Kept free of terminal I/O so it can be driven and rendered headlessly.
+#[cfg(feature = "tui")]
+#[derive(Debug, Clone, Default)]
+pub struct BrowserApp {
+    /// Builds to browse, newest first
+    pub builds: Vec<BuildCache>,
+    /// Index of the selected build
+    pub selected: usize,
+    /// First visible line of the log pane
+    pub scroll: usize,
+    /// Only show error lines of the log
+    pub errors_only: bool,
+    /// Only show log lines containing this text
+    pub search: String,
+    /// Keystrokes go to `search` instead of navigation
+    pub editing_search: bool,
+    /// Log lines of the selected build
+    log: Vec<String>,
+}
+
+#[cfg(feature = "tui")]
+impl BrowserApp {
+    /// Lines the log pane scrolls by on Page Up/Down.
+    const PAGE: usize = 10;
+
+    /// Creates the browser over `builds`, showing the newest one first.
+    pub fn new(cache: &CacheManager, mut builds: Vec<BuildCache>) -> Self {
+        builds.sort_by_key(|b| {
+            std::cmp::Reverse(chrono::DateTime::parse_from_rfc3339(&b.timestamp).ok())
+        });
+        let mut app = Self {
+            builds,
+            ..Default::default()
+        };
+        app.load_log(cache);
+        app
+    }
+
+    /// Returns the selected build, if there are any builds.
+    pub fn selected_build(&self) -> Option<&BuildCache> {
+        self.builds.get(self.selected)
+    }
+
+    /// Reads the selected build's log through [`CacheManager::query_logs_into`].
+    fn load_log(&mut self, cache: &CacheManager) {
+        self.scroll = 0;
+        let Some(build) = self.selected_build() else {
+            self.log.clear();
+            return;
+        };
+        let options = QueryOptions {
+            cache_id: Some(build.cache_id.clone()),
+            ..Default::default()
+        };
+        let mode = if self.errors_only { "errors" } else { "all" };
+        let mut out = Vec::new();
+        self.log = match cache.query_logs_into(mode, None, &options, &mut out) {
+            Ok(_) => String::from_utf8_lossy(&out)
+                .lines()
+                .map(str::to_string)
+                .collect(),
+            Err(e) => vec![format!("Failed to read log: {}", e)],
+        };
+    }
+
+    /// Returns the log lines matching the current search.
+    pub fn visible_lines(&self) -> Vec<&str> {
+        self.log
+            .iter()
+            .map(String::as_str)
+            .filter(|line| self.search.is_empty() || line.contains(&self.search))
+            .collect()
+    }
+
+    /// Applies a key press. Returns true when the user asked to quit.
+    pub fn handle_key(&mut self, cache: &CacheManager, key: crossterm::event::KeyCode) -> bool {
+        use crossterm::event::KeyCode;
+
+        if self.editing_search {
+            match key {
+                KeyCode::Enter => self.editing_search = false,
+                KeyCode::Esc => {
+                    self.editing_search = false;
+                    self.search.clear();
+                }
+                KeyCode::Backspace => {
+                    self.search.pop();
+                }
+                KeyCode::Char(c) => self.search.push(c),
+                _ => {}
+            }
+            self.scroll = 0;
+            return false;
+        }
+
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Down | KeyCode::Char('j') if self.selected + 1 < self.builds.len() => {
+                self.selected += 1;
+                self.load_log(cache);
+            }
+            KeyCode::Up | KeyCode::Char('k') if self.selected > 0 => {
+                self.selected -= 1;
+                self.load_log(cache);
+            }
+            KeyCode::PageDown | KeyCode::Char(' ') => {
+                let last = self.visible_lines().len().saturating_sub(1);
+                self.scroll = (self.scroll + Self::PAGE).min(last);
+            }
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(Self::PAGE),
+            KeyCode::Char('e') => {
+                self.errors_only = !self.errors_only;
+                self.load_log(cache);
+            }
+            KeyCode::Char('/') => {
+                self.editing_search = true;
+                self.search.clear();
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Draws the build list, the log pane and a key help line.
+    pub fn draw(&self, frame: &mut ratatui::Frame) {
+        use ratatui::layout::{Constraint, Direction, Layout};
+        use ratatui::style::{Color, Modifier, Style};
+        use ratatui::text::Line;
+        use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(1)])
+            .split(frame.size());
+        let panes = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(35), Constraint::Percentage(65)])
+            .split(rows[0]);
+
+        let items: Vec<ListItem> = self
+            .builds
+            .iter()
+            .map(|build| {
+                let (marker, color) = match build.status() {
+                    BuildStatus::Success => ("✓", Color::Green),
+                    BuildStatus::Failed => ("✗", Color::Red),
+                    BuildStatus::Unknown => ("?", Color::Yellow),
+                };
+                ListItem::new(format!(
+                    "{} {} {} {}ms",
+                    marker, build.cache_id, build.subcommand, build.duration_ms
+                ))
+                .style(Style::default().fg(color))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("Builds"))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(list, panes[0], &mut state);
+
+        let mut title = match self.selected_build() {
+            Some(build) => format!("Log {}", build.cache_id),
+            None => "Log".to_string(),
+        };
+        if self.errors_only {
+            title.push_str(" [errors]");
+        }
+        if !self.search.is_empty() || self.editing_search {
+            title.push_str(&format!(" /{}", self.search));
+        }
+        let lines: Vec<Line> = self
+            .visible_lines()
+            .into_iter()
+            .skip(self.scroll)
+            .map(Line::from)
+            .collect();
+        let log = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+        frame.render_widget(log, panes[1]);
+
+        let help = if self.editing_search {
+            "type to search  Enter done  Esc clear"
+        } else {
+            "↑/↓ build  PgUp/PgDn scroll  / search  e errors only  q quit"
+        };
+        frame.render_widget(Paragraph::new(help), rows[1]);
+    }
+}
+
+impl Default for CacheManager {
+    fn default() -> Self {
+        Self::new().expect("Failed to create CacheManager")
+    }
+}
+
+/// Exclusive advisory lock on a file in the cache's `locks/` directory.
+///
+/// The lock is released when the value is dropped, or by the OS if the
+/// process dies.
+struct CacheLock {
+    file: File,
+}
+
+impl CacheLock {
+    /// Takes the lock, waiting up to `timeout` for another process to
+    /// release it.
+    fn acquire(path: &Path, timeout: Duration) -> CacheResult<Self> {
+        Self::acquire_with(path, timeout, || {
+            log_info!("Another cargo-save is running, waiting for it to finish writing the cache");
+        })
+    }
+
+    /// Takes the lock like [`acquire`](Self::acquire) and writes this
+    /// process's pid into the lock file, so a process waiting for it can
+    /// name the holder.
+    fn acquire_recording_pid(path: &Path, timeout: Duration) -> CacheResult<Self> {
+        let lock = Self::acquire_with(path, timeout, || {
+            let holder = fs::read_to_string(path)
+                .ok()
+                .and_then(|pid| pid.trim().parse::<u32>().ok());
+            match holder {
+                Some(pid) => log_info!("Waiting for another cargo-save (pid {})…", pid),
+                None => log_info!("Waiting for another cargo-save…"),
+            }
+        })?;
+        lock.file.set_len(0)?;
+        write!(&lock.file, "{}", std::process::id())?;
+        Ok(lock)
+    }
+
+    /// Takes the lock, calling `on_wait` once if it has to wait.
+    fn acquire_with(path: &Path, timeout: Duration, on_wait: impl FnOnce()) -> CacheResult<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+
+        let deadline = std::time::Instant::now() + timeout;
+        let mut on_wait = Some(on_wait);
+
+        loop {
+            match fs2::FileExt::try_lock_exclusive(&file) {
+                Ok(()) => return Ok(Self { file }),
+                Err(e) if e.kind() == fs2::lock_contended_error().kind() => {}
+                Err(e) => return Err(e.into()),
+            }
+            if std::time::Instant::now() >= deadline {
+                return Err(CacheError::Locked(path.to_path_buf()));
+            }
+            if let Some(on_wait) = on_wait.take() {
+                on_wait();
+            }
+            std::thread::sleep(LOCK_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        let _ = fs2::FileExt::unlock(&self.file);
     }
+}
 
-    /// Sets up sccache environment variable
-    fn setup_sccache_env() -> Result<()> {
-        use std::io::{self, Write};
+/// Package hashes of a workspace, reused while its fingerprint is unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WorkspaceMemo {
+    /// See [`CacheManager::workspace_fingerprint`]
+    fingerprint: String,
+    /// Package hashes computed for that fingerprint
+    packages: Vec<PackageHash>,
+}
 
-        let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".to_string());
-        let config_file = if shell.contains("zsh") {
-            "~/.zshrc"
-        } else if shell.contains("fish") {
-            "~/.config/fish/config.fish"
-        } else {
-            "~/.bashrc"
-        };
+/// Packages served from the cache by one build, appended to `hits.jsonl`
+/// for [`CacheManager::build_history`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HitRecord {
+    /// When the build started
+    timestamp: String,
+    /// Cargo subcommand of the build
+    subcommand: String,
+    /// Number of packages served from the cache
+    cached: usize,
+    /// Number of packages the build covered
+    total: usize,
+}
 
-        eprintln!("\n    Add to {}:", config_file);
-        eprintln!("    export RUSTC_WRAPPER=sccache");
-        eprint!("\n    Add automatically? [Y/n]: ");
-        io::stderr().flush()?;
+/// Source hashes of a package around its last build, kept in
+/// `source-hashes.json` to spot builds that modify their own sources.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SourceHashRecord {
+    /// Source hash the package was built from
+    built_from: String,
+    /// Source hash of the package right after that build
+    after_build: String,
+}
 
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        let input = input.trim().to_lowercase();
+/// Writes captured build output to the combined log and per-stream logs.
+///
+/// With `collapse_repeats`, runs of identical consecutive lines are stored
+/// once as `<line> (repeated ×N)`; each log collapses its own runs, and the
+/// last run is written when the writer is dropped.
+struct BuildLogWriter {
+    combined: LogFile,
+    stdout: LogFile,
+    stderr: LogFile,
+    collapse_repeats: bool,
+}
 
-        if input.is_empty() || input == "y" || input == "yes" {
-            let home = std::env::var("HOME")?;
-            let config_path = config_file.replace("~", &home);
-            
-            let line = "\n# Enable sccache for cross-project caching\nexport RUSTC_WRAPPER=sccache\n";
-            
-            if let Ok(mut file) = fs::OpenOptions::new()
-                .append(true)
-                .open(&config_path)
-            {
-                file.write_all(line.as_bytes())?;
-                eprintln!("    Added to {}", config_file);
-                eprintln!("    Restart terminal or run: source {}", config_file);
-            } else {
-                eprintln!("    Could not write to {}", config_file);
-                eprintln!("    Add manually: export RUSTC_WRAPPER=sccache");
-            }
+impl BuildLogWriter {
+    /// Creates the combined `<id>.log` and the `<id>.out.log` / `<id>.err.log` stream logs.
+    fn create(cache_dir: &Path, cache_id: &str, collapse_repeats: bool) -> Result<Self> {
+        let open = |name: String| -> io::Result<LogFile> {
+            Ok(LogFile {
+                file: File::create(cache_dir.join(name))?,
+                run: None,
+            })
+        };
+        Ok(Self {
+            combined: open(format!("{}.log", cache_id))?,
+            stdout: open(format!("{}.out.log", cache_id))?,
+            stderr: open(format!("{}.err.log", cache_id))?,
+            collapse_repeats,
+        })
+    }
+
+    /// Appends a line to the combined log and to the log of its stream.
+    fn write_line(&mut self, line: &str, is_stderr: bool) -> io::Result<()> {
+        let collapse = self.collapse_repeats;
+        self.combined.write_line(line, collapse)?;
+        if is_stderr {
+            self.stderr.write_line(line, collapse)
         } else {
-            eprintln!("    Add manually to {}: export RUSTC_WRAPPER=sccache", config_file);
+            self.stdout.write_line(line, collapse)
         }
+    }
+}
 
-        Ok(())
+impl Drop for BuildLogWriter {
+    fn drop(&mut self) {
+        let _ = self.combined.end_run();
+        let _ = self.stdout.end_run();
+        let _ = self.stderr.end_run();
     }
+}
 
-    /// Interactive setup for sccache integration
-    ///
-    /// Guides the user through installing and configuring sccache
-    /// for cross-project compilation caching.
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if installation or configuration fails.
-    pub fn setup_sccache(&self) -> Result<()> {
-        println!("sccache Setup\n");
+/// One log file of a [`BuildLogWriter`] with its pending run of repeated lines.
+struct LogFile {
+    file: File,
+    /// Last line seen and how many times in a row, not yet written
+    run: Option<(String, usize)>,
+}
 
-        // Check current status
-        if let Ok(wrapper) = std::env::var("RUSTC_WRAPPER") {
-            if wrapper.contains("sccache") {
-                println!("sccache is already configured");
-                println!("RUSTC_WRAPPER={}\n", wrapper);
-                
-                // Show stats if available
-                if let Ok(output) = Command::new("sccache").args(["--show-stats"]).output() {
-                    if output.status.success() {
-                        println!("Statistics:");
-                        println!("{}", String::from_utf8_lossy(&output.stdout));
-                    }
-                }
+impl LogFile {
+    fn write_line(&mut self, line: &str, collapse: bool) -> io::Result<()> {
+        if !collapse {
+            return writeln!(self.file, "{}", line);
+        }
+        if let Some((last, count)) = &mut self.run {
+            if last == line {
+                *count += 1;
                 return Ok(());
             }
         }
+        self.end_run()?;
+        self.run = Some((line.to_string(), 1));
+        Ok(())
+    }
 
-        // Check if installed
-        if Self::is_sccache_installed() {
-            println!("sccache is installed");
-            println!("Configuring environment...\n");
-            Self::setup_sccache_env()?;
-        } else {
-            println!("sccache is not installed");
-            Self::prompt_sccache_setup()?;
+    /// Writes the pending run, if any.
+    fn end_run(&mut self) -> io::Result<()> {
+        match self.run.take() {
+            Some((line, 1)) => writeln!(self.file, "{}", line),
+            Some((line, count)) => writeln!(self.file, "{} (repeated ×{})", line, count),
+            None => Ok(()),
         }
+    }
+}
 
-        println!("\nSetup complete");
-        println!("\nNext steps:");
-        println!("  1. Restart terminal or run: source ~/.bashrc (or ~/.zshrc)");
-        println!("  2. Verify: cargo-save doctor");
-        println!("  3. Use normally: cargo-save build");
+/// Returns true if a log line contains `pattern`; an all-lowercase pattern
+/// matches case-insensitively.
+fn grep_matches(line: &str, pattern: &str) -> bool {
+    if pattern.to_lowercase() == pattern {
+        line.to_lowercase().contains(pattern)
+    } else {
+        line.contains(pattern)
+    }
+}
 
-        Ok(())
+/// Returns true for the log lines `query errors` prints.
+fn is_error_line(line: &str) -> bool {
+    line.contains("error[") || line.contains("error:")
+}
+
+/// Returns the name of a log file without its `.log` or `.log.zst` extension.
+fn log_stem(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    let name = name.strip_suffix(".zst").unwrap_or(name);
+    name.strip_suffix(".log").map(str::to_string)
+}
+
+/// Returns true for per-stream log files (`<id>.out.log` / `<id>.err.log`).
+fn is_stream_log(path: &Path) -> bool {
+    log_stem(path).is_some_and(|stem| stem.ends_with(".out") || stem.ends_with(".err"))
+}
+
+/// Returns true for combined build logs (`<id>.log`).
+fn is_build_log(path: &Path) -> bool {
+    log_stem(path).is_some() && !is_stream_log(path)
+}
+
+/// Returns `key` in a form that is a valid file name on every platform.
+///
+/// Keys made of `[A-Za-z0-9_-]` and at most [`MAX_KEY_FILE_LEN`] long are
+/// returned unchanged. Otherwise other characters become `_`, the key is
+/// shortened and a hash of the original key is appended, so keys that only
+/// differ in replaced characters stay distinct.
+fn file_safe_key(key: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if key.len() <= MAX_KEY_FILE_LEN && key.chars().all(is_safe) {
+        return key.to_string();
     }
+    let digest = blake3::hash(key.as_bytes()).to_hex();
+    let prefix: String = key
+        .chars()
+        .map(|c| if is_safe(c) { c } else { '_' })
+        .take(MAX_KEY_FILE_LEN - HASH_KEY_LEN * 2 - 1)
+        .collect();
+    format!("{}-{}", prefix, &digest[..HASH_KEY_LEN * 2])
+}
 
-    /// Checks environment and integration status.
-    ///
-    /// Displays diagnostic information about:
-    /// - Git availability
-    /// - sccache integration
-    /// - Cache size and location
-    /// - Recommendations for optimization
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if cache statistics cannot be computed.
-    pub fn doctor(&self) -> Result<()> {
-        println!("cargo-save environment check\n");
+/// Derives the blake3 key [`CacheManager`] hashes with from a cache salt,
+/// or None for an empty salt.
+fn salt_key(salt: &str) -> Option<[u8; 32]> {
+    (!salt.is_empty()).then(|| blake3::derive_key("cargo-save cache salt", salt.as_bytes()))
+}
 
-        // Check git
-        let git_available = Command::new("git")
-            .args(["--version"])
-            .output()
-            .map(|o| o.status.success())
-            .unwrap_or(false);
+/// Returns the directory symlinks may point into when hashing `path` outside
+/// git: the nearest ancestor with a `.git`, otherwise the outermost ancestor
+/// with a `Cargo.toml`, i.e. the workspace root.
+fn fallback_repo_root(path: &Path) -> PathBuf {
+    let path = canonicalize_or_raw(path);
+    path.ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .or_else(|| {
+            path.ancestors()
+                .filter(|dir| dir.join("Cargo.toml").is_file())
+                .last()
+        })
+        .unwrap_or(&path)
+        .to_path_buf()
+}
 
-        if git_available {
-            let git_version = Command::new("git")
-                .args(["--version"])
-                .output()
-                .ok()
-                .and_then(|o| String::from_utf8(o.stdout).ok())
-                .unwrap_or_default()
-                .trim()
-                .to_string();
-            println!("Git: {}", git_version);
-        } else {
-            println!("Git: Not found");
-            println!("  cargo-save will fall back to file hashing (slower)");
-            println!("  Install git for optimal performance");
-        }
+/// Canonicalizes `path`, falling back to the path as given when that fails,
+/// e.g. because the directory no longer exists.
+fn canonicalize_or_raw(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
 
-        // Check sccache
-        let rustc_wrapper = std::env::var("RUSTC_WRAPPER");
-        match rustc_wrapper {
-            Ok(wrapper) if !wrapper.is_empty() => {
-                // Try to get sccache version
-                let version_output = Command::new(&wrapper)
-                    .args(["--version"])
-                    .output()
-                    .ok()
-                    .and_then(|o| String::from_utf8(o.stdout).ok())
-                    .unwrap_or_default();
-                
-                if version_output.contains("sccache") {
-                    println!("RUSTC_WRAPPER: {} (cross-project caching enabled)", wrapper);
-                    
-                    // Try to get sccache stats
-                    if let Ok(stats) = Command::new(&wrapper).args(["--show-stats"]).output() {
-                        if stats.status.success() {
-                            let stats_str = String::from_utf8_lossy(&stats.stdout);
-                            if let Some(line) = stats_str.lines().find(|l| l.contains("Cache hits")) {
-                                println!("  {}", line.trim());
-                            }
-                        }
-                    }
-                } else {
-                    println!("RUSTC_WRAPPER: {} (custom wrapper)", wrapper);
-                }
-            }
-            _ => {
-                println!("RUSTC_WRAPPER: Not set");
-                println!("  Run 'cargo-save setup-sccache' for cross-project caching");
-            }
-        }
+/// Returns true for the build log copies `finish_build` writes to a
+/// workspace's `build-logs/`: `<YYYYmmdd_HHMMSS>_<subcommand>.txt`.
+fn is_workspace_build_log(file_name: &str) -> bool {
+    let Some(stem) = file_name.strip_suffix(".txt") else {
+        return false;
+    };
+    let (Some(timestamp), Some(subcommand)) = (stem.get(..15), stem.get(15..)) else {
+        return false;
+    };
+    let timestamp_ok = timestamp.char_indices().all(|(i, c)| match i {
+        8 => c == '_',
+        _ => c.is_ascii_digit(),
+    });
+    let subcommand = subcommand.strip_prefix('_').unwrap_or_default();
+    timestamp_ok
+        && !subcommand.is_empty()
+        && subcommand
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
 
-        println!();
+/// Returns true unless `CARGO_SAVE_NO_COMPRESS` disables log compression.
+fn log_compression_enabled() -> bool {
+    std::env::var("CARGO_SAVE_NO_COMPRESS").map_or(true, |v| v.is_empty() || v == "0")
+}
 
-        // Check cache size
-        let mut total_size = 0u64;
-        let mut log_count = 0u64;
-        let mut meta_count = 0u64;
+/// Replaces a plain log file with a zstd-compressed `<name>.zst` copy.
+fn compress_log(path: &Path) -> Result<()> {
+    let mut compressed = path.as_os_str().to_owned();
+    compressed.push(".zst");
+    let compressed = PathBuf::from(compressed);
+    let tmp = compressed.with_extension("zst.tmp");
+
+    zstd::stream::copy_encode(File::open(path)?, File::create(&tmp)?, 0)?;
+    fs::rename(&tmp, &compressed)?;
+    fs::remove_file(path)?;
+    Ok(())
+}
 
-        for entry in fs::read_dir(&self.cache_dir)?.flatten() {
-            if let Ok(metadata) = entry.metadata() {
-                if entry.path().extension().is_some_and(|e| e == "log") {
-                    total_size += metadata.len();
-                    log_count += 1;
-                }
-            }
-        }
+/// Reads a log file, decompressing `.zst` logs.
+fn read_log(path: &Path) -> Result<String> {
+    if path.extension().is_some_and(|ext| ext == "zst") {
+        let data = zstd::stream::decode_all(File::open(path)?)?;
+        Ok(String::from_utf8(data)?)
+    } else {
+        Ok(fs::read_to_string(path)?)
+    }
+}
 
-        for entry in fs::read_dir(&self.metadata_dir)?.flatten() {
-            if let Ok(metadata) = entry.metadata() {
-                total_size += metadata.len();
-                meta_count += 1;
-            }
-        }
+/// Returns the extensions of artifacts cargo writes to `deps/` on `os`
+/// (as in [`std::env::consts::OS`]); `""` stands for files without one.
+fn artifact_extensions(os: &str) -> &'static [&'static str] {
+    match os {
+        "windows" => &[
+            "rlib", "rmeta", "d", "exe", "pdb", "dll", "dll.lib", "dll.a", "lib", "a",
+        ],
+        "macos" | "ios" => &["rlib", "rmeta", "d", "", "dylib", "a"],
+        _ => &["rlib", "rmeta", "d", "", "so", "a"],
+    }
+}
 
-        let incremental_count = fs::read_dir(&self.incremental_dir)?.count() as u64;
-        for entry in fs::read_dir(&self.incremental_dir)?.flatten() {
-            if let Ok(metadata) = entry.metadata() {
-                total_size += metadata.len();
-            }
-        }
+/// Returns the OS of a target triple in [`std::env::consts::OS`] terms, as far
+/// as it matters for [`artifact_extensions`].
+fn triple_os(triple: &str) -> &'static str {
+    if triple.contains("windows") {
+        "windows"
+    } else if triple.contains("apple-darwin") {
+        "macos"
+    } else if triple.contains("apple-ios") {
+        "ios"
+    } else {
+        "linux"
+    }
+}
+
+/// Returns true if `file_name` is `name`, `name-<hash>` or `libname-<hash>`
+/// followed by one of `extensions`, the way cargo names artifacts of a crate.
+///
+/// Unlike a substring match, `foo` does not match `foobar-1234.rlib` or
+/// `foo-bar-1234`.
+fn is_artifact_of(file_name: &str, name: &str, extensions: &[&str]) -> bool {
+    let candidates = [Some(file_name), file_name.strip_prefix("lib")];
+    candidates.into_iter().flatten().any(|candidate| {
+        let Some(rest) = candidate.strip_prefix(name) else {
+            return false;
+        };
+        let (hash, extension) = rest.split_once('.').unwrap_or((rest, ""));
+        let hash_ok = hash.is_empty()
+            || hash
+                .strip_prefix('-')
+                .is_some_and(|h| !h.is_empty() && h.chars().all(|c| c.is_ascii_hexdigit()));
+        hash_ok && extensions.contains(&extension)
+    })
+}
 
-        let size_mb = total_size as f64 / 1024.0 / 1024.0;
+/// Parses a cache version directory name such as `v4`.
+fn parse_cache_version(name: &str) -> Option<u32> {
+    name.strip_prefix('v')?.parse().ok()
+}
 
-        println!("Cache Status:");
-        println!("  Size: {:.2} MB", size_mb);
-        println!("  Build logs: {}", log_count);
-        println!("  Metadata files: {}", meta_count);
-        println!("  Incremental caches: {}", incremental_count);
-        println!("  Location: {}", self.cache_dir.display());
+/// Asks `question` on stdout and returns true if the user answers `y`.
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} {} [y/N] ", LOG_PREFIX, question);
+    io::stdout().flush()?;
 
-        if size_mb > 1000.0 {
-            println!();
-            println!("Cache is large (>{:.0} MB). Consider:", size_mb);
-            println!("  cargo-save clean --days 30");
-        }
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim().eq_ignore_ascii_case("y"))
+}
 
-        Ok(())
+/// Prints the files a dry run would remove and the space they take up.
+fn print_removal_preview(paths: &[PathBuf]) {
+    let bytes: u64 = paths
+        .iter()
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|m| m.len())
+        .sum();
+    println!(
+        "{} Would remove {} files ({:.2} MB):",
+        LOG_PREFIX,
+        paths.len(),
+        bytes as f64 / 1024.0 / 1024.0
+    );
+    for path in paths {
+        println!("  {}", path.display());
     }
 }
 
-impl Default for CacheManager {
-    fn default() -> Self {
-        Self::new().expect("Failed to create CacheManager")
+/// Counts the files below `path` and their combined size.
+fn dir_usage(path: &Path) -> DirUsage {
+    WalkDir::new(path)
+        .into_iter()
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .fold(DirUsage::default(), |usage, m| DirUsage {
+            files: usage.files + 1,
+            bytes: usage.bytes + m.len(),
+        })
+}
+
+/// Computes the total size of all files below `path`.
+fn dir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Parses the report written by `cargo build --timings` into per-package
+/// durations in milliseconds.
+///
+/// The report embeds a `const UNIT_DATA = [...]` JSON array with one entry
+/// per compilation unit; units belonging to the same package (lib, bin,
+/// build script) are summed.
+pub fn parse_timings_html(content: &str) -> HashMap<String, u64> {
+    #[derive(Deserialize)]
+    struct Unit {
+        name: String,
+        duration: f64,
     }
+
+    let mut timings = HashMap::new();
+    let Some(start) = content.find("const UNIT_DATA = ") else {
+        return timings;
+    };
+    let rest = &content[start + "const UNIT_DATA = ".len()..];
+    let units = serde_json::Deserializer::from_str(rest)
+        .into_iter::<Vec<Unit>>()
+        .next()
+        .and_then(|units| units.ok())
+        .unwrap_or_default();
+
+    for unit in units {
+        *timings.entry(unit.name).or_insert(0) += (unit.duration * 1000.0).round() as u64;
+    }
+    timings
 }
 
 #[cfg(test)]
@@ -2558,6 +10855,54 @@ mod tests {
         assert_eq!(hash1, hash2);
     }
 
+    #[test]
+    fn test_check_entries_do_not_satisfy_build() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+        let package = PackageHash {
+            name: "core".to_string(),
+            version: "0.1.0".to_string(),
+            path: PathBuf::from("core"),
+            source_hash: "0".repeat(64),
+            dependencies: Vec::new(),
+            features_hash: "features".to_string(),
+            dependency_kinds: BTreeMap::new(),
+            lib_name: None,
+            hash_method: HashMethod::Git,
+        };
+        let workspace = WorkspaceState {
+            root: PathBuf::from("/workspace"),
+            packages: vec![package.clone()],
+            cargo_lock_hash: "lock".to_string(),
+            toolchain_hash: "toolchain".to_string(),
+            timestamp: "2024-01-01T00:00:00Z".to_string(),
+            git_features: None,
+            default_members: Vec::new(),
+        };
+        cache
+            .write_incremental_cache(
+                &package,
+                &workspace,
+                "cmd",
+                "env",
+                false,
+                &[],
+                true,
+                0,
+                None,
+                Some("check"),
+            )
+            .unwrap();
+
+        // Same command hash, so only the recorded output kind tells them apart
+        let changed =
+            |subcommand| cache.changed_names_for(&workspace, "cmd", "env", false, &[], subcommand);
+        assert_eq!(changed(Some("build")), vec!["core"]);
+        assert_eq!(changed(Some("test")), vec!["core"]);
+        assert!(changed(Some("clippy")).is_empty());
+        assert!(changed(None).is_empty());
+    }
+
     #[test]
     fn test_is_release_build() {
         let cache = CacheManager::new().unwrap();
@@ -2580,4 +10925,262 @@ mod tests {
         // Different commands should produce different hashes
         assert_ne!(hash1, hash3);
     }
+
+    #[test]
+    fn test_logs_are_stored_compressed() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+        let lines = [
+            "   Compiling foo v0.1.0",
+            "warning: unused variable",
+            "done",
+        ];
+        let mut log = BuildLogWriter::create(&cache.cache_dir, "build-1", false).unwrap();
+        for (i, line) in lines.iter().enumerate() {
+            log.write_line(line, i % 2 == 0).unwrap();
+        }
+        drop(log);
+        cache.compress_build_logs("build-1");
+
+        assert!(!cache.cache_dir.join("build-1.log").exists());
+        let compressed = cache.cache_dir.join("build-1.log.zst");
+        assert!(compressed.exists());
+        assert!(cache.cache_dir.join("build-1.err.log.zst").exists());
+        assert!(is_build_log(&compressed));
+        assert_eq!(log_stem(&compressed).as_deref(), Some("build-1"));
+
+        let mut out = Vec::new();
+        let options = QueryOptions {
+            cache_id: Some("build-1".to_string()),
+            ..Default::default()
+        };
+        cache
+            .query_logs_into("all", None, &options, &mut out)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), lines.join("\n") + "\n");
+    }
+
+    #[test]
+    fn test_repeated_log_lines_are_collapsed() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+        let repeated = "warning: use of deprecated function `old_api`";
+        let mut log = BuildLogWriter::create(&cache.cache_dir, "build-1", true).unwrap();
+        log.write_line("   Compiling foo v0.1.0", true).unwrap();
+        for _ in 0..100 {
+            log.write_line(repeated, true).unwrap();
+        }
+        log.write_line("done", false).unwrap();
+        drop(log);
+
+        let stored = cache.cache_dir.join("build-1.log");
+        assert!(fs::metadata(&stored).unwrap().len() < 2 * repeated.len() as u64 + 64);
+        let expected = format!(
+            "   Compiling foo v0.1.0\n{} (repeated ×100)\ndone\n",
+            repeated
+        );
+        assert_eq!(fs::read_to_string(&stored).unwrap(), expected);
+        assert_eq!(
+            fs::read_to_string(cache.cache_dir.join("build-1.out.log")).unwrap(),
+            "done\n"
+        );
+
+        let mut out = Vec::new();
+        let options = QueryOptions {
+            cache_id: Some("build-1".to_string()),
+            ..Default::default()
+        };
+        cache
+            .query_logs_into("all", None, &options, &mut out)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_cache_lock_times_out_while_held() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("key.lock");
+
+        let held = CacheLock::acquire(&path, Duration::ZERO).unwrap();
+        let err = CacheLock::acquire(&path, Duration::from_millis(100))
+            .err()
+            .unwrap();
+        assert!(matches!(err, CacheError::Locked(_)));
+        assert!(err.to_string().contains("Another cargo-save is running"));
+
+        drop(held);
+        assert!(CacheLock::acquire(&path, Duration::ZERO).is_ok());
+    }
+
+    #[test]
+    fn test_second_build_waits_for_workspace_lock() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = Path::new("/workspace");
+        let first = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+        let second = CacheManager::with_cache_dir(temp_dir.path())
+            .unwrap()
+            .with_build_wait(Duration::from_secs(10));
+
+        let held = first.lock_workspace_build(root).unwrap();
+        let lock_file = fs::read_dir(first.cache_dir.join("locks"))
+            .unwrap()
+            .flatten()
+            .find(|e| e.file_name().to_string_lossy().starts_with("build-"))
+            .unwrap()
+            .path();
+        assert_eq!(
+            fs::read_to_string(&lock_file).unwrap(),
+            std::process::id().to_string()
+        );
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let waiter = std::thread::spawn(move || {
+            let started = std::time::Instant::now();
+            let lock = second.lock_workspace_build(root);
+            tx.send(()).unwrap();
+            (lock.is_ok(), started.elapsed())
+        });
+
+        // Still waiting while the first build holds the lock
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+        drop(held);
+        let (acquired, waited) = waiter.join().unwrap();
+        assert!(acquired);
+        assert!(waited >= Duration::from_millis(200));
+
+        // Without time to wait, a held lock is an error
+        let _held = first.lock_workspace_build(root).unwrap();
+        let impatient = CacheManager::with_cache_dir(temp_dir.path())
+            .unwrap()
+            .with_build_wait(Duration::ZERO);
+        assert!(matches!(
+            impatient.lock_workspace_build(root),
+            Err(CacheError::Locked(_))
+        ));
+    }
+
+    #[test]
+    fn test_is_artifact_of_respects_name_boundaries() {
+        let exts = artifact_extensions("linux");
+        assert!(is_artifact_of("libfoo-0123abcd.rlib", "foo", exts));
+        assert!(is_artifact_of("foo-0123abcd", "foo", exts));
+        assert!(is_artifact_of("foo-0123abcd.d", "foo", exts));
+        assert!(is_artifact_of("libfoo_bar-0123abcd.so", "foo_bar", exts));
+
+        // Names that merely contain the package name
+        assert!(!is_artifact_of("foobar-0123abcd.rlib", "foo", exts));
+        assert!(!is_artifact_of("libfoobar-0123abcd.rlib", "foo", exts));
+        assert!(!is_artifact_of("foo_bar-0123abcd.rlib", "foo", exts));
+        assert!(!is_artifact_of("foo-bar-0123abcd", "foo", &[""]));
+        assert!(is_artifact_of("foo-bar-0123abcd", "foo-bar", &[""]));
+
+        // Extensions of other platforms are not artifacts here
+        assert!(!is_artifact_of("foo-0123abcd.exe", "foo", exts));
+    }
+
+    #[test]
+    fn test_artifact_extensions_per_platform() {
+        let windows = artifact_extensions("windows");
+        for ext in ["exe", "pdb", "dll", "rlib"] {
+            assert!(windows.contains(&ext), "{}", ext);
+        }
+        assert!(!windows.contains(&""));
+        assert!(is_artifact_of("foo-0123abcd.exe", "foo", windows));
+        assert!(is_artifact_of("foo-0123abcd.pdb", "foo", windows));
+        assert!(is_artifact_of("foo.dll", "foo", windows));
+        assert!(!is_artifact_of("foo-0123abcd", "foo", windows));
+
+        let macos = artifact_extensions("macos");
+        assert!(macos.contains(&"dylib") && !macos.contains(&"so"));
+        assert!(is_artifact_of("libfoo-0123abcd.dylib", "foo", macos));
+
+        let linux = artifact_extensions("linux");
+        assert!(linux.contains(&"so") && !linux.contains(&"dylib"));
+        assert!(linux.contains(&""));
+    }
+
+    /// Replays canned `(exit code, stderr)` results and counts the calls.
+    #[cfg(unix)]
+    struct ScriptedRunner {
+        results: std::sync::Mutex<VecDeque<(i32, &'static str)>>,
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    #[cfg(unix)]
+    impl ScriptedRunner {
+        fn new(results: &[(i32, &'static str)]) -> Self {
+            Self {
+                results: std::sync::Mutex::new(results.iter().copied().collect()),
+                calls: Default::default(),
+            }
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    #[cfg(unix)]
+    impl CommandRunner for ScriptedRunner {
+        fn run(&self, _program: &str, _args: &[&str], _cwd: Option<&Path>) -> io::Result<Output> {
+            use std::os::unix::process::ExitStatusExt;
+
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let (code, stderr) = self.results.lock().unwrap().pop_front().unwrap();
+            Ok(Output {
+                status: std::process::ExitStatus::from_raw(code << 8),
+                stdout: if code == 0 {
+                    b".git\n".to_vec()
+                } else {
+                    vec![]
+                },
+                stderr: stderr.as_bytes().to_vec(),
+            })
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_git_retries_transient_failures() {
+        let lock_error = "fatal: Unable to create '/repo/.git/index.lock': File exists.";
+
+        let runner = ScriptedRunner::new(&[(128, lock_error), (0, "")]);
+        let output = run_git(&runner, &["rev-parse", "--git-dir"], None).unwrap();
+        assert!(output.status.success());
+        assert_eq!(runner.calls(), 2);
+
+        // Not a repository is permanent and reported right away
+        let runner = ScriptedRunner::new(&[(
+            128,
+            "fatal: not a git repository (or any of the parent directories): .git",
+        )]);
+        let output = run_git(&runner, &["rev-parse", "--git-dir"], None).unwrap();
+        assert!(!output.status.success());
+        assert_eq!(runner.calls(), 1);
+
+        // Retries are bounded
+        let runner = ScriptedRunner::new(&[(128, lock_error); 3]);
+        let output = run_git(&runner, &["status", "--porcelain"], None).unwrap();
+        assert!(!output.status.success());
+        assert_eq!(runner.calls(), GIT_ATTEMPTS as usize);
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn test_sigv4_signing_key() {
+        // Example from the AWS Signature Version 4 documentation
+        let key = sigv4_signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20150830",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            to_hex(&key),
+            "c4afb1cc5771d871763a393e44b703571b55cc28424d1a5e86da6ed3c154a4b9"
+        );
+        assert_eq!(s3_uri_encode("a b/c+d"), "a%20b/c%2Bd");
+    }
 }
@@ -28,7 +28,11 @@
 //! cargo save query tail
 //! ```
 
-use cargo_save::{CacheManager, Cli};
+use cargo_save::{
+    load_env_file, log, log_level, set_log_level, CacheKeyOptions, CacheManager, CleanOptions,
+    CleanScope, Cli, ColorChoice, Commands, GraphFormat, InvalidateOptions, ListFormat,
+    ListOptions, LogLevel, QueryOptions,
+};
 use clap::Parser;
 
 /// Main entry point for the cargo-save CLI.
@@ -37,95 +41,438 @@ use clap::Parser;
 /// handler based on the subcommand.
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    if cli.quiet {
+        set_log_level(log_level().min(LogLevel::Error));
+    }
     let cache = CacheManager::new()?;
 
     // Dispatch to the appropriate handler based on the CLI subcommand
-    match cli {
+    match cli.command {
         // Handle both "cargo save <cmd>" and "cargo-save <cmd>" syntax
-        Cli::Save { subcommand, args } | Cli::Direct { subcommand, args } => {
-            let workspace = cache.compute_workspace_state(&args)?;
-            let (_, exit_code, _, _) =
-                cache.run_cargo_with_cache(&subcommand, &args, &workspace)?;
+        Commands::Save(save) | Commands::Direct(save) => {
+            let mut cache = cache
+                .with_no_cache_packages(save.no_cache_packages.clone())
+                .with_message_format(save.message_format);
+            if save.committed_only {
+                cache = cache.with_committed_only(true);
+            }
+            if let Some(git_ref) = &save.git_ref {
+                cache = cache.with_git_ref(git_ref);
+            }
+            if save.no_default_ignores {
+                cache = cache.with_hash_all_files(true);
+            }
+            if let Some(wait) = save.wait {
+                cache = cache.with_build_wait(std::time::Duration::from_secs(wait));
+            }
+            if let Some(path) = &save.env_file {
+                cache = cache.with_env_file_vars(load_env_file(path)?, save.env_file_override);
+            }
+            if CacheManager::is_passthrough(&save.subcommand) {
+                if save.dry_run {
+                    log(
+                        LogLevel::Info,
+                        format_args!("cargo {} is not cached, nothing to plan", save.subcommand),
+                    );
+                    return Ok(());
+                }
+                let (_, exit_code, _, _) =
+                    cache.run_cargo_passthrough(&save.subcommand, &save.args)?;
+                std::process::exit(exit_code.unwrap_or(1));
+            }
+
+            let workspace = cache
+                .compute_workspace_state_with_progress_bar(&save.args)?
+                .built_by(&save.args);
+            // Only the checked-out tree can be built
+            cache.check_git_ref_checkout(&workspace.root)?;
+
+            if save.dry_run {
+                let plan = cache.plan(&save.subcommand, &save.args, &workspace)?;
+                if plan.changed_packages.is_empty() {
+                    log(
+                        LogLevel::Info,
+                        format_args!(
+                            "All packages cached, nothing to rebuild for {}",
+                            save.subcommand
+                        ),
+                    );
+                } else {
+                    cache.print_build_plan(&plan);
+                }
+                return Ok(());
+            }
+
+            // The plan has to be taken before the build caches everything
+            let plan = if save.summary_json {
+                Some(cache.plan(&save.subcommand, &save.args, &workspace)?)
+            } else {
+                None
+            };
+            let (cache_id, exit_code, _, duration_ms) = match save.parallel_packages {
+                Some(jobs) => {
+                    cache.run_cargo_parallel(&save.subcommand, &save.args, &workspace, jobs)?
+                }
+                None => cache.run_cargo_with_cache(&save.subcommand, &save.args, &workspace)?,
+            };
+            if save.timing_report {
+                cache.print_phase_timings(&cache.phase_timings());
+            }
+            if let Some(plan) = plan {
+                let summary = cache.build_summary(
+                    &save.subcommand,
+                    &save.args,
+                    &cache_id,
+                    exit_code,
+                    duration_ms,
+                    &plan,
+                );
+                println!("{}", serde_json::to_string(&summary)?);
+            }
             std::process::exit(exit_code.unwrap_or(1));
         }
 
-        Cli::Query {
+        Commands::Query {
             mode,
             param,
             id,
             last,
+            since,
+            follow,
+            stream,
+            all_builds,
+            exit_on_no_match,
         } => {
-            cache.query_logs(&mode, param.as_deref(), id.as_deref(), last)?;
+            let options = QueryOptions {
+                cache_id: id,
+                last,
+                since,
+                follow,
+                stream,
+                color: ColorChoice::from_env().enabled_for_stdout(),
+                all_builds,
+            };
+            // Like grep: 1 for no match, 2 for no log to search
+            let written = match cache.query_logs_with(&mode, param.as_deref(), &options) {
+                Ok(written) => written,
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    std::process::exit(2);
+                }
+            };
+            let searches = matches!(
+                mode.as_str(),
+                "grep" | "errors" | "error" | "warnings" | "warning"
+            );
+            if exit_on_no_match && searches && written == 0 {
+                std::process::exit(1);
+            }
         }
 
-        Cli::List { verbose, workspace } => {
-            cache.list_caches(verbose, workspace)?;
+        Commands::List {
+            verbose,
+            format,
+            workspace,
+            status,
+            subcommand,
+            since,
+            sort,
+            reverse,
+            limit,
+            json,
+        } => {
+            let options = ListOptions {
+                format: if verbose { ListFormat::Long } else { format },
+                workspace_only: workspace,
+                status,
+                subcommand,
+                since,
+                sort,
+                reverse,
+                limit,
+                json,
+                color: ColorChoice::from_env().enabled_for_stdout(),
+            };
+            cache.list_caches_with(&options)?;
         }
 
-        Cli::Clean { days, keep, force } => {
-            cache.clean_old_caches(days, keep, force)?;
+        Commands::Clean {
+            days,
+            keep,
+            force,
+            dry_run,
+            logs_only,
+            incremental_only,
+        } => {
+            let scope = if logs_only {
+                CleanScope::Logs
+            } else if incremental_only {
+                CleanScope::Incremental
+            } else {
+                CleanScope::All
+            };
+            cache.clean_old_caches_with(&CleanOptions {
+                days,
+                keep,
+                force,
+                dry_run,
+                scope,
+            })?;
         }
 
-        Cli::Stats => {
-            cache.show_stats()?;
+        Commands::PruneLogs { days, keep } => {
+            let workspace_root: std::path::PathBuf =
+                cache.get_cargo_metadata()?.workspace_root.into();
+            let dir = workspace_root.join("build-logs");
+            let removed = cache.prune_build_logs(&dir, days, keep)?;
+            log(
+                LogLevel::Info,
+                format_args!("Removed {} build logs from {}", removed, dir.display()),
+            );
         }
 
-        Cli::Invalidate { packages, all } => {
-            cache.invalidate_caches(packages, all)?;
+        Commands::Gc => {
+            let workspace = cache.compute_workspace_state(&[])?;
+            let removed = cache.gc_removed_packages(&workspace)?;
+            log(
+                LogLevel::Info,
+                format_args!("Removed {} caches of former workspace members", removed),
+            );
         }
 
-        Cli::Status { hashes } => {
-            cache.show_status(hashes)?;
+        Commands::Stats {
+            slowest,
+            per_package,
+            json,
+        } => match slowest {
+            Some(n) => cache.show_slowest_packages(n)?,
+            None if per_package => cache.show_per_package_stats(json)?,
+            None => cache.show_stats()?,
+        },
+
+        Commands::Invalidate {
+            packages,
+            all,
+            match_mode,
+            profile,
+            features,
+            subcommand,
+            older_than,
+            changed,
+            force,
+            dry_run,
+        } => {
+            let options = InvalidateOptions {
+                packages,
+                all,
+                match_mode,
+                profile,
+                features,
+                subcommand,
+                older_than,
+                changed,
+                force,
+                dry_run,
+            };
+            cache.invalidate_caches_with(&options)?;
         }
 
-        Cli::CacheKey { platform } => {
-            let workspace = cache.compute_workspace_state(&[])?;
-            let key = format!(
-                "cargo-save-{}-{}",
-                platform,
-                &workspace.toolchain_hash[..16]
-            );
-            println!("{}", key);
+        Commands::Status {
+            hashes,
+            full_hashes,
+            explain,
+            git_ref,
+            committed_only,
+            manifest_path,
+        } => {
+            let cache = match manifest_path {
+                Some(path) => cache.with_manifest_path(path),
+                None => cache,
+            };
+            let cache = match git_ref {
+                Some(git_ref) => cache.with_git_ref(git_ref),
+                None => cache,
+            };
+            let cache = if committed_only {
+                cache.with_committed_only(true)
+            } else {
+                cache
+            };
+            cache.show_status(hashes, full_hashes, explain)?;
         }
 
-        Cli::Warm { release } => {
-            let mut args = vec![];
-            if release {
-                args.push("--release".to_string());
+        Commands::CacheKey {
+            platform,
+            include_target,
+            full_hashes,
+            include_env,
+            include_features,
+            incremental,
+            subcommand,
+            args,
+        } => {
+            if let Some(package) = incremental {
+                let workspace = cache.compute_workspace_state(&args)?;
+                let key = cache
+                    .incremental_cache_key(&package, &subcommand, &args, &workspace)
+                    .ok_or_else(|| anyhow::anyhow!("No package {} in the workspace", package))?;
+                println!("{}", key);
+                return Ok(());
             }
-            let workspace = cache.compute_workspace_state(&args)?;
-            let command_hash = cache.compute_command_hash("warm", &args);
-            let env_hash = cache.compute_env_hash();
-            let is_release = cache.is_release_build(&args);
+            let workspace = cache.compute_workspace_state(&[])?;
+            let options = CacheKeyOptions {
+                include_target,
+                full_hashes,
+                include_env,
+                include_features,
+            };
+            let key = cache.generate_cache_key_with(platform, &workspace, &options);
+            platform.write_output(&key)?;
+            println!("{}", key);
+        }
 
-            let changed =
-                cache.get_changed_packages(&workspace, &command_hash, &env_hash, is_release, &args);
+        Commands::CachePath => {
+            println!("{}", cache.cache_dir.display());
+        }
 
-            if changed.is_empty() {
-                println!("[cargo-save] All packages already cached");
+        Commands::Warm {
+            release,
+            build,
+            committed_only,
+            manifest_path,
+        } => {
+            let cache = match manifest_path {
+                Some(path) => cache.with_manifest_path(path),
+                None => cache,
+            };
+            let cache = if committed_only {
+                cache.with_committed_only(true)
             } else {
-                println!(
-                    "[cargo-save] Pre-computing hashes for {} packages",
-                    changed.len()
-                );
-                for pkg in &changed {
-                    println!("  - {}", pkg.name);
-                }
+                cache
+            };
+            cache.warm_cache(release, build)?;
+        }
+
+        Commands::Restore {
+            artifacts_only,
+            subcommand,
+            args,
+        } => {
+            let workspace = cache.compute_workspace_state_with_progress_bar(&args)?;
+            let report = cache.restore_workspace_artifacts(&subcommand, &args, &workspace);
+            log(
+                LogLevel::Info,
+                format_args!(
+                    "Restored {} files for {} packages, {} packages not cached{}",
+                    report.files,
+                    report.restored.len(),
+                    report.missing.len(),
+                    if report.missing.is_empty() {
+                        String::new()
+                    } else {
+                        format!(": {}", report.missing.join(", "))
+                    }
+                ),
+            );
+            if !artifacts_only {
+                let (_, exit_code, _, _) =
+                    cache.run_cargo_with_cache(&subcommand, &args, &workspace)?;
+                std::process::exit(exit_code.unwrap_or(1));
             }
         }
 
-        Cli::InstallHooks => {
+        Commands::InstallHooks => {
             let workspace = cache.compute_workspace_state(&[])?;
             cache.install_git_hooks(&workspace.root)?;
         }
 
-        Cli::Doctor => {
-            cache.doctor()?;
+        Commands::Ui => {
+            cache.run_ui()?;
+        }
+
+        Commands::Doctor { json } => {
+            if json {
+                println!("{}", serde_json::to_string_pretty(&cache.doctor_report()?)?);
+            } else {
+                cache.doctor()?;
+            }
         }
 
-        Cli::SetupSccache => {
+        Commands::SetupSccache => {
             cache.setup_sccache()?;
         }
+
+        Commands::Export { out } => {
+            let workspace = cache.compute_workspace_state(&[])?;
+            let manifest = cache.export_archive(&out, &workspace)?;
+            log(
+                LogLevel::Info,
+                format_args!(
+                    "Exported {} incremental cache entries to {}",
+                    manifest.entries,
+                    out.display()
+                ),
+            );
+        }
+
+        Commands::Import { archive } => {
+            cache.import_archive(&archive)?;
+        }
+
+        Commands::Diff { a, b } => {
+            let diff = cache.diff_builds(&a, &b)?;
+            cache.print_build_diff(&diff);
+        }
+
+        Commands::WhyRebuild {
+            package,
+            subcommand,
+            args,
+        } => {
+            let workspace = cache.compute_workspace_state_with_progress_bar(&args)?;
+            let reason = cache.why_rebuild_for(&package, &workspace, Some(&subcommand), &args)?;
+            println!("{}", reason);
+        }
+
+        Commands::Changed {
+            subcommand,
+            json,
+            committed_only,
+            args,
+        } => {
+            let cache = if committed_only {
+                cache.with_committed_only(true)
+            } else {
+                cache
+            };
+            let workspace = cache.compute_workspace_state_with_progress_bar(&args)?;
+            let names = cache.changed_package_names(
+                &workspace,
+                &cache.compute_command_hash(&subcommand, &args),
+                &cache.compute_env_hash(),
+                cache.is_release_build(&args),
+                &args,
+            );
+            if json {
+                println!("{}", serde_json::to_string(&names)?);
+            } else {
+                for name in names {
+                    println!("{}", name);
+                }
+            }
+        }
+
+        Commands::History { days, json } => {
+            cache.show_build_history(days, json)?;
+        }
+
+        Commands::Graph { format } => match format {
+            GraphFormat::Dot => print!("{}", cache.dependency_graph_dot()?),
+            GraphFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&cache.dependency_graph_json()?)?
+            ),
+        },
     }
 
     Ok(())
@@ -1,5 +1,8 @@
 //! Integration tests for cargo-save
 
+// Some tests still spell `is_some_and` as `map_or(false, ..)`
+#![allow(clippy::unnecessary_map_or)]
+
 use cargo_save::CacheManager;
 use std::fs;
 use std::sync::Mutex;
@@ -98,6 +101,72 @@ fn test_command_hash_consistency() {
     assert_eq!(hash1, hash2);
 }
 
+#[test]
+fn test_source_hash_ignores_working_directory() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "cwd-fixture", "");
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(&crate_dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?}", args);
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "first"]);
+    // Uncommitted changes are hashed too
+    fs::write(crate_dir.join("src/main.rs"), "fn main() {}\n// dirty\n").unwrap();
+
+    let source_hash = |cwd: &std::path::Path, args: &[&std::ffi::OsStr]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(["status", "--hashes", "--full-hashes"])
+            .args(args)
+            .current_dir(cwd)
+            // Separate caches, so the second run cannot reuse memoized hashes
+            .env(
+                "CARGO_SAVE_CACHE_DIR",
+                temp_dir
+                    .path()
+                    .join("caches")
+                    .join(cwd.file_name().unwrap()),
+            )
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert!(
+            !String::from_utf8_lossy(&output.stderr).contains("file-based hashing"),
+            "{:?}",
+            output
+        );
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("cwd-fixture 0.1.0: "))
+            .unwrap()
+            .to_string()
+    };
+
+    let root = source_hash(&crate_dir, &[]);
+    assert_eq!(source_hash(&crate_dir.join("src"), &[]), root);
+    // From outside the repository, git still hashes the workspace it names
+    let manifest = crate_dir.join("Cargo.toml");
+    let outside = temp_dir.path().join("elsewhere");
+    fs::create_dir_all(&outside).unwrap();
+    assert_eq!(
+        source_hash(
+            &outside,
+            &["--manifest-path".as_ref(), manifest.as_os_str()]
+        ),
+        root
+    );
+}
+
 #[test]
 fn test_command_hash_different_commands() {
     let temp_dir = TempDir::new().unwrap();
@@ -146,6 +215,218 @@ fn test_env_hash_changes_with_rustflags() {
     std::env::remove_var("RUSTFLAGS");
 }
 
+#[test]
+fn test_env_hash_changes_with_cargo_incremental() {
+    let _guard = ENV_MUTEX.lock().unwrap();
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    let previous = std::env::var_os("CARGO_INCREMENTAL");
+    std::env::set_var("CARGO_INCREMENTAL", "0");
+    let disabled = cache.compute_env_hash();
+    std::env::set_var("CARGO_INCREMENTAL", "1");
+    let enabled = cache.compute_env_hash();
+    match previous {
+        Some(value) => std::env::set_var("CARGO_INCREMENTAL", value),
+        None => std::env::remove_var("CARGO_INCREMENTAL"),
+    }
+
+    assert_ne!(disabled, enabled);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_env_hash_includes_compiler_version() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _guard = ENV_MUTEX.lock().unwrap();
+    let temp_dir = TempDir::new().unwrap();
+    let cc = temp_dir.path().join("cc");
+    let install_cc = |version: &str| {
+        fs::write(
+            &cc,
+            format!("#!/bin/sh\necho 'clang version {}'\n", version),
+        )
+        .unwrap();
+        fs::set_permissions(&cc, fs::Permissions::from_mode(0o755)).unwrap();
+    };
+    let cache = || CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+
+    let previous = std::env::var_os("CC");
+    std::env::set_var("CC", &cc);
+    install_cc("17.0.6");
+    let before_cache = cache();
+    let before = before_cache.compute_env_hash();
+    install_cc("18.1.8");
+    // The lookup is cached per CacheManager, a new one sees the upgrade
+    let cached = before_cache.compute_env_hash();
+    let after = cache().compute_env_hash();
+    match previous {
+        Some(value) => std::env::set_var("CC", value),
+        None => std::env::remove_var("CC"),
+    }
+
+    assert_eq!(before, cached);
+    assert_ne!(before, after);
+}
+
+#[test]
+fn test_artifact_discovery_skips_incremental_dir() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let target_dir = temp_dir.path().join("target");
+    let profile_dir = target_dir.join("debug");
+
+    let files = [
+        ".fingerprint/foo-0123abcd/lib-foo",
+        "deps/libfoo-0123abcd.rlib",
+        "incremental/foo-0123abcd/s-abc-def/query-cache.bin",
+        "incremental/foo-0123abcd/s-abc-def.lock",
+    ];
+    for file in files {
+        let path = profile_dir.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "artifact").unwrap();
+    }
+
+    let args = vec!["--target-dir".to_string(), target_dir.display().to_string()];
+    let workspace = workspace_with(vec![package("foo", &[])]);
+    cache
+        .save_incremental_cache(
+            &package("foo", &[]),
+            &workspace,
+            "cmd",
+            "env",
+            false,
+            &args,
+            true,
+            0,
+        )
+        .unwrap();
+
+    let entry = fs::read_dir(&cache.incremental_dir)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let entry: cargo_save::IncrementalCache =
+        serde_json::from_str(&fs::read_to_string(entry.path()).unwrap()).unwrap();
+    let mut recorded: Vec<_> = entry
+        .target_files
+        .iter()
+        .map(|(path, _)| path.strip_prefix(&profile_dir).unwrap().to_path_buf())
+        .collect();
+    recorded.sort();
+    assert_eq!(
+        recorded,
+        vec![
+            std::path::PathBuf::from(".fingerprint/foo-0123abcd/lib-foo"),
+            std::path::PathBuf::from("deps/libfoo-0123abcd.rlib"),
+        ]
+    );
+}
+
+#[test]
+fn test_artifact_discovery_uses_lib_target_names() {
+    let temp_dir = TempDir::new().unwrap();
+    let ws = temp_dir.path().join("ws");
+    let write = |path: &str, content: &str| {
+        let path = ws.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    };
+    let manifest = |name: &str, extra: &str| {
+        format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n{}",
+            name, extra
+        )
+    };
+    write(
+        "Cargo.toml",
+        "[workspace]\nmembers = [\"my-crate\", \"my-crate-utils\"]\n",
+    );
+    write("my-crate/Cargo.toml", &manifest("my-crate", ""));
+    write("my-crate/src/lib.rs", "");
+    write(
+        "my-crate-utils/Cargo.toml",
+        &manifest("my-crate-utils", "\n[lib]\nname = \"utils\"\n"),
+    );
+    write("my-crate-utils/src/lib.rs", "");
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .current_dir(&ws)
+        .exec()
+        .unwrap();
+    let mut packages: Vec<_> = metadata
+        .workspace_packages()
+        .iter()
+        .map(|p| cache.compute_package_hash(p, &metadata, &[]).unwrap())
+        .collect();
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    assert_eq!(packages[0].lib_name.as_deref(), Some("my_crate"));
+    assert_eq!(packages[1].lib_name.as_deref(), Some("utils"));
+
+    let target_dir = temp_dir.path().join("target");
+    let profile_dir = target_dir.join("debug");
+    for file in [
+        ".fingerprint/my-crate-0123abcd/lib-my_crate",
+        ".fingerprint/my-crate-utils-4567abcd/lib-utils",
+        "deps/libmy_crate-0123abcd.rlib",
+        "deps/my_crate-0123abcd.d",
+        "deps/libutils-4567abcd.rlib",
+        "deps/libutils_extra-89abcdef.rlib",
+    ] {
+        let path = profile_dir.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "artifact").unwrap();
+    }
+
+    let args = vec!["--target-dir".to_string(), target_dir.display().to_string()];
+    let mut workspace = workspace_with(packages.clone());
+    workspace.root = ws.clone();
+    let recorded = |package: &cargo_save::PackageHash| {
+        cache
+            .save_incremental_cache(package, &workspace, "cmd", "env", false, &args, true, 0)
+            .unwrap();
+        let entry = fs::read_dir(&cache.incremental_dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.strip_prefix(&format!("{}-", package.name))
+                    .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_hexdigit()))
+            })
+            .unwrap();
+        let entry: cargo_save::IncrementalCache =
+            serde_json::from_str(&fs::read_to_string(entry).unwrap()).unwrap();
+        let mut files: Vec<_> = entry
+            .target_files
+            .iter()
+            .map(|(path, _)| path.strip_prefix(&profile_dir).unwrap().to_path_buf())
+            .collect();
+        files.sort();
+        files
+    };
+
+    // Shared name prefixes do not pull in the other crates' files
+    assert_eq!(
+        recorded(&packages[0]),
+        vec![
+            std::path::PathBuf::from(".fingerprint/my-crate-0123abcd/lib-my_crate"),
+            std::path::PathBuf::from("deps/libmy_crate-0123abcd.rlib"),
+            std::path::PathBuf::from("deps/my_crate-0123abcd.d"),
+        ]
+    );
+    assert_eq!(
+        recorded(&packages[1]),
+        vec![
+            std::path::PathBuf::from(".fingerprint/my-crate-utils-4567abcd/lib-utils"),
+            std::path::PathBuf::from("deps/libutils-4567abcd.rlib"),
+        ]
+    );
+}
+
 #[test]
 fn test_dependency_graph_building() {
     // This test would need a proper Cargo workspace to test fully
@@ -163,6 +444,7 @@ fn test_dependency_graph_building() {
         toolchain_hash: "test".to_string(),
         timestamp: "2024-01-01T00:00:00Z".to_string(),
         git_features: None,
+        default_members: Vec::new(),
     };
 
     let graph = cache.build_dependency_graph(&workspace);
@@ -233,6 +515,42 @@ fn test_get_target_dir_from_env() {
     }
 }
 
+#[test]
+fn test_clean_dry_run_keeps_files() {
+    use cargo_save::CleanOptions;
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    let mut files = Vec::new();
+    for i in 0..4 {
+        let log = cache
+            .cache_dir
+            .join(format!("20240101_00000{}-abcdef12.log", i));
+        let meta = cache
+            .metadata_dir
+            .join(format!("20240101_00000{}-abcdef12.json", i));
+        fs::write(&log, "output\n").unwrap();
+        fs::write(&meta, "{}").unwrap();
+        files.push(log);
+        files.push(meta);
+        // Distinct modification times keep the oldest-first order stable
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    let options = CleanOptions {
+        keep: Some(1),
+        dry_run: true,
+        ..Default::default()
+    };
+    let candidates = cache.clean_old_caches_with(&options).unwrap();
+    // Three builds, each with its log and metadata
+    assert_eq!(candidates.len(), 6);
+    assert_eq!(candidates, cache.clean_candidates(0, Some(1)).unwrap());
+    assert!(!candidates.contains(&files[6]) && !candidates.contains(&files[7]));
+    assert!(files.iter().all(|path| path.exists()));
+}
+
 #[test]
 fn test_cache_clean_keep() {
     let temp_dir = TempDir::new().unwrap();
@@ -266,3 +584,5545 @@ fn test_cache_clean_keep() {
     // Should have 2 log files left (but the cleanup might not work exactly as expected in tests)
     // Just verify the function doesn't panic
 }
+
+#[cfg(unix)]
+#[test]
+fn test_dry_run_does_not_spawn_cargo() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir_all(&bin_dir).unwrap();
+
+    // A `cargo` shim that records its arguments before delegating to the real cargo
+    let spawn_log = temp_dir.path().join("spawned.txt");
+    let shim = bin_dir.join("cargo");
+    fs::write(
+        &shim,
+        format!(
+            "#!/bin/sh\necho \"$@\" >> \"{}\"\nexec \"{}\" \"$@\"\n",
+            spawn_log.display(),
+            env!("CARGO")
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&shim, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+        .args(["save", "--dry-run", "build"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env("CARGO_SAVE_CARGO_BIN", &shim)
+        .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Build plan:") || stderr.contains("nothing to rebuild"));
+
+    let spawned = fs::read_to_string(&spawn_log).unwrap_or_default();
+    assert!(
+        !spawned.lines().any(|l| l.starts_with("build")),
+        "dry run spawned cargo build: {}",
+        spawned
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cargo_bin_override_is_invoked() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "cargo-bin-fixture", "");
+
+    // A wrapper standing in for cross/cargo-zigbuild that delegates to cargo
+    let spawn_log = temp_dir.path().join("spawned.txt");
+    let stub = temp_dir.path().join("my-cargo");
+    fs::write(
+        &stub,
+        format!(
+            "#!/bin/sh\necho \"$@\" >> \"{}\"\nexec \"{}\" \"$@\"\n",
+            spawn_log.display(),
+            env!("CARGO")
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+        .args(["save", "check"])
+        .current_dir(&crate_dir)
+        .env("CARGO_SAVE_CARGO_BIN", &stub)
+        .env("CARGO", "/nonexistent/cargo")
+        .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+        .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let spawned = fs::read_to_string(&spawn_log).unwrap();
+    assert!(spawned.lines().any(|l| l.starts_with("metadata")));
+    assert!(spawned.lines().any(|l| l == "--version"));
+    assert!(spawned.lines().any(|l| l.starts_with("check")));
+}
+
+fn package(name: &str, dependencies: &[&str]) -> cargo_save::PackageHash {
+    cargo_save::PackageHash {
+        name: name.to_string(),
+        version: "0.1.0".to_string(),
+        path: std::path::PathBuf::from(name),
+        source_hash: "0".repeat(64),
+        dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+        features_hash: "features".to_string(),
+        dependency_kinds: Default::default(),
+        lib_name: None,
+        hash_method: Default::default(),
+    }
+}
+
+fn workspace_with(packages: Vec<cargo_save::PackageHash>) -> cargo_save::WorkspaceState {
+    cargo_save::WorkspaceState {
+        root: std::path::PathBuf::from("/workspace"),
+        packages,
+        cargo_lock_hash: "lock".to_string(),
+        toolchain_hash: "toolchain".to_string(),
+        timestamp: "2024-01-01T00:00:00Z".to_string(),
+        git_features: None,
+        default_members: Vec::new(),
+    }
+}
+
+#[test]
+fn test_build_waves_groups_independent_packages() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    // app -> lib -> core, cli -> core, tool is independent
+    let workspace = workspace_with(vec![
+        package("core", &[]),
+        package("lib", &["core"]),
+        package("app", &["lib"]),
+        package("cli", &["core"]),
+        package("tool", &[]),
+    ]);
+    let graph = cache.build_dependency_graph(&workspace);
+
+    let all: Vec<String> = workspace.packages.iter().map(|p| p.name.clone()).collect();
+    let waves = graph.build_waves(&all).unwrap();
+    assert_eq!(
+        waves,
+        vec![
+            vec!["core".to_string(), "tool".to_string()],
+            vec!["cli".to_string(), "lib".to_string()],
+            vec!["app".to_string()],
+        ]
+    );
+
+    // Dependencies that aren't being rebuilt don't hold a package back
+    let subset = vec!["app".to_string(), "cli".to_string()];
+    let waves = graph.build_waves(&subset).unwrap();
+    assert_eq!(waves, vec![vec!["app".to_string(), "cli".to_string()]]);
+}
+
+#[test]
+fn test_build_waves_detects_cycles() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    let workspace = workspace_with(vec![package("a", &["b"]), package("b", &["a"])]);
+    let graph = cache.build_dependency_graph(&workspace);
+
+    assert!(graph
+        .build_waves(&["a".to_string(), "b".to_string()])
+        .is_none());
+}
+
+#[test]
+fn test_parallel_build_respects_exclude_and_skips_cached_workspace() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("ws");
+    for name in ["alpha", "beta"] {
+        fs::create_dir_all(root.join(name).join("src")).unwrap();
+        fs::write(
+            root.join(name).join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+                name
+            ),
+        )
+        .unwrap();
+        fs::write(root.join(name).join("src/lib.rs"), "").unwrap();
+    }
+    fs::write(
+        root.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"alpha\", \"beta\"]\nresolver = \"2\"\n",
+    )
+    .unwrap();
+    let save = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(["save", "--parallel-packages", "2", "check", "--workspace"])
+            .args(extra)
+            .current_dir(&root)
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+            .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    let stderr = save(&["--exclude", "beta"]);
+    assert!(stderr.contains("Checking alpha"), "{}", stderr);
+    assert!(!stderr.contains("Checking beta"), "{}", stderr);
+
+    // Per-package invocations only build their own package
+    let stderr = save(&[]);
+    assert!(stderr.contains("Checking beta"), "{}", stderr);
+    assert!(!stderr.contains("Checking alpha"), "{}", stderr);
+
+    let stderr = save(&[]);
+    assert!(stderr.contains("All packages cached"), "{}", stderr);
+    assert!(!stderr.contains("entangled"), "{}", stderr);
+}
+
+#[test]
+fn test_export_import_round_trip() {
+    let source_dir = TempDir::new().unwrap();
+    let target_dir = TempDir::new().unwrap();
+
+    let source = CacheManager::with_cache_dir(source_dir.path()).unwrap();
+    let target = CacheManager::with_cache_dir(target_dir.path()).unwrap();
+
+    fs::write(source.incremental_dir.join("foo-abc.json"), "{\"foo\":1}").unwrap();
+    fs::write(source.incremental_dir.join("bar-def.json"), "{\"bar\":2}").unwrap();
+
+    let mut workspace = workspace_with(vec![]);
+    workspace.toolchain_hash = source.compute_toolchain_hash().unwrap();
+
+    let archive = source_dir.path().join("cache.tar.zst");
+    let manifest = source.export_archive(&archive, &workspace).unwrap();
+    assert_eq!(manifest.entries, 2);
+
+    let imported = target.import_archive(&archive).unwrap();
+    assert_eq!(imported.workspace_root, workspace.root);
+    assert_eq!(
+        fs::read_to_string(target.incremental_dir.join("foo-abc.json")).unwrap(),
+        "{\"foo\":1}"
+    );
+    assert_eq!(
+        fs::read_to_string(target.incremental_dir.join("bar-def.json")).unwrap(),
+        "{\"bar\":2}"
+    );
+}
+
+#[test]
+fn test_import_rejects_other_cache_version() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    let manifest = serde_json::json!({
+        "cache_version": "v1",
+        "toolchain_hash": "toolchain",
+        "workspace_root": "/workspace",
+        "created": "2024-01-01T00:00:00Z",
+        "entries": 0,
+    })
+    .to_string();
+
+    let archive = temp_dir.path().join("old.tar.zst");
+    let encoder = zstd::Encoder::new(fs::File::create(&archive).unwrap(), 0).unwrap();
+    let mut builder = tar::Builder::new(encoder);
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, "manifest.json", manifest.as_bytes())
+        .unwrap();
+    builder.into_inner().unwrap().finish().unwrap();
+
+    let err = cache.import_archive(&archive).unwrap_err();
+    assert!(err.to_string().contains("cache version"));
+}
+
+#[test]
+fn test_old_cache_versions_are_removed() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let old_version = temp_dir.path().join("v2");
+    fs::create_dir_all(old_version.join("incremental")).unwrap();
+    fs::write(old_version.join("incremental/foo.json"), "stale").unwrap();
+    fs::write(old_version.join("old.log"), "old build output").unwrap();
+
+    let unrelated = temp_dir.path().join("notes");
+    fs::create_dir_all(&unrelated).unwrap();
+    // A versioned directory cargo-save did not create stays
+    let foreign = temp_dir.path().join("v1");
+    fs::create_dir_all(foreign.join("src")).unwrap();
+    fs::write(foreign.join("src/main.rs"), "fn main() {}\n").unwrap();
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    assert!(!old_version.exists());
+    assert!(unrelated.exists());
+    assert!(foreign.join("src/main.rs").exists());
+    assert!(cache.cache_dir.exists());
+
+    // Nothing left to migrate on a second run
+    let report = cache.migrate().unwrap();
+    assert!(report.removed_versions.is_empty());
+    assert_eq!(report.reclaimed_bytes, 0);
+}
+
+fn build_fixture(
+    cache_id: &str,
+    subcommand: &str,
+    exit_code: Option<i32>,
+    duration_ms: u64,
+) -> cargo_save::BuildCache {
+    cargo_save::BuildCache {
+        cache_id: cache_id.to_string(),
+        command: format!("cargo {}", subcommand),
+        subcommand: subcommand.to_string(),
+        args: vec![],
+        timestamp: "2024-01-01T00:00:00+00:00".to_string(),
+        exit_code,
+        workspace_state: workspace_with(vec![]),
+        is_release: false,
+        target_dir: None,
+        lines_count: 10,
+        duration_ms,
+        env_hash: "env".to_string(),
+        env_vars: Default::default(),
+        error_count: 0,
+        warning_count: 0,
+        commit: None,
+        branch: None,
+    }
+}
+
+#[test]
+fn test_previous_cache_version_builds_are_migrated() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let previous = temp_dir.path().join("v3");
+    fs::create_dir_all(previous.join("metadata")).unwrap();
+    let build = build_fixture("20240101_000000-abcdef12", "build", Some(0), 100);
+    fs::write(
+        previous.join("metadata/20240101_000000-abcdef12.json"),
+        serde_json::to_string(&build).unwrap(),
+    )
+    .unwrap();
+    fs::write(previous.join("20240101_000000-abcdef12.log"), "output\n").unwrap();
+    fs::write(previous.join("metadata/corrupt.json"), "not json").unwrap();
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    assert!(!previous.exists());
+    assert!(cache
+        .metadata_dir
+        .join("20240101_000000-abcdef12.json")
+        .exists());
+    // Migrated logs of finished builds are compressed
+    assert!(cache
+        .cache_dir
+        .join("20240101_000000-abcdef12.log.zst")
+        .exists());
+    let mut out = Vec::new();
+    let options = cargo_save::QueryOptions {
+        cache_id: Some("20240101_000000-abcdef12".to_string()),
+        ..Default::default()
+    };
+    cache
+        .query_logs_into("all", None, &options, &mut out)
+        .unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "output\n");
+    assert!(!cache.metadata_dir.join("corrupt.json").exists());
+}
+
+#[test]
+fn test_query_tail_follow_streams_until_build_finishes() {
+    use std::io::Write;
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    let cache_id = "20240101_000000-follow00";
+    let log_path = cache.cache_dir.join(format!("{}.log", cache_id));
+    fs::write(&log_path, "Compiling foo\n").unwrap();
+
+    let meta_path = cache.metadata_dir.join(format!("{}.json", cache_id));
+    let writer = std::thread::spawn(move || {
+        let mut log = fs::OpenOptions::new().append(true).open(&log_path).unwrap();
+        for i in 0..3 {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            writeln!(log, "line {}", i).unwrap();
+        }
+        write!(log, "Finished").unwrap();
+        fs::write(&meta_path, "{}").unwrap();
+    });
+
+    let options = cargo_save::QueryOptions {
+        cache_id: Some(cache_id.to_string()),
+        follow: true,
+        ..Default::default()
+    };
+    let mut out = Vec::new();
+    cache
+        .query_logs_into("tail", None, &options, &mut out)
+        .unwrap();
+    writer.join().unwrap();
+
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "Compiling foo\nline 0\nline 1\nline 2\nFinished\n"
+    );
+}
+
+#[test]
+fn test_query_exit_codes_for_missing_logs_and_no_match() {
+    use cargo_save::{CacheError, QueryOptions};
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let query = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .arg("query")
+            .args(args)
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path())
+            .output()
+            .unwrap()
+    };
+
+    let err = cache
+        .query_logs_into("tail", None, &QueryOptions::default(), &mut Vec::new())
+        .unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(CacheError::NoLogs)));
+    let output = query(&["tail"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No cached logs found"));
+    assert_eq!(query(&["errors", "--last", "1"]).status.code(), Some(2));
+
+    fs::write(
+        cache.cache_dir.join("20240101_000000-abcdef12.log"),
+        "Compiling foo\nwarning: unused variable\n",
+    )
+    .unwrap();
+    let mut out = Vec::new();
+    let written = cache
+        .query_logs_into("errors", None, &QueryOptions::default(), &mut out)
+        .unwrap();
+    assert_eq!((written, out.len()), (0, 0));
+    assert_eq!(
+        cache
+            .query_logs_into("warnings", None, &QueryOptions::default(), &mut Vec::new())
+            .unwrap(),
+        1
+    );
+
+    assert_eq!(query(&["errors"]).status.code(), Some(0));
+    assert_eq!(
+        query(&["errors", "--exit-on-no-match"]).status.code(),
+        Some(1)
+    );
+    assert_eq!(
+        query(&["grep", "absent", "--exit-on-no-match"])
+            .status
+            .code(),
+        Some(1)
+    );
+    assert_eq!(
+        query(&["warnings", "--exit-on-no-match"]).status.code(),
+        Some(0)
+    );
+    // Other modes never report a missing match
+    assert_eq!(
+        query(&["range", "5-9", "--exit-on-no-match"]).status.code(),
+        Some(0)
+    );
+}
+
+/// Creates a minimal binary crate whose `main` runs the given statements.
+fn create_fixture_crate(dir: &std::path::Path, name: &str, main_body: &str) {
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(
+        dir.join("Cargo.toml"),
+        format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[workspace]\n",
+            name
+        ),
+    )
+    .unwrap();
+    fs::write(
+        dir.join("src/main.rs"),
+        format!("fn main() {{\n{}\n}}\n", main_body),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_query_stream_separates_stdout_and_stderr() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(
+        &crate_dir,
+        "stream-fixture",
+        "    println!(\"to stdout\");\n    eprintln!(\"to stderr\");",
+    );
+
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(args)
+            .current_dir(&crate_dir)
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+            .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+            .output()
+            .unwrap()
+    };
+
+    assert!(run(&["save", "run"]).status.success());
+
+    let out = run(&["query", "all", "--stream", "out"]);
+    assert!(out.status.success());
+    let out = String::from_utf8_lossy(&out.stdout);
+    assert!(out.contains("to stdout"));
+    assert!(!out.contains("to stderr"));
+
+    let err = run(&["query", "all", "--stream", "err"]);
+    assert!(err.status.success());
+    let err = String::from_utf8_lossy(&err.stdout);
+    assert!(err.contains("to stderr"));
+    assert!(!err.contains("to stdout"));
+
+    let both = run(&["query", "all"]);
+    let both = String::from_utf8_lossy(&both.stdout);
+    assert!(both.contains("to stdout") && both.contains("to stderr"));
+}
+
+#[test]
+fn test_list_options_filter_and_sort() {
+    use cargo_save::{BuildStatus, ListOptions, ListSort};
+
+    let builds = vec![
+        build_fixture("fast-test-fail", "test", Some(101), 100),
+        build_fixture("build-ok", "build", Some(0), 5000),
+        build_fixture("slow-test-fail", "test", Some(101), 9000),
+        build_fixture("killed-test", "test", None, 300),
+        build_fixture("mid-test-fail", "test", Some(1), 4000),
+    ];
+    let ids = |builds: Vec<cargo_save::BuildCache>| {
+        builds.into_iter().map(|b| b.cache_id).collect::<Vec<_>>()
+    };
+
+    let slowest_failed_tests = ListOptions {
+        status: Some(BuildStatus::Failed),
+        subcommand: Some("test".to_string()),
+        sort: ListSort::Duration,
+        reverse: true,
+        limit: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(
+        ids(slowest_failed_tests.apply(builds.clone())),
+        vec!["slow-test-fail", "mid-test-fail"]
+    );
+
+    let unknown = ListOptions {
+        status: Some(BuildStatus::Unknown),
+        ..Default::default()
+    };
+    assert_eq!(ids(unknown.apply(builds.clone())), vec!["killed-test"]);
+
+    let builds_only = ListOptions {
+        subcommand: Some("build".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(ids(builds_only.apply(builds.clone())), vec!["build-ok"]);
+
+    // Equal timestamps keep the original order
+    assert_eq!(ids(ListOptions::default().apply(builds.clone())).len(), 5);
+    assert_eq!(
+        ids(ListOptions::default().apply(builds))[0],
+        "fast-test-fail"
+    );
+}
+
+#[test]
+fn test_list_formats() {
+    use cargo_save::{ListFormat, ListOptions};
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    let mut release = build_fixture("20240101_000000-aaaaaaaa", "build", Some(0), 1500);
+    release.args = vec!["--release".to_string(), "--features=serde".to_string()];
+    release.command = "cargo build --release --features=serde".to_string();
+    release.is_release = true;
+    let mut failed = build_fixture("20240101_000100-bbbbbbbb", "test", Some(101), 300);
+    failed.timestamp = "2024-01-01T00:01:00+00:00".to_string();
+    failed.error_count = 2;
+    for build in [&release, &failed] {
+        fs::write(
+            cache.metadata_dir.join(format!("{}.json", build.cache_id)),
+            serde_json::to_string(build).unwrap(),
+        )
+        .unwrap();
+    }
+
+    let list = |format: ListFormat| {
+        let mut out = Vec::new();
+        cache
+            .list_caches_into(
+                &ListOptions {
+                    format,
+                    ..Default::default()
+                },
+                &mut out,
+            )
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    assert_eq!(
+        list(ListFormat::Plain),
+        format!(
+            "Cache ID                  Status       Lines    Command                       \n{}\n\
+             20240101_000000-aaaaaaaa  ✓ success    10       cargo build --release --fea...\n\
+             20240101_000100-bbbbbbbb  ✗ failed     10       cargo test                    \n",
+            "-".repeat(80)
+        )
+    );
+    assert_eq!(
+        list(ListFormat::Short),
+        "20240101_000000-aaaaaaaa ✓ success 1500ms\n\
+         20240101_000100-bbbbbbbb ✗ failed  300ms\n"
+    );
+    assert_eq!(
+        list(ListFormat::Long),
+        "20240101_000000-aaaaaaaa ✓ success\n  \
+         Command: cargo build --release --features=serde\n  \
+         Args: --release --features=serde\n  \
+         Timestamp: 2024-01-01T00:00:00+00:00\n  \
+         Duration: 1500ms\n  \
+         Lines: 10\n  \
+         Diagnostics: 0 errors, 0 warnings\n  \
+         Release: true\n  \
+         Packages: 0\n  \
+         Workspace: /workspace\n  \
+         Env hash: env\n\
+         \n\
+         20240101_000100-bbbbbbbb ✗ failed\n  \
+         Command: cargo test\n  \
+         Args: \n  \
+         Timestamp: 2024-01-01T00:01:00+00:00\n  \
+         Duration: 300ms\n  \
+         Lines: 10\n  \
+         Diagnostics: 2 errors, 0 warnings\n  \
+         Release: false\n  \
+         Packages: 0\n  \
+         Workspace: /workspace\n  \
+         Env hash: env\n\
+         \n"
+    );
+}
+
+#[test]
+fn test_parse_since_relative_durations() {
+    use cargo_save::parse_since;
+    use std::time::{Duration, SystemTime};
+
+    for (value, secs) in [
+        ("45s", 45),
+        ("30m", 30 * 60),
+        ("2h", 2 * 60 * 60),
+        ("3d", 3 * 24 * 60 * 60),
+        ("1w", 7 * 24 * 60 * 60),
+    ] {
+        let before = SystemTime::now();
+        let since = parse_since(value).unwrap();
+        let ago = before.duration_since(since).unwrap_or_default();
+        // Allow for the clock moving on between the two calls
+        assert!(
+            ago <= Duration::from_secs(secs)
+                && ago + Duration::from_secs(5) >= Duration::from_secs(secs),
+            "{} parsed as {:?} ago",
+            value,
+            ago
+        );
+    }
+}
+
+#[test]
+fn test_parse_since_absolute_and_invalid() {
+    use cargo_save::parse_since;
+    use std::time::{Duration, SystemTime};
+
+    assert_eq!(
+        parse_since("2024-01-01T00:00:00Z").unwrap(),
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_704_067_200)
+    );
+    assert_eq!(
+        parse_since("2024-01-01T02:00:00+02:00").unwrap(),
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_704_067_200)
+    );
+
+    for invalid in ["", "h", "10", "10y", "yesterday", "-3d"] {
+        assert!(
+            parse_since(invalid).is_err(),
+            "{:?} should not parse",
+            invalid
+        );
+    }
+
+    // Further back than the Unix epoch, and beyond u64 seconds
+    let err = parse_since("100000d").unwrap_err();
+    assert!(err.to_string().contains("out of range"), "{}", err);
+    assert!(parse_since("99999999999999999999w").is_err());
+}
+
+#[test]
+fn test_list_and_query_since() {
+    use cargo_save::{parse_since, ListOptions, QueryOptions};
+
+    let mut old = build_fixture("20240101_000000-00000001", "build", Some(0), 100);
+    old.timestamp = "2024-01-01T00:00:00+00:00".to_string();
+    let mut recent = build_fixture("20240101_000000-00000002", "build", Some(0), 100);
+    recent.timestamp = "2024-03-01T00:00:00+00:00".to_string();
+    let mut newest = build_fixture("20240101_000000-00000003", "test", Some(0), 100);
+    newest.timestamp = "2024-03-02T00:00:00+00:00".to_string();
+
+    let options = ListOptions {
+        since: Some(parse_since("2024-02-01T00:00:00Z").unwrap()),
+        ..Default::default()
+    };
+    let ids: Vec<_> = options
+        .apply(vec![old.clone(), recent.clone(), newest.clone()])
+        .into_iter()
+        .map(|b| b.cache_id)
+        .collect();
+    assert_eq!(ids, vec![recent.cache_id.clone(), newest.cache_id.clone()]);
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    for build in [&old, &recent, &newest] {
+        fs::write(
+            cache.metadata_dir.join(format!("{}.json", build.cache_id)),
+            serde_json::to_string(build).unwrap(),
+        )
+        .unwrap();
+        fs::write(
+            cache.cache_dir.join(format!("{}.log", build.cache_id)),
+            format!("{}\n", build.cache_id),
+        )
+        .unwrap();
+        // Metadata files are ordered by modification time
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+
+    let query = |last: Option<usize>, since: &str| {
+        let options = QueryOptions {
+            last,
+            since: Some(parse_since(since).unwrap()),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        cache
+            .query_logs_into("all", None, &options, &mut out)
+            .map(|_| String::from_utf8(out).unwrap())
+    };
+
+    assert_eq!(
+        query(None, "2024-02-01T00:00:00Z").unwrap(),
+        format!("{}\n", newest.cache_id)
+    );
+    assert_eq!(
+        query(Some(2), "2024-02-01T00:00:00Z").unwrap(),
+        format!("{}\n", recent.cache_id)
+    );
+    // Only two builds are recent enough
+    assert!(query(Some(3), "2024-02-01T00:00:00Z").is_err());
+    assert!(query(None, "1h").is_err());
+}
+
+#[test]
+fn test_package_matcher_modes() {
+    use cargo_save::{MatchMode, PackageMatcher};
+
+    let core = vec!["core".to_string()];
+    let exact = PackageMatcher::new(MatchMode::Exact, &core).unwrap();
+    assert!(exact.is_match("core"));
+    assert!(!exact.is_match("core-utils"));
+
+    let glob = PackageMatcher::new(MatchMode::Glob, &["core*".to_string()]).unwrap();
+    assert!(glob.is_match("core"));
+    assert!(glob.is_match("core-utils"));
+    assert!(!glob.is_match("my-core"));
+
+    let regex = PackageMatcher::new(MatchMode::Regex, &["core(-utils)?".to_string()]).unwrap();
+    assert!(regex.is_match("core-utils"));
+    assert!(!regex.is_match("core-utils-extra"));
+
+    // The default keeps the historical prefix behavior
+    let prefix = PackageMatcher::new(MatchMode::default(), &core).unwrap();
+    assert!(prefix.is_match("core-utils"));
+
+    assert!(PackageMatcher::new(MatchMode::Regex, &["(".to_string()]).is_err());
+}
+
+#[test]
+fn test_invalidate_filters_by_profile() {
+    use cargo_save::{InvalidateOptions, MatchMode, Profile};
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let workspace = workspace_with(vec![]);
+
+    for name in ["core", "core-utils"] {
+        for release in [false, true] {
+            let args = if release {
+                vec!["--release".to_string()]
+            } else {
+                vec![]
+            };
+            cache
+                .save_incremental_cache(
+                    &package(name, &[]),
+                    &workspace,
+                    "cmd",
+                    "env",
+                    release,
+                    &args,
+                    true,
+                    0,
+                )
+                .unwrap();
+        }
+    }
+
+    let removed = cache
+        .invalidate_caches_with(&InvalidateOptions {
+            packages: vec!["core".to_string()],
+            match_mode: MatchMode::Exact,
+            profile: Some(Profile::Release),
+            force: true,
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(removed, 1);
+
+    let remaining: Vec<cargo_save::IncrementalCache> = fs::read_dir(&cache.incremental_dir)
+        .unwrap()
+        .map(|e| serde_json::from_str(&fs::read_to_string(e.unwrap().path()).unwrap()).unwrap())
+        .collect();
+    assert_eq!(remaining.len(), 3);
+    assert!(!remaining
+        .iter()
+        .any(|c| c.package_name == "core" && c.is_release));
+}
+
+#[test]
+fn test_invalidate_dry_run_keeps_entries() {
+    use cargo_save::InvalidateOptions;
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let workspace = workspace_with(vec![
+        package("core", &[]),
+        package("core-macros", &[]),
+        package("app", &["core"]),
+    ]);
+    for pkg in &workspace.packages {
+        cache
+            .save_incremental_cache(pkg, &workspace, "cmd", "env", false, &[], true, 0)
+            .unwrap();
+    }
+
+    let options = InvalidateOptions {
+        packages: vec!["core".to_string()],
+        dry_run: true,
+        ..Default::default()
+    };
+    let candidates = cache.invalidate_candidates(&options).unwrap();
+    assert_eq!(candidates.len(), 2);
+    assert!(candidates.iter().all(|path| {
+        path.file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("core")
+    }));
+
+    // No prompt and no removal, just the count
+    assert_eq!(cache.invalidate_caches_with(&options).unwrap(), 2);
+    assert!(candidates.iter().all(|path| path.exists()));
+    assert_eq!(fs::read_dir(&cache.incremental_dir).unwrap().count(), 3);
+}
+
+#[test]
+fn test_invalidate_without_terminal_does_not_prompt() {
+    use std::process::{Command, Stdio};
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let workspace = workspace_with(vec![package("core", &[]), package("app", &["core"])]);
+    for pkg in &workspace.packages {
+        cache
+            .save_incremental_cache(pkg, &workspace, "cmd", "env", false, &[], true, 0)
+            .unwrap();
+    }
+
+    // As from a git hook: nobody could answer, so the entries go
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+        .args(["invalidate", "core"])
+        .stdin(Stdio::null())
+        .env("CARGO_SAVE_CACHE_DIR", temp_dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Removed 1 cache files"));
+    assert_eq!(fs::read_dir(&cache.incremental_dir).unwrap().count(), 1);
+}
+
+#[test]
+fn test_why_rebuild_reports_environment_change() {
+    use cargo_save::RebuildFactor;
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let workspace = workspace_with(vec![package("core", &[]), package("app", &["core"])]);
+
+    let reason = cache.why_rebuild("core", &workspace, &[]).unwrap();
+    assert_eq!(reason.to_string(), "core: never cached in this workspace");
+
+    // Everything matches the current state except the environment hash
+    cache
+        .save_incremental_cache(
+            &workspace.packages[0],
+            &workspace,
+            "cmd",
+            "stale-env",
+            false,
+            &[],
+            true,
+            0,
+        )
+        .unwrap();
+    let reason = cache.why_rebuild("core", &workspace, &[]).unwrap();
+    assert_eq!(reason.factors, vec![RebuildFactor::Environment]);
+    assert_eq!(reason.to_string(), "core: environment changed");
+
+    let release = vec!["--release".to_string()];
+    let reason = cache.why_rebuild("core", &workspace, &release).unwrap();
+    assert_eq!(
+        reason.factors,
+        vec![RebuildFactor::Environment, RebuildFactor::Profile]
+    );
+
+    // A newer release entry does not hide the debug one from a debug build
+    let env_hash = cache.compute_env_hash();
+    cache
+        .save_incremental_cache(
+            &workspace.packages[0],
+            &workspace,
+            "cmd",
+            &env_hash,
+            true,
+            &release,
+            true,
+            0,
+        )
+        .unwrap();
+    let reason = cache.why_rebuild("core", &workspace, &release).unwrap();
+    assert!(reason.factors.is_empty());
+    let reason = cache.why_rebuild("core", &workspace, &[]).unwrap();
+    assert_eq!(reason.factors, vec![RebuildFactor::Environment]);
+
+    // Entries of the same subcommand win over newer ones of another
+    let debug_entry = fs::read_dir(&cache.incremental_dir)
+        .unwrap()
+        .flatten()
+        .map(|e| fs::read_to_string(e.path()).unwrap())
+        .map(|c| serde_json::from_str::<cargo_save::IncrementalCache>(&c).unwrap())
+        .find(|entry| !entry.is_release)
+        .unwrap();
+    let test_entry = cargo_save::IncrementalCache {
+        env_hash: env_hash.clone(),
+        subcommand: Some("test".to_string()),
+        timestamp: "2000-01-01T00:00:00+00:00".to_string(),
+        ..debug_entry
+    };
+    fs::write(
+        cache.incremental_dir.join("core-test-entry.json"),
+        serde_json::to_string(&test_entry).unwrap(),
+    )
+    .unwrap();
+    let reason = cache
+        .why_rebuild_for("core", &workspace, Some("test"), &[])
+        .unwrap();
+    assert!(reason.factors.is_empty());
+    let reason = cache
+        .why_rebuild_for("core", &workspace, Some("build"), &[])
+        .unwrap();
+    assert_eq!(reason.factors, vec![RebuildFactor::Environment]);
+
+    assert!(cache.why_rebuild("missing", &workspace, &[]).is_err());
+}
+
+#[test]
+fn test_explain_status_annotates_stale_environment() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let workspace = workspace_with(vec![package("core", &[]), package("app", &["core"])]);
+
+    // The entry of another command, built with a different environment
+    cache
+        .save_incremental_cache(
+            &workspace.packages[0],
+            &workspace,
+            "test-cmd",
+            "stale-env",
+            false,
+            &[],
+            true,
+            0,
+        )
+        .unwrap();
+
+    let reasons = cache.explain_status(&workspace).unwrap();
+    assert_eq!(reasons["core"], "environment changed");
+    assert_eq!(reasons["app"], "no prior entry");
+}
+
+#[test]
+fn test_changed_package_names_match_changed_packages() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let workspace = workspace_with(vec![
+        package("core", &[]),
+        package("app", &["core"]),
+        package("tool", &[]),
+    ]);
+    cache
+        .save_incremental_cache(
+            &workspace.packages[2],
+            &workspace,
+            "cmd",
+            "env",
+            false,
+            &[],
+            true,
+            0,
+        )
+        .unwrap();
+
+    let names = cache.changed_package_names(&workspace, "cmd", "env", false, &[]);
+    let packages: Vec<String> = cache
+        .get_changed_packages(&workspace, "cmd", "env", false, &[])
+        .into_iter()
+        .map(|p| p.name)
+        .collect();
+    assert_eq!(names, packages);
+    let mut sorted = names.clone();
+    sorted.sort();
+    assert_eq!(sorted, vec!["app", "core"]);
+}
+
+#[test]
+fn test_cache_keys_are_safe_file_names() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let workspace = workspace_with(vec![
+        package("odd:name/x", &[]),
+        package("odd_name_x", &[]),
+        package(&"long".repeat(60), &[]),
+    ]);
+    for pkg in &workspace.packages {
+        cache
+            .save_incremental_cache(pkg, &workspace, "cmd", "env", false, &[], true, 0)
+            .unwrap();
+    }
+
+    let mut names: Vec<String> = fs::read_dir(&cache.incremental_dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    // Names differing only in replaced characters do not collide
+    assert_eq!(names.len(), 3);
+    for name in &names {
+        let stem = name.strip_suffix(".json").unwrap();
+        assert!(stem.len() <= 200, "{}", stem);
+        assert!(
+            stem.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+            "{}",
+            stem
+        );
+    }
+    assert!(names.iter().any(|name| name.starts_with("odd_name_x-0000")));
+    assert!(cache
+        .get_changed_packages(&workspace, "cmd", "env", false, &[])
+        .is_empty());
+}
+
+#[test]
+fn test_diff_builds_reports_changed_package() {
+    use cargo_save::{PackageChange, PackageDiff};
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    let mut before = build_fixture("before", "build", Some(0), 100);
+    before.workspace_state = workspace_with(vec![package("foo", &[]), package("bar", &[])]);
+
+    let mut after = build_fixture("after", "build", Some(0), 100);
+    let mut foo = package("foo", &[]);
+    foo.source_hash = "1".repeat(64);
+    after.workspace_state = workspace_with(vec![foo, package("bar", &[])]);
+    after.env_hash = "other".to_string();
+    after
+        .env_vars
+        .insert("RUSTFLAGS".to_string(), "-C target-cpu=native".to_string());
+
+    for build in [&before, &after] {
+        fs::write(
+            cache.metadata_dir.join(format!("{}.json", build.cache_id)),
+            serde_json::to_string(build).unwrap(),
+        )
+        .unwrap();
+    }
+
+    let diff = cache.diff_builds("before", "after").unwrap();
+    assert_eq!(
+        diff.packages,
+        vec![PackageDiff {
+            name: "foo".to_string(),
+            change: PackageChange::SourceChanged,
+        }]
+    );
+    assert!(diff.env_changed);
+    assert_eq!(diff.env_vars.len(), 1);
+    assert_eq!(diff.env_vars[0].name, "RUSTFLAGS");
+    assert!(!diff.cargo_lock_changed);
+    assert!(diff.command.is_none());
+
+    assert!(cache.diff_builds("before", "before").unwrap().is_empty());
+    assert!(cache.diff_builds("before", "missing").is_err());
+}
+
+#[test]
+fn test_doctor_json_reports_missing_rustc_wrapper() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+        .args(["doctor", "--json"])
+        .env("CARGO_SAVE_CACHE_DIR", temp_dir.path())
+        .env_remove("RUSTC_WRAPPER")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let report: cargo_save::DoctorReport = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report.rustc_wrapper, None);
+    assert!(!report.sccache);
+    assert!(report
+        .recommendations
+        .iter()
+        .any(|r| r.contains("setup-sccache")));
+    assert_eq!(report.build_logs, 0);
+    assert_eq!(report.incremental_caches, 0);
+    assert!(report.cache_dir.starts_with(temp_dir.path()));
+}
+
+#[test]
+fn test_prune_build_logs_keeps_most_recent() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let dir = temp_dir.path().join("build-logs");
+    fs::create_dir_all(&dir).unwrap();
+
+    let logs = [
+        "20240101_090000_build.txt",
+        "20240101_100000_test.txt",
+        "20240101_110000_nextest.txt",
+        "20240101_120000_build.txt",
+    ];
+    for name in logs {
+        fs::write(dir.join(name), name).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    // Files cargo-save did not write are left alone
+    for name in [
+        "notes.txt",
+        "2024_build.txt",
+        "20240101_080000_.txt",
+        "20240101_080000_build.log",
+    ] {
+        fs::write(dir.join(name), name).unwrap();
+    }
+
+    // Nothing is a week old yet
+    assert_eq!(cache.prune_build_logs(&dir, 7, None).unwrap(), 0);
+
+    assert_eq!(cache.prune_build_logs(&dir, 7, Some(2)).unwrap(), 2);
+    let mut remaining: Vec<String> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    remaining.sort();
+    assert_eq!(
+        remaining,
+        vec![
+            "20240101_080000_.txt",
+            "20240101_080000_build.log",
+            "20240101_110000_nextest.txt",
+            "20240101_120000_build.txt",
+            "2024_build.txt",
+            "notes.txt",
+        ]
+    );
+
+    assert_eq!(cache.prune_build_logs(&dir, 0, Some(0)).unwrap(), 2);
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+    assert_eq!(
+        cache
+            .prune_build_logs(&temp_dir.path().join("missing"), 0, None)
+            .unwrap(),
+        0
+    );
+}
+
+#[test]
+fn test_fallback_hash_walk_depth_and_excludes() {
+    use cargo_save::HashWalkOptions;
+
+    let temp_dir = TempDir::new().unwrap();
+    let pkg = temp_dir.path().join("pkg");
+    let deep = (0..12).fold(pkg.clone(), |dir, i| dir.join(format!("d{}", i)));
+    fs::create_dir_all(&deep).unwrap();
+    fs::create_dir_all(pkg.join("vendor")).unwrap();
+    fs::write(pkg.join("lib.rs"), "pub fn a() {}").unwrap();
+
+    // A cache directory inside the package is never hashed
+    let cache = CacheManager::with_cache_dir(pkg.join(".cache")).unwrap();
+    let raised = CacheManager::with_cache_dir(pkg.join(".cache"))
+        .unwrap()
+        .with_hash_walk(HashWalkOptions {
+            max_depth: 20,
+            exclude: vec!["vendor".to_string()],
+            ..Default::default()
+        });
+    let hash = |cache: &CacheManager| cache.compute_source_hash(&pkg, &[]).unwrap();
+
+    let (shallow_before, raised_before) = (hash(&cache), hash(&raised));
+    fs::write(deep.join("deep.rs"), "pub fn deep() {}").unwrap();
+    assert_eq!(hash(&cache), shallow_before, "default depth stops at 10");
+    assert_ne!(
+        hash(&raised),
+        raised_before,
+        "raised depth sees nested files"
+    );
+
+    let (default_before, raised_before) = (hash(&cache), hash(&raised));
+    fs::write(pkg.join("vendor/dep.rs"), "pub fn vendored() {}").unwrap();
+    assert_ne!(hash(&cache), default_before);
+    assert_eq!(
+        hash(&raised),
+        raised_before,
+        "excluded directories are skipped"
+    );
+
+    let before = hash(&cache);
+    fs::write(cache.cache_dir.join("stray.rs"), "fn main() {}").unwrap();
+    assert_eq!(hash(&cache), before);
+}
+
+#[test]
+fn test_fallback_hash_extensions() {
+    use cargo_save::HashWalkOptions;
+
+    let temp_dir = TempDir::new().unwrap();
+    let pkg = temp_dir.path().join("pkg");
+    fs::create_dir_all(pkg.join("proto")).unwrap();
+    fs::write(pkg.join("lib.rs"), "pub fn a() {}").unwrap();
+    fs::write(pkg.join("proto/api.proto"), "syntax = \"proto3\";").unwrap();
+    fs::write(pkg.join("notes.txt"), "todo").unwrap();
+
+    let with_extensions = |extensions: &[&str]| {
+        CacheManager::with_cache_dir(temp_dir.path().join("cache"))
+            .unwrap()
+            .with_hash_walk(HashWalkOptions {
+                extensions: extensions.iter().map(|e| e.to_string()).collect(),
+                ..Default::default()
+            })
+    };
+    let rust_only = with_extensions(&["rs", "toml"]);
+    let with_proto = with_extensions(&["rs", "toml", "proto"]);
+    let all_files = with_extensions(&["rs", "toml"]).with_hash_all_files(true);
+    let hash = |cache: &CacheManager| cache.compute_source_hash(&pkg, &[]).unwrap();
+
+    let before = [hash(&rust_only), hash(&with_proto), hash(&all_files)];
+    fs::write(pkg.join("proto/api.proto"), "syntax = \"proto2\";").unwrap();
+    assert_eq!(hash(&rust_only), before[0]);
+    assert_ne!(hash(&with_proto), before[1]);
+    assert_ne!(hash(&all_files), before[2]);
+
+    let before = [hash(&with_proto), hash(&all_files)];
+    fs::write(pkg.join("notes.txt"), "done").unwrap();
+    assert_eq!(hash(&with_proto), before[0]);
+    assert_ne!(hash(&all_files), before[1]);
+
+    // .proto files are hashed by default, and the walk order is stable
+    let default = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let before = hash(&default);
+    assert_eq!(hash(&default), before);
+    fs::write(pkg.join("proto/api.proto"), "syntax = \"proto3\";").unwrap();
+    assert_ne!(hash(&default), before);
+}
+
+#[test]
+fn test_identical_trees_have_identical_fallback_hashes() {
+    let temp_dir = TempDir::new().unwrap();
+    let files = [
+        ("src/lib.rs", "pub mod a;\npub mod b;"),
+        ("src/a.rs", "pub fn a() {}"),
+        ("src/b/mod.rs", "pub fn b() {}"),
+        ("src/b.rs.in", "// generated"),
+        ("Cargo.toml", "[package]\nname = \"tree\""),
+    ];
+    // The same tree, created in opposite orders in different locations
+    let first = temp_dir.path().join("one/pkg");
+    for (path, content) in files.iter() {
+        fs::create_dir_all(first.join(path).parent().unwrap()).unwrap();
+        fs::write(first.join(path), content).unwrap();
+    }
+    let second = temp_dir.path().join("two/nested/pkg");
+    for (path, content) in files.iter().rev() {
+        fs::create_dir_all(second.join(path).parent().unwrap()).unwrap();
+        fs::write(second.join(path), content).unwrap();
+    }
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let other = CacheManager::with_cache_dir(temp_dir.path().join("other-cache")).unwrap();
+    assert_eq!(
+        cache.compute_source_hash(&first, &[]).unwrap(),
+        other.compute_source_hash(&second, &[]).unwrap()
+    );
+
+    fs::write(second.join("src/a.rs"), "pub fn a() { todo!() }").unwrap();
+    assert_ne!(
+        cache.compute_source_hash(&first, &[]).unwrap(),
+        other.compute_source_hash(&second, &[]).unwrap()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_hash_method_is_file_fallback_outside_git() {
+    use cargo_save::HashMethod;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "fallback-fixture", "");
+    let args = vec![
+        "--manifest-path".to_string(),
+        crate_dir.join("Cargo.toml").display().to_string(),
+    ];
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let (hash, method) = cache
+        .compute_source_hash_with_method(&crate_dir, &[])
+        .unwrap();
+    assert_eq!(method, HashMethod::FileFallback);
+    assert_eq!(hash, cache.compute_source_hash(&crate_dir, &[]).unwrap());
+
+    let workspace = cache.compute_workspace_state(&args).unwrap();
+    assert_eq!(workspace.packages[0].hash_method, HashMethod::FileFallback);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_fallback_hash_follows_symlinks_inside_repo() {
+    use std::os::unix::fs::symlink;
+
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("repo");
+    let pkg = root.join("crates/app");
+    fs::create_dir_all(pkg.join("src")).unwrap();
+    fs::create_dir_all(root.join("shared")).unwrap();
+    fs::create_dir_all(temp_dir.path().join("outside")).unwrap();
+    fs::write(
+        root.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"crates/app\"]",
+    )
+    .unwrap();
+    fs::write(pkg.join("Cargo.toml"), "[package]\nname = \"app\"").unwrap();
+    fs::write(pkg.join("src/lib.rs"), "mod shared;\nmod util;").unwrap();
+    fs::write(root.join("shared/mod.rs"), "pub fn shared() {}").unwrap();
+    fs::write(root.join("shared/util.rs"), "pub fn util() {}").unwrap();
+    fs::write(
+        temp_dir.path().join("outside/extra.rs"),
+        "pub fn extra() {}",
+    )
+    .unwrap();
+    symlink("../../../shared", pkg.join("src/shared")).unwrap();
+    symlink("../../../shared/util.rs", pkg.join("src/util.rs")).unwrap();
+    symlink("../../../../outside/extra.rs", pkg.join("src/extra.rs")).unwrap();
+    // A link back up the tree must not send the walk into a loop
+    symlink("..", pkg.join("src/parent")).unwrap();
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let before = cache.compute_source_hash(&pkg, &[]).unwrap();
+
+    fs::write(root.join("shared/mod.rs"), "pub fn shared() { todo!() }").unwrap();
+    let dir_edit = cache.compute_source_hash(&pkg, &[]).unwrap();
+    assert_ne!(before, dir_edit);
+
+    fs::write(root.join("shared/util.rs"), "pub fn util() { todo!() }").unwrap();
+    let file_edit = cache.compute_source_hash(&pkg, &[]).unwrap();
+    assert_ne!(dir_edit, file_edit);
+
+    // Targets outside the repository are not followed
+    fs::write(
+        temp_dir.path().join("outside/extra.rs"),
+        "pub fn extra() { todo!() }",
+    )
+    .unwrap();
+    assert_eq!(file_edit, cache.compute_source_hash(&pkg, &[]).unwrap());
+}
+
+#[test]
+fn test_diagnostic_counts_from_captured_log() {
+    use cargo_save::DiagnosticCounts;
+
+    let plain = "\
+   Compiling app v0.1.0 (/ws/app)
+warning: unused variable: `x`
+ --> src/main.rs:2:9
+warning: function `f` is never used
+error[E0425]: cannot find value `y` in this scope
+error: expected `;`, found `}`
+warning: `app` (bin \"app\") generated 2 warnings
+error: could not compile `app` (bin \"app\") due to 2 previous errors; 2 warnings emitted
+warning: build failed, waiting for other jobs to finish...
+note: the error: prefix only counts at the start of a line
+";
+    assert_eq!(
+        DiagnosticCounts::from_log(plain),
+        DiagnosticCounts {
+            errors: 2,
+            warnings: 2
+        }
+    );
+
+    let json = r#"{"reason":"compiler-message","message":{"level":"warning","message":"unused"}}
+{"reason":"compiler-message","message":{"level":"error","message":"mismatched types"}}
+{"reason":"compiler-message","message":{"level":"note","message":"aside"}}
+{"reason":"compiler-artifact","target":{"name":"app"}}
+{"reason":"build-finished","success":false}"#;
+    assert_eq!(
+        DiagnosticCounts::from_log(json),
+        DiagnosticCounts {
+            errors: 1,
+            warnings: 1
+        }
+    );
+}
+
+#[test]
+fn test_build_metadata_records_warning_count() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "noisy-fixture", "    let unused = 1;");
+    let cache_root = temp_dir.path().join("cache");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+        .args(["save", "check"])
+        .current_dir(&crate_dir)
+        .env("CARGO_SAVE_CACHE_DIR", &cache_root)
+        .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let build = CacheManager::with_cache_dir(&cache_root)
+        .unwrap()
+        .latest_build_cache()
+        .unwrap();
+    assert_eq!((build.error_count, build.warning_count), (0, 1));
+}
+
+#[test]
+fn test_no_cache_package_is_always_changed() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path())
+        .unwrap()
+        .with_no_cache_packages(["codegen".to_string()]);
+
+    let mut workspace = workspace_with(vec![
+        package("core", &[]),
+        package("codegen", &[]),
+        package("app", &["codegen"]),
+    ]);
+    workspace.root = temp_dir.path().join("ws");
+
+    for pkg in &workspace.packages {
+        cache
+            .save_incremental_cache(pkg, &workspace, "cmd", "env", false, &[], true, 0)
+            .unwrap();
+    }
+    // Nothing is written for the denylisted package
+    let mut entries: Vec<String> = fs::read_dir(&cache.incremental_dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    entries.sort();
+    assert_eq!(entries.len(), 2);
+    assert!(entries[0].starts_with("app-") && entries[1].starts_with("core-"));
+
+    // Unchanged sources, yet it and its dependents are rebuilt every time
+    let mut changed: Vec<String> = cache
+        .get_changed_packages(&workspace, "cmd", "env", false, &[])
+        .into_iter()
+        .map(|p| p.name)
+        .collect();
+    changed.sort();
+    assert_eq!(changed, vec!["app", "codegen"]);
+}
+
+#[test]
+fn test_cache_key_formats() {
+    use cargo_save::CiPlatform;
+
+    let _guard = ENV_MUTEX.lock().unwrap();
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    let mut workspace = workspace_with(vec![]);
+    workspace.toolchain_hash = "a".repeat(64);
+    workspace.cargo_lock_hash = "b".repeat(64);
+    let env = &cache.compute_env_hash()[..8];
+    let (t, l) = ("a".repeat(16), "b".repeat(16));
+    let dashed = format!("cargo-save-{}-{}-{}", t, l, env);
+
+    for platform in [
+        CiPlatform::Github,
+        CiPlatform::Gitlab,
+        CiPlatform::Buildkite,
+        CiPlatform::Jenkins,
+        CiPlatform::Generic,
+    ] {
+        assert_eq!(
+            cache.generate_cache_key(platform, &workspace, false, false),
+            dashed
+        );
+    }
+    assert_eq!(
+        cache.generate_cache_key(CiPlatform::Circleci, &workspace, false, false),
+        format!("v1-{}", dashed)
+    );
+    assert_eq!(
+        cache.generate_cache_key(CiPlatform::Azure, &workspace, false, false),
+        format!("\"cargo-save\" | {} | {} | {}", t, l, env)
+    );
+
+    // A different toolchain must produce a different key
+    let mut upgraded = workspace.clone();
+    upgraded.toolchain_hash = "c".repeat(64);
+    assert_ne!(
+        cache.generate_cache_key(CiPlatform::Github, &upgraded, false, false),
+        dashed
+    );
+
+    let previous = std::env::var("CARGO_BUILD_TARGET").ok();
+    std::env::remove_var("CARGO_BUILD_TARGET");
+    assert_eq!(
+        cache.generate_cache_key(CiPlatform::Github, &workspace, true, false),
+        format!("{}-host", dashed)
+    );
+    if let Some(target) = previous {
+        std::env::set_var("CARGO_BUILD_TARGET", target);
+    }
+}
+
+#[test]
+fn test_cache_key_github_output() {
+    use cargo_save::CiPlatform;
+
+    let _guard = ENV_MUTEX.lock().unwrap();
+    let temp_dir = TempDir::new().unwrap();
+    let output = temp_dir.path().join("github_output");
+
+    std::env::set_var("GITHUB_OUTPUT", &output);
+    CiPlatform::Gitlab.write_output("ignored").unwrap();
+    CiPlatform::Github.write_output("cargo-save-key").unwrap();
+    std::env::remove_var("GITHUB_OUTPUT");
+
+    assert_eq!(
+        fs::read_to_string(&output).unwrap(),
+        "cache-key=cargo-save-key\n"
+    );
+}
+
+#[test]
+fn test_command_hash_ignores_flag_order_and_output_flags() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let hash = |args: &[&str]| {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        cache.compute_command_hash("build", &args)
+    };
+
+    let base = hash(&["--release", "--features", "a", "-p", "foo"]);
+    // Reordered flags keep their values
+    assert_eq!(base, hash(&["-p", "foo", "--release", "--features", "a"]));
+    assert_eq!(base, hash(&["--features=a", "--package=foo", "--release"]));
+    assert_eq!(base, hash(&["-pfoo", "-Fa", "--release"]));
+    assert_ne!(base, hash(&["--release", "--features", "foo", "-p", "a"]));
+
+    // Output-only flags are ignored along with their values
+    assert_eq!(
+        base,
+        hash(&[
+            "-q",
+            "--release",
+            "--color",
+            "always",
+            "-p",
+            "foo",
+            "-vv",
+            "--features",
+            "a",
+            "-j",
+            "8",
+        ])
+    );
+    assert_eq!(
+        base,
+        hash(&[
+            "--color=never",
+            "--verbose",
+            "--release",
+            "--features=a",
+            "-p",
+            "foo"
+        ])
+    );
+
+    // Positional values and harness arguments keep their order
+    let test_hash = |args: &[&str]| {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        cache.compute_command_hash("test", &args)
+    };
+    assert_eq!(
+        test_hash(&[
+            "--release",
+            "filter",
+            "--",
+            "--nocapture",
+            "--test-threads=1"
+        ]),
+        test_hash(&[
+            "filter",
+            "--release",
+            "--",
+            "--nocapture",
+            "--test-threads=1"
+        ])
+    );
+    assert_ne!(
+        test_hash(&["--", "--nocapture", "--test-threads=1"]),
+        test_hash(&["--", "--test-threads=1", "--nocapture"])
+    );
+    assert_ne!(test_hash(&["a", "b"]), test_hash(&["b", "a"]));
+}
+
+#[test]
+fn test_command_hash_normalizes_network_flags() {
+    use cargo_save::NetworkMode;
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let hash = |args: &[&str]| {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        cache.compute_command_hash("build", &args)
+    };
+
+    assert_ne!(hash(&["--release"]), hash(&["--release", "--offline"]));
+    assert_ne!(hash(&["--offline"]), hash(&["--locked"]));
+    assert_eq!(
+        hash(&["--offline", "--release"]),
+        hash(&["--release", "--offline"])
+    );
+    assert_eq!(
+        hash(&["--locked", "--release", "--offline"]),
+        hash(&["--frozen", "--release"])
+    );
+    // Flags for the test binary are not cargo's network flags
+    assert_ne!(hash(&["--", "--offline"]), hash(&["--offline", "--"]));
+
+    let mode = |args: &[&str]| {
+        NetworkMode::from_args(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+    };
+    assert_eq!(
+        mode(&["--frozen"]),
+        NetworkMode {
+            offline: true,
+            locked: true
+        }
+    );
+    assert_eq!(mode(&["--", "--locked"]), NetworkMode::default());
+}
+
+#[test]
+fn test_command_hash_normalizes_config_overrides() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let hash = |args: &[&str]| {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        cache.compute_command_hash("build", &args)
+    };
+
+    assert_eq!(
+        hash(&["--config", r#"build.rustflags=["-C","target-cpu=native"]"#]),
+        hash(&[r#"--config=build.rustflags = [ '-C', "target-cpu=native" ]"#])
+    );
+    assert_eq!(
+        hash(&["--config", "profile.dev.opt-level=1"]),
+        hash(&["--config", "profile.dev.opt-level = 1"])
+    );
+    assert_ne!(
+        hash(&["--config", "profile.dev.opt-level=1"]),
+        hash(&["--config", "profile.dev.opt-level=2"])
+    );
+    // The last override wins, so their order is kept
+    assert_ne!(
+        hash(&[
+            "--config",
+            "profile.dev.opt-level=1",
+            "--config",
+            "profile.dev.opt-level=2"
+        ]),
+        hash(&[
+            "--config",
+            "profile.dev.opt-level=2",
+            "--config",
+            "profile.dev.opt-level=1"
+        ])
+    );
+
+    let config = temp_dir.path().join("ci.toml");
+    fs::write(&config, "[build]\nincremental = false\n").unwrap();
+    let config = config.display().to_string();
+    let before = hash(&["--config", &config]);
+    assert_eq!(before, hash(&["--config", &config]));
+    assert_ne!(before, hash(&[]));
+
+    fs::write(&config, "[build]\nincremental = true\n").unwrap();
+    assert_ne!(before, hash(&["--config", &config]));
+}
+
+#[test]
+fn test_run_program_args_share_build_cache() {
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(
+        &crate_dir,
+        "run-fixture",
+        "    println!(\"args: {:?}\", std::env::args().skip(1).collect::<Vec<_>>());",
+    );
+    let args = |flag: &str| {
+        vec![
+            "--manifest-path".to_string(),
+            crate_dir.join("Cargo.toml").display().to_string(),
+            "--target-dir".to_string(),
+            temp_dir.path().join("target").display().to_string(),
+            "--".to_string(),
+            flag.to_string(),
+        ]
+    };
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    assert_eq!(
+        cache.compute_command_hash("run", &args("--foo")),
+        cache.compute_command_hash("run", &args("--bar"))
+    );
+    // Test harness arguments select which tests run, so they still count
+    assert_ne!(
+        cache.compute_command_hash("test", &args("--foo")),
+        cache.compute_command_hash("test", &args("--bar"))
+    );
+
+    let workspace = cache.compute_workspace_state(&args("--foo")).unwrap();
+    for flag in ["--foo", "--bar"] {
+        let (cache_id, exit_code, ..) = cache
+            .run_cargo_with_cache("run", &args(flag), &workspace)
+            .unwrap();
+        assert_eq!(exit_code, Some(0));
+        let options = cargo_save::QueryOptions {
+            cache_id: Some(cache_id),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        cache
+            .query_logs_into("all", None, &options, &mut out)
+            .unwrap();
+        let log = String::from_utf8(out).unwrap();
+        assert!(log.contains(&format!("args: [\"{}\"]", flag)), "{}", log);
+        // The first run's entries already cover the second one
+        assert!(cache.is_fully_cached("run", &args("--bar"), &workspace));
+    }
+}
+
+#[test]
+fn test_cache_salt_separates_hash_namespaces() {
+    use std::collections::BTreeMap;
+
+    let temp_dir = TempDir::new().unwrap();
+    let pkg = temp_dir.path().join("pkg");
+    fs::create_dir_all(pkg.join("src")).unwrap();
+    fs::write(pkg.join("Cargo.toml"), "[package]\nname = \"salted\"").unwrap();
+    fs::write(pkg.join("src/lib.rs"), "pub fn f() {}").unwrap();
+    let env: BTreeMap<String, String> =
+        [("RUSTFLAGS".to_string(), "-C debuginfo=0".to_string())].into();
+    let args = vec!["--release".to_string()];
+
+    let manager = |salt: &str| {
+        CacheManager::with_cache_dir(temp_dir.path().join("cache"))
+            .unwrap()
+            .with_cache_salt(salt)
+    };
+    let hashes = |cache: &CacheManager| {
+        (
+            cache.compute_source_hash(&pkg, &[]).unwrap(),
+            cache.compute_command_hash("build", &args),
+            cache.compute_env_hash_from(&env),
+        )
+    };
+
+    let (alice, bob, unsalted) = (manager("alice"), manager("bob"), manager(""));
+    let (a, b, none) = (hashes(&alice), hashes(&bob), hashes(&unsalted));
+    assert_ne!(a.0, b.0);
+    assert_ne!(a.1, b.1);
+    assert_ne!(a.2, b.2);
+    assert_ne!(a.0, none.0);
+    // Deterministic for the same salt
+    assert_eq!(a, hashes(&manager("alice")));
+    assert_eq!(
+        none,
+        hashes(&CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap())
+    );
+}
+
+#[test]
+fn test_locked_build_does_not_create_lock_file() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "locked-fixture", "");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+        .args(["save", "--dry-run", "check", "--locked"])
+        .current_dir(&crate_dir)
+        .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+        .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+        .output()
+        .unwrap();
+
+    // cargo metadata ran with --locked, so it refused to write Cargo.lock
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--locked"));
+    assert!(!crate_dir.join("Cargo.lock").exists());
+}
+
+#[test]
+fn test_list_workspace_matches_symlinked_and_trailing_slash_roots() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "list-fixture", "");
+    let mut roots = vec![
+        (
+            "trailing-slash",
+            std::path::PathBuf::from(format!("{}/", crate_dir.display())),
+        ),
+        ("elsewhere", temp_dir.path().join("removed-workspace")),
+    ];
+    // The symlinked root is only checked where symlinks need no privileges
+    #[cfg(unix)]
+    let link = {
+        let link = temp_dir.path().join("link");
+        std::os::unix::fs::symlink(&crate_dir, &link).unwrap();
+        Some(link)
+    };
+    #[cfg(not(unix))]
+    let link: Option<std::path::PathBuf> = None;
+    roots.extend(link.iter().map(|link| ("via-symlink", link.clone())));
+    let mut expected: Vec<&str> = roots.iter().map(|(id, _)| *id).collect();
+    expected.retain(|id| *id != "elsewhere");
+    expected.sort();
+    let cwd = link.unwrap_or_else(|| crate_dir.clone());
+
+    let cache_root = temp_dir.path().join("cache");
+    let cache = CacheManager::with_cache_dir(&cache_root).unwrap();
+    for (cache_id, root) in roots {
+        let mut build = build_fixture(cache_id, "build", Some(0), 100);
+        build.workspace_state.root = root;
+        fs::write(
+            cache.metadata_dir.join(format!("{}.json", cache_id)),
+            serde_json::to_string(&build).unwrap(),
+        )
+        .unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+        .args(["list", "--workspace", "--json"])
+        .current_dir(&cwd)
+        .env("CARGO_SAVE_CACHE_DIR", &cache_root)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let builds: Vec<cargo_save::BuildCache> = serde_json::from_slice(&output.stdout).unwrap();
+    let mut ids: Vec<_> = builds.into_iter().map(|b| b.cache_id).collect();
+    ids.sort();
+    assert_eq!(ids, expected);
+}
+
+#[test]
+fn test_warm_build_populates_cache() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "warm-fixture", "");
+    let cache_root = temp_dir.path().join("cache");
+
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(args)
+            .current_dir(&crate_dir)
+            .env("CARGO_SAVE_CACHE_DIR", &cache_root)
+            .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["warm", "--build"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("0/1 before, 1/1 after"));
+
+    let cache = CacheManager::with_cache_dir(&cache_root).unwrap();
+    assert_eq!(fs::read_dir(&cache.incremental_dir).unwrap().count(), 1);
+
+    let output = run(&["warm"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("All packages already cached"));
+}
+
+#[test]
+fn test_object_store_deduplicates_shared_artifacts() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+
+    let mut workspace = workspace_with(vec![]);
+    workspace.root = temp_dir.path().join("ws");
+    let deps = workspace.root.join("target/debug/deps");
+    fs::create_dir_all(&deps).unwrap();
+    let rlib = deps.join("libshared-1234.rlib");
+    fs::write(&rlib, b"identical artifact").unwrap();
+
+    let mut pkg = package("shared", &[]);
+    for features in ["a", "b"] {
+        pkg.features_hash = features.to_string();
+        let args = vec!["--features".to_string(), features.to_string()];
+        cache
+            .save_incremental_cache(&pkg, &workspace, "cmd", "env", false, &args, true, 0)
+            .unwrap();
+    }
+
+    assert_eq!(fs::read_dir(&cache.incremental_dir).unwrap().count(), 2);
+    assert_eq!(fs::read_dir(&cache.objects_dir).unwrap().count(), 1);
+
+    // A deleted artifact is still considered cached and can be restored
+    fs::remove_file(&rlib).unwrap();
+    pkg.features_hash = "a".to_string();
+    let args = vec!["--features".to_string(), "a".to_string()];
+    let entry = cache
+        .check_incremental_cache(&pkg, &workspace, "cmd", "env", false, &args)
+        .expect("entry should be restorable");
+    assert_eq!(cache.restore_artifacts(&entry).unwrap(), 1);
+    assert_eq!(fs::read(&rlib).unwrap(), b"identical artifact");
+
+    // The restored file is a copy, so rewriting it in place leaves the store intact
+    fs::write(&rlib, b"relinked artifact").unwrap();
+    let object = fs::read_dir(&cache.objects_dir)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(fs::read(object.path()).unwrap(), b"identical artifact");
+
+    // Objects stay while referenced or recently stored, and are swept once
+    // the entries are gone and the grace period has passed
+    assert_eq!(cache.sweep_objects().unwrap().0, 0);
+    cache.invalidate_caches(vec![], true).unwrap();
+    assert_eq!(cache.sweep_objects().unwrap().0, 0);
+    let cache = cache.with_object_grace(std::time::Duration::ZERO);
+    assert_eq!(cache.sweep_objects().unwrap().0, 1);
+    assert_eq!(fs::read_dir(&cache.objects_dir).unwrap().count(), 0);
+}
+
+#[test]
+fn test_transitive_dependents_large_workspace() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    // 20 independent chains of 10 packages: p{10k} <- p{10k+1} <- ... <- p{10k+9}
+    let names: Vec<String> = (0..200).map(|i| format!("p{}", i)).collect();
+    let packages = (0..200)
+        .map(|i| {
+            let deps: Vec<&str> = if i % 10 == 0 {
+                vec![]
+            } else {
+                vec![names[i - 1].as_str()]
+            };
+            package(&names[i], &deps)
+        })
+        .collect();
+    let graph = cache.build_dependency_graph(&workspace_with(packages));
+
+    assert_eq!(
+        graph.transitive_dependents(&["p5".to_string()]),
+        vec!["p5", "p6", "p7", "p8", "p9"]
+    );
+
+    let closure = graph.transitive_dependents(&["p100".to_string(), "p190".to_string()]);
+    let expected: Vec<String> = (100..110)
+        .chain(190..200)
+        .map(|i| format!("p{}", i))
+        .collect();
+    let mut sorted = closure.clone();
+    sorted.sort_by_key(|n| n[1..].parse::<usize>().unwrap());
+    assert_eq!(sorted, expected);
+    // Seeds come first, each package appears once
+    assert_eq!(&closure[..2], ["p100", "p190"]);
+    assert_eq!(closure.len(), 20);
+}
+
+#[test]
+fn test_transitive_dependents_diamond() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    let workspace = workspace_with(vec![
+        package("core", &[]),
+        package("left", &["core"]),
+        package("right", &["core", "core"]),
+        package("app", &["left", "right"]),
+        package("other", &[]),
+    ]);
+    let graph = cache.build_dependency_graph(&workspace);
+
+    assert_eq!(
+        graph.packages["core"].reverse_dependencies,
+        vec!["left", "right"]
+    );
+    assert_eq!(
+        graph.transitive_dependents(&["core".to_string()]),
+        vec!["core", "left", "right", "app"]
+    );
+}
+
+#[test]
+fn test_plan_leaves_out_excluded_packages() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+
+    let target = temp_dir.path().join("target");
+    fs::create_dir_all(target.join("debug")).unwrap();
+    let target_dir = format!("--target-dir={}", target.display());
+    let args: Vec<String> = [&target_dir, "--workspace", "--exclude", "foo"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let workspace = workspace_with(vec![
+        package("foo", &[]),
+        package("bar", &["foo"]),
+        package("baz", &[]),
+    ]);
+
+    let command_hash = cache.compute_command_hash("build", &args);
+    let env_hash = cache.compute_env_hash();
+    cache
+        .save_incremental_cache(
+            &package("baz", &[]),
+            &workspace,
+            &command_hash,
+            &env_hash,
+            false,
+            &args,
+            true,
+            0,
+        )
+        .unwrap();
+
+    let plan = cache.plan("build", &args, &workspace).unwrap();
+    let changed: Vec<&str> = plan
+        .changed_packages
+        .iter()
+        .map(|p| p.name.as_str())
+        .collect();
+    assert_eq!(changed, vec!["bar"]);
+    assert_eq!(plan.excluded_packages, vec!["foo".to_string()]);
+    assert_eq!(plan.total_packages, 2);
+    assert_eq!(plan.cached_count, 1);
+    let cached: Vec<&str> = plan
+        .cached_packages(&workspace)
+        .map(|p| p.name.as_str())
+        .collect();
+    assert_eq!(cached, vec!["baz"]);
+
+    // cargo only honors --exclude together with --workspace
+    let args: Vec<String> = [&target_dir, "-p", "bar", "--exclude", "foo"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let plan = cache.plan("build", &args, &workspace).unwrap();
+    assert!(plan.excluded_packages.is_empty());
+    assert!(plan.changed_packages.iter().any(|p| p.name == "foo"));
+}
+
+#[test]
+fn test_plan_rebuilds_everything_when_target_dir_is_missing() {
+    let _guard = ENV_MUTEX.lock().unwrap();
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+
+    let target = temp_dir.path().join("target");
+    let args = vec![
+        "--target-dir".to_string(),
+        target.to_string_lossy().to_string(),
+    ];
+    let mut workspace = workspace_with(vec![package("foo", &[]), package("bar", &["foo"])]);
+    workspace.root = temp_dir.path().to_path_buf();
+
+    // Entries without recorded target files stay valid under the per-file check
+    let command_hash = cache.compute_command_hash("build", &args);
+    let env_hash = cache.compute_env_hash();
+    for pkg in &workspace.packages {
+        cache
+            .save_incremental_cache(
+                pkg,
+                &workspace,
+                &command_hash,
+                &env_hash,
+                false,
+                &args,
+                true,
+                0,
+            )
+            .unwrap();
+    }
+
+    let plan = cache.plan("build", &args, &workspace).unwrap();
+    assert_eq!(plan.changed_packages.len(), 2);
+    assert_eq!(plan.cached_count, 0);
+
+    fs::create_dir_all(target.join("debug")).unwrap();
+    let plan = cache.plan("build", &args, &workspace).unwrap();
+    assert!(plan.changed_packages.is_empty());
+}
+
+#[test]
+fn test_log_off_suppresses_cargo_save_messages() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(
+        &crate_dir,
+        "quiet-fixture",
+        "    eprintln!(\"from the program\");",
+    );
+
+    let run = |args: &[&str], log: Option<&str>| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_cargo-save"));
+        command
+            .args(args)
+            .current_dir(&crate_dir)
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+            .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+            .env_remove("CARGO_SAVE_LOG");
+        if let Some(level) = log {
+            command.env("CARGO_SAVE_LOG", level);
+        }
+        command.output().unwrap()
+    };
+
+    let output = run(&["save", "run"], Some("off"));
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("[cargo-save]"), "{}", stderr);
+    assert!(stderr.contains("from the program"));
+
+    let output = run(&["--quiet", "save", "run"], None);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("Running: cargo"), "{}", stderr);
+    assert!(stderr.contains("from the program"));
+
+    let output = run(&["save", "run"], None);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[cargo-save] Running: cargo run"));
+}
+
+#[test]
+fn test_log_level_parse() {
+    use cargo_save::LogLevel;
+
+    assert_eq!(LogLevel::parse("off"), Some(LogLevel::Off));
+    assert_eq!(LogLevel::parse("ERROR"), Some(LogLevel::Error));
+    assert_eq!(LogLevel::parse("debug"), Some(LogLevel::Debug));
+    assert_eq!(LogLevel::parse("loud"), None);
+    assert!(LogLevel::Error < LogLevel::Info);
+}
+
+#[test]
+fn test_query_color_follows_cargo_term_color() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let cache_id = "20240101_000000-color000";
+    fs::write(
+        cache.cache_dir.join(format!("{}.log", cache_id)),
+        "   Compiling foo\nerror[E0425]: cannot find value `x`\nwarning: unused variable\n",
+    )
+    .unwrap();
+
+    let query = |mode: &str, color: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(["query", mode, "--id", cache_id])
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path())
+            .env("CARGO_TERM_COLOR", color)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    for mode in ["errors", "warnings"] {
+        assert!(!query(mode, "never").contains('\x1b'));
+        // Piped output is never a terminal
+        assert!(!query(mode, "auto").contains('\x1b'));
+        assert!(query(mode, "always").contains("\x1b["));
+    }
+    assert_eq!(
+        query("errors", "never"),
+        "error[E0425]: cannot find value `x`\n"
+    );
+}
+
+#[test]
+fn test_cache_error_variants() {
+    use cargo_save::CacheError;
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+
+    assert!(matches!(
+        cache.load_build_cache("missing"),
+        Err(CacheError::BuildNotFound(id)) if id == "missing"
+    ));
+
+    fs::write(cache.metadata_dir.join("broken.json"), "{ not json").unwrap();
+    assert!(matches!(
+        cache.load_build_cache("broken"),
+        Err(CacheError::CorruptCache { .. })
+    ));
+
+    let not_a_repo = temp_dir.path().join("plain");
+    fs::create_dir_all(&not_a_repo).unwrap();
+    assert!(matches!(
+        cache.install_git_hooks(&not_a_repo),
+        Err(CacheError::Git(_))
+    ));
+
+    // A directory where Cargo.lock should be cannot be read
+    fs::create_dir_all(not_a_repo.join("Cargo.lock")).unwrap();
+    assert!(matches!(
+        cache.compute_cargo_lock_hash(&not_a_repo),
+        Err(CacheError::Io(_))
+    ));
+
+    let missing_manifest = cargo_metadata::Error::CargoMetadata {
+        stderr: "error: could not find `Cargo.toml` in `/tmp` or any parent directory".to_string(),
+    };
+    assert!(matches!(
+        CacheError::from(missing_manifest),
+        CacheError::NotAWorkspace(path) if path == std::path::Path::new("/tmp")
+    ));
+    let missing_manifest_path = cargo_metadata::Error::CargoMetadata {
+        stderr: "error: manifest path `/tmp/nope/Cargo.toml` does not exist".to_string(),
+    };
+    assert!(matches!(
+        CacheError::from(missing_manifest_path),
+        CacheError::NotAWorkspace(path) if path == std::path::Path::new("/tmp/nope/Cargo.toml")
+    ));
+    let other = cargo_metadata::Error::CargoMetadata {
+        stderr: "error: failed to parse manifest".to_string(),
+    };
+    assert!(matches!(CacheError::from(other), CacheError::Metadata(_)));
+
+    // Still usable with anyhow for CLI-style callers
+    let err: anyhow::Error = cache.load_build_cache("missing").unwrap_err().into();
+    assert_eq!(err.to_string(), "No build metadata found for missing");
+}
+
+#[test]
+fn test_build_cache_round_trips_by_id_and_latest() {
+    use cargo_save::CacheError;
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    assert!(matches!(
+        cache.latest_build_cache(),
+        Err(CacheError::BuildNotFound(_))
+    ));
+
+    let older = build_fixture("20240101_000000-00000001", "build", Some(0), 1200);
+    let mut newer = build_fixture("20240101_000001-00000002", "test", Some(101), 3400);
+    newer.timestamp = "2024-01-01T00:00:01+00:00".to_string();
+    newer.env_hash = "other-env".to_string();
+    // Written newest first, so file mtimes disagree with the recorded order
+    for build in [&newer, &older] {
+        fs::write(
+            cache.metadata_dir.join(format!("{}.json", build.cache_id)),
+            serde_json::to_string_pretty(build).unwrap(),
+        )
+        .unwrap();
+    }
+
+    let loaded = cache.get_build_cache(&older.cache_id).unwrap();
+    assert_eq!(
+        serde_json::to_value(&loaded).unwrap(),
+        serde_json::to_value(&older).unwrap()
+    );
+
+    let latest = cache.latest_build_cache().unwrap();
+    assert_eq!(latest.cache_id, newer.cache_id);
+    assert_eq!(latest.exit_code, Some(101));
+    assert_eq!(latest.duration_ms, 3400);
+    assert_eq!(latest.env_hash, "other-env");
+}
+
+#[test]
+fn test_parse_timings_html_sums_units_per_package() {
+    let report = r#"<html><script>
+const UNIT_DATA = [
+  {"i":0,"name":"serde","version":"1.0.0","mode":"run-custom-build","target":" build script","features":[],"start":0.1,"duration":0.25,"rmeta_time":null,"unlocked_units":[],"unlocked_rmeta_units":[]},
+  {"i":1,"name":"serde","version":"1.0.0","mode":"todo","target":"","features":["std"],"start":0.4,"duration":1.5,"rmeta_time":0.9,"unlocked_units":[],"unlocked_rmeta_units":[]},
+  {"i":2,"name":"app","version":"0.1.0","mode":"todo","target":" app \"bin\"","features":[],"start":2.0,"duration":0.042,"rmeta_time":null,"unlocked_units":[],"unlocked_rmeta_units":[]}
+];
+const CONCURRENCY_DATA = [];
+</script></html>"#;
+
+    let timings = cargo_save::parse_timings_html(report);
+    assert_eq!(timings.len(), 2);
+    assert_eq!(timings["serde"], 1750);
+    assert_eq!(timings["app"], 42);
+
+    assert!(cargo_save::parse_timings_html("<html></html>").is_empty());
+}
+
+#[test]
+fn test_slowest_packages_uses_latest_entry() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let workspace = workspace_with(vec![]);
+
+    let entries = [
+        ("fast", "--features=a", 100),
+        ("slow", "--features=a", 5000),
+        ("medium", "--features=a", 900),
+        // A newer build of `slow` replaces the older duration
+        ("slow", "--features=b", 2000),
+    ];
+    for (name, feature, duration) in entries {
+        cache
+            .save_incremental_cache(
+                &package(name, &[]),
+                &workspace,
+                "cmd",
+                "env",
+                false,
+                &[feature.to_string()],
+                true,
+                duration,
+            )
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+
+    let slowest = cache.slowest_packages(2).unwrap();
+    assert_eq!(
+        slowest,
+        vec![("slow".to_string(), 2000), ("medium".to_string(), 900)]
+    );
+}
+
+#[test]
+fn test_per_package_stats_groups_entries() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    for (hash, size) in [("aaaa", 1000), ("bbbb", 300), ("cccc", 50)] {
+        fs::write(cache.objects_dir.join(hash), vec![0u8; size]).unwrap();
+    }
+
+    let entries = [
+        (
+            "core-debug",
+            "core",
+            1000,
+            "2024-01-01T00:00:00+00:00",
+            vec!["aaaa", "bbbb"],
+            3,
+        ),
+        // Shares `aaaa` with the debug entry, which is stored only once
+        (
+            "core-release",
+            "core",
+            3000,
+            "2024-01-02T00:00:00+00:00",
+            vec!["aaaa"],
+            1,
+        ),
+        (
+            "cli-debug",
+            "cli",
+            400,
+            "2024-01-01T00:00:00+00:00",
+            vec!["cccc"],
+            0,
+        ),
+    ];
+    for (key, name, duration_ms, timestamp, objects, hits) in entries {
+        let entry = cargo_save::IncrementalCache {
+            package_name: name.to_string(),
+            package_version: "0.1.0".to_string(),
+            source_hash: "0".repeat(64),
+            cargo_lock_hash: "lock".to_string(),
+            command_hash: "cmd".to_string(),
+            env_hash: "env".to_string(),
+            is_release: false,
+            features_hash: "features".to_string(),
+            target_files: vec![],
+            artifact_paths: vec![],
+            timestamp: timestamp.to_string(),
+            build_success: true,
+            duration_ms,
+            objects: objects
+                .iter()
+                .map(|hash| (std::path::PathBuf::from(hash), hash.to_string()))
+                .collect(),
+            tests_passed: None,
+            workspace_root: None,
+            hits,
+            toolchain_hash: None,
+            subcommand: None,
+            output_kind: None,
+        };
+        fs::write(
+            cache.incremental_dir.join(format!("{}.json", key)),
+            serde_json::to_string(&entry).unwrap(),
+        )
+        .unwrap();
+    }
+
+    let stats = cache.per_package_stats().unwrap();
+    assert_eq!(
+        stats,
+        vec![
+            cargo_save::PackageStats {
+                name: "core".to_string(),
+                entries: 2,
+                hits: 4,
+                avg_duration_ms: 2000,
+                last_duration_ms: 3000,
+                artifact_bytes: 1300,
+            },
+            cargo_save::PackageStats {
+                name: "cli".to_string(),
+                entries: 1,
+                hits: 0,
+                avg_duration_ms: 400,
+                last_duration_ms: 400,
+                artifact_bytes: 50,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_second_identical_test_run_is_test_cached() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "tested-fixture", "");
+    fs::write(
+        crate_dir.join("src/lib.rs"),
+        "#[test]\nfn passes() {\n    assert_eq!(1 + 1, 2);\n}\n",
+    )
+    .unwrap();
+    let cache_root = temp_dir.path().join("cache");
+
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(args)
+            .current_dir(&crate_dir)
+            .env("CARGO_SAVE_CACHE_DIR", &cache_root)
+            .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+            .output()
+            .unwrap()
+    };
+
+    let first = run(&["save", "test"]);
+    let stderr = String::from_utf8_lossy(&first.stderr);
+    assert!(first.status.success(), "{}", stderr);
+    assert!(!stderr.contains("Test-cached"));
+
+    let cache = CacheManager::with_cache_dir(&cache_root).unwrap();
+    let entry = fs::read_dir(&cache.incremental_dir)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let entry: cargo_save::IncrementalCache =
+        serde_json::from_str(&fs::read_to_string(entry.path()).unwrap()).unwrap();
+    assert_eq!(entry.tests_passed, Some(true));
+
+    let second = run(&["save", "test"]);
+    let stderr = String::from_utf8_lossy(&second.stderr);
+    assert!(second.status.success(), "{}", stderr);
+    assert!(stderr.contains("Test-cached (unchanged, passed before): tested-fixture"));
+    assert!(stderr.contains("All packages cached, skipping test"));
+    assert!(!String::from_utf8_lossy(&second.stdout).contains("running 1 test"));
+
+    let stats = cache.per_package_stats().unwrap();
+    assert_eq!(stats[0].name, "tested-fixture");
+    assert_eq!(stats[0].hits, 1);
+}
+
+#[test]
+fn test_check_cache_does_not_skip_a_later_build() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "kind-fixture", "");
+    let target_dir = temp_dir.path().join("target");
+
+    let run = |subcommand: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(["save", subcommand])
+            .current_dir(&crate_dir)
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+            .env("CARGO_TARGET_DIR", &target_dir)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        assert!(output.status.success(), "{}", stderr);
+        stderr
+    };
+
+    assert!(!run("check").contains("All packages cached"));
+    assert!(run("check").contains("All packages cached, skipping check"));
+
+    // `check` left only metadata behind, so `build` still has to run cargo
+    let binary = target_dir
+        .join("debug")
+        .join(format!("kind-fixture{}", std::env::consts::EXE_SUFFIX));
+    assert!(!binary.exists());
+    assert!(!run("build").contains("All packages cached"));
+    assert!(binary.exists());
+    assert!(run("build").contains("All packages cached, skipping build"));
+}
+
+#[test]
+fn test_nextest_run_with_all_packages_cached_is_skipped() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+
+    let target = temp_dir.path().join("target");
+    fs::create_dir_all(target.join("debug")).unwrap();
+    let args = vec![
+        "run".to_string(),
+        "--target-dir".to_string(),
+        target.to_string_lossy().to_string(),
+    ];
+    let mut workspace = workspace_with(vec![package("foo", &[]), package("bar", &["foo"])]);
+    workspace.root = temp_dir.path().to_path_buf();
+
+    let command_hash = cache.compute_command_hash("nextest", &args);
+    let env_hash = cache.compute_env_hash();
+    for pkg in &workspace.packages {
+        cache
+            .save_incremental_cache(
+                pkg,
+                &workspace,
+                &command_hash,
+                &env_hash,
+                false,
+                &args,
+                true,
+                0,
+            )
+            .unwrap();
+    }
+
+    // Entries whose tests are not known to pass do not count for `nextest run`
+    let plan = cache.plan("nextest", &args, &workspace).unwrap();
+    assert_eq!(plan.changed_packages.len(), 2);
+
+    for entry in fs::read_dir(&cache.incremental_dir).unwrap() {
+        let path = entry.unwrap().path();
+        let mut entry: cargo_save::IncrementalCache =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        entry.tests_passed = Some(true);
+        fs::write(&path, serde_json::to_string(&entry).unwrap()).unwrap();
+    }
+
+    let plan = cache.plan("nextest", &args, &workspace).unwrap();
+    assert!(plan.changed_packages.is_empty());
+
+    // Takes the skip path without spawning cargo, so nextest need not be installed
+    let (_, exit_code, lines, duration) = cache
+        .run_cargo_with_cache("nextest", &args, &workspace)
+        .unwrap();
+    assert_eq!((exit_code, lines, duration), (Some(0), 0, 0));
+}
+
+#[test]
+fn test_remote_cache_round_trip() {
+    use cargo_save::{MemoryRemoteCache, RemoteCache};
+    use std::sync::Arc;
+
+    let remote = Arc::new(MemoryRemoteCache::new());
+    assert_eq!(remote.get("missing").unwrap(), None);
+    remote.put("key", b"value").unwrap();
+    assert_eq!(remote.get("key").unwrap(), Some(b"value".to_vec()));
+
+    // One machine saves an entry, another with an empty local cache finds it
+    let temp_dir = TempDir::new().unwrap();
+    let mut workspace = workspace_with(vec![]);
+    workspace.root = temp_dir.path().join("ws");
+    let deps = workspace.root.join("target/debug/deps");
+    fs::create_dir_all(&deps).unwrap();
+    let rlib = deps.join("libshared-1234.rlib");
+    fs::write(&rlib, b"remote artifact").unwrap();
+
+    let pkg = package("shared", &[]);
+    workspace.packages = vec![pkg.clone()];
+    let uploader = CacheManager::with_cache_dir(temp_dir.path().join("a"))
+        .unwrap()
+        .with_remote(remote.clone());
+    let command_hash = uploader.compute_command_hash("build", &[]);
+    let env_hash = uploader.compute_env_hash();
+    uploader
+        .save_incremental_cache(
+            &pkg,
+            &workspace,
+            &command_hash,
+            &env_hash,
+            false,
+            &[],
+            true,
+            0,
+        )
+        .unwrap();
+    assert!(remote
+        .keys()
+        .iter()
+        .any(|k| k.starts_with("entries/shared-")));
+    assert!(remote.keys().iter().any(|k| k.starts_with("objects/")));
+
+    fs::remove_file(&rlib).unwrap();
+    let downloader = CacheManager::with_cache_dir(temp_dir.path().join("b"))
+        .unwrap()
+        .with_remote(remote.clone());
+    // Inspecting the cache stays local
+    assert!(downloader
+        .check_incremental_cache(&pkg, &workspace, &command_hash, &env_hash, false, &[])
+        .is_none());
+    let report = downloader.restore_workspace_artifacts("build", &[], &workspace);
+    assert_eq!(report.restored, vec!["shared".to_string()]);
+    assert_eq!(report.files, 1);
+    assert_eq!(
+        fs::read_dir(&downloader.incremental_dir).unwrap().count(),
+        1
+    );
+    assert_eq!(fs::read(&rlib).unwrap(), b"remote artifact");
+
+    // Entries restoring files outside the target directory are rejected
+    let outside = temp_dir.path().join("outside.txt");
+    fs::write(&outside, b"keep me").unwrap();
+    let key = remote
+        .keys()
+        .into_iter()
+        .find(|k| k.starts_with("entries/shared-"))
+        .unwrap();
+    let entry = String::from_utf8(remote.get(&key).unwrap().unwrap()).unwrap();
+    let rlib_json = serde_json::to_string(&rlib).unwrap();
+    let outside_json = serde_json::to_string(&outside).unwrap();
+    remote
+        .put(&key, entry.replace(&rlib_json, &outside_json).as_bytes())
+        .unwrap();
+    let victim = CacheManager::with_cache_dir(temp_dir.path().join("c"))
+        .unwrap()
+        .with_remote(remote.clone());
+    let report = victim.restore_workspace_artifacts("build", &[], &workspace);
+    assert!(report.restored.is_empty());
+    assert_eq!(fs::read_dir(&victim.incremental_dir).unwrap().count(), 0);
+    assert_eq!(fs::read(&outside).unwrap(), b"keep me");
+}
+
+#[test]
+fn test_interleaved_output_does_not_deadlock() {
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    // Far more output than a pipe buffer holds on either stream
+    create_fixture_crate(
+        &crate_dir,
+        "chatty-fixture",
+        "    for i in 0..20000 {\n        println!(\"out {}\", i);\n        eprintln!(\"err {}\", i);\n    }",
+    );
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+        .args(["-q", "save", "run"])
+        .current_dir(&crate_dir)
+        .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+        .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(120);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("cargo-save did not finish; output reading deadlocked");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    assert!(status.success());
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let build = cache.load_build_caches().unwrap().remove(0);
+    let query = |stream| {
+        let mut out = Vec::new();
+        let options = cargo_save::QueryOptions {
+            cache_id: Some(build.cache_id.clone()),
+            stream,
+            ..Default::default()
+        };
+        cache
+            .query_logs_into("all", None, &options, &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    let out = query(cargo_save::LogStream::Out);
+    let err = query(cargo_save::LogStream::Err);
+    assert_eq!(out.lines().filter(|l| l.starts_with("out ")).count(), 20000);
+    assert_eq!(err.lines().filter(|l| l.starts_with("err ")).count(), 20000);
+    assert_eq!(build.lines_count, out.lines().count() + err.lines().count());
+}
+
+#[test]
+fn test_concurrent_saves_of_same_key_do_not_corrupt_entry() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let workspace = workspace_with(vec![]);
+    let pkg = package("contended", &[]);
+
+    std::thread::scope(|scope| {
+        for thread in 0..2u64 {
+            let (cache, workspace, pkg) = (&cache, &workspace, &pkg);
+            scope.spawn(move || {
+                for i in 0..50 {
+                    cache
+                        .save_incremental_cache(
+                            pkg,
+                            workspace,
+                            "cmd",
+                            "env",
+                            false,
+                            &[],
+                            true,
+                            thread * 1000 + i,
+                        )
+                        .unwrap();
+                }
+            });
+        }
+    });
+
+    let entries: Vec<_> = fs::read_dir(&cache.incremental_dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(entries.len(), 1);
+    let entry: cargo_save::IncrementalCache =
+        serde_json::from_str(&fs::read_to_string(&entries[0]).unwrap())
+            .expect("entry should be valid JSON");
+    assert_eq!(entry.package_name, "contended");
+}
+
+#[test]
+fn test_proc_macro_and_build_dependency_changes_rebuild_dependents() {
+    use cargo_save::DependencyKind;
+
+    let temp_dir = TempDir::new().unwrap();
+    let ws = temp_dir.path().join("ws");
+    let write = |path: &str, content: &str| {
+        let path = ws.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    };
+    let manifest = |name: &str, extra: &str| {
+        format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n{}",
+            name, extra
+        )
+    };
+
+    write(
+        "Cargo.toml",
+        "[workspace]\nmembers = [\"macros\", \"helper\", \"app\", \"other\"]\n",
+    );
+    write(
+        "macros/Cargo.toml",
+        &manifest("macros", "\n[lib]\nproc-macro = true\n"),
+    );
+    write(
+        "macros/src/lib.rs",
+        "use proc_macro::TokenStream;\n\n#[proc_macro]\npub fn noop(_: TokenStream) -> TokenStream {\n    TokenStream::new()\n}\n",
+    );
+    write("helper/Cargo.toml", &manifest("helper", ""));
+    write("helper/src/lib.rs", "pub fn help() {}\n");
+    write(
+        "app/Cargo.toml",
+        &manifest(
+            "app",
+            "\n[dependencies]\nmacros = { path = \"../macros\" }\n\n[build-dependencies]\nhelper = { path = \"../helper\" }\n",
+        ),
+    );
+    write("app/build.rs", "fn main() {\n    helper::help();\n}\n");
+    write("app/src/lib.rs", "macros::noop!();\n");
+    write("other/Cargo.toml", &manifest("other", ""));
+    write("other/src/lib.rs", "");
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let hash_packages = || {
+        let metadata = cargo_metadata::MetadataCommand::new()
+            .current_dir(&ws)
+            .exec()
+            .unwrap();
+        metadata
+            .workspace_packages()
+            .iter()
+            .map(|p| cache.compute_package_hash(p, &metadata, &[]).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    let mut workspace = workspace_with(hash_packages());
+    workspace.root = ws.clone();
+    let app = workspace.packages.iter().find(|p| p.name == "app").unwrap();
+    assert_eq!(app.dependency_kinds["macros"], vec![DependencyKind::Normal]);
+    assert_eq!(app.dependency_kinds["helper"], vec![DependencyKind::Build]);
+
+    for pkg in &workspace.packages {
+        cache
+            .save_incremental_cache(pkg, &workspace, "cmd", "env", false, &[], true, 0)
+            .unwrap();
+    }
+    let changed = |workspace: &cargo_save::WorkspaceState| {
+        let mut names: Vec<String> = cache
+            .get_changed_packages(workspace, "cmd", "env", false, &[])
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        names.sort();
+        names
+    };
+    assert!(changed(&workspace).is_empty());
+
+    write(
+        "macros/src/lib.rs",
+        "use proc_macro::TokenStream;\n\n#[proc_macro]\npub fn noop(input: TokenStream) -> TokenStream {\n    input\n}\n",
+    );
+    workspace.packages = hash_packages();
+    assert_eq!(changed(&workspace), vec!["app", "macros"]);
+
+    // Once the macro change is cached, a build-dependency change does the same
+    for pkg in &workspace.packages {
+        cache
+            .save_incremental_cache(pkg, &workspace, "cmd", "env", false, &[], true, 0)
+            .unwrap();
+    }
+    write("helper/src/lib.rs", "pub fn help() {\n    println!();\n}\n");
+    workspace.packages = hash_packages();
+    assert_eq!(changed(&workspace), vec!["app", "helper"]);
+}
+
+#[test]
+fn test_workspace_state_progress_called_once_per_package() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    let calls = Mutex::new(Vec::new());
+    let workspace = cache
+        .compute_workspace_state_with_progress(&[], |done, total| {
+            calls.lock().unwrap().push((done, total));
+        })
+        .unwrap();
+
+    let total = cache
+        .get_cargo_metadata()
+        .unwrap()
+        .workspace_packages()
+        .len();
+    assert_eq!(workspace.packages.len(), total);
+
+    let mut calls = calls.into_inner().unwrap();
+    calls.sort();
+    let expected: Vec<_> = (1..=total).map(|done| (done, total)).collect();
+    assert_eq!(calls, expected);
+}
+
+#[test]
+fn test_target_triples_key_cache_and_artifact_paths() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let target_dir = temp_dir.path().join("target");
+    let args = |extra: &[&str]| {
+        let mut args = vec!["--target-dir".to_string(), target_dir.display().to_string()];
+        args.extend(extra.iter().map(|a| a.to_string()));
+        args
+    };
+
+    assert_eq!(
+        cache.get_target_triples(&args(&[
+            "--target=x86_64-unknown-linux-gnu",
+            "--target",
+            "aarch64-unknown-linux-gnu",
+            "--target",
+            "specs/thumbv7-custom.json",
+            "--",
+            "--target",
+            "ignored"
+        ])),
+        vec![
+            "aarch64-unknown-linux-gnu",
+            "thumbv7-custom",
+            "x86_64-unknown-linux-gnu"
+        ]
+    );
+
+    let mut workspace = workspace_with(vec![]);
+    workspace.root = temp_dir.path().join("ws");
+    let linux = target_dir.join("x86_64-unknown-linux-gnu/debug");
+    let windows = target_dir.join("x86_64-pc-windows-msvc/debug");
+    for (dir, files) in [
+        (
+            &linux,
+            &["deps/libfoo-0123abcd.rlib", "deps/foo-0123abcd"][..],
+        ),
+        (
+            &windows,
+            &["deps/foo-4567cdef.exe", "deps/foo-4567cdef.pdb"][..],
+        ),
+        // Host artifacts are not part of a cross build
+        (
+            &target_dir.join("debug"),
+            &["deps/libfoo-89abcdef.rlib"][..],
+        ),
+    ] {
+        fs::create_dir_all(dir.join("deps")).unwrap();
+        for file in files {
+            fs::write(dir.join(file), file).unwrap();
+        }
+    }
+
+    for target in [
+        "--target=x86_64-unknown-linux-gnu",
+        "--target=x86_64-pc-windows-msvc",
+    ] {
+        cache
+            .save_incremental_cache(
+                &package("foo", &[]),
+                &workspace,
+                "cmd",
+                "env",
+                false,
+                &args(&[target]),
+                true,
+                0,
+            )
+            .unwrap();
+    }
+
+    let mut entries: Vec<(String, cargo_save::IncrementalCache)> =
+        fs::read_dir(&cache.incremental_dir)
+            .unwrap()
+            .map(|e| {
+                let path = e.unwrap().path();
+                let entry = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+                (
+                    path.file_name().unwrap().to_string_lossy().into_owned(),
+                    entry,
+                )
+            })
+            .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(entries.len(), 2);
+    assert!(entries[0].0.ends_with("-x86_64-pc-windows-msvc.json"));
+    assert!(entries[1].0.ends_with("-x86_64-unknown-linux-gnu.json"));
+
+    let artifacts = |entry: &cargo_save::IncrementalCache, dir: &std::path::Path| {
+        let mut files: Vec<String> = entry
+            .artifact_paths
+            .iter()
+            .map(|path| path.strip_prefix(dir).unwrap().display().to_string())
+            .collect();
+        files.sort();
+        files
+    };
+    assert_eq!(
+        artifacts(&entries[0].1, &windows),
+        vec!["deps/foo-4567cdef.exe", "deps/foo-4567cdef.pdb"]
+    );
+    assert_eq!(
+        artifacts(&entries[1].1, &linux),
+        vec!["deps/foo-0123abcd", "deps/libfoo-0123abcd.rlib"]
+    );
+}
+
+#[test]
+fn test_saved_artifacts_exclude_packages_sharing_a_name_prefix() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+
+    // The workspace path itself contains the package name
+    let mut workspace = workspace_with(vec![]);
+    workspace.root = temp_dir.path().join("foo");
+    let debug = workspace.root.join("target/debug");
+    fs::create_dir_all(debug.join("deps")).unwrap();
+    fs::create_dir_all(debug.join(".fingerprint/foo-0123abcd")).unwrap();
+    fs::create_dir_all(debug.join(".fingerprint/foobar-4567cdef")).unwrap();
+    for file in [
+        "deps/libfoo-0123abcd.rlib",
+        "deps/foo-0123abcd.d",
+        "deps/libfoobar-4567cdef.rlib",
+        "deps/foobar-4567cdef.d",
+        ".fingerprint/foo-0123abcd/lib-foo",
+        ".fingerprint/foobar-4567cdef/lib-foobar",
+    ] {
+        fs::write(debug.join(file), file).unwrap();
+    }
+
+    cache
+        .save_incremental_cache(
+            &package("foo", &[]),
+            &workspace,
+            "cmd",
+            "env",
+            false,
+            &[],
+            true,
+            0,
+        )
+        .unwrap();
+
+    let entry = fs::read_dir(&cache.incremental_dir)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let entry: cargo_save::IncrementalCache =
+        serde_json::from_str(&fs::read_to_string(entry.path()).unwrap()).unwrap();
+    let mut files: Vec<String> = entry
+        .target_files
+        .iter()
+        .map(|(path, _)| path.strip_prefix(&debug).unwrap().display().to_string())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        vec![
+            ".fingerprint/foo-0123abcd/lib-foo",
+            "deps/foo-0123abcd.d",
+            "deps/libfoo-0123abcd.rlib",
+        ]
+    );
+}
+
+#[cfg(feature = "tui")]
+#[test]
+fn test_browser_app_navigates_builds_and_filters_logs() {
+    use crossterm::event::KeyCode;
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    let mut old = build_fixture("20240101_000000-old00000", "build", Some(0), 1200);
+    old.timestamp = "2024-01-01T00:00:00+00:00".to_string();
+    let mut new = build_fixture("20240102_000000-new00000", "test", Some(101), 3400);
+    new.timestamp = "2024-01-02T00:00:00+00:00".to_string();
+    fs::write(
+        cache.cache_dir.join(format!("{}.log", old.cache_id)),
+        "Compiling old\nFinished\n",
+    )
+    .unwrap();
+    fs::write(
+        cache.cache_dir.join(format!("{}.log", new.cache_id)),
+        "Compiling new\nerror[E0308]: mismatched types\nwarning: unused variable\n",
+    )
+    .unwrap();
+
+    let mut app = cargo_save::BrowserApp::new(&cache, vec![old.clone(), new.clone()]);
+    assert_eq!(app.selected_build().unwrap().cache_id, new.cache_id);
+    assert_eq!(app.visible_lines().len(), 3);
+
+    assert!(!app.handle_key(&cache, KeyCode::Char('e')));
+    assert_eq!(app.visible_lines(), vec!["error[E0308]: mismatched types"]);
+    app.handle_key(&cache, KeyCode::Char('e'));
+
+    app.handle_key(&cache, KeyCode::Char('/'));
+    for c in "unused".chars() {
+        app.handle_key(&cache, KeyCode::Char(c));
+    }
+    app.handle_key(&cache, KeyCode::Enter);
+    assert_eq!(app.visible_lines(), vec!["warning: unused variable"]);
+    app.handle_key(&cache, KeyCode::Char('/'));
+    app.handle_key(&cache, KeyCode::Esc);
+
+    app.handle_key(&cache, KeyCode::Down);
+    assert_eq!(app.selected_build().unwrap().cache_id, old.cache_id);
+    assert_eq!(app.visible_lines(), vec!["Compiling old", "Finished"]);
+
+    let backend = ratatui::backend::TestBackend::new(100, 10);
+    let mut terminal = ratatui::Terminal::new(backend).unwrap();
+    terminal.draw(|frame| app.draw(frame)).unwrap();
+    let screen: String = terminal
+        .backend()
+        .buffer()
+        .content()
+        .iter()
+        .map(|cell| cell.symbol())
+        .collect();
+    assert!(screen.contains(&new.cache_id));
+    assert!(screen.contains("Compiling old"));
+
+    assert!(app.handle_key(&cache, KeyCode::Char('q')));
+}
+
+#[test]
+fn test_message_format_json_streams_events_for_cached_workspace() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "json-fixture", "");
+    let cache_root = temp_dir.path().join("cache");
+
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(args)
+            .current_dir(&crate_dir)
+            .env("CARGO_SAVE_CACHE_DIR", &cache_root)
+            .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+            .output()
+            .unwrap()
+    };
+
+    let first = run(&["save", "build"]);
+    assert!(
+        first.status.success(),
+        "{}",
+        String::from_utf8_lossy(&first.stderr)
+    );
+
+    let second = run(&["save", "--message-format", "cargo-save-json", "build"]);
+    assert!(
+        second.status.success(),
+        "{}",
+        String::from_utf8_lossy(&second.stderr)
+    );
+    let events: Vec<cargo_save::BuildEvent> = String::from_utf8(second.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(
+        events[0],
+        cargo_save::BuildEvent::BuildPlan {
+            subcommand: "build".to_string(),
+            total_packages: 1,
+            cached_count: 1,
+            changed_packages: vec![],
+        }
+    );
+    assert_eq!(
+        events[1],
+        cargo_save::BuildEvent::PackageCached {
+            package: "json-fixture".to_string(),
+            version: "0.1.0".to_string(),
+        }
+    );
+    assert!(matches!(
+        events.last(),
+        Some(cargo_save::BuildEvent::Finished {
+            success: true,
+            fresh: true,
+            ..
+        })
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_message_format_json_skips_excluded_packages_and_reports_errors() {
+    use cargo_save::BuildEvent;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("ws");
+    for name in ["kept", "skipped"] {
+        fs::create_dir_all(root.join(name).join("src")).unwrap();
+        fs::write(
+            root.join(name).join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+                name
+            ),
+        )
+        .unwrap();
+        fs::write(root.join(name).join("src/lib.rs"), "").unwrap();
+    }
+    fs::write(
+        root.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"kept\", \"skipped\"]\nresolver = \"2\"\n",
+    )
+    .unwrap();
+
+    // A cargo without cargo-nextest
+    let stub = temp_dir.path().join("cargo-stub");
+    fs::write(
+        &stub,
+        format!(
+            "#!/bin/sh\n[ \"$1\" = nextest ] && exit 101\nexec \"{}\" \"$@\"\n",
+            env!("CARGO")
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(["save", "--message-format", "cargo-save-json"])
+            .args(args)
+            .current_dir(&root)
+            .env("CARGO_SAVE_CARGO_BIN", &stub)
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+            .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+            .output()
+            .unwrap();
+        let events: Vec<BuildEvent> = String::from_utf8(output.stdout.clone())
+            .unwrap()
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        (output, events)
+    };
+
+    let (output, events) = run(&["check", "--workspace", "--exclude", "skipped"]);
+    assert!(output.status.success(), "{:?}", output);
+    let packages: Vec<&str> = events
+        .iter()
+        .filter_map(|event| match event {
+            BuildEvent::PackageCached { package, .. }
+            | BuildEvent::PackageRebuilding { package, .. } => Some(package.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(packages, vec!["kept"]);
+
+    // A build that stops early still finishes its event stream
+    let (output, events) = run(&["nextest", "run"]);
+    assert!(!output.status.success(), "{:?}", output);
+    match events.last() {
+        Some(BuildEvent::Finished {
+            success: false,
+            error: Some(error),
+            ..
+        }) => assert!(error.contains("cargo-nextest"), "{}", error),
+        other => panic!("expected a failed finished event, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_gc_removed_packages_collects_orphaned_entries() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    let before = workspace_with(vec![package("kept", &[]), package("removed", &[])]);
+    let mut other = workspace_with(vec![package("removed", &[])]);
+    other.root = std::path::PathBuf::from("/other-workspace");
+    for (workspace, name) in [(&before, "kept"), (&before, "removed"), (&other, "removed")] {
+        cache
+            .save_incremental_cache(
+                &package(name, &[]),
+                workspace,
+                "cmd",
+                if workspace.root == other.root {
+                    "other"
+                } else {
+                    "env"
+                },
+                false,
+                &[],
+                true,
+                0,
+            )
+            .unwrap();
+    }
+
+    let after = workspace_with(vec![package("kept", &[])]);
+    assert_eq!(cache.gc_removed_packages(&after).unwrap(), 1);
+    assert_eq!(cache.gc_removed_packages(&after).unwrap(), 0);
+
+    let mut remaining: Vec<(String, Option<std::path::PathBuf>)> =
+        fs::read_dir(&cache.incremental_dir)
+            .unwrap()
+            .map(|e| {
+                let entry: cargo_save::IncrementalCache =
+                    serde_json::from_str(&fs::read_to_string(e.unwrap().path()).unwrap()).unwrap();
+                (entry.package_name, entry.workspace_root)
+            })
+            .collect();
+    remaining.sort();
+    assert_eq!(
+        remaining,
+        vec![
+            ("kept".to_string(), Some(before.root.clone())),
+            ("removed".to_string(), Some(other.root.clone())),
+        ]
+    );
+}
+
+/// Answers commands from a table of canned outputs.
+#[cfg(unix)]
+struct MockCommandRunner {
+    /// `(program args..., exit code, stdout)`; program paths match by file name
+    responses: Vec<(&'static str, i32, &'static str)>,
+}
+
+#[cfg(unix)]
+impl cargo_save::CommandRunner for MockCommandRunner {
+    fn run(
+        &self,
+        program: &str,
+        args: &[&str],
+        _cwd: Option<&std::path::Path>,
+    ) -> std::io::Result<std::process::Output> {
+        use std::os::unix::process::ExitStatusExt;
+
+        let program = std::path::Path::new(program)
+            .file_name()
+            .unwrap()
+            .to_string_lossy();
+        let call = std::iter::once(program.as_ref())
+            .chain(args.iter().copied())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let (code, stdout, stderr) = match self.responses.iter().find(|(c, ..)| *c == call) {
+            Some(&(_, code, stdout)) => (code, stdout, ""),
+            None => (128, "", "fatal: not a git repository"),
+        };
+        Ok(std::process::Output {
+            status: std::process::ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        })
+    }
+}
+
+/// Records the calls made through a [`MockCommandRunner`]
+#[cfg(unix)]
+struct CountingRunner {
+    inner: MockCommandRunner,
+    calls: std::sync::Arc<Mutex<Vec<String>>>,
+}
+
+#[cfg(unix)]
+impl cargo_save::CommandRunner for CountingRunner {
+    fn run(
+        &self,
+        program: &str,
+        args: &[&str],
+        cwd: Option<&std::path::Path>,
+    ) -> std::io::Result<std::process::Output> {
+        let call = std::iter::once(program).chain(args.iter().copied());
+        self.calls
+            .lock()
+            .unwrap()
+            .push(call.collect::<Vec<_>>().join(" "));
+        self.inner.run(program, args, cwd)
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_git_detection_and_toolchain_hash_use_command_runner() {
+    let temp_dir = TempDir::new().unwrap();
+    let mock = |responses| {
+        CacheManager::with_cache_dir(temp_dir.path())
+            .unwrap()
+            .with_command_runner(MockCommandRunner { responses })
+    };
+
+    // The temp dir is no git repository, but the runner claims it is one
+    let cache = mock(vec![
+        ("git rev-parse --git-dir", 0, ".git\n"),
+        ("git lfs status", 0, ""),
+    ]);
+    let info = cache.get_git_repo_info(temp_dir.path()).unwrap();
+    assert_eq!(info.git_dir, std::path::PathBuf::from(".git"));
+    assert!(!info.is_worktree);
+    assert!(info.has_lfs);
+
+    let worktree = mock(vec![
+        (
+            "git rev-parse --git-dir",
+            0,
+            "/repo/.git/worktrees/feature\n",
+        ),
+        ("git rev-parse --show-toplevel", 0, "/feature\n"),
+    ]);
+    let info = worktree.get_git_repo_info(temp_dir.path()).unwrap();
+    assert!(info.is_worktree);
+    assert!(!info.has_lfs);
+    assert_eq!(
+        info.worktree_root,
+        Some(std::path::PathBuf::from("/feature"))
+    );
+
+    assert!(mock(vec![]).get_git_repo_info(temp_dir.path()).is_none());
+
+    let stable = vec![
+        ("rustc --version", 0, "rustc 1.70.0\n"),
+        ("cargo --version", 0, "cargo 1.70.0\n"),
+    ];
+    let nightly = vec![
+        ("rustc --version", 0, "rustc 1.80.0-nightly\n"),
+        ("cargo --version", 0, "cargo 1.70.0\n"),
+    ];
+    let hash = mock(stable.clone()).compute_toolchain_hash().unwrap();
+    assert_eq!(hash, mock(stable).compute_toolchain_hash().unwrap());
+    assert_ne!(hash, mock(nightly).compute_toolchain_hash().unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_builds_record_git_commit_and_branch() {
+    let temp_dir = TempDir::new().unwrap();
+    let mock = |responses| {
+        CacheManager::with_cache_dir(temp_dir.path().join("cache"))
+            .unwrap()
+            .with_command_runner(MockCommandRunner { responses })
+    };
+    let commit = "0123456789abcdef0123456789abcdef01234567";
+    let on_branch = vec![
+        (
+            "git rev-parse HEAD",
+            0,
+            "0123456789abcdef0123456789abcdef01234567\n",
+        ),
+        ("git rev-parse --abbrev-ref HEAD", 0, "main\n"),
+    ];
+
+    assert_eq!(
+        mock(on_branch.clone()).git_head(temp_dir.path()),
+        (Some(commit.to_string()), Some("main".to_string()))
+    );
+    let detached = mock(vec![
+        (
+            "git rev-parse HEAD",
+            0,
+            "0123456789abcdef0123456789abcdef01234567\n",
+        ),
+        ("git rev-parse --abbrev-ref HEAD", 0, "HEAD\n"),
+    ]);
+    assert_eq!(
+        detached.git_head(temp_dir.path()),
+        (Some(commit.to_string()), None)
+    );
+    assert_eq!(mock(vec![]).git_head(temp_dir.path()), (None, None));
+
+    // A real build stores them in its metadata
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "git-head-fixture", "");
+    let cache = mock(on_branch);
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .current_dir(&crate_dir)
+        .exec()
+        .unwrap();
+    let packages = metadata
+        .workspace_packages()
+        .iter()
+        .map(|p| cache.compute_package_hash(p, &metadata, &[]).unwrap())
+        .collect();
+    let mut workspace = workspace_with(packages);
+    workspace.root = crate_dir.clone();
+    let args = vec![
+        "--manifest-path".to_string(),
+        crate_dir.join("Cargo.toml").display().to_string(),
+        "--target-dir".to_string(),
+        temp_dir.path().join("target").display().to_string(),
+    ];
+    let (cache_id, exit_code, ..) = cache
+        .run_cargo_with_cache("check", &args, &workspace)
+        .unwrap();
+    assert_eq!(exit_code, Some(0));
+
+    let build = cache.load_build_cache(&cache_id).unwrap();
+    assert_eq!(build.commit.as_deref(), Some(commit));
+    assert_eq!(build.branch.as_deref(), Some("main"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_committed_only_ignores_uncommitted_changes() {
+    let temp_dir = TempDir::new().unwrap();
+    let pkg = temp_dir.path().join("pkg");
+    fs::create_dir_all(pkg.join("src")).unwrap();
+    fs::write(pkg.join("src/lib.rs"), "pub fn a() {}\n").unwrap();
+
+    // HEAD has the committed tree; git reports src/lib.rs as modified
+    let leak = |s: String| -> &'static str { Box::leak(s.into_boxed_str()) };
+    let responses = vec![
+        ("git rev-parse --git-dir", 0, ".git\n"),
+        (
+            leak(format!("git ls-tree -r HEAD {}", pkg.display())),
+            0,
+            "100644 blob 0123456789abcdef0123456789abcdef01234567\tsrc/lib.rs\n",
+        ),
+        (
+            leak(format!("git status --porcelain {}", pkg.display())),
+            0,
+            " M src/lib.rs\n",
+        ),
+    ];
+    let cache = |committed_only: bool| {
+        CacheManager::with_cache_dir(temp_dir.path().join("cache"))
+            .unwrap()
+            .with_command_runner(MockCommandRunner {
+                responses: responses.clone(),
+            })
+            .with_committed_only(committed_only)
+    };
+
+    let dirty = cache(false).compute_source_hash(&pkg, &[]).unwrap();
+    let committed = cache(true).compute_source_hash(&pkg, &[]).unwrap();
+    fs::write(pkg.join("src/lib.rs"), "pub fn b() {}\n").unwrap();
+
+    assert_ne!(cache(false).compute_source_hash(&pkg, &[]).unwrap(), dirty);
+    assert_eq!(
+        cache(true).compute_source_hash(&pkg, &[]).unwrap(),
+        committed
+    );
+}
+
+#[test]
+fn test_inspection_commands_accept_committed_only() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "committed-fixture", "");
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(&crate_dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?}", args);
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "first"]);
+
+    // Separate caches, so no run can reuse memoized hashes of another
+    let run = |cache: &str, args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(args)
+            .current_dir(&crate_dir)
+            .env(
+                "CARGO_SAVE_CACHE_DIR",
+                temp_dir.path().join("caches").join(cache),
+            )
+            .env_remove("CARGO_SAVE_COMMITTED_ONLY")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let source_hash = |cache: &str, extra: &[&str]| {
+        let mut args = vec!["status", "--hashes", "--full-hashes"];
+        args.extend(extra);
+        run(cache, &args)
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("committed-fixture 0.1.0: "))
+            .unwrap()
+            .to_string()
+    };
+
+    let clean = source_hash("clean", &[]);
+    let committed = source_hash("committed-clean", &["--committed-only"]);
+    fs::write(crate_dir.join("src/main.rs"), "fn main() {}\n// dirty\n").unwrap();
+    assert_ne!(source_hash("dirty", &[]), clean);
+    assert_eq!(
+        source_hash("committed-dirty", &["--committed-only"]),
+        committed
+    );
+
+    assert!(
+        run("changed", &["changed", "--committed-only", "--json"]).contains("committed-fixture")
+    );
+    assert!(run("warm", &["warm", "--committed-only"]).contains("committed-fixture"));
+}
+
+/// `cargo metadata` output for a workspace member depending on one git crate.
+fn metadata_with_git_dependency(root: &std::path::Path, commit: &str) -> cargo_metadata::Metadata {
+    let source = format!(
+        "git+https://github.com/example/shared?rev={}#{}",
+        &commit[..7],
+        commit
+    );
+    let package = |name: &str, id: String, source: Option<&str>, manifest: String| {
+        serde_json::json!({
+            "name": name,
+            "version": "0.1.0",
+            "id": id,
+            "source": source,
+            "dependencies": [],
+            "targets": [],
+            "features": {},
+            "manifest_path": manifest,
+        })
+    };
+    let app_id = format!("app 0.1.0 (path+file://{})", root.display());
+    serde_json::from_value(serde_json::json!({
+        "packages": [
+            package("app", app_id.clone(), None, format!("{}/Cargo.toml", root.display())),
+            package(
+                "shared",
+                format!("shared 0.1.0 ({})", source),
+                Some(&source),
+                "/git/checkouts/shared/Cargo.toml".to_string(),
+            ),
+        ],
+        "workspace_members": [app_id],
+        "resolve": null,
+        "workspace_root": root,
+        "target_directory": root.join("target"),
+        "version": 1,
+    }))
+    .unwrap()
+}
+
+#[test]
+fn test_git_dependency_rev_change_invalidates_caches() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let root = temp_dir.path().join("workspace");
+    fs::create_dir_all(&root).unwrap();
+    // A branch dependency whose lock file was not regenerated
+    fs::write(root.join("Cargo.lock"), "version = 3\n").unwrap();
+
+    let before = metadata_with_git_dependency(&root, &"a".repeat(40));
+    let after = metadata_with_git_dependency(&root, &"b".repeat(40));
+    assert_eq!(
+        cargo_save::git_dependency_sources(&before),
+        vec![format!(
+            "git+https://github.com/example/shared?rev=aaaaaaa#{}",
+            "a".repeat(40)
+        )]
+    );
+
+    let mut workspace = workspace_with(vec![package("app", &[])]);
+    workspace.root = root.clone();
+    workspace.cargo_lock_hash = cache.compute_dependency_lock_hash(&root, &before).unwrap();
+    cache
+        .save_incremental_cache(
+            &package("app", &[]),
+            &workspace,
+            "cmd",
+            "env",
+            false,
+            &[],
+            true,
+            0,
+        )
+        .unwrap();
+    let check = |workspace: &cargo_save::WorkspaceState| {
+        cache.check_incremental_cache(&package("app", &[]), workspace, "cmd", "env", false, &[])
+    };
+    assert!(check(&workspace).is_some());
+
+    workspace.cargo_lock_hash = cache.compute_dependency_lock_hash(&root, &after).unwrap();
+    assert!(check(&workspace).is_none());
+}
+
+#[test]
+fn test_external_path_dependency_edit_invalidates_dependent() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+
+    // `shared` sits next to the workspace and is no member of it
+    let shared = temp_dir.path().join("shared");
+    fs::create_dir_all(shared.join("src")).unwrap();
+    fs::write(
+        shared.join("Cargo.toml"),
+        "[package]\nname = \"shared\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+    )
+    .unwrap();
+    fs::write(shared.join("src/lib.rs"), "pub fn answer() -> u32 { 42 }\n").unwrap();
+
+    let app = temp_dir.path().join("app");
+    fs::create_dir_all(app.join("src")).unwrap();
+    fs::write(
+        app.join("Cargo.toml"),
+        "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+         [dependencies]\nshared = { path = \"../shared\" }\n\n[workspace]\n",
+    )
+    .unwrap();
+    fs::write(app.join("src/main.rs"), "fn main() {}\n").unwrap();
+
+    let hash_app = || {
+        let metadata = cargo_metadata::MetadataCommand::new()
+            .manifest_path(app.join("Cargo.toml"))
+            .exec()
+            .unwrap();
+        assert_eq!(metadata.workspace_members.len(), 1);
+        let package = metadata.workspace_packages()[0].clone();
+        cache
+            .compute_package_hash(&package, &metadata, &[])
+            .unwrap()
+    };
+
+    let before = hash_app();
+    assert_eq!(hash_app().source_hash, before.source_hash);
+
+    let mut workspace = workspace_with(vec![before.clone()]);
+    workspace.root = app.clone();
+    cache
+        .save_incremental_cache(&before, &workspace, "cmd", "env", false, &[], true, 0)
+        .unwrap();
+    assert!(cache
+        .check_incremental_cache(&before, &workspace, "cmd", "env", false, &[])
+        .is_some());
+
+    fs::write(shared.join("src/lib.rs"), "pub fn answer() -> u32 { 43 }\n").unwrap();
+    let after = hash_app();
+    assert_ne!(after.source_hash, before.source_hash);
+    assert!(cache
+        .check_incremental_cache(&after, &workspace, "cmd", "env", false, &[])
+        .is_none());
+}
+
+#[test]
+fn test_compile_outcomes_from_cargo_output() {
+    use cargo_save::CompileOutcomes;
+
+    let mut outcomes = CompileOutcomes::default();
+    for line in [
+        "   Compiling good v0.1.0 (/ws/good)",
+        "    Checking bad v0.1.0 (/ws/bad)",
+        "   Compiling script v0.1.0 (/ws/script)",
+        "error[E0308]: mismatched types",
+        "error: could not compile `bad` (lib) due to 1 previous error",
+        "error: failed to run custom build command for `script v0.1.0 (/ws/script)`",
+        "    Finished dev [unoptimized + debuginfo] target(s) in 0.5s",
+    ] {
+        outcomes.record(line);
+    }
+
+    assert!(outcomes.succeeded("good"));
+    assert!(!outcomes.succeeded("bad"));
+    assert!(!outcomes.succeeded("script"));
+    assert!(!outcomes.succeeded("never-built"));
+}
+
+#[test]
+fn test_progress_counter_only_counts_planned_packages() {
+    use cargo_save::{LogStream, ProgressCounter};
+
+    let log = [
+        "   Compiling proc-macro2 v1.0.86",
+        "   Compiling unicode-ident v1.0.12",
+        "   Compiling core v0.1.0 (/ws/core)",
+        "   Compiling quote v1.0.36",
+        "   Compiling syn v2.0.72",
+        "warning: unused variable: `x`",
+        "   Compiling macros v0.1.0 (/ws/macros)",
+        "   Compiling serde v1.0.204",
+        "    Checking app v0.1.0 (/ws/app)",
+        "    Finished `dev` profile [unoptimized + debuginfo] target(s) in 4.20s",
+    ];
+    let planned = || ["core", "macros", "app"].map(String::from);
+
+    let mut counter = ProgressCounter::new(planned(), Some(LogStream::Both));
+    let annotations: Vec<String> = log
+        .iter()
+        .filter_map(|line| counter.annotate(line, true))
+        .collect();
+    assert_eq!(annotations, vec![" [1/3]", " [2/3]", " [3/3]"]);
+
+    // Only the selected stream is annotated
+    let mut stdout_only = ProgressCounter::new(planned(), Some(LogStream::Out));
+    assert!(log
+        .iter()
+        .all(|line| stdout_only.annotate(line, true).is_none()));
+    let mut off = ProgressCounter::new(planned(), None);
+    assert!(log.iter().all(|line| off.annotate(line, true).is_none()));
+}
+
+#[test]
+fn test_failed_keep_going_build_caches_compiled_packages() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let ws = temp_dir.path().join("ws");
+    let write = |path: &str, content: &str| {
+        let path = ws.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    };
+    let manifest = |name: &str| {
+        format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+            name
+        )
+    };
+    write(
+        "Cargo.toml",
+        "[workspace]\nmembers = [\"good\", \"bad\", \"script\"]\n",
+    );
+    write("good/Cargo.toml", &manifest("good"));
+    write("good/src/lib.rs", "pub fn good() {}\n");
+    write("bad/Cargo.toml", &manifest("bad"));
+    write(
+        "bad/src/lib.rs",
+        "pub fn bad() -> u32 {\n    \"not a number\"\n}\n",
+    );
+    // A failing build script compiles its package but fails the build
+    write("script/Cargo.toml", &manifest("script"));
+    write("script/src/lib.rs", "");
+    write("script/build.rs", "fn main() {\n    panic!(\"boom\");\n}\n");
+
+    let cache_dir = temp_dir.path().join("cache");
+    let build = || {
+        Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(["save", "build", "--keep-going"])
+            .current_dir(&ws)
+            .env("CARGO_SAVE_CACHE_DIR", &cache_dir)
+            .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+            .output()
+            .unwrap()
+    };
+    assert!(!build().status.success());
+    assert!(!build().status.success());
+
+    let cache = CacheManager::with_cache_dir(cache_dir).unwrap();
+    let entries: Vec<String> = fs::read_dir(&cache.incremental_dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(entries.len(), 1, "{:?}", entries);
+    assert!(entries[0].starts_with("good-"));
+}
+
+#[test]
+fn test_run_cargo_with_cache_env_uses_given_environment() {
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "env-map-fixture", "");
+    // The build script records the rustflags cargo was run with
+    let probe = temp_dir.path().join("probe.txt");
+    fs::write(
+        crate_dir.join("build.rs"),
+        "fn main() {\n    println!(\"cargo:rerun-if-env-changed=RUSTFLAGS\");\n    \
+         let flags = std::env::var(\"CARGO_ENCODED_RUSTFLAGS\").unwrap_or_default();\n    \
+         let path = std::env::var(\"ENV_PROBE_FILE\").unwrap();\n    \
+         std::fs::write(path, flags.replace('\\x1f', \" \")).unwrap();\n}\n",
+    )
+    .unwrap();
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .current_dir(&crate_dir)
+        .exec()
+        .unwrap();
+    let packages = metadata
+        .workspace_packages()
+        .iter()
+        .map(|p| cache.compute_package_hash(p, &metadata, &[]).unwrap())
+        .collect();
+    let mut workspace = workspace_with(packages);
+    workspace.root = crate_dir.clone();
+    let args = vec![
+        "--manifest-path".to_string(),
+        crate_dir.join("Cargo.toml").display().to_string(),
+        "--target-dir".to_string(),
+        temp_dir.path().join("target").display().to_string(),
+    ];
+
+    let env = |flags: &str| {
+        std::collections::BTreeMap::from([
+            ("RUSTFLAGS".to_string(), flags.to_string()),
+            ("ENV_PROBE_FILE".to_string(), probe.display().to_string()),
+        ])
+    };
+    let (a, b) = (env("--cfg probe_a"), env("--cfg probe_b"));
+    assert_ne!(
+        cache.compute_env_hash_from(&a),
+        cache.compute_env_hash_from(&b)
+    );
+
+    for env in [&a, &b] {
+        let (cache_id, exit_code, ..) = cache
+            .run_cargo_with_cache_env("check", &args, &workspace, env)
+            .unwrap();
+        assert_eq!(exit_code, Some(0));
+        assert_eq!(fs::read_to_string(&probe).unwrap(), env["RUSTFLAGS"]);
+
+        let build = cache.load_build_cache(&cache_id).unwrap();
+        assert_eq!(build.env_hash, cache.compute_env_hash_from(env));
+        assert_eq!(
+            build.env_vars.get("RUSTFLAGS"),
+            Some(&env["RUSTFLAGS"].to_string())
+        );
+        assert!(!build.env_vars.contains_key("ENV_PROBE_FILE"));
+    }
+}
+
+#[test]
+fn test_warns_when_build_keeps_changing_sources() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "thrash-fixture", "");
+    // Writes a fresh timestamp into the package on every build
+    fs::write(
+        crate_dir.join("build.rs"),
+        "fn main() {\n    let now = std::time::SystemTime::now()\n        \
+         .duration_since(std::time::UNIX_EPOCH)\n        .unwrap()\n        .as_nanos();\n    \
+         std::fs::write(\"src/generated.rs\", format!(\"// {}\\n\", now)).unwrap();\n}\n",
+    )
+    .unwrap();
+
+    let run = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(["save", "check"])
+            .current_dir(&crate_dir)
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+            .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    let warning = "thrash-fixture is rebuilt although nothing changed since its last build";
+    assert!(!run().contains(warning));
+    for _ in 0..2 {
+        let stderr = run();
+        assert!(stderr.contains(warning), "{}", stderr);
+        assert!(stderr.contains("no_cache"));
+    }
+}
+
+#[test]
+fn test_doc_builds_are_cached() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "doc-fixture", "");
+    let target_dir = temp_dir.path().join("target");
+
+    let run_with = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(["save", "doc"])
+            .args(args)
+            .current_dir(&crate_dir)
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+            .env("CARGO_TARGET_DIR", &target_dir)
+            // `--open` hands the page to $BROWSER
+            .env("BROWSER", "true")
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    let run = || run_with(&[]);
+
+    assert!(!run().contains("All packages cached"));
+    let entries: Vec<_> = fs::read_dir(
+        CacheManager::with_cache_dir(temp_dir.path().join("cache"))
+            .unwrap()
+            .incremental_dir,
+    )
+    .unwrap()
+    .flatten()
+    .collect();
+    assert_eq!(entries.len(), 1);
+    let entry: cargo_save::IncrementalCache =
+        serde_json::from_str(&fs::read_to_string(entries[0].path()).unwrap()).unwrap();
+    let doc_dir = target_dir.join("doc").join("doc_fixture");
+    assert!(entry
+        .target_files
+        .iter()
+        .any(|(path, _)| path.starts_with(&doc_dir)));
+
+    assert!(run().contains("All packages cached, skipping doc"));
+    assert!(!run_with(&["--open"]).contains("All packages cached"));
+
+    // Files shared by the whole site are no part of any entry
+    fs::remove_dir_all(target_dir.join("doc").join("static.files")).unwrap();
+    assert!(!run().contains("All packages cached"));
+    assert!(target_dir.join("doc").join("static.files").is_dir());
+    assert!(run().contains("All packages cached, skipping doc"));
+}
+
+#[test]
+fn test_hash_display_length_does_not_change_cache_keys() {
+    use cargo_save::{display_hash, hash_display_len, CiPlatform};
+
+    let _guard = ENV_MUTEX.lock().unwrap();
+    let previous = std::env::var("CARGO_SAVE_HASH_LEN").ok();
+    let hash = "0123456789abcdef".repeat(4);
+
+    let mut workspace = workspace_with(vec![package("core", &[])]);
+    workspace.toolchain_hash = "a".repeat(64);
+    workspace.cargo_lock_hash = "b".repeat(64);
+    let mut entries = Vec::new();
+    let mut keys = Vec::new();
+    for len in ["8", "64", "3", "100"] {
+        std::env::set_var("CARGO_SAVE_HASH_LEN", len);
+        let temp_dir = TempDir::new().unwrap();
+        let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+        cache
+            .save_incremental_cache(
+                &workspace.packages[0],
+                &workspace,
+                "cmd",
+                "env",
+                false,
+                &[],
+                true,
+                0,
+            )
+            .unwrap();
+        let names: Vec<_> = fs::read_dir(&cache.incremental_dir)
+            .unwrap()
+            .flatten()
+            .map(|e| e.file_name())
+            .collect();
+        entries.push(names);
+
+        // Only what is shown follows the display length
+        let expected = len.parse::<usize>().unwrap().clamp(8, 64);
+        assert_eq!(hash_display_len(), expected);
+        assert_eq!(display_hash(&hash, false).len(), expected);
+        assert_eq!(display_hash(&hash, true), hash);
+        assert!(cache
+            .generate_cache_key(CiPlatform::Github, &workspace, false, true)
+            .contains(&"a".repeat(64)));
+        keys.push(cache.generate_cache_key(CiPlatform::Github, &workspace, false, false));
+    }
+    assert_eq!(entries[0].len(), 1);
+    assert!(entries.iter().all(|names| *names == entries[0]));
+    // CI cache keys are not display output either
+    assert!(keys[0].contains(&"a".repeat(16)) && !keys[0].contains(&"a".repeat(17)));
+    assert!(keys.iter().all(|key| *key == keys[0]));
+
+    std::env::remove_var("CARGO_SAVE_HASH_LEN");
+    assert_eq!(hash_display_len(), 16);
+    if let Some(len) = previous {
+        std::env::set_var("CARGO_SAVE_HASH_LEN", len);
+    }
+}
+
+#[test]
+fn test_cache_key_includes_extra_env_and_features() {
+    use cargo_save::{CacheKeyOptions, CiPlatform};
+
+    let _guard = ENV_MUTEX.lock().unwrap();
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let mut workspace = workspace_with(vec![]);
+    workspace.toolchain_hash = "a".repeat(64);
+    workspace.cargo_lock_hash = "b".repeat(64);
+
+    let plain = cache.generate_cache_key(CiPlatform::Github, &workspace, false, false);
+    let options = CacheKeyOptions {
+        include_env: vec!["CARGO_SAVE_TEST_MATRIX".to_string()],
+        ..Default::default()
+    };
+    let key_for = |value: &str| {
+        std::env::set_var("CARGO_SAVE_TEST_MATRIX", value);
+        cache.generate_cache_key_with(CiPlatform::Github, &workspace, &options)
+    };
+    let (a, b) = (key_for("minimal"), key_for("full"));
+    std::env::remove_var("CARGO_SAVE_TEST_MATRIX");
+
+    assert_ne!(a, b);
+    assert_eq!(a, key_for("minimal"));
+    std::env::remove_var("CARGO_SAVE_TEST_MATRIX");
+    assert!(a.starts_with(&format!("{}-", plain)));
+
+    let features = |list: &str| {
+        cache.generate_cache_key_with(
+            CiPlatform::Github,
+            &workspace,
+            &CacheKeyOptions {
+                include_features: Some(list.to_string()),
+                ..Default::default()
+            },
+        )
+    };
+    assert_ne!(features("serde"), features("serde,tokio"));
+    assert_ne!(features("serde"), plain);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unchanged_workspace_reuses_memoized_package_hashes() {
+    use std::sync::{Arc, Mutex};
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "memo-fixture", "");
+    let crate_dir = crate_dir.canonicalize().unwrap();
+    let args = vec![
+        "--manifest-path".to_string(),
+        crate_dir.join("Cargo.toml").display().to_string(),
+    ];
+
+    let leak = |s: String| -> &'static str { Box::leak(s.into_boxed_str()) };
+    let repo = |status: &'static str| {
+        vec![
+            ("git rev-parse --git-dir", 0, ".git\n"),
+            (
+                "git rev-parse --show-toplevel HEAD",
+                0,
+                leak(format!(
+                    "{}\n0123456789abcdef0123456789abcdef01234567\n",
+                    crate_dir.display()
+                )),
+            ),
+            ("git status --porcelain -z --untracked-files=all", 0, status),
+            (
+                leak(format!("git ls-tree -r HEAD {}", crate_dir.display())),
+                0,
+                "100644 blob 0123456789abcdef0123456789abcdef01234567\tsrc/main.rs\n",
+            ),
+        ]
+    };
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let cache = |status| {
+        CacheManager::with_cache_dir(temp_dir.path().join("cache"))
+            .unwrap()
+            .with_command_runner(CountingRunner {
+                inner: MockCommandRunner {
+                    responses: repo(status),
+                },
+                calls: calls.clone(),
+            })
+    };
+    let hashed = || {
+        let calls = std::mem::take(&mut *calls.lock().unwrap());
+        calls
+            .iter()
+            .filter(|c| c.starts_with("git ls-tree"))
+            .count()
+    };
+
+    let first = cache("").compute_workspace_state(&args).unwrap();
+    assert_eq!(hashed(), 1);
+    let second = cache("").compute_workspace_state(&args).unwrap();
+    assert_eq!(hashed(), 0);
+    assert_eq!(
+        first.packages[0].source_hash,
+        second.packages[0].source_hash
+    );
+
+    // A file showing up in `git status` invalidates the memo
+    let dirty = cache(" M src/main.rs\0")
+        .compute_workspace_state(&args)
+        .unwrap();
+    assert_eq!(hashed(), 1);
+    assert_eq!(dirty.packages.len(), 1);
+    cache(" M src/main.rs\0")
+        .compute_workspace_state(&args)
+        .unwrap();
+    assert_eq!(hashed(), 0);
+
+    // So does editing a file that was already modified
+    fs::write(
+        crate_dir.join("src/main.rs"),
+        "fn main() {\n    // edited\n}\n",
+    )
+    .unwrap();
+    cache(" M src/main.rs\0")
+        .compute_workspace_state(&args)
+        .unwrap();
+    assert_eq!(hashed(), 1);
+}
+
+#[test]
+fn test_query_all_builds() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let matching = "20250101_100000-aaaaaaaa";
+    let clean = "20250101_110000-bbbbbbbb";
+    fs::write(
+        cache.cache_dir.join(format!("{}.log", matching)),
+        "   Compiling foo v0.1.0\nerror[E0599]: no method named `bar` found\n",
+    )
+    .unwrap();
+    fs::write(
+        cache.cache_dir.join(format!("{}.log", clean)),
+        "   Compiling foo v0.1.0\n    Finished `dev` profile\n",
+    )
+    .unwrap();
+
+    let hits = cache.grep_all("E0599").unwrap();
+    assert_eq!(
+        hits,
+        vec![(
+            matching.to_string(),
+            "error[E0599]: no method named `bar` found".to_string()
+        )]
+    );
+    assert_eq!(cache.grep_all("compiling").unwrap().len(), 2);
+
+    let options = cargo_save::QueryOptions {
+        all_builds: true,
+        ..Default::default()
+    };
+    let mut out = Vec::new();
+    cache
+        .query_logs_into("errors", None, &options, &mut out)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        format!("{}: error[E0599]: no method named `bar` found\n", matching)
+    );
+    assert!(cache
+        .query_logs_into("tail", None, &options, &mut Vec::new())
+        .is_err());
+}
+
+#[test]
+fn test_clean_keep_prunes_unused_incremental_caches() {
+    use cargo_save::CleanOptions;
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let pause = || std::thread::sleep(std::time::Duration::from_millis(10));
+    let entry = |name: &str| {
+        let path = cache.incremental_dir.join(format!("{}.json", name));
+        fs::write(&path, "{}").unwrap();
+        path
+    };
+    let build = |id: &str| {
+        let log = cache.cache_dir.join(format!("{}.log", id));
+        fs::write(&log, "output\n").unwrap();
+        fs::write(cache.metadata_dir.join(format!("{}.json", id)), "{}").unwrap();
+        log
+    };
+
+    // Entries were last used by the builds before them
+    let abandoned = entry("gone-1111-cmd-ws-env-debug-f");
+    pause();
+    let old_log = build("20240101_000000-aaaaaaaa");
+    pause();
+    let old_only = entry("old-2222-cmd-ws-env-debug-f");
+    pause();
+    let kept_log = build("20240101_000001-bbbbbbbb");
+    pause();
+    let in_use = entry("core-3333-cmd-ws-env-debug-f");
+
+    let candidates = cache.clean_candidates(0, Some(1)).unwrap();
+    assert!(candidates.contains(&old_log));
+    assert!(candidates.contains(&abandoned) && candidates.contains(&old_only));
+    assert!(!candidates.contains(&kept_log) && !candidates.contains(&in_use));
+
+    let removed = cache
+        .clean_old_caches_with(&CleanOptions {
+            keep: Some(1),
+            force: true,
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(removed, candidates);
+    assert!(!abandoned.exists() && !old_only.exists() && !old_log.exists());
+    assert!(in_use.exists() && kept_log.exists());
+}
+
+#[test]
+fn test_clean_scope_limits_removed_files() {
+    use cargo_save::{CleanOptions, CleanScope};
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+    let pause = || std::thread::sleep(std::time::Duration::from_millis(10));
+    let populate = || {
+        let entry = cache
+            .incremental_dir
+            .join("old-1111-cmd-ws-env-debug-f.json");
+        fs::write(&entry, "{}").unwrap();
+        pause();
+        let old_log = cache.cache_dir.join("20240101_000000-aaaaaaaa.log");
+        fs::write(&old_log, "output\n").unwrap();
+        pause();
+        fs::write(
+            cache.cache_dir.join("20240101_000001-bbbbbbbb.log"),
+            "output\n",
+        )
+        .unwrap();
+        (entry, old_log)
+    };
+    let clean = |scope| {
+        cache
+            .clean_old_caches_with(&CleanOptions {
+                keep: Some(1),
+                force: true,
+                scope,
+                ..Default::default()
+            })
+            .unwrap()
+    };
+
+    let (entry, old_log) = populate();
+    assert_eq!(clean(CleanScope::Logs), vec![old_log.clone()]);
+    assert!(!old_log.exists());
+    assert!(entry.exists());
+
+    let (entry, old_log) = populate();
+    assert_eq!(clean(CleanScope::Incremental), vec![entry.clone()]);
+    assert!(!entry.exists());
+    assert!(old_log.exists());
+}
+
+#[test]
+fn test_manifest_path_selects_other_workspace() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("elsewhere");
+    create_fixture_crate(&crate_dir, "manifest-path-fixture", "");
+    let manifest = crate_dir.join("Cargo.toml");
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache"))
+        .unwrap()
+        .with_manifest_path(&manifest);
+    let workspace = cache.compute_workspace_state(&[]).unwrap();
+    assert_eq!(
+        workspace.root.canonicalize().unwrap(),
+        crate_dir.canonicalize().unwrap()
+    );
+    assert_eq!(workspace.packages.len(), 1);
+    assert_eq!(workspace.packages[0].name, "manifest-path-fixture");
+    let metadata = cache.get_cargo_metadata().unwrap();
+    assert_eq!(
+        std::path::PathBuf::from(metadata.workspace_root)
+            .canonicalize()
+            .unwrap(),
+        crate_dir.canonicalize().unwrap()
+    );
+
+    // The CLI resolves the workspace from the manifest, not the current directory
+    let out = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+        .args(["status", "--manifest-path"])
+        .arg(&manifest)
+        .current_dir(temp_dir.path())
+        .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+        .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains(&format!("Root: {}", crate_dir.display())));
+    assert!(stdout.contains("Packages: 1"));
+}
+
+#[test]
+fn test_dependency_graph_dot_and_json() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().to_path_buf()).unwrap();
+
+    // app -> lib -> core, app -> serde (not a workspace member)
+    let workspace = workspace_with(vec![
+        package("core", &[]),
+        package("lib", &["core"]),
+        package("app", &["lib", "serde"]),
+    ]);
+    let graph = cache.build_dependency_graph(&workspace);
+    let changed = vec!["lib".to_string(), "app".to_string()];
+
+    let dot = graph.to_dot(&changed);
+    assert!(dot.starts_with("digraph workspace {"));
+    assert!(dot.contains("\"app\" -> \"lib\";"));
+    assert!(dot.contains("\"lib\" -> \"core\";"));
+    assert!(!dot.contains("serde"));
+    assert!(dot.contains("\"lib\" [style=filled"));
+    assert!(dot.contains("    \"core\";\n"));
+
+    let json = graph.to_json(&changed);
+    assert_eq!(json["nodes"].as_array().unwrap().len(), 3);
+    assert_eq!(json["nodes"][1]["name"], "core");
+    assert_eq!(json["nodes"][1]["changed"], false);
+    assert_eq!(
+        json["edges"],
+        serde_json::json!([
+            { "from": "app", "to": "lib" },
+            { "from": "lib", "to": "core" },
+        ])
+    );
+}
+
+#[test]
+fn test_cache_usage_counts_nested_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().to_path_buf()).unwrap();
+
+    fs::write(cache.incremental_dir.join("entry.json"), vec![0u8; 100]).unwrap();
+    let nested = cache.incremental_dir.join("ab").join("cd");
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(nested.join("object"), vec![0u8; 2000]).unwrap();
+    fs::write(
+        cache.incremental_dir.join("ab").join("other"),
+        vec![0u8; 30],
+    )
+    .unwrap();
+    fs::write(cache.metadata_dir.join("build.json"), vec![0u8; 7]).unwrap();
+
+    let usage = cache.cache_usage().unwrap();
+    assert_eq!(
+        usage.incremental,
+        cargo_save::DirUsage {
+            files: 3,
+            bytes: 2130
+        }
+    );
+    assert_eq!(usage.metadata.bytes, 7);
+    assert_eq!(usage.total_bytes(), 2137);
+
+    let report = cache.doctor_report().unwrap();
+    assert_eq!(report.incremental_caches, 3);
+    assert_eq!(report.cache_size_bytes, 2137);
+}
+
+#[test]
+fn test_env_file_feeds_env_hash_and_cargo() {
+    use std::process::Command;
+
+    let _guard = ENV_MUTEX.lock().unwrap();
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "env-file-fixture", "");
+    // The build script records the rustflags and a custom variable
+    let probe = temp_dir.path().join("probe.txt");
+    fs::write(
+        crate_dir.join("build.rs"),
+        "fn main() {\n    println!(\"cargo:rerun-if-env-changed=RUSTFLAGS\");\n    \
+         let flags = std::env::var(\"CARGO_ENCODED_RUSTFLAGS\").unwrap_or_default();\n    \
+         let team = std::env::var(\"TEAM_SETTING\").unwrap_or_default();\n    \
+         let path = std::env::var(\"ENV_PROBE_FILE\").unwrap();\n    \
+         std::fs::write(path, format!(\"{} {}\", flags.replace('\\x1f', \" \"), team)).unwrap();\n}\n",
+    )
+    .unwrap();
+    let env_file = crate_dir.join(".cargo-save.env");
+    fs::write(
+        &env_file,
+        format!(
+            "# build settings\nexport RUSTFLAGS=\"--cfg from_file\"\nTEAM_SETTING='blue'\n\nENV_PROBE_FILE={}\n",
+            probe.display()
+        ),
+    )
+    .unwrap();
+
+    let vars = cargo_save::load_env_file(&env_file).unwrap();
+    assert_eq!(vars["RUSTFLAGS"], "--cfg from_file");
+    assert_eq!(vars["TEAM_SETTING"], "blue");
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let loaded = CacheManager::with_cache_dir(temp_dir.path().join("cache"))
+        .unwrap()
+        .with_env_file_vars(vars.clone(), true);
+    assert_eq!(
+        loaded.capture_env_vars().get("RUSTFLAGS"),
+        Some(&"--cfg from_file".to_string())
+    );
+    assert!(!loaded.capture_env_vars().contains_key("TEAM_SETTING"));
+    let mut expected = cache.capture_env_vars();
+    expected.insert("RUSTFLAGS".to_string(), "--cfg from_file".to_string());
+    assert_eq!(
+        loaded.compute_env_hash(),
+        cache.compute_env_hash_from(&expected)
+    );
+    assert_ne!(loaded.compute_env_hash(), cache.compute_env_hash());
+
+    let run = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(["save", "--env-file"])
+            .arg(&env_file)
+            .args(extra)
+            .arg("check")
+            .current_dir(&crate_dir)
+            .env("RUSTFLAGS", "--cfg from_process")
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+            .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        fs::read_to_string(&probe).unwrap()
+    };
+
+    // The process environment wins unless --env-file-override is given
+    assert_eq!(run(&[]), "--cfg from_process blue");
+    assert_eq!(run(&["--env-file-override"]), "--cfg from_file blue");
+    let build = cache.latest_build_cache().unwrap();
+    assert_eq!(
+        build.env_vars.get("RUSTFLAGS"),
+        Some(&"--cfg from_file".to_string())
+    );
+    assert!(!build.env_vars.contains_key("TEAM_SETTING"));
+}
+
+#[cfg(unix)]
+#[cfg(unix)]
+#[test]
+fn test_fmt_runs_without_hashing_workspace() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "fmt-fixture", "");
+    assert!(CacheManager::is_passthrough("fmt"));
+    assert!(!CacheManager::is_passthrough("build"));
+
+    // A cargo stub recording its arguments; hashing would run `cargo metadata`
+    let spawn_log = temp_dir.path().join("spawned.txt");
+    let stub = temp_dir.path().join("cargo-stub");
+    fs::write(
+        &stub,
+        format!("#!/bin/sh\necho \"$@\" >> \"{}\"\n", spawn_log.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let cache_root = temp_dir.path().join("cache");
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+        .args(["save", "fmt", "--check"])
+        .current_dir(&crate_dir)
+        .env("CARGO_SAVE_CARGO_BIN", &stub)
+        .env("CARGO_SAVE_CACHE_DIR", &cache_root)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(fs::read_to_string(&spawn_log).unwrap(), "fmt --check\n");
+
+    let cache = CacheManager::with_cache_dir(&cache_root).unwrap();
+    // The output is logged, but no build metadata is written
+    let logged = fs::read_dir(&cache.cache_dir)
+        .unwrap()
+        .flatten()
+        .any(|e| e.file_name().to_string_lossy().ends_with(".log"));
+    assert!(logged);
+    assert!(cache.load_build_caches().unwrap().is_empty());
+}
+
+#[test]
+fn test_phase_timings_cover_build() {
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "timing-fixture", "");
+    let args = vec![
+        "--manifest-path".to_string(),
+        crate_dir.join("Cargo.toml").display().to_string(),
+        "--target-dir".to_string(),
+        temp_dir.path().join("target").display().to_string(),
+    ];
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    assert_eq!(cache.phase_timings(), cargo_save::PhaseTimings::default());
+    let workspace = cache.compute_workspace_state(&args).unwrap();
+    let (_, exit_code, ..) = cache
+        .run_cargo_with_cache("check", &args, &workspace)
+        .unwrap();
+    assert_eq!(exit_code, Some(0));
+
+    let timings = cache.phase_timings();
+    assert!(timings.metadata > std::time::Duration::ZERO);
+    assert!(timings.source_hashing > std::time::Duration::ZERO);
+    assert!(timings.change_detection > std::time::Duration::ZERO);
+    assert!(timings.cargo > std::time::Duration::ZERO);
+    assert!(timings.cache_save > std::time::Duration::ZERO);
+    let phases = timings.metadata
+        + timings.source_hashing
+        + timings.change_detection
+        + timings.cache_restore
+        + timings.cargo
+        + timings.cache_save;
+    assert!(phases <= timings.total);
+    // Little time is spent outside the recorded phases
+    assert!(phases * 4 >= timings.total * 3, "{:?}", timings);
+}
+
+#[test]
+fn test_is_fully_cached_after_build() {
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "cached-fixture", "");
+    let args = vec![
+        "--manifest-path".to_string(),
+        crate_dir.join("Cargo.toml").display().to_string(),
+        "--target-dir".to_string(),
+        temp_dir.path().join("target").display().to_string(),
+    ];
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let workspace = cache.compute_workspace_state(&args).unwrap();
+    assert!(!cache.is_fully_cached("check", &args, &workspace));
+    let (_, exit_code, ..) = cache
+        .run_cargo_with_cache("check", &args, &workspace)
+        .unwrap();
+    assert_eq!(exit_code, Some(0));
+    assert!(cache.is_fully_cached("check", &args, &workspace));
+    // A different command has no entries of its own yet
+    assert!(!cache.is_fully_cached("build", &args, &workspace));
+    assert!(!cache.is_fully_cached("clean", &args, &workspace));
+
+    fs::write(crate_dir.join("src/main.rs"), "fn main() { let _ = 1; }\n").unwrap();
+    let workspace = cache.compute_workspace_state(&args).unwrap();
+    assert!(!cache.is_fully_cached("check", &args, &workspace));
+}
+
+#[test]
+fn test_restore_artifacts_only_fills_target_dir() {
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "restore-fixture", "");
+    let target_dir = temp_dir.path().join("target");
+    let args = vec![
+        "--manifest-path".to_string(),
+        crate_dir.join("Cargo.toml").display().to_string(),
+        "--target-dir".to_string(),
+        target_dir.display().to_string(),
+    ];
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let workspace = cache.compute_workspace_state(&args).unwrap();
+    let report = cache.restore_workspace_artifacts("build", &args, &workspace);
+    assert_eq!(report.missing, vec!["restore-fixture"]);
+
+    let (_, exit_code, ..) = cache
+        .run_cargo_with_cache("build", &args, &workspace)
+        .unwrap();
+    assert_eq!(exit_code, Some(0));
+    let entry = fs::read_dir(&cache.incremental_dir)
+        .unwrap()
+        .map(|e| fs::read_to_string(e.unwrap().path()).unwrap())
+        .map(|c| serde_json::from_str::<cargo_save::IncrementalCache>(&c).unwrap())
+        .next()
+        .unwrap();
+    assert!(!entry.target_files.is_empty());
+
+    fs::remove_dir_all(&target_dir).unwrap();
+    let report = cache.restore_workspace_artifacts("build", &args, &workspace);
+    assert_eq!(report.restored, vec!["restore-fixture"]);
+    assert!(report.missing.is_empty());
+    assert_eq!(report.files, entry.objects.len());
+    for (path, size) in &entry.target_files {
+        assert!(path.starts_with(&target_dir));
+        assert_eq!(
+            fs::metadata(path).unwrap().len(),
+            *size,
+            "{}",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn test_missing_target_dir_only_restores_this_workspaces_objects() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let run = |name: &str, args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .arg("save")
+            .args(args)
+            .current_dir(temp_dir.path().join(name))
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+            .env(
+                "CARGO_TARGET_DIR",
+                temp_dir.path().join(name).join("target"),
+            )
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    for name in ["built", "fresh"] {
+        create_fixture_crate(&temp_dir.path().join(name), name, "");
+    }
+
+    run("built", &["build"]);
+    // Objects of another workspace are no reason to check every entry
+    assert!(run("fresh", &["--dry-run", "build"]).contains("rebuilding all packages"));
+
+    fs::remove_dir_all(temp_dir.path().join("built/target")).unwrap();
+    let stderr = run("built", &["--dry-run", "build"]);
+    assert!(stderr.contains("restoring cached artifacts"), "{}", stderr);
+}
+
+#[test]
+fn test_bare_build_only_plans_default_members() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("ws");
+    for (name, deps) in [
+        ("app", "lib = { path = \"../lib\" }"),
+        ("lib", ""),
+        ("extra", ""),
+    ] {
+        fs::create_dir_all(root.join(name).join("src")).unwrap();
+        fs::write(
+            root.join(name).join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\n{}\n",
+                name, deps
+            ),
+        )
+        .unwrap();
+        fs::write(root.join(name).join("src/lib.rs"), "").unwrap();
+    }
+    fs::write(
+        root.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"app\", \"lib\", \"extra\"]\ndefault-members = [\"app\"]\nresolver = \"2\"\n",
+    )
+    .unwrap();
+    let manifest = vec![
+        "--manifest-path".to_string(),
+        root.join("Cargo.toml").display().to_string(),
+    ];
+    let with = |extra: &[&str]| {
+        let mut args = manifest.clone();
+        args.extend(extra.iter().map(|a| a.to_string()));
+        args
+    };
+    let names = |workspace: &cargo_save::WorkspaceState| {
+        let mut names: Vec<String> = workspace.packages.iter().map(|p| p.name.clone()).collect();
+        names.sort();
+        names
+    };
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    // The state covers every member, so inspection commands see them all
+    let state = cache.compute_workspace_state(&manifest).unwrap();
+    assert_eq!(names(&state), vec!["app", "extra", "lib"]);
+    assert!(cache.why_rebuild("extra", &state, &manifest).is_ok());
+
+    // Builds cover the default members and the members they depend on
+    let bare = state.built_by(&manifest);
+    assert_eq!(names(&bare), vec!["app", "lib"]);
+    let plan = cache.plan("build", &manifest, &bare).unwrap();
+    assert!(plan.changed_packages.iter().all(|p| p.name != "extra"));
+    assert_eq!(plan.total_packages, 2);
+
+    let all = state.built_by(&with(&["--workspace"]));
+    assert_eq!(names(&all), vec!["app", "extra", "lib"]);
+    let selected = state.built_by(&with(&["-p", "extra"]));
+    assert_eq!(names(&selected), vec!["app", "extra", "lib"]);
+}
+
+#[test]
+fn test_invalidate_filters_by_subcommand_profile_and_age() {
+    use cargo_save::{InvalidateOptions, Profile};
+
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    let now = chrono::Local::now().to_rfc3339();
+    let entries = [
+        ("debug-build", false, "build", now.as_str()),
+        ("release-build", true, "build", now.as_str()),
+        ("debug-test", false, "test", now.as_str()),
+        ("old-build", false, "build", "2024-01-01T00:00:00+00:00"),
+    ];
+    for (key, is_release, subcommand, timestamp) in entries {
+        let entry = cargo_save::IncrementalCache {
+            package_name: "core".to_string(),
+            package_version: "0.1.0".to_string(),
+            source_hash: "0".repeat(64),
+            cargo_lock_hash: "lock".to_string(),
+            command_hash: "cmd".to_string(),
+            env_hash: "env".to_string(),
+            is_release,
+            features_hash: "features".to_string(),
+            target_files: vec![],
+            artifact_paths: vec![],
+            timestamp: timestamp.to_string(),
+            build_success: true,
+            duration_ms: 0,
+            objects: vec![],
+            tests_passed: None,
+            workspace_root: None,
+            hits: 0,
+            toolchain_hash: None,
+            subcommand: Some(subcommand.to_string()),
+            output_kind: None,
+        };
+        fs::write(
+            cache.incremental_dir.join(format!("{}.json", key)),
+            serde_json::to_string(&entry).unwrap(),
+        )
+        .unwrap();
+    }
+
+    let selected = |options: InvalidateOptions| -> Vec<String> {
+        cache
+            .invalidate_candidates(&options)
+            .unwrap()
+            .iter()
+            .map(|path| path.file_stem().unwrap().to_string_lossy().into_owned())
+            .collect()
+    };
+    let day_ago = cargo_save::parse_since("1d").unwrap();
+
+    // Filters alone select every package; without them nothing is selected
+    assert!(selected(InvalidateOptions::default()).is_empty());
+    assert_eq!(
+        selected(InvalidateOptions {
+            older_than: Some(day_ago),
+            ..Default::default()
+        }),
+        vec!["old-build"]
+    );
+    assert_eq!(
+        selected(InvalidateOptions {
+            all: true,
+            subcommand: Some("test".to_string()),
+            ..Default::default()
+        }),
+        vec!["debug-test"]
+    );
+    // Filters combine with AND
+    assert_eq!(
+        selected(InvalidateOptions {
+            packages: vec!["core".to_string()],
+            subcommand: Some("build".to_string()),
+            profile: Some(Profile::Debug),
+            ..Default::default()
+        }),
+        vec!["debug-build", "old-build"]
+    );
+
+    let removed = cache
+        .invalidate_caches_with(&InvalidateOptions {
+            profile: Some(Profile::Release),
+            force: true,
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(removed, 1);
+    assert!(!cache.incremental_dir.join("release-build.json").exists());
+    for key in ["debug-build", "debug-test", "old-build"] {
+        assert!(cache.incremental_dir.join(format!("{}.json", key)).exists());
+    }
+}
+
+#[test]
+fn test_missing_cargo_reports_how_to_fix() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "no-cargo-fixture", "");
+
+    for args in [&["save", "build"][..], &["save", "fmt"], &["status"]] {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(args)
+            .current_dir(&crate_dir)
+            .env("PATH", "")
+            .env_remove("CARGO")
+            .env_remove("CARGO_SAVE_CARGO_BIN")
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("cargo not found on PATH; set CARGO or CARGO_SAVE_CARGO_BIN"),
+            "{:?}: {}",
+            args,
+            stderr
+        );
+    }
+}
+
+#[test]
+fn test_build_history_buckets_by_day() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path()).unwrap();
+
+    let now = chrono::Local::now();
+    let at = |days_ago: i64| (now - chrono::Duration::days(days_ago)).to_rfc3339();
+    let date = |days_ago: i64| {
+        (now - chrono::Duration::days(days_ago))
+            .format("%Y-%m-%d")
+            .to_string()
+    };
+
+    let builds = [
+        ("a", 0, 1000),
+        ("b", 2, 3000),
+        ("c", 2, 5000),
+        ("d", 40, 100),
+    ];
+    for (id, days_ago, duration_ms) in builds {
+        let mut build = build_fixture(id, "build", Some(0), duration_ms);
+        build.timestamp = at(days_ago);
+        fs::write(
+            cache.metadata_dir.join(format!("{}.json", id)),
+            serde_json::to_string(&build).unwrap(),
+        )
+        .unwrap();
+    }
+    // One line per build plan, including builds skipped as fully cached
+    let hits: String = [(0, 4, 4), (2, 1, 4), (2, 3, 4), (40, 4, 4)]
+        .iter()
+        .map(|(days_ago, cached, total)| {
+            format!(
+                "{{\"timestamp\":\"{}\",\"subcommand\":\"build\",\"cached\":{},\"total\":{}}}\n",
+                at(*days_ago),
+                cached,
+                total
+            )
+        })
+        .collect();
+    fs::write(cache.cache_dir.join("hits.jsonl"), hits).unwrap();
+
+    let history = cache.build_history(30).unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].date, date(2));
+    assert_eq!(history[0].builds, 2);
+    assert_eq!(history[0].avg_duration_ms, 4000);
+    assert_eq!(history[0].cache_hit_rate, Some(0.5));
+    assert_eq!(history[1].date, date(0));
+    assert_eq!(history[1].builds, 1);
+    assert_eq!(history[1].cache_hit_rate, Some(1.0));
+
+    assert_eq!(cache.build_history(60).unwrap().len(), 3);
+}
+
+#[test]
+fn test_max_builds_prunes_oldest_builds() {
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "max-builds-fixture", "");
+    let args = vec![
+        "--manifest-path".to_string(),
+        crate_dir.join("Cargo.toml").display().to_string(),
+        "--target-dir".to_string(),
+        temp_dir.path().join("target").display().to_string(),
+    ];
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache"))
+        .unwrap()
+        .with_max_builds(3);
+    let workspace = cache.compute_workspace_state(&args).unwrap();
+    let mut cache_ids = Vec::new();
+    // A different config override per build keeps every build out of the cache
+    for run in 0..5 {
+        let mut args = args.clone();
+        args.extend(["--config".to_string(), format!("env.BUILD_RUN=\"{}\"", run)]);
+        let (cache_id, exit_code, ..) = cache
+            .run_cargo_with_cache("build", &args, &workspace)
+            .unwrap();
+        assert_eq!(exit_code, Some(0));
+        cache_ids.push(cache_id);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    let remaining: Vec<_> = cache
+        .load_build_caches()
+        .unwrap()
+        .into_iter()
+        .map(|build| build.cache_id)
+        .collect();
+    assert_eq!(remaining, cache_ids[2..]);
+    assert_eq!(fs::read_dir(&cache.metadata_dir).unwrap().count(), 3);
+}
+
+#[test]
+fn test_invalidate_changed_removes_only_modified_packages() {
+    use cargo_save::{IncrementalCache, InvalidateOptions, Profile};
+
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("ws");
+    for name in ["edited", "untouched"] {
+        fs::create_dir_all(root.join(name).join("src")).unwrap();
+        fs::write(
+            root.join(name).join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+                name
+            ),
+        )
+        .unwrap();
+        fs::write(root.join(name).join("src/lib.rs"), "").unwrap();
+    }
+    fs::write(
+        root.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"edited\", \"untouched\"]\nresolver = \"2\"\n",
+    )
+    .unwrap();
+    let manifest = root.join("Cargo.toml");
+    let args = vec![
+        "--manifest-path".to_string(),
+        manifest.display().to_string(),
+    ];
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache"))
+        .unwrap()
+        .with_manifest_path(&manifest);
+    let workspace = cache.compute_workspace_state(&args).unwrap();
+    let (_, exit_code, ..) = cache
+        .run_cargo_with_cache("build", &args, &workspace)
+        .unwrap();
+    assert_eq!(exit_code, Some(0));
+    let cached_packages = || {
+        let mut names: Vec<String> = fs::read_dir(&cache.incremental_dir)
+            .unwrap()
+            .map(|e| fs::read_to_string(e.unwrap().path()).unwrap())
+            .map(|c| serde_json::from_str::<IncrementalCache>(&c).unwrap())
+            .map(|entry| entry.package_name)
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(cached_packages(), vec!["edited", "untouched"]);
+    // A same-named package of another workspace sharing the cache
+    let mut other = workspace_with(vec![package("edited", &[])]);
+    other.root = temp_dir.path().join("other-ws");
+    cache
+        .save_incremental_cache(
+            &other.packages[0],
+            &other,
+            "cmd",
+            "env",
+            false,
+            &[],
+            true,
+            0,
+        )
+        .unwrap();
+
+    let changed = InvalidateOptions {
+        changed: true,
+        force: true,
+        ..Default::default()
+    };
+    assert!(cache.invalidate_candidates(&changed).unwrap().is_empty());
+
+    fs::write(root.join("edited/src/lib.rs"), "pub fn edited() {}\n").unwrap();
+    // Release entries of the edited package don't exist, so nothing matches
+    let release_only = InvalidateOptions {
+        profile: Some(Profile::Release),
+        ..changed.clone()
+    };
+    assert!(cache
+        .invalidate_candidates(&release_only)
+        .unwrap()
+        .is_empty());
+    assert_eq!(cache.invalidate_caches_with(&changed).unwrap(), 1);
+    assert_eq!(cached_packages(), vec!["edited", "untouched"]);
+}
+
+#[test]
+fn test_package_features_hash_uses_default_features() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("ws");
+    for (name, features) in [
+        ("with-defaults", "default = [\"fast\"]\nfast = []\n"),
+        ("without-defaults", "fast = []\n"),
+    ] {
+        fs::create_dir_all(root.join(name).join("src")).unwrap();
+        fs::write(
+            root.join(name).join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[features]\n{}",
+                name, features
+            ),
+        )
+        .unwrap();
+        fs::write(root.join(name).join("src/lib.rs"), "").unwrap();
+    }
+    fs::write(
+        root.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"with-defaults\", \"without-defaults\"]\nresolver = \"2\"\n",
+    )
+    .unwrap();
+    let args = |extra: &[&str]| {
+        let mut args = vec![
+            "--manifest-path".to_string(),
+            root.join("Cargo.toml").display().to_string(),
+        ];
+        args.extend(extra.iter().map(|a| a.to_string()));
+        args
+    };
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let features_hashes = |args: &[String]| {
+        let workspace = cache.compute_workspace_state(args).unwrap();
+        let hash = |name: &str| {
+            workspace
+                .packages
+                .iter()
+                .find(|p| p.name == name)
+                .unwrap()
+                .features_hash
+                .clone()
+        };
+        (hash("with-defaults"), hash("without-defaults"))
+    };
+
+    let (with, without) = features_hashes(&args(&[]));
+    assert_ne!(with, without);
+    // Features of another package leave this one's hash alone
+    let (with_fast, without_fast) =
+        features_hashes(&args(&["--features", "without-defaults/fast"]));
+    assert_eq!(with_fast, with);
+    assert_ne!(without_fast, without);
+    // Without defaults both packages build the same (empty) feature set
+    let (with, without) = features_hashes(&args(&["--no-default-features"]));
+    assert_eq!(with, without);
+
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .manifest_path(root.join("Cargo.toml"))
+        .no_deps()
+        .exec()
+        .unwrap();
+    let package = metadata
+        .packages
+        .iter()
+        .find(|p| p.name == "with-defaults")
+        .unwrap();
+    let features = |extra: &[&str]| cargo_save::effective_features(package, &args(extra));
+    assert_eq!(features(&[]), ["default", "fast"].map(String::from).into());
+    assert!(features(&["--no-default-features"]).is_empty());
+    assert_eq!(
+        features(&["--no-default-features", "-F", "fast"]),
+        ["fast"].map(String::from).into()
+    );
+}
+
+#[test]
+fn test_workspace_features_follow_dependency_declarations() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("ws");
+    for (name, sections) in [
+        (
+            "app",
+            "[dependencies]\nlib = { path = \"../lib\", default-features = false, features = [\"extra\"] }\n",
+        ),
+        ("lib", "[features]\ndefault = [\"fast\"]\nfast = []\nextra = []\n"),
+        ("tool", "[features]\ndefault = [\"fast\"]\nfast = []\n"),
+    ] {
+        fs::create_dir_all(root.join(name).join("src")).unwrap();
+        fs::write(
+            root.join(name).join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n{}",
+                name, sections
+            ),
+        )
+        .unwrap();
+        fs::write(root.join(name).join("src/lib.rs"), "").unwrap();
+    }
+    fs::write(
+        root.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"app\", \"lib\", \"tool\"]\nresolver = \"2\"\n",
+    )
+    .unwrap();
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let features_hash = |name: &str, extra: &[&str]| {
+        let mut args = vec![
+            "--manifest-path".to_string(),
+            root.join("Cargo.toml").display().to_string(),
+        ];
+        args.extend(extra.iter().map(|a| a.to_string()));
+        let workspace = cache.compute_workspace_state(&args).unwrap();
+        let package = workspace.packages.iter().find(|p| p.name == name).unwrap();
+        package.features_hash.clone()
+    };
+
+    // `lib` gets the features `app` declares on it, without its defaults
+    assert_eq!(
+        features_hash("lib", &["-p", "app"]),
+        features_hash(
+            "lib",
+            &["-p", "lib", "--no-default-features", "-F", "extra"]
+        )
+    );
+    // Feature flags only apply to the selected package
+    let unselected = features_hash("tool", &["-p", "tool", "--no-default-features"]);
+    assert_eq!(features_hash("tool", &["-p", "app"]), unselected);
+    assert_eq!(
+        features_hash("tool", &["-p", "app", "--all-features"]),
+        unselected
+    );
+    assert_ne!(features_hash("tool", &["-p", "tool"]), unselected);
+}
+
+#[test]
+fn test_short_features_flag_invalidates_incremental_cache() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let app = temp_dir.path().join("app");
+    fs::create_dir_all(app.join("src")).unwrap();
+    fs::write(
+        app.join("Cargo.toml"),
+        "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+         [features]\nfast = []\n\n[workspace]\n",
+    )
+    .unwrap();
+    fs::write(app.join("src/lib.rs"), "").unwrap();
+
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .manifest_path(app.join("Cargo.toml"))
+        .no_deps()
+        .exec()
+        .unwrap();
+    let package = metadata.workspace_packages()[0].clone();
+    let hash_app = |args: &[&str]| {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let hash = cache
+            .compute_package_hash(&package, &metadata, &args)
+            .unwrap();
+        (hash, args)
+    };
+
+    let (plain, plain_args) = hash_app(&[]);
+    let mut workspace = workspace_with(vec![plain.clone()]);
+    workspace.root = app.clone();
+    cache
+        .save_incremental_cache(
+            &plain,
+            &workspace,
+            "cmd",
+            "env",
+            false,
+            &plain_args,
+            true,
+            0,
+        )
+        .unwrap();
+    assert!(cache
+        .check_incremental_cache(&plain, &workspace, "cmd", "env", false, &plain_args)
+        .is_some());
+
+    for extra in [&["-F", "fast"][..], &["-Ffast"], &["--features=fast"]] {
+        let (fast, fast_args) = hash_app(extra);
+        assert_ne!(fast.features_hash, plain.features_hash, "{:?}", extra);
+        assert_ne!(
+            cache.compute_features_hash(&fast_args),
+            cache.compute_features_hash(&plain_args),
+            "{:?}",
+            extra
+        );
+        assert!(cache
+            .check_incremental_cache(&fast, &workspace, "cmd", "env", false, &fast_args)
+            .is_none());
+    }
+}
+
+#[test]
+fn test_summary_json_reports_fully_cached_build() {
+    use cargo_save::BuildSummary;
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "summary-fixture", "");
+    let run = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(["save", "--summary-json", "build"])
+            .current_dir(&crate_dir)
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+            .env("CARGO_TARGET_DIR", temp_dir.path().join("target"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let line = stdout.lines().last().unwrap().to_string();
+        let summary: BuildSummary = serde_json::from_str(&line).unwrap();
+        assert_eq!(serde_json::to_string(&summary).unwrap(), line);
+        summary
+    };
+
+    let first = run();
+    assert_eq!(first.exit_code, Some(0));
+    assert_eq!((first.total_packages, first.rebuilt_count), (1, 1));
+    assert!(first.log_path.is_some_and(|path| path.exists()));
+
+    let cached = run();
+    assert_eq!(cached.exit_code, Some(0));
+    assert_eq!(cached.rebuilt_count, 0);
+    assert_eq!(cached.cached_count, cached.total_packages);
+    assert_eq!(cached.log_path, None);
+}
+
+#[test]
+fn test_cache_path_and_incremental_cache_key_commands() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "cache-key-fixture", "");
+    let args = vec![
+        "--manifest-path".to_string(),
+        crate_dir.join("Cargo.toml").display().to_string(),
+        "--target-dir".to_string(),
+        temp_dir.path().join("target").display().to_string(),
+    ];
+    let run = |command: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(command)
+            .args(&args)
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    };
+
+    let cache = CacheManager::with_cache_dir(temp_dir.path().join("cache")).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+        .arg("cache-path")
+        .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        cache.cache_dir.display().to_string()
+    );
+
+    run(&["save", "build"]);
+    let key = run(&["cache-key", "--incremental", "cache-key-fixture"]);
+    let workspace = cache.compute_workspace_state(&args).unwrap();
+    assert_eq!(
+        cache.incremental_cache_key("cache-key-fixture", "build", &args, &workspace),
+        Some(key.clone())
+    );
+    assert!(cache.incremental_dir.join(format!("{}.json", key)).exists());
+    assert_eq!(
+        cache.incremental_cache_key("missing", "build", &args, &workspace),
+        None
+    );
+
+    // Cargo arguments only make sense for an incremental cache key
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+        .arg("cache-key")
+        .args(&args)
+        .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_git_ref_hashes_sources_at_that_ref() {
+    use std::process::Command;
+
+    let temp_dir = TempDir::new().unwrap();
+    let crate_dir = temp_dir.path().join("fixture");
+    create_fixture_crate(&crate_dir, "git-ref-fixture", "");
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(&crate_dir)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?}", args);
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "first"]);
+    git(&["tag", "v1"]);
+    fs::write(crate_dir.join("src/main.rs"), "fn main() {}\n// v2\n").unwrap();
+    git(&["commit", "-q", "-am", "second"]);
+    // Uncommitted changes are not part of a ref's hash
+    fs::write(crate_dir.join("src/main.rs"), "fn main() {}\n// dirty\n").unwrap();
+
+    let status = |git_ref: &str| {
+        Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args(["status", "--hashes", "--full-hashes", "--git-ref", git_ref])
+            .current_dir(&crate_dir)
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+            .output()
+            .unwrap()
+    };
+    let source_hash = |git_ref: &str| {
+        let output = status(git_ref);
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("git-ref-fixture 0.1.0: "))
+            .unwrap()
+            .to_string()
+    };
+
+    let v1 = source_hash("v1");
+    let head = source_hash("HEAD");
+    assert_ne!(v1, head);
+    assert_eq!(source_hash("HEAD~1"), v1);
+    assert!(!status("no-such-ref").status.success());
+
+    // Builds need the ref checked out without uncommitted changes
+    let target_dir = temp_dir.path().join("target").display().to_string();
+    let save = |git_ref: &str| {
+        Command::new(env!("CARGO_BIN_EXE_cargo-save"))
+            .args([
+                "save",
+                "check",
+                "--git-ref",
+                git_ref,
+                "--target-dir",
+                &target_dir,
+            ])
+            .current_dir(&crate_dir)
+            .env("CARGO_SAVE_CACHE_DIR", temp_dir.path().join("cache"))
+            .output()
+            .unwrap()
+    };
+    assert!(!save("HEAD").status.success());
+    fs::create_dir_all(crate_dir.join("extra")).unwrap();
+    fs::write(crate_dir.join("extra/notes.txt"), "new in third\n").unwrap();
+    git(&["add", "-A"]);
+    git(&["commit", "-q", "-m", "third"]);
+    git(&["tag", "v3"]);
+    assert!(!save("v1").status.success());
+    let output = save("HEAD");
+    assert!(output.status.success(), "{:?}", output);
+
+    // The library refuses to build a ref other than the checked-out one
+    let cache = |git_ref: &str| {
+        CacheManager::with_cache_dir(temp_dir.path().join("cache"))
+            .unwrap()
+            .with_git_ref(git_ref)
+    };
+    let args = vec![
+        "--manifest-path".to_string(),
+        crate_dir.join("Cargo.toml").display().to_string(),
+        "--target-dir".to_string(),
+        target_dir.clone(),
+    ];
+    let v1 = cache("v1");
+    assert!(v1.check_git_ref_checkout(&crate_dir).is_err());
+    let workspace = v1.compute_workspace_state(&args).unwrap();
+    assert!(v1.run_cargo_with_cache("check", &args, &workspace).is_err());
+    assert!(v1
+        .run_cargo_parallel("check", &args, &workspace, 2)
+        .is_err());
+    // Paths missing at the ref are not hashed from the working tree
+    assert!(v1
+        .compute_source_hash(&crate_dir.join("extra"), &[])
+        .is_err());
+
+    // Builds record the commit of the ref
+    let v3 = cache("v3");
+    v3.check_git_ref_checkout(&crate_dir).unwrap();
+    let workspace = v3.compute_workspace_state(&args).unwrap();
+    // `save check` above cached everything, so build instead
+    let (cache_id, exit_code, ..) = v3.run_cargo_with_cache("build", &args, &workspace).unwrap();
+    assert_eq!(exit_code, Some(0));
+    let output = Command::new("git")
+        .args(["rev-parse", "v3^{commit}"])
+        .current_dir(&crate_dir)
+        .output()
+        .unwrap();
+    let commit = String::from_utf8(output.stdout).unwrap().trim().to_string();
+    assert_eq!(v3.load_build_cache(&cache_id).unwrap().commit, Some(commit));
+}